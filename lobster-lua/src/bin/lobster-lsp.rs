@@ -0,0 +1,8 @@
+//! Language server for lobster, speaking LSP over stdin/stdout.
+
+fn main() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    lobster_lua::lsp::run(&mut stdin.lock(), &mut stdout.lock())
+        .expect("lost connection to the client");
+}
@@ -0,0 +1,421 @@
+//! `lobster-lua compile`: turns a lobster program into a C file that builds
+//! into a native executable with nothing but the system C compiler.
+//!
+//! The C file starts with a runtime, `compile_runtime.c`, that follows the
+//! interpreter closely: values are tagged, fractions are exact, names are
+//! looked up in the same scopes, and the standard library is a port of
+//! `stdlib.rs`. The program itself becomes one C function per lobster
+//! function plus `lb_main`, with every operand evaluated into a temporary so
+//! the order of side effects is the interpreter's, left to right.
+//!
+//! ```sh
+//! lobster-lua compile foo.lua -o foo.c
+//! cc -O2 foo.c -o foo -lm
+//! ```
+
+use std::fmt::Write;
+
+use crate::{MAX_CALL_DEPTH, callee_name};
+use crate::fraction::Fraction;
+use crate::parser::{BinOp, Expr, LobsterParser, ParseError, Stmt, StmtKind};
+use crate::tokenizer::{RomanMode, fraction_glyph};
+
+const INDENT: &str = "    ";
+const RUNTIME: &str = include_str!("compile_runtime.c");
+
+pub fn compile(
+    source: &str,
+    chunk_name: &str,
+    roman_mode: RomanMode,
+) -> Result<String, ParseError> {
+    let parser = LobsterParser::with_roman_mode(source, roman_mode);
+    let ast = parser.try_parse()?;
+
+    let mut out = format!(
+        "/* Generated by `lobster-lua compile` from {}. */\n",
+        chunk_name.replace("*/", "* /")
+    );
+    out.push_str("#include <stddef.h>\n\n");
+    writeln!(
+        out,
+        "static const char LB_CHUNK[] = {};",
+        c_string(chunk_name)
+    )
+    .unwrap();
+    let unciae: Vec<String> = (0..12)
+        .map(
+            |twelfths| match fraction_glyph(Fraction::new(twelfths, 12)) {
+                Some(glyph) if twelfths > 0 => c_string(glyph),
+                _ => "NULL".to_owned(),
+            },
+        )
+        .collect();
+    writeln!(
+        out,
+        "static const char *const LB_UNCIAE[12] = {{{}}};",
+        unciae.join(", ")
+    )
+    .unwrap();
+    writeln!(out, "#define LB_MAX_CALL_DEPTH {MAX_CALL_DEPTH}\n").unwrap();
+    out.push_str(RUNTIME);
+    out.push('\n');
+    out.push_str(&compile_program(&ast, source));
+    Ok(out)
+}
+
+/// The program without the runtime in front of it.
+pub fn compile_program(stmts: &[Stmt], source: &str) -> String {
+    let mut program = Program {
+        line_starts: std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect(),
+        declarations: String::new(),
+        definitions: String::new(),
+        functions: 0,
+    };
+    let main = program.function("lb_main", stmts);
+    let mut out = String::new();
+    if !program.declarations.is_empty() {
+        out.push_str(&program.declarations);
+        out.push('\n');
+    }
+    out.push_str(&program.definitions);
+    out.push_str(&main);
+    out
+}
+
+struct Program {
+    line_starts: Vec<usize>,
+    /// Prototypes and parameter lists of the functions, so they can refer to
+    /// each other in any order.
+    declarations: String,
+    definitions: String,
+    functions: usize,
+}
+
+impl Program {
+    /// Like [`crate::Context::line_of`].
+    fn line_of(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+
+    fn function(&mut self, name: &str, body: &[Stmt]) -> String {
+        let mut function = Function {
+            program: self,
+            out: String::new(),
+            indent: 1,
+            temps: 0,
+            scopes: 0,
+            loops: vec![],
+        };
+        function.block(body);
+        function.line("return lb_nil();");
+        let body = function.out;
+        format!("static Value {name}(Lb *L) {{\n{body}}}\n")
+    }
+
+    /// Compiles a `function(...) ... end` and returns the name of its
+    /// `LbFunction`.
+    fn closure(&mut self, arguments: &[String], body: &[Stmt]) -> String {
+        self.functions += 1;
+        let id = self.functions;
+        let params: Vec<String> = arguments.iter().map(|a| c_string(a)).collect();
+        writeln!(self.declarations, "static Value lb_fn_{id}(Lb *L);").unwrap();
+        if !params.is_empty() {
+            writeln!(
+                self.declarations,
+                "static const char *const lb_params_{id}[] = {{{}}};",
+                params.join(", ")
+            )
+            .unwrap();
+        }
+        let params_ref = if params.is_empty() {
+            "NULL".to_owned()
+        } else {
+            format!("lb_params_{id}")
+        };
+        writeln!(
+            self.declarations,
+            "static const LbFunction lb_function_{id} = {{{}, {params_ref}, lb_fn_{id}}};",
+            params.len()
+        )
+        .unwrap();
+        let definition = self.function(&format!("lb_fn_{id}"), body);
+        self.definitions.push_str(&definition);
+        self.definitions.push('\n');
+        format!("lb_function_{id}")
+    }
+}
+
+struct Function<'a> {
+    program: &'a mut Program,
+    out: String,
+    indent: usize,
+    temps: usize,
+    /// Scopes entered since the start of the function.
+    scopes: usize,
+    /// `scopes` outside of each enclosing loop.
+    loops: Vec<usize>,
+}
+
+impl Function<'_> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn temp(&mut self, value: &str) -> String {
+        self.temps += 1;
+        let temp = format!("t{}", self.temps);
+        self.line(&format!("Value {temp} = {value};"));
+        temp
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    /// A block in a scope of its own, between braces.
+    fn scoped_block(&mut self, stmts: &[Stmt]) {
+        self.line("lb_enter_scope(L);");
+        self.scopes += 1;
+        self.block(stmts);
+        self.scopes -= 1;
+        self.line("lb_leave_scope(L);");
+    }
+
+    fn open(&mut self, text: &str) {
+        self.line(text);
+        self.indent += 1;
+    }
+
+    fn close(&mut self, text: &str) {
+        self.indent -= 1;
+        self.line(text);
+    }
+
+    fn leave_scopes(&mut self, outside: usize) {
+        let n = self.scopes - outside;
+        if n > 0 {
+            self.line(&format!("lb_leave_scopes(L, {n});"));
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let line = self.program.line_of(stmt.span.start);
+        self.line(&format!("lb_line(L, {line});"));
+        match &stmt.kind {
+            StmtKind::Break => match self.loops.last() {
+                Some(&outside) => {
+                    self.leave_scopes(outside);
+                    self.line("break;");
+                }
+                // like the interpreter, a `break` outside a loop ends the
+                // function
+                None => {
+                    self.leave_scopes(0);
+                    self.line("return lb_nil();");
+                }
+            },
+            StmtKind::Return(exprs) => {
+                let values: Vec<String> = exprs.iter().map(|e| self.expr(e)).collect();
+                let value = values.first().map_or("lb_nil()", String::as_str).to_owned();
+                self.leave_scopes(0);
+                self.line(&format!("return {value};"));
+            }
+            StmtKind::While { cond, body } => {
+                self.open("for (;;) {");
+                let cond = self.expr(cond);
+                self.line(&format!("if (!lb_is_true({cond})) break;"));
+                self.loops.push(self.scopes);
+                self.scoped_block(body);
+                self.loops.pop();
+                self.close("}");
+            }
+            StmtKind::DoEnd { body } => {
+                self.open("{");
+                self.scoped_block(body);
+                self.close("}");
+            }
+            StmtKind::Assignment { variable, value } => {
+                let value = self.expr(value);
+                self.line(&format!(
+                    "lb_assign(L, {}, {value});",
+                    c_string(&variable.name)
+                ));
+            }
+            StmtKind::Local { variable, value } => {
+                let value = match value {
+                    Some(value) => self.expr(value),
+                    None => "lb_nil()".to_owned(),
+                };
+                self.line(&format!(
+                    "lb_local(L, {}, {value});",
+                    c_string(&variable.name)
+                ));
+            }
+            StmtKind::FunctionCall {
+                function_name,
+                args,
+            } => {
+                let call = self.call(function_name, args);
+                self.line(&format!("{call};"));
+            }
+            StmtKind::Error => unreachable!("try_parse has no error nodes"),
+            StmtKind::If { cond, then, r#else } => {
+                let cond = self.expr(cond);
+                self.open(&format!("if (lb_is_true({cond})) {{"));
+                self.scoped_block(then);
+                self.close("} else {");
+                self.indent += 1;
+                self.scoped_block(r#else);
+                self.close("}");
+            }
+        }
+    }
+
+    fn call(&mut self, function_name: &Expr, args: &[Expr]) -> String {
+        let function = self.expr(function_name);
+        let args: Vec<String> = args.iter().map(|a| self.expr(a)).collect();
+        let name = c_string(&callee_name(function_name));
+        if args.is_empty() {
+            format!("lb_call(L, {function}, {name}, 0, NULL)")
+        } else {
+            format!(
+                "lb_call(L, {function}, {name}, {}, (Value[]){{{}}})",
+                args.len(),
+                args.join(", ")
+            )
+        }
+    }
+
+    /// Emits what evaluating `expr` takes and returns a C expression for the
+    /// result that has no side effects left.
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Nil => "lb_nil()".to_owned(),
+            Expr::Numeral(n) => format!("lb_int({})", c_int(*n)),
+            Expr::Fraction(f) => {
+                format!(
+                    "lb_frac({}, {})",
+                    c_int(f.numerator()),
+                    c_int(f.denominator())
+                )
+            }
+            Expr::Boolean(b) => format!("lb_bool({})", *b as u8),
+            Expr::String(s) => format!("lb_str({})", c_string(s)),
+            Expr::BinOp { op, lhs, rhs } => {
+                let op_name = c_op(*op);
+                let lhs = self.expr(lhs);
+                if matches!(op, BinOp::And | BinOp::Or) {
+                    let res = self.temp(&lhs);
+                    self.open(&format!("if (!lb_short_circuits({op_name}, {res})) {{"));
+                    let rhs = self.expr(rhs);
+                    self.line(&format!("{res} = lb_binop({op_name}, {res}, {rhs});"));
+                    self.close("}");
+                    return res;
+                }
+                let rhs = self.expr(rhs);
+                self.temp(&format!("lb_binop({op_name}, {lhs}, {rhs})"))
+            }
+            Expr::Var(name) => self.temp(&format!("lb_get(L, {})", c_string(name))),
+            Expr::Index { table, key } => {
+                let table = self.expr(table);
+                let key = self.expr(key);
+                self.temp(&format!("lb_index({table}, {key})"))
+            }
+            Expr::FunctionCall {
+                function_name,
+                args,
+            } => {
+                let call = self.call(function_name, args);
+                self.temp(&call)
+            }
+            Expr::Error => unreachable!("try_parse has no error nodes"),
+            Expr::FunctionDef { arguments, body, .. } => {
+                let arguments: Vec<String> = arguments.iter().map(|a| a.name.clone()).collect();
+                let function = self.program.closure(&arguments, body);
+                format!("lb_closure(&{function})")
+            }
+        }
+    }
+}
+
+fn c_op(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Plus => "LB_PLUS",
+        BinOp::Minus => "LB_MINUS",
+        BinOp::Mul => "LB_MUL",
+        BinOp::Div => "LB_DIV",
+        BinOp::IDiv => "LB_IDIV",
+        BinOp::Exp => "LB_EXP",
+        BinOp::Mod => "LB_MOD",
+        BinOp::And => "LB_AND",
+        BinOp::Or => "LB_OR",
+        BinOp::LShift => "LB_LSHIFT",
+        BinOp::RShift => "LB_RSHIFT",
+        BinOp::GT => "LB_GT",
+        BinOp::LT => "LB_LT",
+        BinOp::GEQ => "LB_GEQ",
+        BinOp::LEQ => "LB_LEQ",
+        BinOp::BitOR => "LB_BITOR",
+        BinOp::BitAnd => "LB_BITAND",
+        BinOp::BitXor => "LB_BITXOR",
+        BinOp::Equals => "LB_EQUALS",
+        BinOp::NotEquals => "LB_NOT_EQUALS",
+        BinOp::Concat => "LB_CONCAT",
+    }
+}
+
+/// `i64::MIN` has no literal in C, only `-9223372036854775807 - 1`.
+fn c_int(n: i64) -> String {
+    if n == i64::MIN {
+        "INT64_MIN".to_owned()
+    } else {
+        format!("INT64_C({n})")
+    }
+}
+
+/// A C string literal. Everything outside printable ASCII is an octal
+/// escape, which unlike `\x` cannot run into the next character.
+fn c_string(s: &str) -> String {
+    let mut res = String::from('"');
+    for b in s.bytes() {
+        match b {
+            b'"' => res.push_str("\\\""),
+            b'\\' => res.push_str("\\\\"),
+            b'\n' => res.push_str("\\n"),
+            b'\t' => res.push_str("\\t"),
+            // `??` could start a trigraph
+            b'?' => res.push_str("\\?"),
+            b' '..=b'~' => res.push(b as char),
+            b => write!(res, "\\{b:03o}").unwrap(),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program() {
+        let source =
+            "f = function(a)\n  return a * II\nend\nx = f(S) == I and true\nwhile x do break end";
+        let ast = LobsterParser::new(source).parse();
+        insta::assert_snapshot!(compile_program(&ast, source));
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(c_string("a\"b\\c\nd"), r#""a\"b\\c\nd""#);
+        assert_eq!(c_string("S∴ ??="), r#""S\342\210\264 \?\?=""#);
+    }
+}
@@ -0,0 +1,1256 @@
+/*
+ * Runtime for C compiled from lobster-lua: tagged values, exact fractions,
+ * tables, the scopes and call stack of the interpreter, and its standard
+ * library. It follows `Context` and `stdlib.rs` closely, so a compiled
+ * program prints what the interpreter prints.
+ *
+ * The compiler puts `LB_CHUNK` (the name of the source file) and
+ * `LB_UNCIAE` (the glyph for n/12, or NULL) in front of this file and
+ * `lb_main` after it. Nothing is ever freed: a compiled program runs once
+ * and exits.
+ */
+#include <errno.h>
+#include <inttypes.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* What compiled programs call; not every program needs all of it. */
+#define LB_API static __attribute__((unused))
+
+typedef struct Lb Lb;
+typedef struct Table Table;
+typedef struct Value Value;
+
+typedef struct {
+    int nparams;
+    const char *const *params;
+    Value (*body)(Lb *L);
+} LbFunction;
+
+typedef enum {
+    LB_NIL,
+    LB_NUMBER,
+    LB_FRACTION,
+    LB_STRING,
+    LB_BOOL,
+    LB_CLOSURE,
+    LB_BUILTIN,
+    LB_TABLE,
+} LbTag;
+
+struct Value {
+    LbTag tag;
+    union {
+        int64_t n;
+        struct {
+            int64_t num, den;
+        } f;
+        const char *s;
+        int b;
+        const LbFunction *fn;
+        const char *builtin;
+        Table *t;
+    } as;
+};
+
+typedef enum {
+    LB_PLUS,
+    LB_MINUS,
+    LB_MUL,
+    LB_DIV,
+    LB_IDIV,
+    LB_EXP,
+    LB_MOD,
+    LB_AND,
+    LB_OR,
+    LB_LSHIFT,
+    LB_RSHIFT,
+    LB_GT,
+    LB_LT,
+    LB_GEQ,
+    LB_LEQ,
+    LB_BITOR,
+    LB_BITAND,
+    LB_BITXOR,
+    LB_EQUALS,
+    LB_NOT_EQUALS,
+    LB_CONCAT,
+} LbOp;
+
+typedef enum { LB_DECIMAL, LB_ROMAN, LB_MIXED } LbFormat;
+
+static Value lb_main(Lb *L);
+
+/* ---- errors and memory ---- */
+
+static void lb_error(const char *fmt, ...) {
+    va_list args;
+    fflush(stdout);
+    fputs("lobster: ", stderr);
+    va_start(args, fmt);
+    vfprintf(stderr, fmt, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static void *lb_alloc(size_t size) {
+    void *p = calloc(1, size ? size : 1);
+    if (!p) lb_error("out of memory");
+    return p;
+}
+
+static void *lb_grow(void *p, size_t *cap, size_t len, size_t item) {
+    if (len < *cap) return p;
+    *cap = *cap ? *cap * 2 : 8;
+    p = realloc(p, *cap * item);
+    if (!p) lb_error("out of memory");
+    return p;
+}
+
+typedef struct {
+    char *data;
+    size_t len, cap;
+} Buf;
+
+static void buf_putn(Buf *b, const char *s, size_t n) {
+    while (b->len + n + 1 > b->cap) {
+        b->cap = b->cap ? b->cap * 2 : 64;
+        b->data = realloc(b->data, b->cap);
+        if (!b->data) lb_error("out of memory");
+    }
+    memcpy(b->data + b->len, s, n);
+    b->len += n;
+    b->data[b->len] = '\0';
+}
+
+static void buf_puts(Buf *b, const char *s) { buf_putn(b, s, strlen(s)); }
+
+static void buf_printf(Buf *b, const char *fmt, ...) {
+    char small[64];
+    va_list args;
+    va_start(args, fmt);
+    int n = vsnprintf(small, sizeof small, fmt, args);
+    va_end(args);
+    if ((size_t)n < sizeof small) {
+        buf_putn(b, small, (size_t)n);
+        return;
+    }
+    char *big = lb_alloc((size_t)n + 1);
+    va_start(args, fmt);
+    vsnprintf(big, (size_t)n + 1, fmt, args);
+    va_end(args);
+    buf_putn(b, big, (size_t)n);
+    free(big);
+}
+
+static const char *buf_str(Buf *b) { return b->data ? b->data : ""; }
+
+/* ---- values ---- */
+
+LB_API Value lb_nil(void) { return (Value){.tag = LB_NIL}; }
+LB_API Value lb_int(int64_t n) { return (Value){.tag = LB_NUMBER, .as.n = n}; }
+LB_API Value lb_bool(int b) { return (Value){.tag = LB_BOOL, .as.b = b}; }
+LB_API Value lb_str(const char *s) { return (Value){.tag = LB_STRING, .as.s = s}; }
+LB_API Value lb_closure(const LbFunction *fn) { return (Value){.tag = LB_CLOSURE, .as.fn = fn}; }
+static Value lb_builtin(const char *name) { return (Value){.tag = LB_BUILTIN, .as.builtin = name}; }
+
+typedef struct {
+    int64_t num, den;
+} Fraction;
+
+static int64_t add64(int64_t a, int64_t b) {
+    int64_t r;
+    if (__builtin_add_overflow(a, b, &r)) lb_error("attempt to add with overflow");
+    return r;
+}
+
+static int64_t sub64(int64_t a, int64_t b) {
+    int64_t r;
+    if (__builtin_sub_overflow(a, b, &r)) lb_error("attempt to subtract with overflow");
+    return r;
+}
+
+static int64_t mul64(int64_t a, int64_t b) {
+    int64_t r;
+    if (__builtin_mul_overflow(a, b, &r)) lb_error("attempt to multiply with overflow");
+    return r;
+}
+
+/* Like `Fraction::reduced`: reduced, with the sign on the numerator. The
+ * parts are wide enough for the product of any two int64_t. */
+static Fraction frac_reduced(__int128 num, __int128 den, const char *overflow) {
+    if (den == 0) lb_error("division by zero");
+    unsigned __int128 a = num < 0 ? -(unsigned __int128)num : (unsigned __int128)num;
+    unsigned __int128 b = den < 0 ? -(unsigned __int128)den : (unsigned __int128)den;
+    while (b != 0) {
+        unsigned __int128 c = b;
+        b = a % b;
+        a = c;
+    }
+    __int128 gcd = den < 0 ? -(__int128)a : (__int128)a;
+    num /= gcd;
+    den /= gcd;
+    if (num < INT64_MIN || num > INT64_MAX || den > INT64_MAX) lb_error("%s", overflow);
+    return (Fraction){(int64_t)num, (int64_t)den};
+}
+
+static Fraction frac_new(int64_t num, int64_t den) {
+    return frac_reduced(num, den, "attempt to negate with overflow");
+}
+
+static Value lb_fraction(Fraction f) {
+    return (Value){.tag = LB_FRACTION, .as.f = {f.num, f.den}};
+}
+
+LB_API Value lb_frac(int64_t num, int64_t den) { return lb_fraction(frac_new(num, den)); }
+
+static const char *lb_type_name(Value v);
+
+static Fraction as_fraction(Value v) {
+    if (v.tag == LB_NUMBER) return (Fraction){v.as.n, 1};
+    if (v.tag == LB_FRACTION) return (Fraction){v.as.f.num, v.as.f.den};
+    lb_error("attempt to perform arithmetic on a %s value", lb_type_name(v));
+    return (Fraction){0, 1};
+}
+
+/* both operands as fractions, left first so the error is about the same
+ * operand as in the interpreter */
+static void as_fractions(Value l, Value r, Fraction *a, Fraction *b) {
+    *a = as_fraction(l);
+    *b = as_fraction(r);
+}
+
+static Fraction frac_add(Fraction a, Fraction b) {
+    return frac_reduced((__int128)a.num * b.den + (__int128)a.den * b.num, (__int128)a.den * b.den,
+                        "attempt to add with overflow");
+}
+
+static Fraction frac_sub(Fraction a, Fraction b) {
+    return frac_reduced((__int128)a.num * b.den - (__int128)a.den * b.num, (__int128)a.den * b.den,
+                        "attempt to subtract with overflow");
+}
+
+static Fraction frac_mul(Fraction a, Fraction b) {
+    return frac_reduced((__int128)a.num * b.num, (__int128)a.den * b.den,
+                        "attempt to multiply with overflow");
+}
+
+static Fraction frac_div(Fraction a, Fraction b) {
+    return frac_reduced((__int128)a.num * b.den, (__int128)a.den * b.num,
+                        "attempt to divide with overflow");
+}
+
+static int lb_equal(Value a, Value b);
+
+/* ---- tables: entries sorted by key, booleans before numbers before
+ * strings, like `TableKey` ---- */
+
+typedef struct {
+    Value key, value;
+} Entry;
+
+struct Table {
+    Entry *entries;
+    size_t len, cap;
+};
+
+static Value lb_table(void) {
+    return (Value){.tag = LB_TABLE, .as.t = lb_alloc(sizeof(Table))};
+}
+
+/* The key `v` indexes with, or 0 if it cannot be one. */
+static int table_key(Value v, Value *key) {
+    switch (v.tag) {
+    case LB_BOOL:
+    case LB_NUMBER:
+    case LB_STRING:
+        *key = v;
+        return 1;
+    case LB_FRACTION:
+        if (v.as.f.den != 1) return 0;
+        *key = lb_int(v.as.f.num);
+        return 1;
+    default:
+        return 0;
+    }
+}
+
+static int key_rank(Value k) { return k.tag == LB_BOOL ? 0 : k.tag == LB_NUMBER ? 1 : 2; }
+
+static int key_cmp(Value a, Value b) {
+    if (key_rank(a) != key_rank(b)) return key_rank(a) - key_rank(b);
+    switch (a.tag) {
+    case LB_BOOL:
+        return a.as.b - b.as.b;
+    case LB_NUMBER:
+        return (a.as.n > b.as.n) - (a.as.n < b.as.n);
+    default:
+        return strcmp(a.as.s, b.as.s);
+    }
+}
+
+/* Index of `key`, or of where it would go. */
+static size_t table_find(Table *t, Value key, int *found) {
+    size_t lo = 0, hi = t->len;
+    while (lo < hi) {
+        size_t mid = (lo + hi) / 2;
+        int c = key_cmp(t->entries[mid].key, key);
+        if (c == 0) {
+            *found = 1;
+            return mid;
+        }
+        if (c < 0) lo = mid + 1;
+        else hi = mid;
+    }
+    *found = 0;
+    return lo;
+}
+
+static Value table_get(Table *t, Value key) {
+    int found;
+    size_t i = table_find(t, key, &found);
+    return found ? t->entries[i].value : lb_nil();
+}
+
+/* Assigning nil removes the key. */
+static void table_set(Table *t, Value key, Value value) {
+    int found;
+    size_t i = table_find(t, key, &found);
+    if (found && value.tag == LB_NIL) {
+        memmove(&t->entries[i], &t->entries[i + 1], (t->len - i - 1) * sizeof(Entry));
+        t->len--;
+    } else if (found) {
+        t->entries[i].value = value;
+    } else if (value.tag != LB_NIL) {
+        t->entries = lb_grow(t->entries, &t->cap, t->len, sizeof(Entry));
+        memmove(&t->entries[i + 1], &t->entries[i], (t->len - i) * sizeof(Entry));
+        t->entries[i] = (Entry){key, value};
+        t->len++;
+    }
+}
+
+static void table_set_field(Table *t, const char *name, Value value) {
+    table_set(t, lb_str(name), value);
+}
+
+LB_API Value lb_index(Value table, Value key) {
+    if (table.tag != LB_TABLE) lb_error("attempt to index a %s value", lb_type_name(table));
+    Value k;
+    return table_key(key, &k) ? table_get(table.as.t, k) : lb_nil();
+}
+
+/* ---- the context: scopes, frames and the debug hook ---- */
+
+typedef struct {
+    const char *name;
+    Value value;
+} Var;
+
+typedef struct {
+    Var *vars;
+    size_t len, cap;
+} Scope;
+
+typedef struct {
+    const char *function;
+    int64_t line;
+    int main;
+} Frame;
+
+struct Lb {
+    Scope globals;
+    Scope *scopes;
+    size_t nscopes, scopes_cap;
+    Frame *frames;
+    size_t nframes, frames_cap;
+    LbFormat number_format;
+    struct {
+        int set, running, has_last;
+        Value function;
+        const char *mask;
+        size_t last_depth;
+        int64_t last_line;
+    } hook;
+};
+
+static Value *scope_find(Scope *s, const char *name) {
+    for (size_t i = 0; i < s->len; i++) {
+        if (strcmp(s->vars[i].name, name) == 0) return &s->vars[i].value;
+    }
+    return NULL;
+}
+
+static void scope_set(Scope *s, const char *name, Value value) {
+    Value *slot = scope_find(s, name);
+    if (slot) {
+        *slot = value;
+        return;
+    }
+    s->vars = lb_grow(s->vars, &s->cap, s->len, sizeof(Var));
+    s->vars[s->len++] = (Var){name, value};
+}
+
+LB_API Value lb_get(Lb *L, const char *name) {
+    for (size_t i = L->nscopes; i-- > 0;) {
+        Value *slot = scope_find(&L->scopes[i], name);
+        if (slot) return *slot;
+    }
+    Value *slot = scope_find(&L->globals, name);
+    return slot ? *slot : lb_nil();
+}
+
+/* Assigns to the innermost local called `name`, or to the global. */
+LB_API void lb_assign(Lb *L, const char *name, Value value) {
+    for (size_t i = L->nscopes; i-- > 0;) {
+        Value *slot = scope_find(&L->scopes[i], name);
+        if (slot) {
+            *slot = value;
+            return;
+        }
+    }
+    scope_set(&L->globals, name, value);
+}
+
+LB_API void lb_local(Lb *L, const char *name, Value value) {
+    scope_set(&L->scopes[L->nscopes - 1], name, value);
+}
+
+LB_API void lb_enter_scope(Lb *L) {
+    L->scopes = lb_grow(L->scopes, &L->scopes_cap, L->nscopes, sizeof(Scope));
+    L->scopes[L->nscopes++] = (Scope){0};
+}
+
+LB_API void lb_leave_scopes(Lb *L, size_t n) { L->nscopes -= n; }
+
+LB_API void lb_leave_scope(Lb *L) { lb_leave_scopes(L, 1); }
+
+static void push_frame(Lb *L, const char *function, int main) {
+    L->frames = lb_grow(L->frames, &L->frames_cap, L->nframes, sizeof(Frame));
+    L->frames[L->nframes++] = (Frame){function, 0, main};
+}
+
+static Value lb_call(Lb *L, Value function, const char *name, int argc, const Value *argv);
+
+/* Calls the `debug.sethook` function for `event`, unless it is already
+ * running. */
+static void fire_hook(Lb *L, const char *event, int64_t line) {
+    if (!L->hook.set || L->hook.running) return;
+    int wanted;
+    if (strcmp(event, "line") == 0) {
+        int new_line = !L->hook.has_last || L->hook.last_depth != L->nframes ||
+                       L->hook.last_line != line;
+        L->hook.has_last = 1;
+        L->hook.last_depth = L->nframes;
+        L->hook.last_line = line;
+        wanted = new_line && strchr(L->hook.mask, 'l');
+    } else if (strcmp(event, "call") == 0) {
+        wanted = strchr(L->hook.mask, 'c') != NULL;
+    } else {
+        wanted = strchr(L->hook.mask, 'r') != NULL;
+    }
+    if (!wanted) return;
+    L->hook.running = 1;
+    Value args[2] = {lb_str(event), line < 0 ? lb_nil() : lb_int(line)};
+    lb_call(L, L->hook.function, "hook", 2, args);
+    L->hook.running = 0;
+}
+
+/* Before every statement. */
+LB_API void lb_line(Lb *L, int64_t line) {
+    L->frames[L->nframes - 1].line = line;
+    fire_hook(L, "line", line);
+}
+
+static Value call_builtin(Lb *L, const char *name, int argc, const Value *argv);
+
+LB_API Value lb_call(Lb *L, Value function, const char *name, int argc, const Value *argv) {
+    if (function.tag == LB_BUILTIN) return call_builtin(L, function.as.builtin, argc, argv);
+    if (function.tag != LB_CLOSURE)
+        lb_error("attempt to call a %s value (%s)", lb_type_name(function), name);
+    const LbFunction *fn = function.as.fn;
+    if (fn->nparams != argc) lb_error("calling with wrong number of parameters");
+    if (L->nframes > LB_MAX_CALL_DEPTH) lb_error("stack overflow");
+    push_frame(L, name, 0);
+    fire_hook(L, "call", -1);
+    lb_enter_scope(L);
+    for (int i = 0; i < argc; i++) lb_local(L, fn->params[i], argv[i]);
+    Value res = fn->body(L);
+    lb_leave_scope(L);
+    fire_hook(L, "return", -1);
+    L->nframes--;
+    return res;
+}
+
+/* ---- operators ---- */
+
+LB_API int lb_is_true(Value v) { return v.tag == LB_BOOL && v.as.b; }
+
+/* Whether `and` or `or` is decided by its left side alone. */
+LB_API int lb_short_circuits(LbOp op, Value lhs) {
+    return lhs.tag == LB_BOOL && ((op == LB_AND && !lhs.as.b) || (op == LB_OR && lhs.as.b));
+}
+
+static int lb_equal(Value a, Value b) {
+    if (a.tag != b.tag) return 0;
+    switch (a.tag) {
+    case LB_NIL:
+        return 1;
+    case LB_NUMBER:
+        return a.as.n == b.as.n;
+    case LB_FRACTION:
+        return a.as.f.num == b.as.f.num && a.as.f.den == b.as.f.den;
+    case LB_STRING:
+        return strcmp(a.as.s, b.as.s) == 0;
+    case LB_BOOL:
+        return a.as.b == b.as.b;
+    case LB_CLOSURE:
+        return a.as.fn == b.as.fn;
+    case LB_BUILTIN:
+        return strcmp(a.as.builtin, b.as.builtin) == 0;
+    case LB_TABLE:
+        return a.as.t == b.as.t;
+    }
+    return 0;
+}
+
+/* the kind of value, as error messages call it, like `Value::type_name` */
+static const char *lb_type_name(Value v) {
+    switch (v.tag) {
+    case LB_NIL:
+        return "nil";
+    case LB_NUMBER:
+        return "number";
+    case LB_FRACTION:
+        return "fraction";
+    case LB_STRING:
+        return "string";
+    case LB_BOOL:
+        return "boolean";
+    case LB_CLOSURE:
+    case LB_BUILTIN:
+        return "function";
+    case LB_TABLE:
+        return "table";
+    }
+    return "?";
+}
+
+/* the operand an error is about: the left one, unless only the right one
+ * has the wanted tag */
+static const char *blame(Value l, Value r, LbTag ok) {
+    return lb_type_name(l.tag == ok ? r : l);
+}
+
+static void compare_error(Value l, Value r) {
+    const char *a = lb_type_name(l), *b = lb_type_name(r);
+    if (strcmp(a, b) == 0) lb_error("attempt to compare two %s values", a);
+    lb_error("attempt to compare %s with %s", a, b);
+}
+
+LB_API Value lb_binop(LbOp op, Value l, Value r) {
+    int numbers = l.tag == LB_NUMBER && r.tag == LB_NUMBER;
+    Fraction a, b;
+    switch (op) {
+    case LB_PLUS:
+        if (numbers) return lb_int(add64(l.as.n, r.as.n));
+        as_fractions(l, r, &a, &b);
+        return lb_fraction(frac_add(a, b));
+    case LB_MINUS:
+        if (numbers) return lb_int(sub64(l.as.n, r.as.n));
+        as_fractions(l, r, &a, &b);
+        return lb_fraction(frac_sub(a, b));
+    case LB_MUL:
+        if (numbers) return lb_int(mul64(l.as.n, r.as.n));
+        as_fractions(l, r, &a, &b);
+        return lb_fraction(frac_mul(a, b));
+    case LB_DIV:
+    case LB_IDIV:
+        as_fractions(l, r, &a, &b);
+        return lb_fraction(frac_div(a, b));
+    case LB_EXP:
+        if (!numbers) lb_error("attempt to perform arithmetic on a %s value", blame(l, r, LB_NUMBER));
+        if (r.as.n < 0) lb_error("attempt to raise to a negative power");
+        {
+            /* like `i64::checked_pow`, by squaring; past UINT32_MAX only 0,
+             * 1 and -1 stay in range */
+            uint32_t e = r.as.n > UINT32_MAX ? UINT32_MAX - (r.as.n % 2 == 0) : (uint32_t)r.as.n;
+            int64_t base = l.as.n, res = 1;
+            for (;;) {
+                if (e & 1) res = mul64(res, base);
+                e >>= 1;
+                if (!e) break;
+                base = mul64(base, base);
+            }
+            return lb_int(res);
+        }
+    case LB_MOD:
+        if (!numbers) lb_error("attempt to perform arithmetic on a %s value", blame(l, r, LB_NUMBER));
+        if (r.as.n == 0) lb_error("attempt to calculate the remainder with a divisor of zero");
+        return lb_int(r.as.n == -1 ? 0 : l.as.n % r.as.n);
+    case LB_AND:
+    case LB_OR:
+        if (l.tag != LB_BOOL || r.tag != LB_BOOL)
+            lb_error("attempt to perform logical operation on a %s value", blame(l, r, LB_BOOL));
+        return lb_bool(op == LB_AND ? l.as.b && r.as.b : l.as.b || r.as.b);
+    case LB_LSHIFT:
+    case LB_RSHIFT:
+        if (!numbers) lb_error("attempt to perform bitwise operation on a %s value", blame(l, r, LB_NUMBER));
+        if (r.as.n < 0 || r.as.n >= 64) lb_error("attempt to shift with overflow");
+        return lb_int(op == LB_LSHIFT ? (int64_t)((uint64_t)l.as.n << r.as.n) : l.as.n >> r.as.n);
+    case LB_GT:
+    case LB_LT:
+    case LB_GEQ:
+    case LB_LEQ:
+        if (!numbers) compare_error(l, r);
+        switch (op) {
+        case LB_GT:
+            return lb_bool(l.as.n > r.as.n);
+        case LB_LT:
+            return lb_bool(l.as.n < r.as.n);
+        case LB_GEQ:
+            return lb_bool(l.as.n >= r.as.n);
+        default:
+            return lb_bool(l.as.n <= r.as.n);
+        }
+    case LB_BITOR:
+    case LB_BITAND:
+    case LB_BITXOR:
+        if (!numbers) lb_error("attempt to perform bitwise operation on a %s value", blame(l, r, LB_NUMBER));
+        return lb_int(op == LB_BITOR    ? l.as.n | r.as.n
+                      : op == LB_BITAND ? l.as.n & r.as.n
+                                        : l.as.n ^ r.as.n);
+    case LB_EQUALS:
+        return lb_bool(lb_equal(l, r));
+    case LB_NOT_EQUALS:
+        return lb_bool(!lb_equal(l, r));
+    case LB_CONCAT:
+        if (l.tag != LB_STRING || r.tag != LB_STRING)
+            lb_error("attempt to concatenate a %s value", blame(l, r, LB_STRING));
+        {
+            Buf b = {0};
+            buf_puts(&b, l.as.s);
+            buf_puts(&b, r.as.s);
+            return lb_str(buf_str(&b));
+        }
+    }
+    return lb_nil();
+}
+
+/* ---- turning values into text, like `numeral.rs` ---- */
+
+static const char *const ROMAN_DIGITS[] = {"M", "CM", "D", "CD", "C", "XC", "L",
+                                           "XL", "X", "IX", "V", "IV", "I"};
+static const int64_t ROMAN_VALUES[] = {1000, 900, 500, 400, 100, 90, 50, 40, 10, 9, 5, 4, 1};
+
+static void format_integer(Buf *b, int64_t n, LbFormat format) {
+    if (format != LB_ROMAN || (n != 0 && (n >= 1000000 || n <= -1000000))) {
+        buf_printf(b, "%" PRId64, n);
+    } else if (n == 0) {
+        buf_puts(b, "N");
+    } else {
+        if (n < 0) buf_puts(b, "-");
+        n = n < 0 ? -n : n;
+        for (int i = 0; i < 13; i++) {
+            while (n >= ROMAN_VALUES[i]) {
+                buf_puts(b, ROMAN_DIGITS[i]);
+                n -= ROMAN_VALUES[i];
+            }
+        }
+    }
+}
+
+static void format_fraction(Buf *b, Fraction f, LbFormat format) {
+    if (format == LB_DECIMAL) {
+        buf_printf(b, "%" PRId64 "/%" PRId64, f.num, f.den);
+        return;
+    }
+    const char *sign = f.num < 0 ? "-" : "";
+    uint64_t num = f.num < 0 ? -(uint64_t)f.num : (uint64_t)f.num;
+    uint64_t den = (uint64_t)f.den;
+    uint64_t whole = num / den;
+    Fraction rest = frac_new((int64_t)(num % den), (int64_t)den);
+    buf_puts(b, sign);
+    if (rest.num == 0) {
+        format_integer(b, (int64_t)whole, format);
+        return;
+    }
+    if (whole != 0) {
+        format_integer(b, (int64_t)whole, format);
+        buf_puts(b, " ");
+    }
+    if (format == LB_ROMAN && 12 % rest.den == 0 && LB_UNCIAE[rest.num * (12 / rest.den)]) {
+        buf_puts(b, LB_UNCIAE[rest.num * (12 / rest.den)]);
+    } else {
+        buf_printf(b, "%" PRId64 "/%" PRId64, rest.num, rest.den);
+    }
+}
+
+static void format_value(Buf *b, Value v, LbFormat format) {
+    switch (v.tag) {
+    case LB_NIL:
+        buf_puts(b, "nil");
+        break;
+    case LB_NUMBER:
+        format_integer(b, v.as.n, format);
+        break;
+    case LB_FRACTION:
+        format_fraction(b, (Fraction){v.as.f.num, v.as.f.den}, format);
+        break;
+    case LB_STRING:
+        buf_puts(b, v.as.s);
+        break;
+    case LB_BOOL:
+        buf_puts(b, v.as.b ? "true" : "false");
+        break;
+    case LB_CLOSURE:
+        buf_puts(b, "function");
+        break;
+    case LB_BUILTIN:
+        buf_puts(b, "function: builtin");
+        break;
+    case LB_TABLE:
+        buf_printf(b, "table: %p", (void *)v.as.t);
+        break;
+    }
+}
+
+static int format_from_name(const char *name, LbFormat *format) {
+    if (strcmp(name, "decimal") == 0) *format = LB_DECIMAL;
+    else if (strcmp(name, "roman") == 0) *format = LB_ROMAN;
+    else if (strcmp(name, "mixed") == 0) *format = LB_MIXED;
+    else return 0;
+    return 1;
+}
+
+/* ---- json, like `json.rs` ---- */
+
+static void json_string(Buf *b, const char *s) {
+    buf_puts(b, "\"");
+    for (; *s; s++) {
+        unsigned char c = (unsigned char)*s;
+        switch (c) {
+        case '"':
+            buf_puts(b, "\\\"");
+            break;
+        case '\\':
+            buf_puts(b, "\\\\");
+            break;
+        case '\b':
+            buf_puts(b, "\\b");
+            break;
+        case '\f':
+            buf_puts(b, "\\f");
+            break;
+        case '\n':
+            buf_puts(b, "\\n");
+            break;
+        case '\r':
+            buf_puts(b, "\\r");
+            break;
+        case '\t':
+            buf_puts(b, "\\t");
+            break;
+        default:
+            if (c < 0x20) buf_printf(b, "\\u%04x", c);
+            else buf_putn(b, (const char *)&c, 1);
+        }
+    }
+    buf_puts(b, "\"");
+}
+
+typedef struct {
+    int pretty, fraction_objects;
+    Table **seen;
+    size_t nseen, seen_cap;
+} JsonEncoder;
+
+static void json_newline(Buf *b, JsonEncoder *e, int level) {
+    if (!e->pretty) return;
+    buf_puts(b, "\n");
+    for (int i = 0; i < level; i++) buf_puts(b, "  ");
+}
+
+typedef struct {
+    const char *key;
+    Value value;
+} JsonMember;
+
+static int member_cmp(const void *a, const void *b) {
+    const JsonMember *x = a, *y = b;
+    int c = strcmp(x->key, y->key);
+    /* equal keys keep their order, so the last one can win */
+    return c ? c : (x < y ? -1 : 1);
+}
+
+static void json_encode(Buf *b, Value v, JsonEncoder *e, int level) {
+    switch (v.tag) {
+    case LB_NIL:
+        buf_puts(b, "null");
+        return;
+    case LB_BOOL:
+        buf_puts(b, v.as.b ? "true" : "false");
+        return;
+    case LB_NUMBER:
+        buf_printf(b, "%" PRId64, v.as.n);
+        return;
+    case LB_FRACTION:
+        if (v.as.f.den == 1) {
+            buf_printf(b, "%" PRId64, v.as.f.num);
+        } else if (!e->fraction_objects) {
+            buf_printf(b, "\"%" PRId64 "/%" PRId64 "\"", v.as.f.num, v.as.f.den);
+        } else {
+            buf_puts(b, "{");
+            json_newline(b, e, level + 1);
+            buf_printf(b, e->pretty ? "\"denominator\": %" PRId64 "," : "\"denominator\":%" PRId64 ",",
+                       v.as.f.den);
+            json_newline(b, e, level + 1);
+            buf_printf(b, e->pretty ? "\"numerator\": %" PRId64 : "\"numerator\":%" PRId64, v.as.f.num);
+            json_newline(b, e, level);
+            buf_puts(b, "}");
+        }
+        return;
+    case LB_STRING:
+        json_string(b, v.as.s);
+        return;
+    case LB_CLOSURE:
+    case LB_BUILTIN:
+        lb_error("json.encode: cannot encode a function");
+        return;
+    case LB_TABLE:
+        break;
+    }
+    Table *t = v.as.t;
+    for (size_t i = 0; i < e->nseen; i++) {
+        if (e->seen[i] == t) lb_error("json.encode: cannot encode a table that contains itself");
+    }
+    e->seen = lb_grow(e->seen, &e->seen_cap, e->nseen, sizeof(Table *));
+    e->seen[e->nseen++] = t;
+    int is_array = t->len > 0;
+    for (size_t i = 0; i < t->len; i++) {
+        Value k = t->entries[i].key;
+        if (k.tag != LB_NUMBER || k.as.n != (int64_t)i) is_array = 0;
+    }
+    if (is_array) {
+        buf_puts(b, "[");
+        for (size_t i = 0; i < t->len; i++) {
+            if (i) buf_puts(b, ",");
+            json_newline(b, e, level + 1);
+            json_encode(b, t->entries[i].value, e, level + 1);
+        }
+        json_newline(b, e, level);
+        buf_puts(b, "]");
+    } else if (t->len == 0) {
+        buf_puts(b, "{}");
+    } else {
+        JsonMember *members = lb_alloc(t->len * sizeof(JsonMember));
+        for (size_t i = 0; i < t->len; i++) {
+            Buf key = {0};
+            format_value(&key, t->entries[i].key, LB_DECIMAL);
+            members[i] = (JsonMember){buf_str(&key), t->entries[i].value};
+        }
+        qsort(members, t->len, sizeof(JsonMember), member_cmp);
+        buf_puts(b, "{");
+        int first = 1;
+        for (size_t i = 0; i < t->len; i++) {
+            if (i + 1 < t->len && strcmp(members[i].key, members[i + 1].key) == 0) continue;
+            if (!first) buf_puts(b, ",");
+            first = 0;
+            json_newline(b, e, level + 1);
+            json_string(b, members[i].key);
+            buf_puts(b, e->pretty ? ": " : ":");
+            json_encode(b, members[i].value, e, level + 1);
+        }
+        json_newline(b, e, level);
+        buf_puts(b, "}");
+    }
+    e->nseen--;
+}
+
+/* Options from a script: a table, or the same as words. */
+static void json_options(Value opts, JsonEncoder *e) {
+    const char *fractions = NULL;
+    if (opts.tag == LB_TABLE) {
+        e->pretty = lb_is_true(table_get(opts.as.t, lb_str("pretty")));
+        Value repr = table_get(opts.as.t, lb_str("fractions"));
+        if (repr.tag == LB_STRING) fractions = repr.as.s;
+    } else if (opts.tag == LB_STRING) {
+        Buf words = {0};
+        buf_puts(&words, opts.as.s);
+        for (char *word = strtok(words.data, " \t\n\r"); word; word = strtok(NULL, " \t\n\r")) {
+            if (strcmp(word, "pretty") == 0) e->pretty = 1;
+            else if (strncmp(word, "fractions=", 10) == 0) fractions = word + 10;
+            else lb_error("json.encode: unknown option `%s`", word);
+        }
+    } else if (opts.tag != LB_NIL) {
+        Buf text = {0};
+        format_value(&text, opts, LB_DECIMAL);
+        lb_error("json.encode: options must be a table or a string, got %s", buf_str(&text));
+    }
+    if (!fractions || strcmp(fractions, "string") == 0) e->fraction_objects = 0;
+    else if (strcmp(fractions, "object") == 0) e->fraction_objects = 1;
+    else lb_error("json.encode: fractions must be string or object, got %s", fractions);
+}
+
+typedef struct {
+    const char *p;
+    /* whether the last value read was a number that fits an int64_t */
+    int integer;
+} JsonDecoder;
+
+static void json_fail(void) { lb_error("json.decode: invalid JSON"); }
+
+static void json_ws(JsonDecoder *d) {
+    while (*d->p == ' ' || *d->p == '\t' || *d->p == '\n' || *d->p == '\r') d->p++;
+}
+
+static void utf8(Buf *b, uint32_t c) {
+    char out[4];
+    size_t n;
+    if (c < 0x80) {
+        out[0] = (char)c;
+        n = 1;
+    } else if (c < 0x800) {
+        out[0] = (char)(0xC0 | c >> 6);
+        out[1] = (char)(0x80 | (c & 0x3F));
+        n = 2;
+    } else if (c < 0x10000) {
+        out[0] = (char)(0xE0 | c >> 12);
+        out[1] = (char)(0x80 | ((c >> 6) & 0x3F));
+        out[2] = (char)(0x80 | (c & 0x3F));
+        n = 3;
+    } else {
+        out[0] = (char)(0xF0 | c >> 18);
+        out[1] = (char)(0x80 | ((c >> 12) & 0x3F));
+        out[2] = (char)(0x80 | ((c >> 6) & 0x3F));
+        out[3] = (char)(0x80 | (c & 0x3F));
+        n = 4;
+    }
+    buf_putn(b, out, n);
+}
+
+static uint32_t json_hex4(JsonDecoder *d) {
+    uint32_t c = 0;
+    for (int i = 0; i < 4; i++) {
+        char h = *d->p++;
+        c <<= 4;
+        if (h >= '0' && h <= '9') c |= (uint32_t)(h - '0');
+        else if (h >= 'a' && h <= 'f') c |= (uint32_t)(h - 'a' + 10);
+        else if (h >= 'A' && h <= 'F') c |= (uint32_t)(h - 'A' + 10);
+        else json_fail();
+    }
+    return c;
+}
+
+static const char *json_parse_string(JsonDecoder *d) {
+    Buf b = {0};
+    buf_puts(&b, "");
+    d->p++;
+    while (*d->p != '"') {
+        unsigned char c = (unsigned char)*d->p++;
+        if (c < 0x20) json_fail();
+        if (c != '\\') {
+            buf_putn(&b, (const char *)&c, 1);
+            continue;
+        }
+        switch (*d->p++) {
+        case '"': buf_puts(&b, "\""); break;
+        case '\\': buf_puts(&b, "\\"); break;
+        case '/': buf_puts(&b, "/"); break;
+        case 'b': buf_puts(&b, "\b"); break;
+        case 'f': buf_puts(&b, "\f"); break;
+        case 'n': buf_puts(&b, "\n"); break;
+        case 'r': buf_puts(&b, "\r"); break;
+        case 't': buf_puts(&b, "\t"); break;
+        case 'u': {
+            uint32_t u = json_hex4(d);
+            if (u >= 0xD800 && u < 0xDC00) {
+                if (d->p[0] != '\\' || d->p[1] != 'u') json_fail();
+                d->p += 2;
+                uint32_t low = json_hex4(d);
+                if (low < 0xDC00 || low >= 0xE000) json_fail();
+                u = 0x10000 + ((u - 0xD800) << 10) + (low - 0xDC00);
+            } else if (u >= 0xDC00 && u < 0xE000) {
+                json_fail();
+            }
+            utf8(&b, u);
+            break;
+        }
+        default:
+            json_fail();
+        }
+    }
+    d->p++;
+    return b.data;
+}
+
+/* Integers stay integers, decimals become the fraction they spell. */
+static Value json_number(JsonDecoder *d) {
+    const char *start = d->p;
+    int integer = 1;
+    if (*d->p == '-') d->p++;
+    if (*d->p == '0') d->p++;
+    else if (*d->p >= '1' && *d->p <= '9')
+        while (*d->p >= '0' && *d->p <= '9') d->p++;
+    else json_fail();
+    if (*d->p == '.') {
+        integer = 0;
+        d->p++;
+        if (!(*d->p >= '0' && *d->p <= '9')) json_fail();
+        while (*d->p >= '0' && *d->p <= '9') d->p++;
+    }
+    if (*d->p == 'e' || *d->p == 'E') {
+        integer = 0;
+        d->p++;
+        if (*d->p == '+' || *d->p == '-') d->p++;
+        if (!(*d->p >= '0' && *d->p <= '9')) json_fail();
+        while (*d->p >= '0' && *d->p <= '9') d->p++;
+    }
+    Buf text = {0};
+    buf_putn(&text, start, (size_t)(d->p - start));
+    if (integer) {
+        char *end;
+        errno = 0;
+        long long n = strtoll(text.data, &end, 10);
+        if (errno == 0) {
+            d->integer = 1;
+            return lb_int(n);
+        }
+    }
+    double x = strtod(text.data, NULL);
+    for (int64_t den = 1; den <= 1000000000000; den *= 10) {
+        double num = x * (double)den;
+        if (num == trunc(num) && fabs(num) < 9223372036854775808.0) {
+            Fraction f = frac_new((int64_t)num, den);
+            return f.den == 1 ? lb_int(f.num) : lb_fraction(f);
+        }
+    }
+    lb_error("json.decode: cannot represent %s as a number or fraction", text.data);
+    return lb_nil();
+}
+
+static Value json_value(JsonDecoder *d) {
+    d->integer = 0;
+    json_ws(d);
+    switch (*d->p) {
+    case 'n':
+        if (strncmp(d->p, "null", 4) != 0) json_fail();
+        d->p += 4;
+        return lb_nil();
+    case 't':
+        if (strncmp(d->p, "true", 4) != 0) json_fail();
+        d->p += 4;
+        return lb_bool(1);
+    case 'f':
+        if (strncmp(d->p, "false", 5) != 0) json_fail();
+        d->p += 5;
+        return lb_bool(0);
+    case '"':
+        return lb_str(json_parse_string(d));
+    case '[': {
+        Value table = lb_table();
+        d->p++;
+        json_ws(d);
+        if (*d->p == ']') {
+            d->p++;
+            return table;
+        }
+        for (int64_t i = 0;; i++) {
+            table_set(table.as.t, lb_int(i), json_value(d));
+            json_ws(d);
+            if (*d->p == ']') break;
+            if (*d->p++ != ',') json_fail();
+        }
+        d->p++;
+        d->integer = 0;
+        return table;
+    }
+    case '{': {
+        Value table = lb_table();
+        Value members = lb_table(); /* every key, even of nulls */
+        int64_t numerator = 0, denominator = 0;
+        int fraction_parts = 0;
+        d->p++;
+        json_ws(d);
+        if (*d->p == '}') {
+            d->p++;
+            return table;
+        }
+        for (;;) {
+            json_ws(d);
+            if (*d->p != '"') json_fail();
+            const char *key = json_parse_string(d);
+            json_ws(d);
+            if (*d->p++ != ':') json_fail();
+            Value value = json_value(d);
+            table_set(table.as.t, lb_str(key), value);
+            table_set(members.as.t, lb_str(key), lb_bool(1));
+            if (d->integer && strcmp(key, "numerator") == 0) {
+                numerator = value.as.n;
+                fraction_parts |= 1;
+            } else if (d->integer && strcmp(key, "denominator") == 0 && value.as.n != 0) {
+                denominator = value.as.n;
+                fraction_parts |= 2;
+            } else if (strcmp(key, "numerator") == 0) {
+                fraction_parts &= ~1;
+            } else if (strcmp(key, "denominator") == 0) {
+                fraction_parts &= ~2;
+            }
+            json_ws(d);
+            if (*d->p == '}') break;
+            if (*d->p++ != ',') json_fail();
+        }
+        d->p++;
+        d->integer = 0;
+        if (members.as.t->len == 2 && fraction_parts == 3) {
+            return lb_fraction(frac_new(numerator, denominator));
+        }
+        return table;
+    }
+    default:
+        if (*d->p == '-' || (*d->p >= '0' && *d->p <= '9')) return json_number(d);
+        json_fail();
+        return lb_nil();
+    }
+}
+
+static Value json_decode(const char *text) {
+    JsonDecoder d = {text, 0};
+    Value v = json_value(&d);
+    json_ws(&d);
+    if (*d.p) json_fail();
+    return v;
+}
+
+/* ---- the standard library, like `stdlib.rs` ---- */
+
+static void write_traceback(Lb *L, Buf *b) {
+    buf_puts(b, "stack traceback:");
+    for (size_t i = L->nframes; i-- > 0;) {
+        Frame *f = &L->frames[i];
+        buf_printf(b, "\n\t%s:%" PRId64 ": ", LB_CHUNK, f->line);
+        if (f->main) buf_puts(b, "in main chunk");
+        else buf_printf(b, "in function '%s'", f->function);
+    }
+}
+
+static Value getinfo(Lb *L, int argc, const Value *argv) {
+    int64_t level = argc > 0 && argv[0].tag == LB_NUMBER ? argv[0].as.n : 1;
+    if (level < 1 || (uint64_t)level > L->nframes) return lb_nil();
+    Frame *f = &L->frames[L->nframes - (size_t)level];
+    Value info = lb_table();
+    table_set_field(info.as.t, "currentline", lb_int(f->line));
+    table_set_field(info.as.t, "short_src", lb_str(LB_CHUNK));
+    table_set_field(info.as.t, "what", lb_str(f->main ? "main" : "Lua"));
+    if (!f->main) table_set_field(info.as.t, "name", lb_str(f->function));
+    return info;
+}
+
+static Value string_format(Lb *L, int argc, const Value *argv) {
+    if (argc < 1 || argv[0].tag != LB_STRING) lb_error("bad argument #1 to 'format' (string expected)");
+    Buf b = {0};
+    buf_puts(&b, "");
+    int next = 1;
+    for (const char *p = argv[0].as.s; *p; p++) {
+        if (*p != '%') {
+            buf_putn(&b, p, 1);
+            continue;
+        }
+        LbFormat format;
+        switch (*++p) {
+        case '%':
+            buf_puts(&b, "%");
+            continue;
+        case 's': format = L->number_format; break;
+        case 'd': format = LB_DECIMAL; break;
+        case 'R': format = LB_ROMAN; break;
+        case 'M': format = LB_MIXED; break;
+        case '\0': lb_error("invalid conversion '%%' to 'format'"); return lb_nil();
+        default: lb_error("invalid conversion '%%%c' to 'format'", *p); return lb_nil();
+        }
+        format_value(&b, next < argc ? argv[next] : lb_nil(), format);
+        next++;
+    }
+    return lb_str(b.data);
+}
+
+static Value call_builtin(Lb *L, const char *name, int argc, const Value *argv) {
+    if (strcmp(name, "print") == 0) {
+        Buf b = {0};
+        for (int i = 0; i < argc; i++) {
+            if (i) buf_puts(&b, ", ");
+            format_value(&b, argv[i], L->number_format);
+        }
+        buf_puts(&b, "\n");
+        fputs(b.data, stdout);
+        return lb_nil();
+    }
+    if (strcmp(name, "tostring") == 0) {
+        LbFormat format = L->number_format;
+        if (argc > 1 && argv[1].tag == LB_STRING && !format_from_name(argv[1].as.s, &format)) {
+            lb_error("unknown number format %s", argv[1].as.s);
+        }
+        Buf b = {0};
+        format_value(&b, argc > 0 ? argv[0] : lb_nil(), format);
+        return lb_str(b.data);
+    }
+    if (strcmp(name, "format") == 0) return string_format(L, argc, argv);
+    if (strcmp(name, "encode") == 0) {
+        JsonEncoder e = {0};
+        json_options(argc > 1 ? argv[1] : lb_nil(), &e);
+        Buf b = {0};
+        json_encode(&b, argc > 0 ? argv[0] : lb_nil(), &e, 0);
+        return lb_str(b.data);
+    }
+    if (strcmp(name, "decode") == 0) {
+        if (argc < 1 || argv[0].tag != LB_STRING) lb_error("bad argument #1 to 'decode' (string expected)");
+        return json_decode(argv[0].as.s);
+    }
+    if (strcmp(name, "traceback") == 0) {
+        Buf b = {0};
+        if (argc > 0 && argv[0].tag != LB_NIL) {
+            format_value(&b, argv[0], LB_DECIMAL);
+            buf_puts(&b, "\n");
+        }
+        write_traceback(L, &b);
+        return lb_str(b.data);
+    }
+    if (strcmp(name, "getinfo") == 0) return getinfo(L, argc, argv);
+    if (strcmp(name, "sethook") == 0) {
+        Value function = argc > 0 ? argv[0] : lb_nil();
+        L->hook.set = function.tag != LB_NIL;
+        L->hook.function = function;
+        L->hook.mask = argc > 1 && argv[1].tag == LB_STRING ? argv[1].as.s : "";
+        L->hook.has_last = 0;
+        L->hook.running = 0;
+        return lb_nil();
+    }
+    lb_error("unknown builtin %s", name);
+    return lb_nil();
+}
+
+static void set_library(Lb *L, const char *library, const char *const *names) {
+    Value table = lb_table();
+    for (; *names; names++) table_set_field(table.as.t, *names, lb_builtin(*names));
+    scope_set(&L->globals, library, table);
+}
+
+int main(void) {
+    static const char *const debug[] = {"traceback", "getinfo", "sethook", NULL};
+    static const char *const string[] = {"format", NULL};
+    static const char *const json[] = {"encode", "decode", NULL};
+    Lb *L = lb_alloc(sizeof(Lb));
+    scope_set(&L->globals, "print", lb_builtin("print"));
+    scope_set(&L->globals, "tostring", lb_builtin("tostring"));
+    set_library(L, "debug", debug);
+    set_library(L, "string", string);
+    set_library(L, "json", json);
+    lb_enter_scope(L);
+    push_frame(L, "main chunk", 1);
+    lb_main(L);
+    return 0;
+}
@@ -0,0 +1,155 @@
+//! `--coverage`: which lines of a script ran, in the lcov format that
+//! `genhtml` and most CI services read.
+//!
+//! Every line a statement starts on can be covered, including the ones in
+//! function bodies. Its count is the number of statements that ran on it.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::parser::{Expr, Stmt, StmtKind};
+use crate::tokenizer::line_col;
+use crate::{Context, Hook};
+
+/// Counts statements through the hook from [`Coverage::hook`].
+pub struct Coverage(Rc<RefCell<BTreeMap<usize, usize>>>);
+
+struct CoverageHook(Rc<RefCell<BTreeMap<usize, usize>>>);
+
+impl Hook for CoverageHook {
+    fn before_stmt(&mut self, stmt: &Stmt, context: &mut Context) {
+        let line = context.line_of(stmt.span.start);
+        *self.0.borrow_mut().entry(line).or_default() += 1;
+    }
+}
+
+impl Coverage {
+    /// Starts with a count of 0 for every line of `ast` with a statement.
+    pub fn new(ast: &[Stmt], source: &str) -> Self {
+        let mut lines = BTreeMap::new();
+        stmt_lines(ast, source, &mut lines);
+        Self(Rc::new(RefCell::new(lines)))
+    }
+
+    /// The hook to give to [`Context::add_hook`].
+    pub fn hook(&self) -> Box<dyn Hook> {
+        Box::new(CoverageHook(self.0.clone()))
+    }
+
+    /// The tracefile for `path`, the script that was run.
+    pub fn lcov(&self, path: &str) -> String {
+        let lines = self.0.borrow();
+        let mut res = format!("TN:\nSF:{path}\n");
+        for (line, hits) in lines.iter() {
+            res.push_str(&format!("DA:{line},{hits}\n"));
+        }
+        let hit = lines.values().filter(|&&hits| hits > 0).count();
+        res.push_str(&format!("LF:{}\nLH:{hit}\nend_of_record\n", lines.len()));
+        res
+    }
+}
+
+fn stmt_lines(stmts: &[Stmt], source: &str, lines: &mut BTreeMap<usize, usize>) {
+    for stmt in stmts {
+        lines.insert(line_col(source, stmt.span.start).0, 0);
+        match &stmt.kind {
+            StmtKind::Break | StmtKind::Error => {}
+            StmtKind::Return(exprs) => exprs.iter().for_each(|e| expr_lines(e, source, lines)),
+            StmtKind::While { cond, body } => {
+                expr_lines(cond, source, lines);
+                stmt_lines(body, source, lines);
+            }
+            StmtKind::DoEnd { body } => stmt_lines(body, source, lines),
+            StmtKind::Assignment { value, .. }
+            | StmtKind::Local {
+                value: Some(value), ..
+            } => expr_lines(value, source, lines),
+            StmtKind::Local { value: None, .. } => {}
+            StmtKind::FunctionCall {
+                function_name,
+                args,
+            } => {
+                expr_lines(function_name, source, lines);
+                args.iter().for_each(|e| expr_lines(e, source, lines));
+            }
+            StmtKind::If { cond, then, r#else } => {
+                expr_lines(cond, source, lines);
+                stmt_lines(then, source, lines);
+                stmt_lines(r#else, source, lines);
+            }
+        }
+    }
+}
+
+/// The statements in function bodies inside `expr`.
+fn expr_lines(expr: &Expr, source: &str, lines: &mut BTreeMap<usize, usize>) {
+    match expr {
+        Expr::BinOp { lhs, rhs, .. } => {
+            expr_lines(lhs, source, lines);
+            expr_lines(rhs, source, lines);
+        }
+        Expr::Index { table, key } => {
+            expr_lines(table, source, lines);
+            expr_lines(key, source, lines);
+        }
+        Expr::FunctionCall {
+            function_name,
+            args,
+        } => {
+            expr_lines(function_name, source, lines);
+            args.iter().for_each(|e| expr_lines(e, source, lines));
+        }
+        Expr::FunctionDef { body, .. } => stmt_lines(body, source, lines),
+        Expr::Nil
+        | Expr::Numeral(_)
+        | Expr::Fraction(_)
+        | Expr::Boolean(_)
+        | Expr::String(_)
+        | Expr::Var(_)
+        | Expr::Error => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::LobsterParser;
+    use crate::run_block;
+
+    #[test]
+    fn lcov_report() {
+        let source = "\
+f = function(n)
+  if n == I then
+    return [[one]]
+  else
+    return [[many]]
+  end
+end
+print(f(I)) print(f(I))
+";
+        let ast = LobsterParser::new(source).parse();
+        let coverage = Coverage::new(&ast, source);
+        let mut context = Context::new();
+        context.test_stdout = Some(String::new());
+        context.set_source("test.lua", source);
+        context.add_hook(coverage.hook());
+        run_block(&ast, &mut context).unwrap();
+        assert_eq!(
+            coverage.lcov("test.lua"),
+            "\
+TN:
+SF:test.lua
+DA:1,1
+DA:2,2
+DA:3,2
+DA:5,0
+DA:8,2
+LF:5
+LH:4
+end_of_record
+"
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! An interactive step debugger, driven by `lobster-lua debug <file>`.
+//!
+//! It runs as a [`Hook`] and reads commands whenever the program stops: at
+//! the first line, at breakpoints and after stepping.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::parser::{LobsterParser, Stmt};
+use crate::stdlib::GLOBAL_NAMES;
+use crate::{Context, Hook, Value, eval};
+
+const HELP: &str = "\
+break N, b N      stop before line N
+delete N          remove the breakpoint at line N
+breakpoints       list breakpoints
+continue, c       run until the next breakpoint
+step, s           run to the next line, entering calls
+next, n           run to the next line in this function
+finish, out       run until this function returns
+locals            show local variables of every frame
+globals           show global variables
+print E, p E      evaluate the expression E
+watch E           show E every time the program stops
+unwatch N         remove watch number N
+backtrace, bt     show the call stack
+quit, q           stop the program
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Continue,
+    Step,
+    /// Stop at the next line at most this deep.
+    Next(usize),
+    /// Stop at the next line less deep than this.
+    Finish(usize),
+    /// The input is gone, run to the end.
+    Detached,
+}
+
+pub struct Debugger {
+    source_lines: Vec<String>,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    breakpoints: BTreeSet<usize>,
+    watches: Vec<String>,
+    mode: Mode,
+    /// The (depth, line) of the previous statement, so a line with several
+    /// statements is only stopped at once.
+    last_line: Option<(usize, usize)>,
+    last_command: String,
+}
+
+impl Debugger {
+    /// A debugger for `source` that starts paused at the first line.
+    pub fn new(source: &str, input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Self {
+            source_lines: source.lines().map(str::to_owned).collect(),
+            input,
+            output,
+            breakpoints: BTreeSet::new(),
+            watches: vec![],
+            mode: Mode::Step,
+            last_line: None,
+            last_command: String::new(),
+        }
+    }
+
+    fn should_stop(&self, depth: usize, line: usize) -> bool {
+        match self.mode {
+            Mode::Detached => false,
+            Mode::Step => true,
+            Mode::Next(max) => depth <= max || self.breakpoints.contains(&line),
+            Mode::Finish(below) => depth < below || self.breakpoints.contains(&line),
+            Mode::Continue => self.breakpoints.contains(&line),
+        }
+    }
+
+    fn stop(&mut self, line: usize, context: &mut Context) -> io::Result<()> {
+        let frame = context.frames().last().unwrap();
+        writeln!(self.output, "stopped at line {line} in {}", frame.function)?;
+        if let Some(text) = self.source_lines.get(line.wrapping_sub(1)) {
+            writeln!(self.output, "{line:>4} | {}", text.trim_end())?;
+        }
+        for (i, watch) in self.watches.iter().enumerate() {
+            writeln!(
+                self.output,
+                "{}: {watch} = {}",
+                i + 1,
+                evaluate(watch, context)
+            )?;
+        }
+        loop {
+            write!(self.output, "(ldb) ")?;
+            self.output.flush()?;
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                writeln!(self.output)?;
+                self.mode = Mode::Detached;
+                return Ok(());
+            }
+            let mut command = command.trim().to_owned();
+            if command.is_empty() {
+                command = self.last_command.clone();
+            }
+            self.last_command = command.clone();
+            if self.command(&command, context)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs one command, returns whether the program should resume.
+    fn command(&mut self, command: &str, context: &mut Context) -> io::Result<bool> {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let arg = arg.trim();
+        let depth = context.frames().len();
+        match name {
+            "" => {}
+            "break" | "b" => match arg.parse() {
+                Ok(line) => {
+                    self.breakpoints.insert(line);
+                    writeln!(self.output, "breakpoint at line {line}")?;
+                }
+                Err(_) => writeln!(self.output, "usage: break LINE")?,
+            },
+            "delete" => match arg.parse() {
+                Ok(line) if self.breakpoints.remove(&line) => {
+                    writeln!(self.output, "deleted breakpoint at line {line}")?;
+                }
+                _ => writeln!(self.output, "no breakpoint at line {arg}")?,
+            },
+            "breakpoints" => {
+                if self.breakpoints.is_empty() {
+                    writeln!(self.output, "no breakpoints")?;
+                }
+                for line in &self.breakpoints {
+                    writeln!(self.output, "line {line}")?;
+                }
+            }
+            "continue" | "c" => {
+                self.mode = Mode::Continue;
+                return Ok(true);
+            }
+            "step" | "s" => {
+                self.mode = Mode::Step;
+                return Ok(true);
+            }
+            "next" | "n" => {
+                self.mode = Mode::Next(depth);
+                return Ok(true);
+            }
+            "finish" | "out" => {
+                self.mode = Mode::Finish(depth);
+                return Ok(true);
+            }
+            "locals" => self.locals(context)?,
+            "globals" => {
+                let mut globals: Vec<_> = context
+                    .globals()
+                    .iter()
+                    .filter(|(name, _)| !GLOBAL_NAMES.contains(&name.as_str()))
+                    .collect();
+                globals.sort_by_key(|(name, _)| name.as_str());
+                for (name, value) in globals {
+                    writeln!(self.output, "{name} = {}", show(value))?;
+                }
+            }
+            "print" | "p" => writeln!(self.output, "{}", evaluate(arg, context))?,
+            "watch" => {
+                self.watches.push(arg.to_owned());
+                let number = self.watches.len();
+                writeln!(self.output, "{number}: {arg} = {}", evaluate(arg, context))?;
+            }
+            "unwatch" => match arg.parse::<usize>() {
+                Ok(n) if (1..=self.watches.len()).contains(&n) => {
+                    self.watches.remove(n - 1);
+                }
+                _ => writeln!(self.output, "no watch {arg}")?,
+            },
+            "backtrace" | "bt" => writeln!(self.output, "{}", context.traceback())?,
+            "quit" | "q" => {
+                context.halt();
+                return Ok(true);
+            }
+            "help" => write!(self.output, "{HELP}")?,
+            _ => writeln!(self.output, "unknown command `{name}`, try `help`")?,
+        }
+        Ok(false)
+    }
+
+    /// Locals of every frame, innermost first.
+    fn locals(&mut self, context: &Context) -> io::Result<()> {
+        let frames = context.frames();
+        let scopes = context.scopes();
+        for (i, frame) in frames.iter().enumerate().rev() {
+            let end = frames.get(i + 1).map_or(scopes.len(), |f| f.scope_start);
+            writeln!(self.output, "{}:", frame.function)?;
+            let mut locals: Vec<_> = scopes[frame.scope_start..end]
+                .iter()
+                .rev()
+                .flat_map(|scope| scope.iter())
+                .collect();
+            // inner scopes come first, so shadowed names are skipped
+            locals.sort_by_key(|(name, _)| name.as_str());
+            locals.dedup_by_key(|(name, _)| name.as_str());
+            for (name, value) in locals {
+                writeln!(self.output, "  {name} = {}", show(value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Hook for Debugger {
+    fn before_stmt(&mut self, stmt: &Stmt, context: &mut Context) {
+        let line = context.line_of(stmt.span.start);
+        let depth = context.frames().len();
+        if self.last_line == Some((depth, line)) {
+            return;
+        }
+        self.last_line = Some((depth, line));
+        if self.should_stop(depth, line) && self.stop(line, context).is_err() {
+            self.mode = Mode::Detached;
+        }
+    }
+}
+
+/// Like `Display`, but strings are quoted.
+fn show(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{s:?}"),
+        value => value.to_string(),
+    }
+}
+
+/// Evaluates `expr` in the current scope. Errors are shown rather than
+/// ending the program.
+fn evaluate(expr: &str, context: &mut Context) -> String {
+    let expr = match LobsterParser::new(expr).parse_expression() {
+        Ok(expr) => expr,
+        Err(e) => return format!("error: {}", e.message),
+    };
+    match context.catch_error(|context| eval(&expr, context)) {
+        Ok(value) => show(&value),
+        Err(message) => format!("error: {message}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::run_block;
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs `source` under the debugger, returns what the debugger and what
+    /// the program printed.
+    fn debug(source: &str, commands: &str) -> (String, String) {
+        let output = Shared::default();
+        let debugger = Debugger::new(
+            source,
+            Box::new(Cursor::new(commands.to_owned())),
+            Box::new(output.clone()),
+        );
+        let mut context = Context::new();
+        context.test_stdout = Some(String::new());
+        context.set_source("test.lua", source);
+        context.add_hook(Box::new(debugger));
+        let ast = LobsterParser::new(source).parse();
+        run_block(&ast, &mut context).unwrap();
+        let output = String::from_utf8(output.0.take()).unwrap();
+        (output, context.test_stdout.unwrap())
+    }
+
+    fn stops(out: &str) -> Vec<&str> {
+        out.split("stopped at line ")
+            .skip(1)
+            .map(|s| s.lines().next().unwrap())
+            .collect()
+    }
+
+    const SOURCE: &str = "\
+local add_one = function(x)
+  local y = x + I
+  return y
+end
+a = add_one(II)
+print(a)
+";
+
+    #[test]
+    fn starts_paused() {
+        let (out, stdout) = debug(SOURCE, "c\n");
+        assert_eq!(
+            out,
+            "stopped at line 1 in main chunk\n   1 | local add_one = function(x)\n(ldb) "
+        );
+        assert_eq!(stdout, "3\n");
+    }
+
+    #[test]
+    fn breakpoint_and_locals() {
+        let (out, _) = debug(SOURCE, "b 3\nc\nlocals\nc\n");
+        assert!(out.contains("stopped at line 3 in add_one\n"), "{out}");
+        assert!(
+            out.contains("add_one:\n  x = 2\n  y = 3\nmain chunk:\n  add_one = function\n"),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn step_into_and_finish() {
+        let (out, _) = debug(SOURCE, "n\ns\ns\nbt\nfinish\nn\n");
+        assert_eq!(
+            stops(&out),
+            [
+                "1 in main chunk",
+                "5 in main chunk",
+                "2 in add_one",
+                "3 in add_one",
+                "6 in main chunk",
+            ]
+        );
+        assert!(
+            out.contains(
+                "stack traceback:\n\ttest.lua:3: in function 'add_one'\n\ttest.lua:5: in main chunk\n"
+            ),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let (out, _) = debug(SOURCE, "n\nn\nn\n");
+        assert_eq!(
+            stops(&out),
+            ["1 in main chunk", "5 in main chunk", "6 in main chunk"]
+        );
+    }
+
+    #[test]
+    fn print_and_watch() {
+        let (out, _) = debug(
+            SOURCE,
+            "watch a\nn\nn\np a * X\np nope\np 1 +\nglobals\nc\n",
+        );
+        assert!(out.contains("1: a = nil\n"), "{out}");
+        assert!(out.contains("1: a = 3\n"), "{out}");
+        assert!(out.contains("(ldb) 30\n"), "{out}");
+        assert!(out.contains("(ldb) nil\n"), "{out}");
+        assert!(
+            out.contains("(ldb) error: Expected expression, got EOF\n"),
+            "{out}"
+        );
+        assert!(out.contains("(ldb) a = 3\n"), "{out}");
+    }
+
+    #[test]
+    fn quit_stops_program() {
+        let (_, stdout) = debug(SOURCE, "q\n");
+        assert_eq!(stdout, "");
+    }
+
+    #[test]
+    fn end_of_input_runs_to_end() {
+        let (out, stdout) = debug(SOURCE, "b 3\n");
+        assert!(!out.contains("line 3 in"), "{out}");
+        assert_eq!(stdout, "3\n");
+    }
+}
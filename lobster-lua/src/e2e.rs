@@ -1,23 +1,98 @@
-use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::Context;
+use crate::compile::compile;
 use crate::parser::LobsterParser;
-use crate::{Context, Value, run_block};
+use crate::run_block;
+use crate::tokenizer::RomanMode;
 
+/// Runs `source` in the interpreter and, if there is a C compiler, as a
+/// compiled program too, checking that both print the same.
 fn run(source: &str) -> String {
-    let parser = LobsterParser::new(source.to_owned());
+    let parser = LobsterParser::new(source);
     let ast = parser.parse();
 
-    let globals: HashMap<String, Value> = Default::default();
+    let mut context = Context::new();
+    context.test_stdout = Some(String::new());
+    context.set_source("test.lua", source);
 
-    let mut context: Context = Context {
-        test_stdout: Some(String::new()),
-        globals,
-        locals: vec![HashMap::new()],
-    };
+    run_block(&ast, &mut context).unwrap();
+
+    let out = context.test_stdout.unwrap();
+    if let Some(compiled) = run_compiled(source) {
+        assert_eq!(compiled, out, "the compiled program printed something else");
+    }
+    out
+}
+
+/// Runs `source` in the interpreter, which has to fail, and checks that the
+/// compiled program prints the same before failing with the same message.
+/// Returns the error.
+fn run_error(source: &str) -> String {
+    let ast = LobsterParser::new(source).parse();
+    let mut context = Context::new();
+    context.test_stdout = Some(String::new());
+    context.set_source("test.lua", source);
+
+    let error = run_block(&ast, &mut context).unwrap_err().message;
 
-    run_block(&ast, &mut context);
+    let out = context.test_stdout.unwrap();
+    if let Some(compiled) = compiled_output(source) {
+        assert_eq!(compiled.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8(compiled.stderr).unwrap(),
+            format!("lobster: {error}\n")
+        );
+        assert_eq!(String::from_utf8(compiled.stdout).unwrap(), out);
+    }
+    error
+}
 
-    context.test_stdout.unwrap()
+/// Stdout of `source` compiled to C, or `None` without `cc`.
+fn run_compiled(source: &str) -> Option<String> {
+    let run = compiled_output(source)?;
+    assert!(
+        run.status.success(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+    Some(String::from_utf8(run.stdout).unwrap())
+}
+
+/// What `source` compiled to C does when run, or `None` without `cc`.
+fn compiled_output(source: &str) -> Option<Output> {
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let dir: PathBuf = std::env::temp_dir().join(format!(
+        "lobster-e2e-{}-{}",
+        std::process::id(),
+        BUILDS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let c_file = dir.join("test.c");
+    let exe = dir.join("test");
+    std::fs::write(&c_file, compile(source, "test.lua", RomanMode::default()).unwrap()).unwrap();
+    let build = Command::new("cc")
+        .arg("-O1")
+        .arg(&c_file)
+        .arg("-o")
+        .arg(&exe)
+        .arg("-lm")
+        .output();
+    let res = match build {
+        Err(_) => None,
+        Ok(build) => {
+            assert!(
+                build.status.success(),
+                "{}",
+                String::from_utf8_lossy(&build.stderr)
+            );
+            Some(Command::new(&exe).output().unwrap())
+        }
+    };
+    std::fs::remove_dir_all(&dir).unwrap();
+    res
 }
 
 #[test]
@@ -60,3 +135,196 @@ All the fractions
 "
     );
 }
+
+#[test]
+fn locals_shadow_globals() {
+    let out = run(r#"
+x = 1
+f = function(a)
+    local x = a
+    x = x + 1
+    print(x)
+end
+f(10)
+do
+    local x
+    print(x)
+end
+print(x)
+    "#);
+    assert_eq!(out, "11\nnil\n1\n");
+}
+
+#[test]
+fn return_and_break() {
+    let out = run(r#"
+double = function(a)
+    return a * II
+end
+print(double(XXI))
+n = I
+while true do
+    n = n + I
+    if n == V then
+        break
+    end
+end
+print(n)
+    "#);
+    assert_eq!(out, "42\n5\n");
+}
+
+#[test]
+fn tables_and_fields() {
+    let out = run(r#"
+info = debug.getinfo(I)
+print(info.what, info.currentline, info[ [[short_src]] ])
+print(info.name)
+    "#);
+    assert_eq!(out, "main, 2, test.lua\nnil\n");
+}
+
+#[test]
+fn debug_traceback() {
+    let out = run(r#"
+inner = function()
+    print(debug.traceback([[oops]]))
+end
+outer = function()
+    inner()
+end
+outer()
+    "#);
+    assert_eq!(
+        out,
+        "oops
+stack traceback:
+\ttest.lua:3: in function 'inner'
+\ttest.lua:6: in function 'outer'
+\ttest.lua:8: in main chunk
+"
+    );
+}
+
+#[test]
+fn debug_getinfo_levels() {
+    let out = run(r#"
+f = function()
+    print(debug.getinfo(I).name, debug.getinfo(II).currentline)
+    print(debug.getinfo(III))
+end
+f()
+    "#);
+    assert_eq!(out, "f, 6\nnil\n");
+}
+
+#[test]
+fn debug_sethook() {
+    let out = run(r#"
+hook = function(event, line)
+    print(event, line)
+end
+f = function()
+    return I
+end
+debug.sethook(hook, [[lcr]])
+f()
+debug.sethook()
+print([[done]])
+    "#);
+    assert_eq!(
+        out,
+        "line, 9\ncall, nil\nline, 6\nreturn, nil\nline, 10\ndone\n"
+    );
+}
+
+#[test]
+fn roman_output() {
+    let out = run(r#"
+x = XIV + S∴
+print(tostring(x, [[roman]]), tostring(x, [[mixed]]), tostring(x))
+print(string.format([[%R pedes, %M feet, %d%%]], MCMXCIV, x, L))
+    "#);
+    assert_eq!(
+        out,
+        "XIV S∴, 14 3/4, 59/4\nMCMXCIV pedes, 14 3/4 feet, 50%\n"
+    );
+}
+
+#[test]
+fn default_number_format() {
+    let parser = LobsterParser::new("print(XIV + S∴, tostring(III), tostring(S, [[decimal]]))");
+    let mut context = Context::new();
+    context.test_stdout = Some(String::new());
+    context.set_number_format(crate::numeral::NumberFormat::Roman);
+    run_block(&parser.parse(), &mut context).unwrap();
+    assert_eq!(context.test_stdout.unwrap(), "XIV S∴, III, 1/2\n");
+}
+
+#[test]
+fn json_round_trip() {
+    let out = run(r#"
+data = json.decode([[{"crew": ["Ana", "Bo"], "share": 0.75, "ship": null}]])
+print(data.crew[I], data.share, data.ship)
+print(json.encode(data))
+print(json.encode(data.share, [[fractions=object]]))
+print(json.encode(nil), json.encode(XIV))
+    "#);
+    assert_eq!(
+        out,
+        r#"Bo, 3/4, nil
+{"crew":["Ana","Bo"],"share":"3/4"}
+{"denominator":4,"numerator":3}
+null, 14
+"#
+    );
+}
+
+#[test]
+fn interpolation() {
+    let out = run(r#"
+sum = CCCLXV + S
+print(f[[Total: {sum} ({sum / XII} per month)]])
+print(`{sum:roman}, {sum:mixed}, {{{nil}}} and \`{f[[{I + I}]]}\``)
+    "#);
+    assert_eq!(
+        out,
+        "Total: 731/2 (731/24 per month)\nCCCLXV S, 365 1/2, {nil} and `2`\n"
+    );
+}
+
+#[test]
+fn runtime_errors() {
+    let cases = [
+        (
+            "print([[before]]) s = [[a]] print(I + s)",
+            "attempt to perform arithmetic on a string value",
+        ),
+        (
+            "print(II / nil)",
+            "attempt to perform arithmetic on a nil value",
+        ),
+        (
+            "print(II ^ (I / II))",
+            "attempt to perform arithmetic on a fraction value",
+        ),
+        (
+            "print(true and I)",
+            "attempt to perform logical operation on a number value",
+        ),
+        (
+            "s = [[a]] print(s .. true)",
+            "attempt to concatenate a boolean value",
+        ),
+        ("x = I x()", "attempt to call a number value (x)"),
+        ("x = I print(x.y)", "attempt to index a number value"),
+        (
+            "f = function() end f(I)",
+            "calling with wrong number of parameters",
+        ),
+        ("f = function() return f() end f()", "stack overflow"),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(run_error(source), expected, "{source}");
+    }
+}
@@ -0,0 +1,472 @@
+//! Re-emits a parsed program as canonical lobster source.
+//!
+//! Blocks are indented with two spaces, binary operators get a space on
+//! either side and parentheses are only written where the precedence table
+//! in [`crate::parser::BinOp::get_precedence`] needs them. Comments are not part of the
+//! AST; they are merged back in by their position in the original source.
+//! Number literals keep their spelling, `XIV` stays `XIV` and `14` stays
+//! `14`: the AST visits them in source order, so they are matched up with
+//! the literal tokens one by one.
+
+use std::iter::Peekable;
+
+use crate::parser::{Expr, LobsterParser, ParseError, Stmt, StmtKind};
+use crate::tokenizer::{Comment, Keyword, Span, Token, Tokenizer};
+
+const INDENT: &str = "  ";
+
+pub fn format_source(source: &str) -> Result<String, ParseError> {
+    let parser = LobsterParser::new(source);
+    let (ast, comments) = parser.parse_with_comments()?;
+    // it parsed, so the tokens read again without errors
+    let mut tokenizer = Tokenizer::new(source);
+    let elses: Vec<usize> = tokenizer
+        .by_ref()
+        .flatten()
+        .filter(|tok| tok.value == Token::Keyword(Keyword::Else))
+        .map(|tok| tok.span.start)
+        .collect();
+    let mut formatter = Formatter {
+        source,
+        out: String::new(),
+        indent: 0,
+        comments: comments.into_iter().peekable(),
+        literals: tokenizer.take_literals().into_iter(),
+        elses: &elses,
+        last_end: None,
+    };
+    formatter.block(&ast, source.len());
+    Ok(formatter.out)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    out: String,
+    indent: usize,
+    comments: Peekable<std::vec::IntoIter<Comment>>,
+    /// Spans of the number literals not written yet.
+    literals: std::vec::IntoIter<Span>,
+    /// Where each `else` keyword starts.
+    elses: &'a [usize],
+    /// End of the last statement or comment written, to decide whether a
+    /// blank line from the source should be kept.
+    last_end: Option<usize>,
+}
+
+impl Formatter<'_> {
+    fn line(&mut self, start: usize, text: &str) {
+        if let Some(last_end) = self.last_end {
+            let gap = &self.source[last_end.min(start)..start];
+            if gap.matches('\n').count() > 1 {
+                self.out.push('\n');
+            }
+        }
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Writes a line that closes or splits a block, like `end` or `else`.
+    /// These never get a blank line in front.
+    fn closing_line(&mut self, text: &str) {
+        self.last_end = None;
+        self.line(0, text);
+    }
+
+    /// Writes every pending comment that starts before `pos`.
+    fn comments_before(&mut self, pos: usize) {
+        while let Some(comment) = self.comments.next_if(|c| c.span.start < pos) {
+            if !comment.own_line && self.out.ends_with('\n') && self.last_end.is_some() {
+                // trailing comment, glue it back onto the line it came from
+                self.out.pop();
+                self.out.push(' ');
+                self.out.push_str(&comment.text);
+                self.out.push('\n');
+            } else {
+                self.line(comment.span.start, &comment.text);
+            }
+            self.last_end = Some(comment.span.end);
+        }
+    }
+
+    /// Writes the statements of a block, followed by the comments that sit
+    /// between its last statement and `end`.
+    fn block(&mut self, stmts: &[Stmt], end: usize) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.comments_before(end);
+    }
+
+    fn nested_block(&mut self, stmts: &[Stmt], end: usize) {
+        self.indent += 1;
+        self.block(stmts, end);
+        self.indent -= 1;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        self.comments_before(stmt.span.start);
+        let start = stmt.span.start;
+        match &stmt.kind {
+            StmtKind::Break => self.line(start, "break"),
+            StmtKind::Return(exprs) => {
+                if exprs.is_empty() {
+                    self.line(start, "return");
+                } else {
+                    let exprs = self.expr_list(exprs);
+                    self.line(start, &format!("return {exprs}"));
+                }
+            }
+            StmtKind::While { cond, body } => {
+                let cond = self.expr(cond);
+                self.line(start, &format!("while {cond} do"));
+                self.last_end = None;
+                self.nested_block(body, stmt.span.end);
+                self.closing_line("end");
+            }
+            StmtKind::DoEnd { body } => {
+                self.line(start, "do");
+                self.last_end = None;
+                self.nested_block(body, stmt.span.end);
+                self.closing_line("end");
+            }
+            StmtKind::Assignment { variable, value } => {
+                let value = self.expr(value);
+                self.line(start, &format!("{variable} = {value}"));
+            }
+            StmtKind::Local { variable, value } => match value {
+                Some(value) => {
+                    let value = self.expr(value);
+                    self.line(start, &format!("local {variable} = {value}"));
+                }
+                None => self.line(start, &format!("local {variable}")),
+            },
+            StmtKind::FunctionCall {
+                function_name,
+                args,
+            } => {
+                let call = self.call(function_name, args);
+                self.line(start, &call);
+            }
+            StmtKind::Error => unreachable!("try_parse has no error nodes"),
+            StmtKind::If { cond, then, r#else } => {
+                let cond = self.expr(cond);
+                self.line(start, &format!("if {cond} then"));
+                self.last_end = None;
+                self.if_tail(then, r#else, stmt.span.end);
+            }
+        }
+        self.last_end = Some(stmt.span.end);
+    }
+
+    /// Everything of an `if` after its `then`: the block, `elseif`s, `else`
+    /// and the final `end`.
+    fn if_tail(&mut self, then: &[Stmt], r#else: &[Stmt], end: usize) {
+        let then_end = match r#else {
+            [] => end,
+            [
+                Stmt {
+                    kind: StmtKind::If { .. },
+                    span,
+                },
+            ] => span.start,
+            [first, ..] => self.else_before(first.span.start),
+        };
+        self.nested_block(then, then_end);
+        match r#else {
+            [] => {}
+            [
+                Stmt {
+                    kind: StmtKind::If { cond, then, r#else },
+                    span,
+                },
+            ] => {
+                self.comments_before(span.start);
+                let cond = self.expr(cond);
+                self.closing_line(&format!("elseif {cond} then"));
+                return self.if_tail(then, r#else, end);
+            }
+            stmts => {
+                self.closing_line("else");
+                self.nested_block(stmts, end);
+            }
+        }
+        self.closing_line("end");
+    }
+
+    /// Position of the last `else` keyword before `pos`.
+    fn else_before(&self, pos: usize) -> usize {
+        let before = self.elses.partition_point(|&start| start < pos);
+        before.checked_sub(1).map_or(pos, |idx| self.elses[idx])
+    }
+
+    /// An expression that gets called or indexed, in parentheses unless it
+    /// is a name, call or index already.
+    fn prefix(&mut self, expr: &Expr) -> String {
+        let res = self.expr(expr);
+        match expr {
+            Expr::Var(_) | Expr::FunctionCall { .. } | Expr::Index { .. } => res,
+            _ => format!("({res})"),
+        }
+    }
+
+    fn call(&mut self, function_name: &Expr, args: &[Expr]) -> String {
+        let callee = self.prefix(function_name);
+        let args = self.expr_list(args);
+        format!("{callee}({args})")
+    }
+
+    fn expr_list(&mut self, exprs: &[Expr]) -> String {
+        exprs
+            .iter()
+            .map(|e| self.expr(e))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Nil => "nil".to_owned(),
+            Expr::Numeral(_) | Expr::Fraction(_) => {
+                let span = self
+                    .literals
+                    .next()
+                    .expect("a literal token for each literal");
+                self.source[span.start..span.end].to_owned()
+            }
+            Expr::Boolean(b) => b.to_string(),
+            Expr::String(s) => long_string(s),
+            Expr::BinOp { op, lhs, rhs } => {
+                let (l_prec, r_prec) = op.get_precedence();
+                let lhs_str = self.expr(lhs);
+                let lhs_str = match lhs.as_ref() {
+                    Expr::BinOp { op: inner, .. } if l_prec >= inner.get_precedence().1 => {
+                        format!("({lhs_str})")
+                    }
+                    _ => lhs_str,
+                };
+                let rhs_str = self.expr(rhs);
+                let rhs_str = match rhs.as_ref() {
+                    Expr::BinOp { op: inner, .. } if inner.get_precedence().0 < r_prec => {
+                        format!("({rhs_str})")
+                    }
+                    _ => rhs_str,
+                };
+                format!("{lhs_str} {} {rhs_str}", op.to_s_expr())
+            }
+            Expr::Var(name) => name.to_string(),
+            Expr::Index { table, key } => {
+                let table = self.prefix(table);
+                match key.as_ref() {
+                    Expr::String(name) if is_plain_name(name) => format!("{table}.{name}"),
+                    key => {
+                        let key = self.expr(key);
+                        if key.starts_with('[') {
+                            // `t[[[x]]]` would read as a long string
+                            format!("{table}[ {key} ]")
+                        } else {
+                            format!("{table}[{key}]")
+                        }
+                    }
+                }
+            }
+            Expr::FunctionCall {
+                function_name,
+                args,
+            } => self.call(function_name, args),
+            Expr::Error => unreachable!("try_parse has no error nodes"),
+            Expr::FunctionDef {
+                arguments,
+                body,
+                end,
+            } => {
+                let mut inner = Formatter {
+                    source: self.source,
+                    out: String::new(),
+                    indent: self.indent + 1,
+                    comments: std::mem::replace(
+                        &mut self.comments,
+                        Vec::new().into_iter().peekable(),
+                    ),
+                    literals: std::mem::take(&mut self.literals),
+                    elses: self.elses,
+                    last_end: None,
+                };
+                inner.block(body, end.0);
+                self.comments = inner.comments;
+                self.literals = inner.literals;
+
+                let arguments: Vec<&str> = arguments.iter().map(|a| a.name.as_str()).collect();
+                let mut res = format!("function({})\n", arguments.join(", "));
+                res.push_str(&inner.out);
+                for _ in 0..self.indent {
+                    res.push_str(INDENT);
+                }
+                res.push_str("end");
+                res
+            }
+        }
+    }
+}
+
+/// Whether `s` can be written after a `.`, i.e. reads back as exactly that
+/// identifier.
+fn is_plain_name(s: &str) -> bool {
+    let mut tokenizer = Tokenizer::new(s);
+    matches!(tokenizer.next(), Some(Ok(tok)) if tok.value == Token::Ident(s.to_owned()))
+        && tokenizer.next().is_none()
+}
+
+/// Spells `s` as a long bracket string whose level is high enough that the
+/// closing bracket cannot appear inside.
+fn long_string(s: &str) -> String {
+    let mut level = 0;
+    loop {
+        let close = format!("]{}]", "=".repeat(level));
+        if format!("{s}{close}").find(&close) == Some(s.len()) {
+            return format!("[{}[{s}{close}", "=".repeat(level));
+        }
+        level += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_idempotent(source: &str) -> String {
+        let once = format_source(source).unwrap();
+        let twice = format_source(&once).unwrap();
+        assert_eq!(once, twice, "formatting is not idempotent for {source:?}");
+        assert_eq!(
+            LobsterParser::new(source).parse(),
+            LobsterParser::new(&once).parse(),
+            "formatting changed the meaning of {source:?}"
+        );
+        once
+    }
+
+    macro_rules! fmt_test {
+        ($name:ident, $source:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(check_idempotent($source), $expected);
+            }
+        };
+    }
+
+    fmt_test!(fmt_assignment, "x=1+2*3", "x = 1 + 2 * 3\n");
+    fmt_test!(
+        fmt_keeps_needed_parens,
+        "x = (1 + 2) * 3",
+        "x = (1 + 2) * 3\n"
+    );
+    fmt_test!(
+        fmt_drops_useless_parens,
+        "x = ((1)) + (2 * 3)",
+        "x = 1 + 2 * 3\n"
+    );
+    fmt_test!(
+        fmt_left_assoc,
+        "x = 1 - (2 - 3) - 4",
+        "x = 1 - (2 - 3) - 4\n"
+    );
+    fmt_test!(
+        fmt_right_assoc,
+        "x = (2 ^ 3) ^ 4 ^ 5",
+        "x = (2 ^ 3) ^ 4 ^ 5\n"
+    );
+    fmt_test!(
+        fmt_concat_right_assoc,
+        "x = [[a]] .. ([[b]] .. [[c]])",
+        "x = [[a]] .. [[b]] .. [[c]]\n"
+    );
+    fmt_test!(fmt_roman, "x = XIV + S∴", "x = XIV + S∴\n");
+    fmt_test!(fmt_decimal, "x = 14+XIV * 0", "x = 14 + XIV * 0\n");
+    fmt_test!(
+        fmt_literals_in_interpolation,
+        "x = IV .. f[[{XIV:roman} {2}]] .. S",
+        "x = IV .. (tostring(XIV, [[roman]]) .. [[ ]] .. tostring(2)) .. S\n"
+    );
+    fmt_test!(
+        fmt_else_in_names_and_strings,
+        "if a then elsewhere = [[else]] else x = 1 end",
+        "if a then\n  elsewhere = [[else]]\nelse\n  x = 1\nend\n"
+    );
+    fmt_test!(fmt_half, "x = S", "x = S\n");
+    fmt_test!(fmt_local, "local  x=1 local y", "local x = 1\nlocal y\n");
+    fmt_test!(
+        fmt_string_level,
+        "x = [==[a]]b]=]]==]",
+        "x = [==[a]]b]=]]==]\n"
+    );
+    fmt_test!(
+        fmt_string_trailing_bracket,
+        "x = [=[a]]=]",
+        "x = [=[a]]=]\n"
+    );
+    fmt_test!(
+        fmt_while,
+        "while i ~= 0 do\n\tres = res * i i = i - 1 end",
+        "while i ~= 0 do\n  res = res * i\n  i = i - 1\nend\n"
+    );
+    fmt_test!(
+        fmt_if_elseif_else,
+        "if a then b = 1 elseif c then b = 2 else b = 3 end",
+        "if a then\n  b = 1\nelseif c then\n  b = 2\nelse\n  b = 3\nend\n"
+    );
+    fmt_test!(
+        fmt_function,
+        "add = function (a,b)\nc = a+b\nend",
+        "add = function(a, b)\n  c = a + b\nend\n"
+    );
+    fmt_test!(
+        fmt_nested_function,
+        "do f = function() g = function() return 1 end end end",
+        "do\n  f = function()\n    g = function()\n      return 1\n    end\n  end\nend\n"
+    );
+    fmt_test!(fmt_call_callee, "x = (f)(1, 2)", "x = f(1, 2)\n");
+    fmt_test!(
+        fmt_index,
+        "x = (a).b[ [[c d]] ][1](2) debug . sethook (f)",
+        "x = a.b[ [[c d]] ][1](2)\ndebug.sethook(f)\n"
+    );
+    fmt_test!(fmt_index_roman_key, "x = t[ [[MIX]] ]", "x = t[ [[MIX]] ]\n");
+    fmt_test!(
+        fmt_comments,
+        "-- leading\nx = 1 -- trailing\n\n\n--[[ block\n  comment ]]\ny = 2",
+        "-- leading\nx = 1 -- trailing\n\n--[[ block\n  comment ]]\ny = 2\n"
+    );
+    fmt_test!(
+        fmt_comment_before_end,
+        "while x do\n  y = 1\n  -- last\nend\n-- eof",
+        "while x do\n  y = 1\n  -- last\nend\n-- eof\n"
+    );
+    fmt_test!(
+        fmt_comment_in_if_branches,
+        "if a then\n-- one\nx = 1\nelseif b then\n-- two\nx = 2\nelse\n-- three\nx = 3\nend",
+        "if a then\n  -- one\n  x = 1\nelseif b then\n  -- two\n  x = 2\nelse\n  -- three\n  x = 3\nend\n"
+    );
+    fmt_test!(
+        fmt_comment_at_end_of_function,
+        "f = function()\nx = 1\n-- last\nend\ng = function()\n-- only\nend\n-- after",
+        "f = function()\n  x = 1\n  -- last\nend\ng = function()\n  -- only\nend\n-- after\n"
+    );
+    fmt_test!(
+        fmt_comment_at_end_of_function_argument,
+        "call(function(a)\nreturn a -- trailing\n-- last\nend, b)",
+        "call(function(a)\n  return a -- trailing\n  -- last\nend, b)\n"
+    );
+    fmt_test!(
+        fmt_comment_in_function,
+        "f = function()\n-- inside\nx = 1\nend",
+        "f = function()\n  -- inside\n  x = 1\nend\n"
+    );
+
+    #[test]
+    fn fmt_sample_is_idempotent() {
+        check_idempotent(include_str!("../sample.lua"));
+    }
+}
@@ -1,5 +1,3 @@
-use std::ops::Add;
-
 use serde::Serialize;
 
 #[derive(PartialEq, Eq, Clone, Copy, Serialize)]
@@ -29,19 +27,28 @@ impl Fraction {
     }
 
     pub fn new(numerator: i64, denominator: i64) -> Self {
-        let mut this = Self {
-            numerator,
-            denominator,
-        };
-        this.reduce();
-        this
+        Self::reduced(numerator.into(), denominator.into(), "attempt to negate with overflow")
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn numerator(&self) -> i64 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> i64 {
+        self.denominator
     }
 
-    fn reduce(&mut self) {
-        assert_ne!(self.denominator, 0);
+    /// `numerator / denominator` in lowest terms, with the sign on the
+    /// numerator. The parts are wide enough for the product of any two
+    /// `i64`s; if the result does not fit again, that is `overflow`.
+    fn reduced(numerator: i128, denominator: i128, overflow: &'static str) -> Result<Self, &'static str> {
+        if denominator == 0 {
+            return Err("division by zero");
+        }
 
-        let mut a = self.numerator;
-        let mut b = self.denominator;
+        let mut a = numerator.unsigned_abs();
+        let mut b = denominator.unsigned_abs();
 
         while b != 0 {
             let c = b;
@@ -49,10 +56,42 @@ impl Fraction {
             a = c
         }
 
-        assert_eq!(self.denominator % a, 0);
-        assert_eq!(self.numerator % a, 0);
-        self.denominator /= a;
-        self.numerator /= a;
+        let gcd = a as i128 * denominator.signum();
+        Ok(Self {
+            numerator: (numerator / gcd).try_into().map_err(|_| overflow)?,
+            denominator: (denominator / gcd).try_into().map_err(|_| overflow)?,
+        })
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Result<Self, &'static str> {
+        let (a, b, c, d) = self.wide(rhs);
+        Self::reduced(a * d + b * c, b * d, "attempt to add with overflow")
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Result<Self, &'static str> {
+        let (a, b, c, d) = self.wide(rhs);
+        Self::reduced(a * d - b * c, b * d, "attempt to subtract with overflow")
+    }
+
+    pub fn checked_mul(self, rhs: Self) -> Result<Self, &'static str> {
+        let (a, b, c, d) = self.wide(rhs);
+        Self::reduced(a * c, b * d, "attempt to multiply with overflow")
+    }
+
+    pub fn checked_div(self, rhs: Self) -> Result<Self, &'static str> {
+        let (a, b, c, d) = self.wide(rhs);
+        Self::reduced(a * d, b * c, "attempt to divide with overflow")
+    }
+
+    /// `a/b` and `c/d` as `(a, b, c, d)`, widened so products cannot
+    /// overflow.
+    fn wide(self, rhs: Self) -> (i128, i128, i128, i128) {
+        (
+            self.numerator.into(),
+            self.denominator.into(),
+            rhs.numerator.into(),
+            rhs.denominator.into(),
+        )
     }
 }
 
@@ -60,20 +99,15 @@ impl std::ops::Add for Fraction {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Fraction::new(
-            self.numerator * rhs.denominator + self.denominator * rhs.numerator,
-            self.denominator * rhs.denominator,
-        )
+        self.checked_add(rhs).unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
 impl std::ops::Neg for Fraction {
     type Output = Self;
 
-    // WTF mut => self is copied and just the local self is mut then or something
-    fn neg(mut self) -> Self::Output {
-        self.numerator *= -1;
-        self
+    fn neg(self) -> Self::Output {
+        Fraction::new(0, 1) - self
     }
 }
 
@@ -81,7 +115,7 @@ impl std::ops::Sub for Fraction {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        self + -rhs
+        self.checked_sub(rhs).unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
@@ -89,7 +123,7 @@ impl std::ops::Mul for Fraction {
     type Output = Self;
     
     fn mul(self, rhs: Self) -> Self::Output {
-        Self::new(self.numerator * rhs.numerator, self.denominator * rhs.denominator)
+        self.checked_mul(rhs).unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
@@ -97,7 +131,7 @@ impl std::ops::Div for Fraction {
     type Output = Self;
     
     fn div(self, rhs: Self) -> Self::Output {
-        Self::new(self.numerator * rhs.denominator, self.denominator * rhs.numerator)
+        self.checked_div(rhs).unwrap_or_else(|e| panic!("{e}"))
     }
 }
 
@@ -122,14 +156,28 @@ mod tests {
         check_reduce(-2, 3, -2, 3);
         check_reduce(2, -3, -2, 3);
         check_reduce(-20, -30, 2, 3);
+        check_reduce(-13, 12, -13, 12);
+        check_reduce(40, -3, -40, 3);
     }
 
     #[test]
-    #[should_panic]
+    #[should_panic = "division by zero"]
     fn reduce_panic_test() {
         Fraction::new(3, 0);
     }
 
+    #[test]
+    fn overflow_is_an_error() {
+        let big = Fraction::new(i64::MAX, 3);
+        assert_eq!(big.checked_add(Fraction::new(1, 2)), Err("attempt to add with overflow"));
+        assert_eq!(big.checked_mul(Fraction::new(1, 3)), Ok(Fraction::new(i64::MAX, 9)));
+        assert_eq!(
+            Fraction::new(i64::MIN, 1).checked_div(Fraction::new(-1, 1)),
+            Err("attempt to divide with overflow")
+        );
+        assert_eq!(Fraction::new(i64::MIN, 2), Fraction::new(i64::MIN / 2, 1));
+    }
+
     #[test]
     fn add_test() {
         let a = Fraction::new(73, 111678);
@@ -0,0 +1,255 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`.
+//!
+//! Each function takes any source at all and panics only when it finds a
+//! bug: a crash in the tokenizer, parser or interpreter, or two ways of
+//! running a program that disagree. Errors in the script are a
+//! [`crate::RuntimeError`], so any panic at all is a bug.
+//! Reproducers the fuzzer finds go in the tests at the bottom.
+
+use crate::optimizer::{Passes, optimize};
+use crate::parser::{LobsterParser, Stmt};
+use crate::tokenizer::Tokenizer;
+use crate::{Context, formatter, linter, run_block};
+
+/// Statements and loop iterations a fuzzed program gets before it is halted.
+pub const STEP_LIMIT: usize = 10_000;
+
+/// Tokenizes `source` to the end, with and without trivia.
+pub fn tokenize(source: &str) {
+    let tokens = Tokenizer::new(source).count();
+    let mut tokenizer = Tokenizer::new(source);
+    tokenizer.set_trivia(true);
+    let mut end = 0;
+    for token in tokenizer {
+        let span = match token {
+            Ok(token) => token.span,
+            Err(e) => e.span,
+        };
+        assert!(span.start <= span.end && span.end <= source.len());
+        end = end.max(span.end);
+    }
+    assert!(tokens <= source.len() + 1);
+    assert!(end <= source.len());
+}
+
+/// Parses `source`, checking that the recovering parser and
+/// [`LobsterParser::try_parse`] agree on whether it has errors, and lints it
+/// like the language server does on every change.
+pub fn parse(source: &str) {
+    let (_, errors) = LobsterParser::new(source).parse_recovering();
+    let first = LobsterParser::new(source).try_parse().err();
+    assert_eq!(errors.first(), first.as_ref());
+    linter::check_source(source);
+}
+
+/// Runs `source` for at most [`STEP_LIMIT`] steps.
+pub fn run(source: &str) {
+    if let Ok(ast) = LobsterParser::new(source).try_parse() {
+        execute(&ast);
+    }
+}
+
+/// Checks that the constant folder and the other optimisations do not
+/// change what a program prints, and that formatting a program and parsing
+/// it again gives back the same AST.
+pub fn differential(source: &str) {
+    let Ok(ast) = LobsterParser::new(source).try_parse() else {
+        return;
+    };
+
+    let formatted = formatter::format_source(source).expect("it parsed before");
+    let reparsed = LobsterParser::new(&formatted).try_parse();
+    assert_eq!(reparsed.as_ref(), Ok(&ast), "formatted as:\n{formatted}");
+
+    let plain = execute(&optimize(ast.clone(), Passes::NONE));
+    let optimized = execute(&optimize(ast, Passes::ALL));
+    if !plain.out_of_steps && !optimized.out_of_steps {
+        assert_eq!(plain.stdout, optimized.stdout);
+        assert_eq!(plain.error, optimized.error);
+    }
+}
+
+struct Outcome {
+    stdout: String,
+    error: Option<String>,
+    out_of_steps: bool,
+}
+
+fn execute(ast: &[Stmt]) -> Outcome {
+    let mut context = Context::new();
+    context.test_stdout = Some(String::new());
+    context.set_step_limit(STEP_LIMIT);
+    let error = run_block(ast, &mut context).err().map(|e| e.message);
+    Outcome {
+        stdout: context.test_stdout.take().unwrap(),
+        error,
+        out_of_steps: context.out_of_steps(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn everything(source: &str) {
+        tokenize(source);
+        parse(source);
+        run(source);
+        differential(source);
+    }
+
+    fn error(source: &str) -> Option<String> {
+        let ast = LobsterParser::new(source).parse();
+        execute(&ast).error
+    }
+
+    #[test]
+    fn unfinished_long_brackets() {
+        for source in ["x = [[abc", "x = [[", "--[[ abc", "--[[", "print(I) --[["] {
+            everything(source);
+            assert!(LobsterParser::new(source).try_parse().is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn digits_that_are_not_ascii() {
+        for source in ["x = ½", "x = ١٢", "x = ²", "print(I٣)"] {
+            everything(source);
+        }
+    }
+
+    #[test]
+    fn literal_too_large() {
+        let source = "x = 99999999999999999999999";
+        everything(source);
+        let e = LobsterParser::new(source).try_parse().unwrap_err();
+        assert_eq!(
+            e.message,
+            "`99999999999999999999999` is too large for a number"
+        );
+    }
+
+    #[test]
+    fn nested_too_deeply() {
+        let deep = |open: &str, close: &str| {
+            let n = crate::parser::MAX_DEPTH + 1;
+            format!("{}{}", open.repeat(n), close.repeat(n))
+        };
+        for source in [
+            deep("do ", "end "),
+            format!("x = {}", deep("(", ")")),
+            format!("x = I{}", " + I".repeat(10_000)),
+            format!("x = a{}", ".b".repeat(10_000)),
+            format!("x = f{}", "()".repeat(10_000)),
+            format!("x = {}", deep("function() return ", " end")),
+        ] {
+            everything(&source);
+            let e = LobsterParser::new(&source).try_parse().unwrap_err();
+            assert_eq!(e.message, "Nested too deeply, the limit is 200 levels");
+        }
+    }
+
+    #[test]
+    fn just_within_the_depth_limit() {
+        let n = crate::parser::MAX_DEPTH / 2 - 1;
+        let source = format!("{}print(I){}", "do ".repeat(n), " end".repeat(n));
+        everything(&source);
+    }
+
+    #[test]
+    fn interpolated_strings() {
+        for source in [
+            "print(f[[{I}{S:roman}{[[}]]}]], `{`{nil}`}`)",
+            "print(f[[{",
+            "print(`{x:}`)",
+            "print(`\\",
+            "x = f[=[{f[[}]]}]=]",
+        ] {
+            everything(source);
+        }
+    }
+
+    #[test]
+    fn infinite_recursion() {
+        let source = "f = function(n) return f(n + I) end f(I)";
+        everything(source);
+        assert_eq!(error(source).as_deref(), Some("stack overflow"));
+    }
+
+    #[test]
+    fn infinite_loops_run_out_of_steps() {
+        for source in [
+            "while true do end",
+            "while true do x = I end",
+            "while true do if false then end end",
+            "f = function() while true do end end f()",
+        ] {
+            let ast = LobsterParser::new(source).parse();
+            let outcome = execute(&ast);
+            assert!(outcome.out_of_steps, "{source}");
+            assert_eq!(outcome.error, None);
+        }
+    }
+
+    #[test]
+    fn overflow_is_a_script_error() {
+        let cases = [
+            (
+                "x = 9223372036854775807 + I",
+                "attempt to add with overflow",
+            ),
+            (
+                "x = 9223372036854775807 * II",
+                "attempt to multiply with overflow",
+            ),
+            (
+                "x = 9223372036854775807 * S + S∴",
+                "attempt to add with overflow",
+            ),
+            ("x = II ^ LXIV", "attempt to multiply with overflow"),
+            ("x = II ^ 4294967296", "attempt to multiply with overflow"),
+            ("x = II ^ (I - II)", "attempt to raise to a negative power"),
+        ];
+        for (source, expected) in cases {
+            everything(source);
+            assert_eq!(error(source).as_deref(), Some(expected), "{source}");
+        }
+    }
+
+    #[test]
+    fn wrong_number_of_parameters() {
+        let source = "f = function(a) return a end print(f())";
+        everything(source);
+        assert_eq!(
+            error(source).as_deref(),
+            Some("calling with wrong number of parameters")
+        );
+    }
+
+    /// Each level of a deeply nested function is a level of native stack in
+    /// the interpreter, and the function can call itself [`crate::MAX_CALL_DEPTH`]
+    /// times. Run on a thread with as much stack as the main thread gets.
+    #[test]
+    fn nesting_in_deep_recursion() {
+        let nested = |open: &str, close: &str, n, inner: &str| {
+            format!("{}{inner}{}", open.repeat(n), close.repeat(n))
+        };
+        let sources = [
+            format!("return {}", nested("I + (", ")", 60, "f(n)")),
+            format!("return {}", nested("g(", ")", 60, "f(n)")),
+            nested("do ", " end", 190, "return f(n)"),
+            nested("if true then ", " end", 190, "return f(n)"),
+            nested("while true do ", " end", 190, "return f(n)"),
+        ]
+        .map(|body| format!("f = function(n) {body} end print(f(I))"));
+        let thread = std::thread::Builder::new().stack_size(8 << 20);
+        let errors = thread
+            .spawn(move || sources.map(|source| error(&source)))
+            .unwrap()
+            .join()
+            .unwrap();
+        for error in errors {
+            assert_eq!(error.as_deref(), Some("stack overflow"));
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! The `json` module: `json.encode(value, opts)` and `json.decode(text)`.
+//!
+//! Tables with the keys `0..n` become arrays, like everywhere else our lists
+//! start at 0. Other tables become objects, with their keys as strings and
+//! always sorted. `nil` is `null`, and since a table cannot hold `nil`, a
+//! `null` inside an array or object disappears when decoding.
+
+use serde_json::{Map, Number, Value as Json};
+
+use crate::Value;
+use crate::fraction::Fraction;
+use crate::table::{Table, TableKey};
+
+/// How fractions are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FractionRepr {
+    /// `"3/4"`
+    #[default]
+    String,
+    /// `{"denominator": 4, "numerator": 3}`, which [`decode`] reads back as a
+    /// fraction.
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncodeOptions {
+    pub pretty: bool,
+    pub fractions: FractionRepr,
+}
+
+impl EncodeOptions {
+    /// Options from a script: a table like the result of
+    /// `json.decode([[{"pretty": true, "fractions": "object"}]])`, or as
+    /// there is no table syntax yet, the same as words: `[[pretty
+    /// fractions=object]]`.
+    pub fn from_value(value: &Value) -> Result<Self, String> {
+        let mut opts = Self::default();
+        let mut fractions = None;
+        match value {
+            Value::Nil => {}
+            Value::Table(table) => {
+                opts.pretty = table.get(&"pretty".into()) == Value::Bool(true);
+                if let Value::String(repr) = table.get(&"fractions".into()) {
+                    fractions = Some(repr);
+                }
+            }
+            Value::String(words) => {
+                for word in words.split_whitespace() {
+                    match word.split_once('=') {
+                        None if word == "pretty" => opts.pretty = true,
+                        Some(("fractions", repr)) => fractions = Some(repr.to_owned()),
+                        _ => return Err(format!("unknown option `{word}`")),
+                    }
+                }
+            }
+            value => return Err(format!("options must be a table or a string, got {value}")),
+        }
+        opts.fractions = match fractions.as_deref() {
+            None | Some("string") => FractionRepr::String,
+            Some("object") => FractionRepr::Object,
+            Some(repr) => return Err(format!("fractions must be string or object, got {repr}")),
+        };
+        Ok(opts)
+    }
+}
+
+pub fn encode(value: &Value, opts: EncodeOptions) -> Result<String, String> {
+    let json = to_json(value, opts, &mut vec![])?;
+    let text = if opts.pretty {
+        serde_json::to_string_pretty(&json)
+    } else {
+        serde_json::to_string(&json)
+    };
+    Ok(text.expect("a serde_json::Value always serializes"))
+}
+
+pub fn decode(text: &str) -> Result<Value, String> {
+    let json: Json = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    from_json(json)
+}
+
+/// `seen` holds the tables we are inside of, to catch cycles.
+fn to_json(value: &Value, opts: EncodeOptions, seen: &mut Vec<*const ()>) -> Result<Json, String> {
+    Ok(match value {
+        Value::Nil => Json::Null,
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Number(n) => Json::from(*n),
+        Value::Fraction(f) if f.denominator() == 1 => Json::from(f.numerator()),
+        Value::Fraction(f) => match opts.fractions {
+            FractionRepr::String => Json::String(f.to_string()),
+            FractionRepr::Object => {
+                let mut object = Map::new();
+                object.insert("numerator".to_owned(), Json::from(f.numerator()));
+                object.insert("denominator".to_owned(), Json::from(f.denominator()));
+                Json::Object(object)
+            }
+        },
+        Value::String(s) => Json::String(s.clone()),
+        Value::Table(table) => {
+            if seen.contains(&table.id()) {
+                return Err("cannot encode a table that contains itself".to_owned());
+            }
+            seen.push(table.id());
+            let entries = table.entries();
+            let is_array = !entries.is_empty()
+                && entries
+                    .iter()
+                    .enumerate()
+                    .all(|(i, (key, _))| *key == TableKey::Number(i as i64));
+            let json = if is_array {
+                let items = entries.iter().map(|(_, v)| to_json(v, opts, seen));
+                Json::Array(items.collect::<Result<_, _>>()?)
+            } else {
+                let mut object = Map::new();
+                for (key, v) in &entries {
+                    object.insert(key.to_value().to_string(), to_json(v, opts, seen)?);
+                }
+                Json::Object(object)
+            };
+            seen.pop();
+            json
+        }
+        Value::Closure { .. } | Value::Builtin(_) => {
+            return Err("cannot encode a function".to_owned());
+        }
+    })
+}
+
+fn from_json(json: Json) -> Result<Value, String> {
+    Ok(match json {
+        Json::Null => Value::Nil,
+        Json::Bool(b) => Value::Bool(b),
+        Json::Number(n) => number(&n)?,
+        Json::String(s) => Value::String(s),
+        Json::Array(items) => {
+            let table = Table::new();
+            for (i, item) in items.into_iter().enumerate() {
+                table.set(TableKey::Number(i as i64), from_json(item)?);
+            }
+            Value::Table(table)
+        }
+        Json::Object(object) => {
+            if let Some(f) = fraction_object(&object) {
+                return Ok(Value::Fraction(f));
+            }
+            let table = Table::new();
+            for (key, item) in object {
+                table.set(TableKey::String(key), from_json(item)?);
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+fn fraction_object(object: &Map<String, Json>) -> Option<Fraction> {
+    if object.len() != 2 {
+        return None;
+    }
+    let numerator = object.get("numerator")?.as_i64()?;
+    let denominator = object.get("denominator")?.as_i64().filter(|&d| d != 0)?;
+    Some(Fraction::new(numerator, denominator))
+}
+
+/// Integers stay integers. Decimals become the fraction they spell, so
+/// `0.75` is `3/4`.
+fn number(n: &Number) -> Result<Value, String> {
+    if let Some(i) = n.as_i64() {
+        return Ok(Value::Number(i));
+    }
+    let x = n.as_f64().unwrap();
+    let mut denominator: i64 = 1;
+    while denominator <= 1_000_000_000_000 {
+        let numerator = x * denominator as f64;
+        if numerator.fract() == 0.0 && numerator.abs() < i64::MAX as f64 {
+            return Ok(match Fraction::new(numerator as i64, denominator) {
+                f if f.denominator() == 1 => Value::Number(f.numerator()),
+                f => Value::Fraction(f),
+            });
+        }
+        denominator *= 10;
+    }
+    Err(format!("cannot represent {n} as a number or fraction"))
+}
+
+impl serde::Serialize for Value {
+    /// Like `json.encode` with the default options.
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        to_json(self, EncodeOptions::default(), &mut vec![])
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Value {
+    /// Like `json.decode`.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        from_json(Json::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(entries: &[(&str, Value)]) -> Value {
+        let table = Table::new();
+        for (key, value) in entries {
+            table.set((*key).into(), value.clone());
+        }
+        Value::Table(table)
+    }
+
+    fn array(items: &[Value]) -> Value {
+        let table = Table::new();
+        for (i, item) in items.iter().enumerate() {
+            table.set(TableKey::Number(i as i64), item.clone());
+        }
+        Value::Table(table)
+    }
+
+    #[test]
+    fn encode_values() {
+        let value = object(&[
+            ("name", Value::String("Lobster".to_owned())),
+            ("legs", Value::Number(10)),
+            ("share", Value::Fraction(Fraction::new(3, 4))),
+            ("claws", array(&[Value::Bool(true), Value::Bool(false)])),
+            ("whole", Value::Fraction(Fraction::new(4, 2))),
+        ]);
+        assert_eq!(
+            encode(&value, EncodeOptions::default()).unwrap(),
+            r#"{"claws":[true,false],"legs":10,"name":"Lobster","share":"3/4","whole":2}"#
+        );
+        let opts = EncodeOptions {
+            pretty: true,
+            fractions: FractionRepr::Object,
+        };
+        assert_eq!(
+            encode(
+                &object(&[("share", Value::Fraction(Fraction::new(3, 4)))]),
+                opts
+            )
+            .unwrap(),
+            "{\n  \"share\": {\n    \"denominator\": 4,\n    \"numerator\": 3\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn keys_are_sorted_as_strings() {
+        let table = Table::new();
+        table.set(TableKey::Number(2), Value::Bool(true));
+        table.set(TableKey::Number(10), Value::Bool(false));
+        table.set(TableKey::Bool(true), Value::Number(1));
+        table.set("a".into(), Value::Number(0));
+        assert_eq!(
+            encode(&Value::Table(table), EncodeOptions::default()).unwrap(),
+            r#"{"10":false,"2":true,"a":0,"true":1}"#
+        );
+    }
+
+    #[test]
+    fn arrays_start_at_zero() {
+        let table = Table::new();
+        table.set(TableKey::Number(1), Value::Number(1));
+        table.set(TableKey::Number(2), Value::Number(2));
+        assert_eq!(
+            encode(&Value::Table(table), EncodeOptions::default()).unwrap(),
+            r#"{"1":1,"2":2}"#
+        );
+        let decoded = decode("[5, 6]").unwrap();
+        let Value::Table(table) = &decoded else {
+            panic!("{decoded:?}");
+        };
+        assert_eq!(table.get(&TableKey::Number(0)), Value::Number(5));
+        assert_eq!(table.get(&TableKey::Number(1)), Value::Number(6));
+    }
+
+    #[test]
+    fn decode_values() {
+        assert_eq!(decode("null").unwrap(), Value::Nil);
+        assert_eq!(
+            decode("0.75").unwrap(),
+            Value::Fraction(Fraction::new(3, 4))
+        );
+        assert_eq!(
+            decode("-2.5").unwrap(),
+            Value::Fraction(Fraction::new(-5, 2))
+        );
+        assert_eq!(decode("3.0").unwrap(), Value::Number(3));
+        assert_eq!(
+            decode(r#"{"numerator": 6, "denominator": 8}"#).unwrap(),
+            Value::Fraction(Fraction::new(3, 4))
+        );
+        assert_eq!(decode(r#""3/4""#).unwrap(), Value::String("3/4".to_owned()));
+        assert!(decode("[1,").is_err());
+        assert!(decode("1e300").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let text =
+            r#"{"a":[1,"two",{"three":"3/4"}],"b":{"denominator":4,"numerator":3},"c":true}"#;
+        let opts = EncodeOptions {
+            pretty: false,
+            fractions: FractionRepr::Object,
+        };
+        assert_eq!(encode(&decode(text).unwrap(), opts).unwrap(), text);
+    }
+
+    #[test]
+    fn cycles_and_functions_are_errors() {
+        let table = Table::new();
+        table.set("me".into(), Value::Table(table.clone()));
+        assert_eq!(
+            encode(&Value::Table(table), EncodeOptions::default()),
+            Err("cannot encode a table that contains itself".to_owned())
+        );
+        assert_eq!(
+            encode(&Value::Builtin("print"), EncodeOptions::default()),
+            Err("cannot encode a function".to_owned())
+        );
+        // the same table twice is fine
+        let inner = array(&[Value::Number(1)]);
+        let outer = array(&[inner.clone(), inner]);
+        assert_eq!(
+            encode(&outer, EncodeOptions::default()).unwrap(),
+            "[[1],[1]]"
+        );
+    }
+
+    #[test]
+    fn options_from_script() {
+        let opts = EncodeOptions::from_value(&Value::String("pretty fractions=object".to_owned()));
+        assert_eq!(
+            opts,
+            Ok(EncodeOptions {
+                pretty: true,
+                fractions: FractionRepr::Object,
+            })
+        );
+        let table = decode(r#"{"fractions": "string"}"#).unwrap();
+        assert_eq!(
+            EncodeOptions::from_value(&table),
+            Ok(EncodeOptions::default())
+        );
+        assert!(EncodeOptions::from_value(&Value::String("ugly".to_owned())).is_err());
+    }
+
+    #[test]
+    fn serde_for_rust_hosts() {
+        let value: Value = serde_json::from_str(r#"{"x": [1, 0.5]}"#).unwrap();
+        assert_eq!(serde_json::to_string(&value).unwrap(), r#"{"x":[1,"1/2"]}"#);
+    }
+}
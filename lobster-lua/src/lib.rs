@@ -0,0 +1,888 @@
+use std::collections::HashMap;
+// decisions:
+// our lua starts at 0
+
+use crate::parser::{Stmt, StmtKind};
+
+pub mod compile;
+pub mod coverage;
+pub mod debugger;
+#[cfg(test)]
+mod e2e;
+pub mod formatter;
+pub mod fraction;
+pub mod fuzz;
+pub mod json;
+pub mod linter;
+pub mod optimizer;
+pub mod lsp;
+pub mod numeral;
+pub mod parser;
+pub mod profiler;
+pub mod repl;
+pub mod stdlib;
+pub mod table;
+pub mod tokenizer;
+pub mod transpile;
+
+use fraction::Fraction;
+use numeral::NumberFormat;
+use table::{Table, TableKey};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Nil,
+    Number(i64),
+    Fraction(Fraction),
+    String(String),
+    Bool(bool),
+    Closure {
+        params: Vec<String>,
+        body: Vec<Stmt>,
+    },
+    /// A function implemented in Rust, see [`stdlib`].
+    Builtin(&'static str),
+    Table(Table),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::Fraction(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Closure { .. } => write!(f, "function"),
+            Value::Builtin(_) => write!(f, "function: builtin"),
+            Value::Table(t) => write!(f, "{t}"),
+        }
+    }
+}
+
+/// An error the script ran into, like arithmetic on a string or calling
+/// something that is not a function. It ends the program, unless the REPL
+/// or the debugger ran the code that failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<&'static str> for RuntimeError {
+    fn from(message: &'static str) -> Self {
+        Self::new(message)
+    }
+}
+
+impl Value {
+    /// The kind of value, as error messages call it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Number(_) => "number",
+            Value::Fraction(_) => "fraction",
+            Value::String(_) => "string",
+            Value::Bool(_) => "boolean",
+            Value::Closure { .. } | Value::Builtin(_) => "function",
+            Value::Table(_) => "table",
+        }
+    }
+
+    fn as_fraction(&self) -> Result<Fraction, RuntimeError> {
+        match self {
+            Self::Number(n) => Ok(Fraction::new(*n, 1)),
+            Self::Fraction(f) => Ok(*f),
+            v => Err(arithmetic_error(v)),
+        }
+    }
+
+    fn add(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => {
+                Ok(Self::Number(l.checked_add(r).ok_or("attempt to add with overflow")?))
+            }
+            (lv, rv) => Ok(Value::Fraction(lv.as_fraction()?.checked_add(rv.as_fraction()?)?)),
+        }
+    }
+
+    fn sub(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => {
+                Ok(Self::Number(l.checked_sub(r).ok_or("attempt to subtract with overflow")?))
+            }
+            (lv, rv) => Ok(Value::Fraction(lv.as_fraction()?.checked_sub(rv.as_fraction()?)?)),
+        }
+    }
+    fn div(self, rhs: Self) -> Result<Value, RuntimeError> {
+        Ok(Value::Fraction(self.as_fraction()?.checked_div(rhs.as_fraction()?)?))
+    }
+    fn mul(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => {
+                Ok(Self::Number(l.checked_mul(r).ok_or("attempt to multiply with overflow")?))
+            }
+            (lv, rv) => Ok(Value::Fraction(lv.as_fraction()?.checked_mul(rv.as_fraction()?)?)),
+        }
+    }
+    fn exp(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => {
+                if r < 0 {
+                    return Err("attempt to raise to a negative power".into());
+                }
+                // past `u32::MAX` only 0, 1 and -1 stay in range
+                let r = u32::try_from(r).unwrap_or(u32::MAX - u32::from(r % 2 == 0));
+                let n = l.checked_pow(r).ok_or("attempt to multiply with overflow")?;
+                Ok(Self::Number(n))
+            }
+            (l, r) => Err(arithmetic_error(blame(&l, &r, is_integer))),
+        }
+    }
+    fn r#mod(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(_), Value::Number(0)) => {
+                Err("attempt to calculate the remainder with a divisor of zero".into())
+            }
+            // `i64::MIN % -1` overflows, but the remainder is 0 anyway
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l.checked_rem(r).unwrap_or(0))),
+            (l, r) => Err(arithmetic_error(blame(&l, &r, is_integer))),
+        }
+    }
+    fn and(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Bool(l), Value::Bool(r)) => Ok(Self::Bool(l && r)),
+            (l, r) => Err(logical_error(&l, &r)),
+        }
+    }
+    fn or(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Bool(l), Value::Bool(r)) => Ok(Self::Bool(l || r)),
+            (l, r) => Err(logical_error(&l, &r)),
+        }
+    }
+    fn lshift(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => {
+                Ok(Self::Number(((l as u64) << shift_amount(r)?) as i64))
+            }
+            (l, r) => Err(bitwise_error(&l, &r)),
+        }
+    }
+    fn rshift(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l >> shift_amount(r)?)),
+            (l, r) => Err(bitwise_error(&l, &r)),
+        }
+    }
+
+    fn concat(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::String(l), Value::String(r)) => Ok(Self::String(l + &r)),
+            (l, r) => {
+                let bad = blame(&l, &r, |v| matches!(v, Value::String(_)));
+                Err(RuntimeError::new(format!(
+                    "attempt to concatenate a {} value",
+                    bad.type_name()
+                )))
+            }
+        }
+    }
+
+    fn gt(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l > r)),
+            (l, r) => Err(compare_error(&l, &r)),
+        }
+    }
+
+    fn lt(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l < r)),
+            (l, r) => Err(compare_error(&l, &r)),
+        }
+    }
+
+    fn geq(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l >= r)),
+            (l, r) => Err(compare_error(&l, &r)),
+        }
+    }
+
+    fn leq(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l <= r)),
+            (l, r) => Err(compare_error(&l, &r)),
+        }
+    }
+
+    fn bitor(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l | r)),
+            (l, r) => Err(bitwise_error(&l, &r)),
+        }
+    }
+
+    fn bitand(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l & r)),
+            (l, r) => Err(bitwise_error(&l, &r)),
+        }
+    }
+
+    fn bitxor(self, rhs: Self) -> Result<Value, RuntimeError> {
+        match (self, rhs) {
+            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l ^ r)),
+            (l, r) => Err(bitwise_error(&l, &r)),
+        }
+    }
+}
+
+fn is_integer(v: &Value) -> bool {
+    matches!(v, Value::Number(_))
+}
+
+/// The operand an error is about: the left one, unless only the right one
+/// is not `ok`.
+fn blame<'a>(l: &'a Value, r: &'a Value, ok: impl Fn(&Value) -> bool) -> &'a Value {
+    if ok(l) { r } else { l }
+}
+
+fn arithmetic_error(bad: &Value) -> RuntimeError {
+    RuntimeError::new(format!(
+        "attempt to perform arithmetic on a {} value",
+        bad.type_name()
+    ))
+}
+
+fn logical_error(l: &Value, r: &Value) -> RuntimeError {
+    let bad = blame(l, r, |v| matches!(v, Value::Bool(_)));
+    RuntimeError::new(format!(
+        "attempt to perform logical operation on a {} value",
+        bad.type_name()
+    ))
+}
+
+fn bitwise_error(l: &Value, r: &Value) -> RuntimeError {
+    RuntimeError::new(format!(
+        "attempt to perform bitwise operation on a {} value",
+        blame(l, r, is_integer).type_name()
+    ))
+}
+
+/// Like Lua: `attempt to compare two table values`, or `attempt to compare
+/// number with nil`.
+fn compare_error(l: &Value, r: &Value) -> RuntimeError {
+    let (l, r) = (l.type_name(), r.type_name());
+    RuntimeError::new(if l == r {
+        format!("attempt to compare two {l} values")
+    } else {
+        format!("attempt to compare {l} with {r}")
+    })
+}
+
+/// A shift by `r` bits, which has to be one of the 64 bits of a number.
+fn shift_amount(r: i64) -> Result<u32, &'static str> {
+    u32::try_from(r)
+        .ok()
+        .filter(|&r| r < i64::BITS)
+        .ok_or("attempt to shift with overflow")
+}
+
+/// Gets a look at every statement right before it runs. This is how the
+/// debugger plugs into the interpreter.
+pub trait Hook {
+    fn before_stmt(&mut self, stmt: &Stmt, context: &mut Context);
+}
+
+/// A function invocation on the call stack.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// The name the function was called by, or `main chunk`.
+    pub function: String,
+    /// Line of the statement running in this frame, 0 before the first.
+    pub line: usize,
+    /// Index of the first of `Context::locals` that belongs to this frame.
+    pub scope_start: usize,
+    /// Counts up with every call, to tell two calls of the same function
+    /// apart. The main chunk is 0.
+    pub id: usize,
+}
+
+/// A hook installed from a script with `debug.sethook`.
+struct ScriptHook {
+    function: Value,
+    mask: String,
+    /// The (depth, line) of the last line event, so a line with several
+    /// statements only fires once.
+    last_line: Option<(usize, usize)>,
+    running: bool,
+}
+
+pub struct Context {
+    test_stdout: Option<String>,
+    number_format: NumberFormat,
+    globals: HashMap<String, Value>,
+    locals: Vec<HashMap<String, Value>>,
+    frames: Vec<Frame>,
+    chunk_name: String,
+    line_starts: Vec<usize>,
+    hooks: Vec<Box<dyn Hook>>,
+    calls: usize,
+    script_hook: Option<ScriptHook>,
+    halted: bool,
+    /// How many blocks and expressions are being run, see [`MAX_NESTING`].
+    nesting: usize,
+    /// Statements and loop iterations left before the program is halted.
+    steps_left: Option<usize>,
+}
+
+/// How deeply functions can call each other before it is a stack overflow.
+pub const MAX_CALL_DEPTH: usize = 200;
+
+/// How deeply blocks and expressions can nest while running, counting those
+/// in the functions being called, before it is a stack overflow. The
+/// interpreter recurses for each one, and would run out of native stack long
+/// before [`MAX_CALL_DEPTH`] calls of a deeply nested function.
+pub const MAX_NESTING: usize = 1000;
+
+impl Context {
+    pub fn new() -> Self {
+        Self {
+            test_stdout: None,
+            number_format: NumberFormat::default(),
+            globals: stdlib::globals(),
+            locals: vec![HashMap::new()],
+            frames: vec![Frame {
+                function: "main chunk".to_owned(),
+                line: 0,
+                scope_start: 0,
+                id: 0,
+            }],
+            chunk_name: "?".to_owned(),
+            line_starts: vec![],
+            hooks: vec![],
+            calls: 0,
+            script_hook: None,
+            halted: false,
+            nesting: 0,
+            steps_left: None,
+        }
+    }
+
+    /// Tells the context which source the statements come from, so it can
+    /// report line numbers.
+    pub fn set_source(&mut self, chunk_name: &str, source: &str) {
+        self.chunk_name = chunk_name.to_owned();
+        self.line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+    }
+
+    /// 1-based line of a byte offset, or 0 without a source.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+
+    pub fn chunk_name(&self) -> &str {
+        &self.chunk_name
+    }
+
+    /// How `print` and `tostring` write numbers, unless told otherwise.
+    pub fn set_number_format(&mut self, format: NumberFormat) {
+        self.number_format = format;
+    }
+
+    pub fn number_format(&self) -> NumberFormat {
+        self.number_format
+    }
+
+    /// Adds a hook. Hooks see each statement in the order they were added.
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
+    }
+
+    /// Stops the program at the next statement.
+    pub fn halt(&mut self) {
+        self.halted = true;
+    }
+
+    /// Halts the program once it has run `steps` statements and loop
+    /// iterations, so that not even `while true do end` runs forever.
+    pub fn set_step_limit(&mut self, steps: usize) {
+        self.steps_left = Some(steps);
+    }
+
+    /// Whether the program ran out of steps, see [`Context::set_step_limit`].
+    pub fn out_of_steps(&self) -> bool {
+        self.steps_left == Some(0)
+    }
+
+    /// Runs `f` a level deeper, see [`MAX_NESTING`].
+    fn nested<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        if self.nesting == MAX_NESTING {
+            return Err("stack overflow".into());
+        }
+        self.nesting += 1;
+        let value = f(self);
+        self.nesting -= 1;
+        value
+    }
+
+    /// Counts a step, and halts if there are none left.
+    fn step(&mut self) -> bool {
+        match &mut self.steps_left {
+            Some(0) => self.halted = true,
+            Some(steps) => *steps -= 1,
+            None => {}
+        }
+        self.halted
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn scopes(&self) -> &[HashMap<String, Value>] {
+        &self.locals
+    }
+
+    pub fn globals(&self) -> &HashMap<String, Value> {
+        &self.globals
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        for scope in self.locals.iter().rev() {
+            if let Some(val) = scope.get(name) {
+                return Some(val.clone());
+            }
+        }
+        self.globals.get(name).cloned()
+    }
+
+    pub fn insert_global(&mut self, name: String, value: Value) {
+        self.globals.insert(name, value);
+    }
+
+    /// Assigns to the innermost local called `name`, or to the global if no
+    /// local is in scope.
+    pub fn assign(&mut self, name: &str, value: Value) {
+        for scope in self.locals.iter_mut().rev() {
+            if let Some(slot) = scope.get_mut(name) {
+                *slot = value;
+                return;
+            }
+        }
+        self.insert_global(name.to_owned(), value);
+    }
+
+    pub fn insert_local(&mut self, name: String, value: Value) {
+        self.locals.last_mut().unwrap().insert(name, value);
+    }
+
+    pub fn enter_scope(&mut self) {
+        self.locals.push(HashMap::new());
+    }
+
+    pub fn leave_scope(&mut self) {
+        self.locals.pop();
+    }
+
+    /// Runs `f`. On an error the calls that were under way are dropped, so
+    /// the context can be used again.
+    pub(crate) fn catch_error<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let (frames, locals, nesting) = (self.frames.len(), self.locals.len(), self.nesting);
+        let result = f(self);
+        if result.is_err() {
+            self.frames.truncate(frames);
+            self.locals.truncate(locals);
+            self.nesting = nesting;
+        }
+        result
+    }
+
+    fn write_stdout(&mut self, s: &str) {
+        if let Some(test_stdout) = &mut self.test_stdout {
+            test_stdout.push_str(s);
+        } else {
+            print!("{s}");
+        }
+    }
+
+    /// `stack traceback:` followed by one line per frame, innermost first.
+    pub fn traceback(&self) -> String {
+        let mut res = "stack traceback:".to_owned();
+        for frame in self.frames.iter().rev() {
+            let place = if frame.scope_start == 0 {
+                "in main chunk".to_owned()
+            } else {
+                format!("in function '{}'", frame.function)
+            };
+            res.push_str(&format!("\n\t{}:{}: {place}", self.chunk_name, frame.line));
+        }
+        res
+    }
+
+    /// Calls the `debug.sethook` function for `event`, unless it is already
+    /// running.
+    fn fire_script_hook(&mut self, event: &str, line: Option<usize>) -> Result<(), RuntimeError> {
+        let depth = self.frames.len();
+        let Some(hook) = &mut self.script_hook else {
+            return Ok(());
+        };
+        if hook.running {
+            return Ok(());
+        }
+        let wanted = match event {
+            "line" => {
+                let here = (depth, line.unwrap_or(0));
+                let new_line = hook.last_line != Some(here);
+                hook.last_line = Some(here);
+                new_line && hook.mask.contains('l')
+            }
+            "call" => hook.mask.contains('c'),
+            _ => hook.mask.contains('r'),
+        };
+        if !wanted {
+            return Ok(());
+        }
+        hook.running = true;
+        let function = hook.function.clone();
+        let line = line.map_or(Value::Nil, |l| Value::Number(l as i64));
+        let args = vec![Value::String(event.to_owned()), line];
+        let result = call(function, "hook", args, self);
+        // the hook may have replaced or removed itself
+        if let Some(hook) = &mut self.script_hook {
+            hook.running = false;
+        }
+        result.map(|_| ())
+    }
+
+    /// `debug.sethook(f, mask)`, or without a function removes the hook.
+    pub(crate) fn set_script_hook(&mut self, function: Value, mask: String) {
+        self.script_hook = match function {
+            Value::Nil => None,
+            function => Some(ScriptHook {
+                function,
+                mask,
+                last_line: None,
+                running: false,
+            }),
+        };
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How a block finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Flow {
+    Normal,
+    Break,
+    Return(Value),
+    /// The program was stopped, e.g. from the debugger.
+    Halt,
+}
+
+fn run_scoped_block(stmts: &[parser::Stmt], context: &mut Context) -> Result<Flow, RuntimeError> {
+    context.enter_scope();
+    let flow = run_block(stmts, context)?;
+    context.leave_scope();
+    Ok(flow)
+}
+
+/// Runs the statements until they are done or one of them fails. After an
+/// error the context is left as it was at the failing statement, with the
+/// calls under way still on its stack.
+pub fn run_block(stmts: &[parser::Stmt], context: &mut Context) -> Result<Flow, RuntimeError> {
+    context.nested(|context| run_stmts(stmts, context))
+}
+
+fn run_stmts(stmts: &[parser::Stmt], context: &mut Context) -> Result<Flow, RuntimeError> {
+    for stmt in stmts {
+        if context.step() {
+            return Ok(Flow::Halt);
+        }
+        if before_stmt(stmt, context)? {
+            return Ok(Flow::Halt);
+        }
+        // dbg!(stmt);
+        let flow = match &stmt.kind {
+            StmtKind::Assignment { variable, value } => {
+                let res = eval(value, context)?;
+                context.assign(variable, res);
+                Flow::Normal
+            }
+            StmtKind::Local { variable, value } => {
+                let res = match value {
+                    Some(value) => eval(value, context)?,
+                    None => Value::Nil,
+                };
+                context.insert_local(variable.name.clone(), res);
+                Flow::Normal
+            }
+            StmtKind::Error => return Err("syntax error".into()),
+            StmtKind::If { cond, then, r#else } => {
+                if eval(cond, context)? == Value::Bool(true) {
+                    run_scoped_block(then, context)?
+                } else {
+                    run_scoped_block(r#else, context)?
+                }
+            }
+            StmtKind::While { cond, body } => run_while(cond, body, context)?,
+            StmtKind::Break => Flow::Break,
+            StmtKind::Return(exprs) => Flow::Return(eval_return(exprs, context)?),
+            StmtKind::DoEnd { body } => run_scoped_block(body, context)?,
+            StmtKind::FunctionCall {
+                function_name,
+                args,
+            } => {
+                eval_call(function_name, args, context)?;
+                Flow::Normal
+            }
+        };
+        if flow != Flow::Normal {
+            return Ok(flow);
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+/// Marks the line `stmt` is on and runs the hooks, returning whether one of
+/// them halted the program. Kept out of [`run_stmts`], which is on the native
+/// stack once per nested block.
+fn before_stmt(stmt: &parser::Stmt, context: &mut Context) -> Result<bool, RuntimeError> {
+    let line = context.line_of(stmt.span.start);
+    context.frames.last_mut().unwrap().line = line;
+    if !context.hooks.is_empty() {
+        let mut hooks = std::mem::take(&mut context.hooks);
+        for hook in &mut hooks {
+            hook.before_stmt(stmt, context);
+        }
+        context.hooks = hooks;
+        if context.halted {
+            return Ok(true);
+        }
+    }
+    if context.script_hook.is_some() {
+        context.fire_script_hook("line", Some(line))?;
+    }
+    Ok(false)
+}
+
+fn run_while(
+    cond: &parser::Expr,
+    body: &[parser::Stmt],
+    context: &mut Context,
+) -> Result<Flow, RuntimeError> {
+    while eval(cond, context)? == Value::Bool(true) {
+        if context.step() {
+            return Ok(Flow::Halt);
+        }
+        match run_scoped_block(body, context)? {
+            Flow::Normal => {}
+            Flow::Break => break,
+            other => return Ok(other),
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+/// Evaluates all of `exprs`, returning the first.
+fn eval_return(exprs: &[parser::Expr], context: &mut Context) -> Result<Value, RuntimeError> {
+    let values = exprs
+        .iter()
+        .map(|e| eval(e, context))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(values.into_iter().next().unwrap_or(Value::Nil))
+}
+
+/// The name a function is called by, for stack traces.
+pub(crate) fn callee_name(expr: &parser::Expr) -> String {
+    match expr {
+        parser::Expr::Var(name) => name.to_string(),
+        parser::Expr::Index { table, key } => match key.as_ref() {
+            parser::Expr::String(key) => format!("{}.{key}", callee_name(table)),
+            _ => format!("{}[?]", callee_name(table)),
+        },
+        _ => "?".to_owned(),
+    }
+}
+
+fn eval_call(
+    function_name: &parser::Expr,
+    args: &[parser::Expr],
+    context: &mut Context,
+) -> Result<Value, RuntimeError> {
+    let function = eval(function_name, context)?;
+    let evaluated_args = args
+        .iter()
+        .map(|arg| eval(arg, context))
+        .collect::<Result<Vec<_>, _>>()?;
+    let name = callee_name(function_name);
+    call(function, &name, evaluated_args, context)
+}
+
+pub fn call(
+    function: Value,
+    name: &str,
+    args: Vec<Value>,
+    context: &mut Context,
+) -> Result<Value, RuntimeError> {
+    match function {
+        Value::Closure { params, body } => {
+            if params.len() != args.len() {
+                return Err("calling with wrong number of parameters".into());
+            }
+            if context.frames.len() > MAX_CALL_DEPTH {
+                return Err("stack overflow".into());
+            }
+            context.calls += 1;
+            context.frames.push(Frame {
+                function: name.to_owned(),
+                line: 0,
+                scope_start: context.locals.len(),
+                id: context.calls,
+            });
+            if context.script_hook.is_some() {
+                context.fire_script_hook("call", None)?;
+            }
+            context.enter_scope();
+            for (param, arg) in params.iter().zip(args) {
+                context.insert_local(param.clone(), arg);
+            }
+            let flow = run_block(&body, context)?;
+            context.leave_scope();
+            if context.script_hook.is_some() {
+                context.fire_script_hook("return", None)?;
+            }
+            context.frames.pop();
+            Ok(match flow {
+                Flow::Return(value) => value,
+                _ => Value::Nil,
+            })
+        }
+        Value::Builtin(builtin) => stdlib::call_builtin(builtin, args, context),
+        x => Err(RuntimeError::new(format!(
+            "attempt to call a {} value ({name})",
+            x.type_name()
+        ))),
+    }
+}
+
+fn eval(expr: &parser::Expr, context: &mut Context) -> Result<Value, RuntimeError> {
+    context.nested(|context| eval_nested(expr, context))
+}
+
+fn eval_nested(expr: &parser::Expr, context: &mut Context) -> Result<Value, RuntimeError> {
+    Ok(match expr {
+        parser::Expr::Nil => Value::Nil,
+        parser::Expr::Numeral(i) => Value::Number(*i),
+        parser::Expr::Fraction(f) => Value::Fraction(*f),
+        parser::Expr::Boolean(b) => Value::Bool(*b),
+        parser::Expr::String(s) => Value::String(s.clone()),
+        parser::Expr::BinOp { op, lhs, rhs } => eval_binop(*op, lhs, rhs, context)?,
+        // like in Lua, a global that was never assigned is nil
+        parser::Expr::Var(ident) => context.get(ident).unwrap_or(Value::Nil),
+        parser::Expr::Index { table, key } => eval_index(table, key, context)?,
+        parser::Expr::FunctionCall {
+            function_name,
+            args,
+        } => eval_call(function_name, args, context)?,
+        parser::Expr::Error => return Err("syntax error".into()),
+        parser::Expr::FunctionDef { arguments, body, .. } => Value::Closure {
+            params: arguments.iter().map(|a| a.name.clone()).collect(),
+            body: body.clone(),
+        },
+    })
+}
+
+fn eval_binop(
+    op: parser::BinOp,
+    lhs: &parser::Expr,
+    rhs: &parser::Expr,
+    context: &mut Context,
+) -> Result<Value, RuntimeError> {
+    let lhs = eval(lhs, context)?;
+    if let (parser::BinOp::And, Value::Bool(false)) = (op, &lhs) {
+        return Ok(Value::Bool(false));
+    }
+    if let (parser::BinOp::Or, Value::Bool(true)) = (op, &lhs) {
+        return Ok(Value::Bool(true));
+    }
+    let rhs = eval(rhs, context)?;
+    binop(op, lhs, rhs)
+}
+
+fn eval_index(
+    table: &parser::Expr,
+    key: &parser::Expr,
+    context: &mut Context,
+) -> Result<Value, RuntimeError> {
+    let table = match eval(table, context)? {
+        Value::Table(table) => table,
+        other => {
+            return Err(RuntimeError::new(format!(
+                "attempt to index a {} value",
+                other.type_name()
+            )));
+        }
+    };
+    let key = eval(key, context)?;
+    Ok(match TableKey::from_value(&key) {
+        Some(key) => table.get(&key),
+        None => Value::Nil,
+    })
+}
+
+/// Applies `op` to two evaluated operands. `and` and `or` short-circuit in
+/// [`eval`], before getting here.
+pub(crate) fn binop(op: parser::BinOp, lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    match op {
+        parser::BinOp::Plus => lhs.add(rhs),
+        parser::BinOp::Minus => lhs.sub(rhs),
+        parser::BinOp::Mul => lhs.mul(rhs),
+        parser::BinOp::Div => lhs.div(rhs),
+        parser::BinOp::IDiv => lhs.div(rhs),
+        parser::BinOp::Exp => lhs.exp(rhs),
+        parser::BinOp::Mod => lhs.r#mod(rhs),
+        parser::BinOp::And => lhs.and(rhs),
+        parser::BinOp::Or => lhs.or(rhs),
+        parser::BinOp::LShift => lhs.lshift(rhs),
+        parser::BinOp::RShift => lhs.rshift(rhs),
+        parser::BinOp::GT => lhs.gt(rhs),
+        parser::BinOp::LT => lhs.lt(rhs),
+        parser::BinOp::GEQ => lhs.geq(rhs),
+        parser::BinOp::LEQ => lhs.leq(rhs),
+        parser::BinOp::BitOR => lhs.bitor(rhs),
+        parser::BinOp::BitAnd => lhs.bitand(rhs),
+        parser::BinOp::BitXor => lhs.bitxor(rhs),
+        parser::BinOp::Equals => Ok(Value::Bool(lhs.eq(&rhs))),
+        parser::BinOp::NotEquals => Ok(Value::Bool(!lhs.eq(&rhs))),
+        parser::BinOp::Concat => lhs.concat(rhs),
+    }
+}
@@ -0,0 +1,541 @@
+//! Static checks over the AST, for `lobster-lua check`.
+//!
+//! Scoping follows the interpreter: `local` and function parameters live
+//! until the end of the enclosing block, every other assignment writes a
+//! global.
+
+use std::collections::HashSet;
+
+use crate::parser::{Expr, LobsterParser, Name, Stmt, StmtKind};
+use crate::stdlib::GLOBAL_NAMES;
+use crate::tokenizer::{RomanMode, Span, Token, Tokenizer, canonical_roman, line_col};
+
+/// Canonical Roman numerals that are easy to mistake for a word.
+const ROMAN_WORDS: &[&str] = &["CD", "CV", "DC", "DIV", "CLI", "MC", "MD", "MIX", "MCM"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+pub fn check_source(source: &str) -> Vec<Warning> {
+    check_source_with_mode(source, RomanMode::default())
+}
+
+/// Like [`check_source`], reading Roman numerals in `roman_mode`.
+pub fn check_source_with_mode(source: &str, roman_mode: RomanMode) -> Vec<Warning> {
+    let mut warnings = roman_pitfalls(source, roman_mode);
+
+    let (ast, errors) = LobsterParser::with_roman_mode(source, roman_mode).parse_recovering();
+    for e in errors {
+        warnings.push(Warning {
+            code: "syntax-error",
+            message: e.message,
+            span: e.span,
+        });
+    }
+    let mut linter = Linter {
+        source,
+        scopes: vec![vec![]],
+        global_defs: HashSet::new(),
+        global_reads: vec![],
+        warnings: vec![],
+    };
+    linter.block(&ast);
+    linter.pop_scope();
+
+    for name in linter.global_reads {
+        if !linter.global_defs.contains(&name.name) && !GLOBAL_NAMES.contains(&name.name.as_str()) {
+            linter.warnings.push(Warning {
+                code: "undefined-global",
+                message: format!("`{name}` is read but never assigned"),
+                span: name.span,
+            });
+        }
+    }
+    warnings.append(&mut linter.warnings);
+    warnings.sort_by_key(|w| (w.span.start, w.span.end));
+    warnings
+}
+
+/// Words made only of `IVXLCDM` are number literals, not identifiers. In
+/// strict mode, non-canonical ones are syntax errors instead.
+fn roman_pitfalls(source: &str, roman_mode: RomanMode) -> Vec<Warning> {
+    let mut warnings = vec![];
+    let mut tokenizer = Tokenizer::new(source);
+    tokenizer.set_roman_mode(roman_mode);
+    for tok in tokenizer.map_while(Result::ok) {
+        let Token::NumberLiteral(n) = tok.value else {
+            continue;
+        };
+        let span = tok.span;
+        let lexeme = &source[span.start..span.end];
+        if !lexeme.starts_with(|c: char| c.is_alphabetic()) {
+            continue;
+        }
+        let canonical = canonical_roman(n).unwrap_or_default();
+        let message = if canonical != lexeme {
+            format!(
+                "`{lexeme}` is not an identifier, it reads as the number {n} (canonically `{canonical}`)"
+            )
+        } else if ROMAN_WORDS.contains(&lexeme) {
+            format!("`{lexeme}` is not an identifier, it reads as the number {n}")
+        } else {
+            continue;
+        };
+        warnings.push(Warning {
+            code: "roman-numeral",
+            message,
+            span,
+        });
+    }
+    warnings
+}
+
+struct Local {
+    name: Name,
+    is_param: bool,
+    used: bool,
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    scopes: Vec<Vec<Local>>,
+    global_defs: HashSet<String>,
+    global_reads: Vec<Name>,
+    warnings: Vec<Warning>,
+}
+
+impl Linter<'_> {
+    fn warn(&mut self, code: &'static str, message: String, span: Span) {
+        self.warnings.push(Warning {
+            code,
+            message,
+            span,
+        });
+    }
+
+    fn declare(&mut self, name: &Name, is_param: bool) {
+        let shadowed = self
+            .scopes
+            .iter()
+            .flatten()
+            .rev()
+            .find(|local| local.name.name == name.name)
+            .map(|local| local.name.span);
+        if let Some(shadowed) = shadowed {
+            let (line, _) = line_col(self.source, shadowed.start);
+            self.warn(
+                "shadowing",
+                format!("`{name}` shadows the variable declared on line {line}"),
+                name.span,
+            );
+        }
+        self.scopes.last_mut().unwrap().push(Local {
+            name: name.clone(),
+            is_param,
+            used: false,
+        });
+    }
+
+    fn find_local(&mut self, name: &str) -> Option<&mut Local> {
+        self.scopes
+            .iter_mut()
+            .flatten()
+            .rev()
+            .find(|local| local.name.name == name)
+    }
+
+    fn read(&mut self, name: &Name) {
+        match self.find_local(name) {
+            Some(local) => local.used = true,
+            None => self.global_reads.push(name.clone()),
+        }
+    }
+
+    fn write(&mut self, name: &Name) {
+        if self.find_local(name).is_none() {
+            self.global_defs.insert(name.name.clone());
+        }
+    }
+
+    fn pop_scope(&mut self) {
+        for local in self.scopes.pop().unwrap() {
+            if local.used || local.name.starts_with('_') {
+                continue;
+            }
+            let (code, what) = if local.is_param {
+                ("unused-parameter", "parameter")
+            } else {
+                ("unused-local", "local")
+            };
+            self.warn(
+                code,
+                format!("{what} `{}` is never read", local.name),
+                local.name.span,
+            );
+        }
+    }
+
+    fn scoped_block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(vec![]);
+        self.block(stmts);
+        self.pop_scope();
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        let unreachable = stmts
+            .iter()
+            .position(terminates)
+            .and_then(|i| stmts.get(i + 1));
+        if let Some(stmt) = unreachable {
+            self.warn(
+                "unreachable-code",
+                "this statement is never executed".to_owned(),
+                stmt.span,
+            );
+        }
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Break | StmtKind::Error => {}
+            StmtKind::Return(exprs) => exprs.iter().for_each(|e| self.expr(e)),
+            StmtKind::While { cond, body } => {
+                if *cond != Expr::Boolean(true) {
+                    self.condition(cond, stmt.span);
+                }
+                self.expr(cond);
+                self.scoped_block(body);
+            }
+            StmtKind::DoEnd { body } => self.scoped_block(body),
+            StmtKind::Assignment { variable, value } => {
+                self.expr(value);
+                self.write(variable);
+            }
+            StmtKind::Local { variable, value } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                self.declare(variable, false);
+            }
+            StmtKind::FunctionCall {
+                function_name,
+                args,
+            } => {
+                self.expr(function_name);
+                args.iter().for_each(|e| self.expr(e));
+            }
+            StmtKind::If { cond, then, r#else } => {
+                self.condition(cond, stmt.span);
+                self.expr(cond);
+                self.scoped_block(then);
+                self.scoped_block(r#else);
+            }
+        }
+    }
+
+    fn condition(&mut self, cond: &Expr, span: Span) {
+        let message = match cond {
+            Expr::Boolean(b) => format!("condition is always {b}"),
+            Expr::Nil => "condition is always false".to_owned(),
+            cond if is_constant(cond) => "condition is a constant".to_owned(),
+            _ => return,
+        };
+        self.warn("constant-condition", message, span);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Nil
+            | Expr::Numeral(_)
+            | Expr::Fraction(_)
+            | Expr::Boolean(_)
+            | Expr::String(_)
+            | Expr::Error => {}
+            Expr::BinOp { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            Expr::Var(name) => self.read(name),
+            Expr::Index { table, key } => {
+                self.expr(table);
+                self.expr(key);
+            }
+            Expr::FunctionCall {
+                function_name,
+                args,
+            } => {
+                self.expr(function_name);
+                args.iter().for_each(|e| self.expr(e));
+            }
+            Expr::FunctionDef { arguments, body, .. } => {
+                self.scopes.push(vec![]);
+                for arg in arguments {
+                    self.declare(arg, true);
+                }
+                self.block(body);
+                self.pop_scope();
+            }
+        }
+    }
+}
+
+/// Whether control never reaches the statement after `stmt`.
+fn terminates(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Break | StmtKind::Return(_) => true,
+        StmtKind::DoEnd { body } => body.last().is_some_and(terminates),
+        StmtKind::If { then, r#else, .. } => {
+            then.last().is_some_and(terminates) && r#else.last().is_some_and(terminates)
+        }
+        _ => false,
+    }
+}
+
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Nil | Expr::Numeral(_) | Expr::Fraction(_) | Expr::Boolean(_) | Expr::String(_) => {
+            true
+        }
+        Expr::BinOp { lhs, rhs, .. } => is_constant(lhs) && is_constant(rhs),
+        _ => false,
+    }
+}
+
+/// `path:line:col: warning[code]: message`, followed by the first line of
+/// the span with carets under it.
+pub fn render(warning: &Warning, source: &str, path: &str) -> String {
+    let (line, col) = line_col(source, warning.span.start);
+    let line_start = source[..warning.span.start]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    let line_end = source[line_start..]
+        .find('\n')
+        .map_or(source.len(), |i| line_start + i);
+    // tabs would throw off the carets
+    let text = source[line_start..line_end].replace('\t', " ");
+    let width = source[warning.span.start..warning.span.end.clamp(warning.span.start, line_end)]
+        .chars()
+        .count()
+        .max(1);
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{path}:{line}:{col}: warning[{}]: {}\n{gutter} |\n{line} | {text}\n{gutter} | {}{}\n",
+        warning.code,
+        warning.message,
+        " ".repeat(col - 1),
+        "^".repeat(width),
+    )
+}
+
+/// All warnings as a JSON array, one object per warning.
+pub fn render_json(warnings: &[Warning], source: &str, path: &str) -> String {
+    let objects: Vec<String> = warnings
+        .iter()
+        .map(|w| {
+            let (line, column) = line_col(source, w.span.start);
+            let (end_line, end_column) = line_col(source, w.span.end);
+            format!(
+                "{{\"file\":{},\"code\":{},\"message\":{},\"line\":{line},\"column\":{column},\"end_line\":{end_line},\"end_column\":{end_column},\"start\":{},\"end\":{}}}",
+                json_string(path),
+                json_string(w.code),
+                json_string(&w.message),
+                w.span.start,
+                w.span.end,
+            )
+        })
+        .collect();
+    format!("[{}]", objects.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(source: &str) -> Vec<(&'static str, String)> {
+        check_source(source)
+            .into_iter()
+            .map(|w| (w.code, source[w.span.start..w.span.end].to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn undefined_global() {
+        assert_eq!(
+            codes("x = 1 print(x, y)"),
+            vec![("undefined-global", "y".to_owned())]
+        );
+    }
+
+    #[test]
+    fn global_assigned_later_is_defined() {
+        assert_eq!(codes("f = function() print(x) end x = 1 f()"), vec![]);
+    }
+
+    #[test]
+    fn unused_local_and_parameter() {
+        assert_eq!(
+            codes("f = function(a, _b, c) local d = c local _e end f(1, 2, 3)"),
+            vec![
+                ("unused-parameter", "a".to_owned()),
+                ("unused-local", "d".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn assignment_is_not_a_use() {
+        assert_eq!(
+            codes("do local x = 1 x = 2 end"),
+            vec![("unused-local", "x".to_owned())]
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(
+            codes("local x = 1 f = function(x) print(x) end print(x) f(x)"),
+            vec![("shadowing", "x".to_owned())]
+        );
+    }
+
+    #[test]
+    fn unreachable_after_break() {
+        assert_eq!(
+            codes("while x do break print(x) end x = true"),
+            vec![("unreachable-code", "print(x)".to_owned())]
+        );
+    }
+
+    #[test]
+    fn unreachable_after_terminating_do() {
+        assert_eq!(
+            codes("f = function() do return end print(1) end f()"),
+            vec![("unreachable-code", "print(1)".to_owned())]
+        );
+    }
+
+    #[test]
+    fn constant_conditions() {
+        let warnings: Vec<_> = check_source("if nil then end if 1 + 2 then end while false do end")
+            .into_iter()
+            .map(|w| (w.code, w.message))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                ("constant-condition", "condition is always false".to_owned()),
+                ("constant-condition", "condition is a constant".to_owned()),
+                ("constant-condition", "condition is always false".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn while_true_is_fine() {
+        assert_eq!(codes("while true do break end"), vec![]);
+    }
+
+    #[test]
+    fn roman_numeral_pitfalls() {
+        assert_eq!(
+            codes("x = MIX + XIV + IIIIII + 4"),
+            vec![
+                ("roman-numeral", "MIX".to_owned()),
+                ("roman-numeral", "IIIIII".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_romans_are_errors() {
+        let warnings: Vec<_> = check_source_with_mode("x = MIX + IIIIII", RomanMode::Strict)
+            .into_iter()
+            .map(|w| (w.code, w.message))
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                (
+                    "roman-numeral",
+                    "`MIX` is not an identifier, it reads as the number 1009".to_owned()
+                ),
+                (
+                    "syntax-error",
+                    "`IIIIII` is not a canonical Roman numeral, write `VI` for 6".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn romans_off_are_identifiers() {
+        let warnings = check_source_with_mode("MIX = 1 print(MIX, IIIIII)", RomanMode::Off);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "`IIIIII` is read but never assigned");
+    }
+
+    #[test]
+    fn roman_pragma_applies_to_lint() {
+        assert_eq!(codes("--!roman off\nMIX = 1 print(MIX)"), vec![]);
+    }
+
+    #[test]
+    fn syntax_error() {
+        assert_eq!(codes("x = 1 y z"), vec![("syntax-error", "z".to_owned())]);
+    }
+
+    #[test]
+    fn lints_around_syntax_errors() {
+        assert_eq!(
+            codes("print(y)\nz = )\nprint(z, w"),
+            vec![
+                ("undefined-global", "y".to_owned()),
+                ("syntax-error", ")".to_owned()),
+                ("undefined-global", "w".to_owned()),
+                ("syntax-error", "".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn render_points_at_span() {
+        let source = "x = 1\nprint(y)\n";
+        let warnings = check_source(source);
+        assert_eq!(
+            render(&warnings[0], source, "test.lua"),
+            "test.lua:2:7: warning[undefined-global]: `y` is read but never assigned\n  |\n2 | print(y)\n  |       ^\n"
+        );
+    }
+
+    #[test]
+    fn render_json_escapes() {
+        let source = "print(y)";
+        let warnings = check_source(source);
+        assert_eq!(
+            render_json(&warnings, source, "a\"b.lua"),
+            r#"[{"file":"a\"b.lua","code":"undefined-global","message":"`y` is read but never assigned","line":1,"column":7,"end_line":1,"end_column":8,"start":6,"end":7}]"#
+        );
+    }
+}
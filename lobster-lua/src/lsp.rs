@@ -0,0 +1,659 @@
+//! A Language Server Protocol server for lobster, spoken over stdio by the
+//! `lobster-lsp` binary.
+//!
+//! Documents are synced in full on every change and re-analysed from
+//! scratch; lobster scripts are small enough for that.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde_json::{Value as Json, json};
+
+use crate::Value;
+use crate::linter;
+use crate::parser::{Expr, LobsterParser, Name, Stmt, StmtKind};
+use crate::stdlib::GLOBAL_NAMES;
+use crate::tokenizer::{Span, Token, Tokenizer};
+
+/// Reads one `Content-Length` framed message, or `None` at end of input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(len) = header.strip_prefix("Content-Length:") {
+            content_length = len.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(len) = content_length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without Content-Length",
+        ));
+    };
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    writer.flush()
+}
+
+/// Serves requests from `reader` until the client sends `exit` or hangs up.
+pub fn run(reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(reader)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        for out in server.handle(&message) {
+            write_message(writer, &out)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+}
+
+impl Server {
+    /// Handles one incoming message and returns everything to send back.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let result = match message["method"].as_str().unwrap_or_default() {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "lobster-lsp" },
+            }),
+            "shutdown" => Json::Null,
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_owned(), text.to_owned());
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didChange" => {
+                if let Some(change) = params["contentChanges"].as_array().and_then(|c| c.last()) {
+                    let text = change["text"].as_str().unwrap_or_default();
+                    self.documents.insert(uri.to_owned(), text.to_owned());
+                }
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )];
+            }
+            "textDocument/hover" => self.with_position(params, hover),
+            "textDocument/definition" => self.with_position(params, |source, offset| {
+                let target = definition(source, offset)?;
+                Some(json!({ "uri": uri, "range": range(source, target) }))
+            }),
+            "textDocument/documentSymbol" => match self.documents.get(uri) {
+                Some(source) => document_symbols(source),
+                None => Json::Null,
+            },
+            "textDocument/completion" => match self.documents.get(uri) {
+                Some(source) => completions(source),
+                None => Json::Null,
+            },
+            method => {
+                if message.get("id").is_none() {
+                    // notifications we don't care about, like `initialized`
+                    return vec![];
+                }
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": { "code": -32601, "message": format!("unknown method {method}") },
+                })];
+            }
+        };
+        vec![json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })]
+    }
+
+    fn with_position(&self, params: &Json, f: impl FnOnce(&str, usize) -> Option<Json>) -> Json {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let Some(source) = self.documents.get(uri) else {
+            return Json::Null;
+        };
+        let offset = position_to_offset(source, &params["position"]);
+        f(source, offset).unwrap_or(Json::Null)
+    }
+
+    fn diagnostics(&self, uri: &str) -> Json {
+        let source = self.documents.get(uri).map_or("", String::as_str);
+        let warnings = linter::check_source(source);
+        let diagnostics: Vec<Json> = warnings
+            .iter()
+            .map(|w| {
+                let severity = match w.code {
+                    "syntax-error" => 1,
+                    _ => 2,
+                };
+                json!({
+                    "range": range(source, w.span),
+                    "severity": severity,
+                    "code": w.code,
+                    "source": "lobster",
+                    "message": w.message,
+                })
+            })
+            .collect();
+        notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// LSP positions count UTF-16 code units, which matters for `S∴` and 🦞.
+fn position(source: &str, offset: usize) -> Json {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({ "line": before.matches('\n').count(), "character": character })
+}
+
+fn range(source: &str, span: Span) -> Json {
+    json!({ "start": position(source, span.start), "end": position(source, span.end) })
+}
+
+fn position_to_offset(source: &str, position: &Json) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let line_start = source
+        .split_inclusive('\n')
+        .take(line)
+        .map(str::len)
+        .sum::<usize>();
+    let mut units = 0;
+    for (i, c) in source[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+/// Every token with its span, up to the first one the tokenizer rejects.
+fn tokens(source: &str) -> Vec<(Token, Span)> {
+    let mut res = vec![];
+    for tok in Tokenizer::new(source).map_while(Result::ok) {
+        res.push((tok.value, tok.span));
+    }
+    res
+}
+
+fn hover(source: &str, offset: usize) -> Option<Json> {
+    let (tok, span) = tokens(source)
+        .into_iter()
+        .find(|(_, span)| span.start <= offset && offset < span.end)?;
+    let lexeme = &source[span.start..span.end];
+    let text = match tok {
+        Token::NumberLiteral(n) => format!("`{lexeme}` = {}", Value::Number(n)),
+        Token::FractionLiteral(f) => format!("`{lexeme}` = {}", Value::Fraction(f)),
+        Token::Ident(name) => {
+            let resolved = resolve(source);
+            let what = match resolved.reference_at(offset).map(|r| &r.target) {
+                Some(Target::Local { is_param: true, .. }) => "parameter",
+                Some(Target::Local { .. }) => "local",
+                _ if GLOBAL_NAMES.contains(&name.as_str()) => "builtin",
+                _ => "global",
+            };
+            format!("{what} `{name}`")
+        }
+        _ => return None,
+    };
+    Some(json!({
+        "contents": { "kind": "markdown", "value": text },
+        "range": range(source, span),
+    }))
+}
+
+fn definition(source: &str, offset: usize) -> Option<Span> {
+    let resolved = resolve(source);
+    match &resolved.reference_at(offset)?.target {
+        Target::Local { decl, .. } => Some(*decl),
+        Target::Global(name) => resolved.globals.get(name).map(|g| g.span),
+    }
+}
+
+fn document_symbols(source: &str) -> Json {
+    let resolved = resolve(source);
+    let mut globals: Vec<(&String, &Global)> = resolved.globals.iter().collect();
+    globals.sort_by_key(|(_, g)| g.span.start);
+    let symbols: Vec<Json> = globals
+        .into_iter()
+        .map(|(name, g)| {
+            // SymbolKind: 12 is Function, 13 is Variable
+            json!({
+                "name": name,
+                "kind": if g.is_function { 12 } else { 13 },
+                "range": range(source, g.stmt_span),
+                "selectionRange": range(source, g.span),
+            })
+        })
+        .collect();
+    Json::Array(symbols)
+}
+
+fn completions(source: &str) -> Json {
+    let mut items: HashMap<String, bool> =
+        GLOBAL_NAMES.iter().map(|b| (b.to_string(), true)).collect();
+    for (name, g) in resolve(source).globals {
+        items.insert(name, g.is_function);
+    }
+    let mut items: Vec<(String, bool)> = items.into_iter().collect();
+    items.sort();
+    let items: Vec<Json> = items
+        .into_iter()
+        .map(|(label, is_function)| {
+            // CompletionItemKind: 3 is Function, 6 is Variable
+            json!({ "label": label, "kind": if is_function { 3 } else { 6 } })
+        })
+        .collect();
+    Json::Array(items)
+}
+
+enum Target {
+    Local { decl: Span, is_param: bool },
+    Global(String),
+}
+
+struct Reference {
+    span: Span,
+    target: Target,
+}
+
+struct Global {
+    /// The name in the first assignment to the global.
+    span: Span,
+    stmt_span: Span,
+    is_function: bool,
+}
+
+/// Which declaration every identifier in a program refers to.
+#[derive(Default)]
+struct Resolved {
+    references: Vec<Reference>,
+    globals: HashMap<String, Global>,
+}
+
+impl Resolved {
+    fn reference_at(&self, offset: usize) -> Option<&Reference> {
+        self.references
+            .iter()
+            .find(|r| r.span.start <= offset && offset < r.span.end)
+    }
+}
+
+fn resolve(source: &str) -> Resolved {
+    // half-written code still gives an AST, just without the broken parts
+    let (ast, _) = LobsterParser::new(source).parse_recovering();
+    let mut resolver = Resolver {
+        scopes: vec![vec![]],
+        resolved: Resolved::default(),
+    };
+    resolver.block(&ast);
+    resolver.resolved
+}
+
+struct Resolver {
+    scopes: Vec<Vec<(Name, bool)>>,
+    resolved: Resolved,
+}
+
+impl Resolver {
+    fn declare(&mut self, name: &Name, is_param: bool) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .push((name.clone(), is_param));
+        self.reference(name);
+    }
+
+    fn reference(&mut self, name: &Name) {
+        let target = match self
+            .scopes
+            .iter()
+            .flatten()
+            .rev()
+            .find(|(n, _)| n.name == name.name)
+        {
+            Some((decl, is_param)) => Target::Local {
+                decl: decl.span,
+                is_param: *is_param,
+            },
+            None => Target::Global(name.name.clone()),
+        };
+        self.resolved.references.push(Reference {
+            span: name.span,
+            target,
+        });
+    }
+
+    fn scoped_block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(vec![]);
+        self.block(stmts);
+        self.scopes.pop();
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Break | StmtKind::Error => {}
+            StmtKind::Return(exprs) => exprs.iter().for_each(|e| self.expr(e)),
+            StmtKind::While { cond, body } => {
+                self.expr(cond);
+                self.scoped_block(body);
+            }
+            StmtKind::DoEnd { body } => self.scoped_block(body),
+            StmtKind::Assignment { variable, value } => {
+                self.expr(value);
+                self.reference(variable);
+                if let Some(Reference {
+                    target: Target::Global(name),
+                    ..
+                }) = self.resolved.references.last()
+                {
+                    self.resolved.globals.entry(name.clone()).or_insert(Global {
+                        span: variable.span,
+                        stmt_span: stmt.span,
+                        is_function: matches!(value, Expr::FunctionDef { .. }),
+                    });
+                }
+            }
+            StmtKind::Local { variable, value } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                self.declare(variable, false);
+            }
+            StmtKind::FunctionCall {
+                function_name,
+                args,
+            } => {
+                self.expr(function_name);
+                args.iter().for_each(|e| self.expr(e));
+            }
+            StmtKind::If { cond, then, r#else } => {
+                self.expr(cond);
+                self.scoped_block(then);
+                self.scoped_block(r#else);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::BinOp { lhs, rhs, .. } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            Expr::Var(name) => self.reference(name),
+            Expr::Index { table, key } => {
+                self.expr(table);
+                self.expr(key);
+            }
+            Expr::FunctionCall {
+                function_name,
+                args,
+            } => {
+                self.expr(function_name);
+                args.iter().for_each(|e| self.expr(e));
+            }
+            Expr::FunctionDef { arguments, body, .. } => {
+                self.scopes.push(vec![]);
+                for arg in arguments {
+                    self.declare(arg, true);
+                }
+                self.block(body);
+                self.scopes.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///test.lua";
+
+    /// Plays `requests` against the server through the real framing and
+    /// returns everything it wrote back.
+    fn session(requests: &[Json]) -> Vec<Json> {
+        let mut input = vec![];
+        for request in requests {
+            write_message(&mut input, request).unwrap();
+        }
+        let mut output = vec![];
+        run(&mut io::Cursor::new(input), &mut output).unwrap();
+
+        let mut reader = io::Cursor::new(output);
+        let mut responses = vec![];
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            responses.push(message);
+        }
+        responses
+    }
+
+    fn open(text: &str) -> Json {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "languageId": "lua", "version": 1, "text": text } },
+        })
+    }
+
+    fn request(id: u64, method: &str, line: u64, character: u64) -> Json {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+            },
+        })
+    }
+
+    fn result(responses: &[Json], id: u64) -> Json {
+        responses
+            .iter()
+            .find(|r| r["id"] == id)
+            .map(|r| r["result"].clone())
+            .unwrap()
+    }
+
+    #[test]
+    fn initialize_and_exit() {
+        let responses = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+        ]);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(result(&responses, 1)["capabilities"]["hoverProvider"], true);
+        assert_eq!(result(&responses, 2), Json::Null);
+    }
+
+    #[test]
+    fn diagnostics_for_syntax_errors() {
+        let responses = session(&[open("x = 1\ny z")]);
+        assert_eq!(responses[0]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = &responses[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["severity"], 1);
+        assert_eq!(diagnostics[0]["code"], "syntax-error");
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 3 } })
+        );
+    }
+
+    #[test]
+    fn diagnostics_follow_changes() {
+        let responses = session(&[
+            open("print(y)"),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 2 },
+                    "contentChanges": [{ "text": "y = 1 print(y)" }],
+                },
+            }),
+        ]);
+        assert_eq!(
+            responses[0]["params"]["diagnostics"][0]["code"],
+            "undefined-global"
+        );
+        assert_eq!(responses[1]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn hover_shows_literal_values() {
+        let responses = session(&[
+            open("x = S∴ + XIV"),
+            request(1, "textDocument/hover", 0, 4),
+            request(2, "textDocument/hover", 0, 10),
+            request(3, "textDocument/hover", 0, 0),
+        ]);
+        assert_eq!(result(&responses, 1)["contents"]["value"], "`S∴` = 3/4");
+        assert_eq!(result(&responses, 2)["contents"]["value"], "`XIV` = 14");
+        assert_eq!(result(&responses, 3)["contents"]["value"], "global `x`");
+    }
+
+    #[test]
+    fn hover_counts_utf16() {
+        // 🦞 is two UTF-16 code units
+        let responses = session(&[open("🦞 = ·"), request(1, "textDocument/hover", 0, 5)]);
+        assert_eq!(result(&responses, 1)["contents"]["value"], "`·` = 1/12");
+    }
+
+    #[test]
+    fn goto_definition() {
+        let source = "f = function(a)\n  local b = a\n  print(b)\nend\nf(1)";
+        let responses = session(&[
+            open(source),
+            // `a` in `local b = a`
+            request(1, "textDocument/definition", 1, 12),
+            // `b` in `print(b)`
+            request(2, "textDocument/definition", 2, 8),
+            // `f` in `f(1)`
+            request(3, "textDocument/definition", 4, 0),
+            // `print` has no definition
+            request(4, "textDocument/definition", 2, 3),
+        ]);
+        let start = |id| result(&responses, id)["range"]["start"].clone();
+        assert_eq!(start(1), json!({ "line": 0, "character": 13 }));
+        assert_eq!(start(2), json!({ "line": 1, "character": 8 }));
+        assert_eq!(start(3), json!({ "line": 0, "character": 0 }));
+        assert_eq!(result(&responses, 4), Json::Null);
+    }
+
+    #[test]
+    fn document_symbols_and_completion() {
+        let responses = session(&[
+            open("f = function() end\nx = 1\nx = 2"),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "textDocument/documentSymbol",
+                "params": { "textDocument": { "uri": URI } },
+            }),
+            request(2, "textDocument/completion", 2, 0),
+        ]);
+        let symbols = result(&responses, 1);
+        assert_eq!(symbols[0]["name"], "f");
+        assert_eq!(symbols[0]["kind"], 12);
+        assert_eq!(symbols[1]["name"], "x");
+        assert_eq!(symbols[1]["selectionRange"]["start"]["line"], 1);
+        let labels: Vec<Json> = result(&responses, 2)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].clone())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                json!("debug"),
+                json!("f"),
+                json!("json"),
+                json!("print"),
+                json!("string"),
+                json!("tostring"),
+                json!("x")
+            ]
+        );
+    }
+
+    #[test]
+    fn completion_on_broken_code() {
+        let responses = session(&[
+            open("foo = 1\nbar = foo +"),
+            request(1, "textDocument/completion", 1, 11),
+        ]);
+        let labels: Vec<Json> = result(&responses, 1)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].clone())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                json!("bar"),
+                json!("debug"),
+                json!("foo"),
+                json!("json"),
+                json!("print"),
+                json!("string"),
+                json!("tostring")
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_request() {
+        let responses =
+            session(&[json!({ "jsonrpc": "2.0", "id": 7, "method": "workspace/frobnicate" })]);
+        assert_eq!(responses[0]["error"]["code"], -32601);
+    }
+}
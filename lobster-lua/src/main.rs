@@ -1,340 +1,341 @@
-use std::{collections::HashMap, process::Stdio};
-// decisions:
-// our lua starts at 0
 use std::fs::read_to_string;
 
-use crate::parser::{LobsterParser, Stmt};
+use lobster_lua::coverage::Coverage;
+use lobster_lua::debugger::Debugger;
+use lobster_lua::numeral::NumberFormat;
+use lobster_lua::optimizer::{self, Passes};
+use lobster_lua::parser::{LobsterParser, ParseError};
+use lobster_lua::profiler::Profiler;
+use lobster_lua::repl::Repl;
+use lobster_lua::tokenizer::RomanMode;
+use lobster_lua::{
+    Context, RuntimeError, compile, formatter, linter, run_block, tokenizer, transpile,
+};
 
-mod e2e;
-mod fraction;
-mod parser;
-mod tokenizer;
-
-use fraction::Fraction;
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Value {
-    Nil,
-    Number(i64),
-    Fraction(Fraction),
-    String(String),
-    Bool(bool),
-    Closure {
-        params: Vec<String>,
-        body: Vec<Stmt>,
-    },
-}
-
-impl std::fmt::Display for Value {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Value::Nil => write!(f, "nil"),
-            Value::Number(n) => write!(f, "{n}"),
-            Value::Fraction(n) => write!(f, "{n}"),
-            Value::String(s) => write!(f, "{s}"),
-            Value::Bool(b) => write!(f, "{b}"),
-            Value::Closure { params, body } => write!(f, "function"),
-        }
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("fmt") => fmt_files(&args[1..]),
+        Some("check") => check_files(&args[1..]),
+        Some("debug") => match &args[1..] {
+            [path] => debug_file(path),
+            _ => usage_error("usage: lobster-lua debug <file>"),
+        },
+        Some("repl") => repl(&args[1..]),
+        Some("transpile") => transpile_file(&args[1..]),
+        Some("compile") => compile_file(&args[1..]),
+        _ => run_file(&args),
     }
 }
 
-impl Value {
-    fn as_fraction(&self) -> Result<Fraction, &'static str> {
-        match self {
-            Self::Number(n) => Ok(Fraction::new(*n, 1)),
-            Self::Fraction(f) => Ok(*f),
-            _ => Err("NOT FRACTIONABLE"),
-        }
-    }
-
-    fn add(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l + r)),
-            (lv, rv) => Ok(Value::Fraction(lv.as_fraction()? + rv.as_fraction()?)),
+/// `lobster-lua fmt [--check] <files>`: rewrites the files in place, or with
+/// `--check` only reports the ones that are not formatted.
+fn fmt_files(args: &[String]) {
+    let check = args.iter().any(|a| a == "--check");
+    let mut unformatted = false;
+    for path in args.iter().filter(|a| *a != "--check") {
+        let source = read_source(path);
+        let formatted = match formatter::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                let (line, col) = tokenizer::line_col(&source, e.span.start);
+                eprintln!("{path}:{line}:{col}: {}", e.message);
+                unformatted = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
         }
-    }
-
-    fn sub(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l - r)),
-            (lv, rv) => Ok(Value::Fraction(lv.as_fraction()? - rv.as_fraction()?)),
+        if check {
+            eprintln!("{path} is not formatted");
+            unformatted = true;
+        } else {
+            write_file(path, formatted);
         }
     }
-    fn div(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (lv, rv) => Ok(Value::Fraction(lv.as_fraction()? / rv.as_fraction()?)),
-        }
+    if unformatted {
+        std::process::exit(1);
     }
-    fn mul(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l * r)),
-            (lv, rv) => Ok(Value::Fraction(lv.as_fraction()? * rv.as_fraction()?)),
+}
+
+/// `lobster-lua check [--json] [--roman creative|strict|off] <files>`: prints
+/// lint warnings and exits with 1 if there were any.
+fn check_files(args: &[String]) {
+    let mut json = false;
+    let mut roman_mode = RomanMode::default();
+    let mut paths = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--roman" => roman_mode = roman_mode_arg(args.next()),
+            _ => paths.push(arg),
         }
     }
-    fn exp(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => {
-                Ok(Self::Number(l.pow(r.try_into().expect("TODO"))))
+    let mut any_warnings = false;
+    for path in paths {
+        let source = read_source(path);
+        let warnings = linter::check_source_with_mode(&source, roman_mode);
+        any_warnings |= !warnings.is_empty();
+        if json {
+            println!("{}", linter::render_json(&warnings, &source, path));
+        } else {
+            for warning in &warnings {
+                print!("{}", linter::render(warning, &source, path));
             }
-            _ => Err("PANIK"),
         }
     }
-    fn r#mod(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l % r)),
-            _ => Err("PANIK"),
-        }
+    if any_warnings {
+        std::process::exit(1);
     }
-    fn and(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Bool(l), Value::Bool(r)) => Ok(Self::Bool(l && r)),
-            _ => Err("PANIK"),
+}
+
+/// `lobster-lua [--no-optimize | --no-fold | --no-dead-branches |
+/// --no-inline] [--numbers decimal|roman|mixed] [--roman creative|strict|off]
+/// [--profile[=<out>]] [--coverage[=<out>]] [--trace-tokens] [<file>]`: runs
+/// the file, by default `sample.lua`.
+///
+/// `--profile` prints a flat profile to stderr and writes folded stacks to
+/// `<out>`, by default the file with a `.folded` extension next to it, so
+/// `--profile` on `dir/fib.lua` writes `dir/fib.folded`. `--coverage` writes
+/// lcov to `<out>`, by default `dir/fib.lcov`. Both turn the optimizer off,
+/// so lines and calls are the ones in the source.
+/// `--trace-tokens` prints every token the parser reads to stderr.
+fn run_file(args: &[String]) {
+    let mut passes = Passes::ALL;
+    let mut number_format = NumberFormat::default();
+    let mut roman_mode = RomanMode::default();
+    let mut profile = None;
+    let mut coverage = None;
+    let mut trace_tokens = false;
+    let mut path = "sample.lua";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-optimize" => passes = Passes::NONE,
+            "--no-fold" => passes.fold_constants = false,
+            "--no-dead-branches" => passes.eliminate_dead_branches = false,
+            "--no-inline" => passes.inline_functions = false,
+            "--numbers" => {
+                number_format = args
+                    .next()
+                    .and_then(|name| NumberFormat::from_name(name))
+                    .unwrap_or_else(|| usage_error("--numbers takes decimal, roman or mixed"));
+            }
+            "--roman" => roman_mode = roman_mode_arg(args.next()),
+            "--profile" => profile = Some(None),
+            "--coverage" => coverage = Some(None),
+            arg if arg.starts_with("--profile=") => profile = Some(Some(&arg[10..])),
+            arg if arg.starts_with("--coverage=") => coverage = Some(Some(&arg[11..])),
+            "--trace-tokens" => trace_tokens = true,
+            _ => path = arg,
         }
     }
-    fn or(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Bool(l), Value::Bool(r)) => Ok(Self::Bool(l || r)),
-            _ => Err("PANIK"),
-        }
+    if profile == Some(Some("")) || coverage == Some(Some("")) {
+        usage_error("--profile= and --coverage= take a file name");
     }
-    fn lshift(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l << r)),
-            _ => Err("PANIK"),
-        }
+    if profile.is_some() || coverage.is_some() {
+        passes = Passes::NONE;
     }
-    fn rshift(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l >> r)),
-            _ => Err("PANIK"),
-        }
+    let source = read_source(path);
+    let mut parser = LobsterParser::with_roman_mode(&source, roman_mode);
+    parser.set_trace(trace_tokens);
+    let (ast, errors) = parser.parse_recovering();
+    if !errors.is_empty() {
+        syntax_errors(path, &source, errors);
     }
+    let ast = optimizer::optimize(ast, passes);
 
-    fn concat(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::String(l), Value::String(r)) => Ok(Self::String(l + &r)),
-            _ => Err("PANIK"),
-        }
+    let mut context = Context::new();
+    context.set_source(path, &source);
+    context.set_number_format(number_format);
+    let profiler = profile.map(|_| Profiler::new());
+    if let Some(profiler) = &profiler {
+        context.add_hook(profiler.hook());
     }
-
-    fn gt(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l > r)),
-            _ => Err("PANIK"),
-        }
+    let coverage_tracker = coverage.map(|_| Coverage::new(&ast, &source));
+    if let Some(coverage) = &coverage_tracker {
+        context.add_hook(coverage.hook());
     }
+    let result = run_block(&ast, &mut context);
 
-    fn lt(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l < r)),
-            _ => Err("PANIK"),
-        }
+    if let Some(profiler) = profiler {
+        profiler.finish();
+        eprint!("{}", profiler.flat());
+        let out = output_path(path, profile.flatten(), "folded");
+        write_file(&out, profiler.folded());
+        eprintln!("folded stacks written to {out}");
     }
-
-    fn geq(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l >= r)),
-            _ => Err("PANIK"),
-        }
+    if let Some(tracker) = coverage_tracker {
+        let out = output_path(path, coverage.flatten(), "lcov");
+        write_file(&out, tracker.lcov(path));
+        eprintln!("coverage written to {out}");
     }
-
-    fn leq(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Bool(l <= r)),
-            _ => Err("PANIK"),
-        }
+    if let Err(e) = result {
+        runtime_error(e);
     }
+}
 
-    fn bitor(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l | r)),
-            _ => Err("PANIK"),
-        }
+/// `lobster-lua debug <file>`: runs the file under the step debugger.
+fn debug_file(path: &str) {
+    let source = read_source(path);
+    let (ast, errors) = LobsterParser::new(&source).parse_recovering();
+    if !errors.is_empty() {
+        syntax_errors(path, &source, errors);
     }
 
-    fn bitand(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l & r)),
-            _ => Err("PANIK"),
-        }
+    let mut context = Context::new();
+    context.set_source(path, &source);
+    context.add_hook(Box::new(Debugger::new(
+        &source,
+        Box::new(std::io::stdin().lock()),
+        Box::new(std::io::stdout()),
+    )));
+    if let Err(e) = run_block(&ast, &mut context) {
+        runtime_error(e);
     }
+}
 
-    fn bitxor(self, rhs: Self) -> Result<Value, &'static str> {
-        match (self, rhs) {
-            (Value::Number(l), Value::Number(r)) => Ok(Self::Number(l ^ r)),
-            _ => Err("PANIK"),
-        }
+/// Reports every syntax error in the script and exits.
+fn syntax_errors(path: &str, source: &str, errors: Vec<ParseError>) -> ! {
+    for e in errors {
+        let (line, col) = tokenizer::line_col(source, e.span.start);
+        eprintln!("{path}:{line}:{col}: {}", e.message);
     }
+    std::process::exit(1);
 }
 
-pub struct Context {
-    test_stdout: Option<String>,
-    globals: HashMap<String, Value>,
-    locals: Vec<HashMap<String, Value>>,
+/// Reports an error in the script and exits, the same way a compiled program
+/// does.
+fn runtime_error(e: RuntimeError) -> ! {
+    eprintln!("lobster: {e}");
+    std::process::exit(1);
 }
 
-impl Context {
-    pub fn get(&self, name: &str) -> Option<Value> {
-        for scope in self.locals.iter().rev() {
-            if let Some(val) = scope.get(name) {
-                return Some(val.clone());
-            }
+/// `lobster-lua repl [--roman creative|strict|off]`
+fn repl(args: &[String]) {
+    let mut roman_mode = RomanMode::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--roman" => roman_mode = roman_mode_arg(args.next()),
+            _ => usage_error("usage: lobster-lua repl [--roman creative|strict|off]"),
         }
-        self.globals.get(name).cloned()
     }
-
-    pub fn insert_global(&mut self, name: String, value: Value) {
-        self.globals.insert(name, value);
+    let result = Repl::new(roman_mode).run(std::io::stdin().lock(), std::io::stdout());
+    if let Err(e) = result {
+        eprintln!("lobster-lua: {e}");
+        std::process::exit(1);
     }
+}
 
-    pub fn insert_local(&mut self, name: String, value: Value) {
-        self.locals.last_mut().unwrap().insert(name, value);
+/// `lobster-lua transpile [--roman creative|strict|off] <file> [-o <out>]`:
+/// writes the file as Lua 5.4, to stdout unless there is `-o`.
+fn transpile_file(args: &[String]) {
+    let mut roman_mode = RomanMode::default();
+    let mut output = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--roman" => roman_mode = roman_mode_arg(args.next()),
+            "-o" => {
+                output = Some(
+                    args.next()
+                        .unwrap_or_else(|| usage_error("-o takes a file")),
+                )
+            }
+            _ => path = Some(arg),
+        }
     }
-
-    pub fn enter_scope(&mut self) {
-        self.locals.push(HashMap::new());
+    let path =
+        path.unwrap_or_else(|| usage_error("usage: lobster-lua transpile <file> [-o <out>]"));
+    let source = read_source(path);
+    let lua = match transpile::transpile(&source, roman_mode) {
+        Ok(lua) => lua,
+        Err(e) => {
+            let (line, col) = tokenizer::line_col(&source, e.span.start);
+            eprintln!("{path}:{line}:{col}: {}", e.message);
+            std::process::exit(1);
+        }
+    };
+    match output {
+        Some(output) => write_file(output, lua),
+        None => print!("{lua}"),
     }
+}
 
-    pub fn leave_scope(&mut self) {
-        self.locals.pop();
+/// `lobster-lua compile [--roman creative|strict|off] <file> [-o <out>]`:
+/// writes the file as C, to stdout unless there is `-o`. Build it with
+/// `cc -O2 foo.c -o foo -lm`.
+fn compile_file(args: &[String]) {
+    let mut roman_mode = RomanMode::default();
+    let mut output = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--roman" => roman_mode = roman_mode_arg(args.next()),
+            "-o" => {
+                output = Some(
+                    args.next()
+                        .unwrap_or_else(|| usage_error("-o takes a file")),
+                )
+            }
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage_error("usage: lobster-lua compile <file> [-o <out>]"));
+    let source = read_source(path);
+    let c = match compile::compile(&source, path, roman_mode) {
+        Ok(c) => c,
+        Err(e) => {
+            let (line, col) = tokenizer::line_col(&source, e.span.start);
+            eprintln!("{path}:{line}:{col}: {}", e.message);
+            std::process::exit(1);
+        }
+    };
+    match output {
+        Some(output) => write_file(output, c),
+        None => print!("{c}"),
     }
 }
 
-pub struct StackFrame {
-    locals: HashMap<String, Value>,
+fn roman_mode_arg(name: Option<&String>) -> RomanMode {
+    name.and_then(|name| RomanMode::from_name(name))
+        .unwrap_or_else(|| usage_error("--roman takes creative, strict or off"))
 }
 
-fn main() {
-    let source = read_to_string("sample.lua").expect("todo");
-    let parser = LobsterParser::new(source);
-    let ast = parser.parse();
-
-    let globals: HashMap<String, Value> = Default::default();
-    let mut context: Context = Context {
-        test_stdout: None,
-        globals,
-        locals: vec![HashMap::new()],
-    };
-
-    run_block(&ast, &mut context);
+/// Reports a bad command line and exits.
+fn usage_error(message: &str) -> ! {
+    eprintln!("lobster-lua: {message}");
+    std::process::exit(1);
 }
 
-fn run_block(stmts: &[parser::Stmt], context: &mut Context) {
-    for stmt in stmts {
-        // dbg!(stmt);
-        match stmt {
-            parser::Stmt::Assignment { variable, value } => {
-                let res = eval(value, context);
-                context.insert_global(variable.clone(), res);
-            }
-            parser::Stmt::If { cond, then, r#else } => {
-                if eval(cond, context) == Value::Bool(true) {
-                    run_block(then, context);
-                } else {
-                    run_block(r#else, context);
-                }
-            }
-            parser::Stmt::While { cond, body } => {
-                while eval(cond, context) == Value::Bool(true) {
-                    run_block(body, context)
-                }
-            }
-            parser::Stmt::Break => todo!(),
-            parser::Stmt::Return(exprs) => todo!(),
-            parser::Stmt::DoEnd { body } => todo!(),
-            parser::Stmt::FunctionCall {
-                function_name,
-                args,
-            } => {
-                let evaluated_args: Vec<_> =
-                    args.into_iter().map(|arg| eval(arg, context)).collect();
-
-                if function_name == "print" {
-                    let mut line = evaluated_args
-                        .iter()
-                        .map(|a| a.to_string())
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    line.push('\n');
+/// The contents of `path`, or exits saying why there are none.
+fn read_source(path: &str) -> String {
+    read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("{path}: {e}");
+        std::process::exit(1);
+    })
+}
 
-                    if let Some(test_stdout) = &mut context.test_stdout {
-                        test_stdout.push_str(&line);
-                    } else {
-                        print!("{line}");
-                    }
-                } else {
-                    let function = context.get(function_name).expect("TODO");
-                    match function {
-                        Value::Closure { params, body } => {
-                            context.enter_scope();
-                            assert_eq!(
-                                params.len(),
-                                args.len(),
-                                "calling with wrong number of parameters"
-                            );
-                            for (param, arg) in params.iter().zip(evaluated_args) {
-                                context.insert_local(param.clone(), arg);
-                            }
-                            run_block(&body.clone() /* TODO: get rid of clone */, context);
-                            context.leave_scope();
-                        }
-                        x => panic!("{x:?} is not callable"),
-                    }
-                }
-            }
-        }
+fn write_file(path: &str, contents: String) {
+    if let Err(e) = std::fs::write(path, contents) {
+        eprintln!("{path}: {e}");
+        std::process::exit(1);
     }
 }
 
-fn eval(expr: &parser::Expr, context: &mut Context) -> Value {
-    match expr {
-        parser::Expr::Nil => Value::Nil,
-        parser::Expr::Numeral(i) => Value::Number(*i),
-        parser::Expr::Fraction(f) => Value::Fraction(*f),
-        parser::Expr::Boolean(b) => Value::Bool(*b),
-        parser::Expr::String(s) => Value::String(s.clone()),
-        parser::Expr::BinOp { op, lhs, rhs } => {
-            let lhs = eval(lhs, context);
-            if let (parser::BinOp::And, Value::Bool(false)) = (op, &lhs) {
-                return Value::Bool(false);
-            }
-            if let (parser::BinOp::Or, Value::Bool(true)) = (op, &lhs) {
-                return Value::Bool(true);
-            }
-            let rhs = eval(rhs, context);
-            match op {
-                parser::BinOp::Plus => lhs.add(rhs),
-                parser::BinOp::Minus => lhs.sub(rhs),
-                parser::BinOp::Mul => lhs.mul(rhs),
-                parser::BinOp::Div => lhs.div(rhs),
-                parser::BinOp::IDiv => lhs.div(rhs),
-                parser::BinOp::Exp => lhs.exp(rhs),
-                parser::BinOp::Mod => lhs.r#mod(rhs),
-                parser::BinOp::And => lhs.and(rhs),
-                parser::BinOp::Or => lhs.or(rhs),
-                parser::BinOp::LShift => lhs.lshift(rhs),
-                parser::BinOp::RShift => lhs.rshift(rhs),
-                parser::BinOp::GT => lhs.gt(rhs),
-                parser::BinOp::LT => lhs.lt(rhs),
-                parser::BinOp::GEQ => lhs.geq(rhs),
-                parser::BinOp::LEQ => lhs.leq(rhs),
-                parser::BinOp::BitOR => lhs.bitor(rhs),
-                parser::BinOp::BitAnd => lhs.bitand(rhs),
-                parser::BinOp::BitXor => lhs.bitxor(rhs),
-                parser::BinOp::Equals => Ok(Value::Bool(lhs.eq(&rhs))),
-                parser::BinOp::NotEquals => Ok(Value::Bool(!lhs.eq(&rhs))),
-                parser::BinOp::Concat => lhs.concat(rhs),
-            }
-            .expect("TODO")
-        }
-        parser::Expr::Var(ident) => context.get(ident).expect("TODO").clone(),
-        parser::Expr::FunctionCall {
-            function_name,
-            args,
-        } => todo!(),
-        parser::Expr::FunctionDef { arguments, body } => Value::Closure {
-            params: arguments.clone(),
-            body: body.clone(),
-        },
+/// `out` if it was given, else `script` with its extension swapped for
+/// `extension`.
+fn output_path(script: &str, out: Option<&str>, extension: &str) -> String {
+    match out {
+        Some(out) => out.to_owned(),
+        None => std::path::Path::new(script)
+            .with_extension(extension)
+            .to_string_lossy()
+            .into_owned(),
     }
 }
@@ -0,0 +1,240 @@
+//! Turning numbers back into text: plain decimal, Roman numerals with uncia
+//! glyphs for the twelfths (`XIV S∴`), or mixed numbers (`14 3/4`).
+
+use crate::Value;
+use crate::fraction::Fraction;
+use crate::tokenizer::{canonical_roman, fraction_glyph, roman_number};
+
+/// Numbers from here on are written in decimal, as there would be too many
+/// `M`s.
+const ROMAN_LIMIT: i64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberFormat {
+    /// `14` and `59/4`.
+    #[default]
+    Decimal,
+    /// `XIV` and `XIV S∴`. Zero is `N`, for *nulla*.
+    Roman,
+    /// `14` and `14 3/4`.
+    Mixed,
+}
+
+impl NumberFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "decimal" => Some(Self::Decimal),
+            "roman" => Some(Self::Roman),
+            "mixed" => Some(Self::Mixed),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Decimal => "decimal",
+            Self::Roman => "roman",
+            Self::Mixed => "mixed",
+        }
+    }
+}
+
+/// `value` as text, with numbers written in `format`.
+pub fn format_value(value: &Value, format: NumberFormat) -> String {
+    match value {
+        Value::Number(n) => format_integer(*n, format),
+        Value::Fraction(f) => format_fraction(*f, format),
+        value => value.to_string(),
+    }
+}
+
+fn format_integer(n: i64, format: NumberFormat) -> String {
+    match format {
+        NumberFormat::Roman => roman(n),
+        _ => n.to_string(),
+    }
+}
+
+fn roman(n: i64) -> String {
+    if n == 0 {
+        "N".to_owned()
+    } else if n.unsigned_abs() >= ROMAN_LIMIT as u64 {
+        n.to_string()
+    } else {
+        let sign = if n < 0 { "-" } else { "" };
+        format!("{sign}{}", canonical_roman(n.abs()).unwrap())
+    }
+}
+
+fn format_fraction(f: Fraction, format: NumberFormat) -> String {
+    if format == NumberFormat::Decimal {
+        return f.to_string();
+    }
+    let sign = if f.numerator() < 0 { "-" } else { "" };
+    let numerator = f.numerator().unsigned_abs();
+    let denominator = f.denominator().unsigned_abs();
+    let whole = numerator / denominator;
+    let rest = Fraction::new((numerator % denominator) as i64, denominator as i64);
+    let rest = match format {
+        NumberFormat::Roman => fraction_glyph(rest)
+            .map(str::to_owned)
+            .unwrap_or_else(|| rest.to_string()),
+        _ => rest.to_string(),
+    };
+    match (whole, rest.as_str()) {
+        (_, "0/1") => format!("{sign}{}", format_integer(whole as i64, format)),
+        (0, _) => format!("{sign}{rest}"),
+        _ => format!("{sign}{} {rest}", format_integer(whole as i64, format)),
+    }
+}
+
+/// Reads back what [`format_value`] writes for a number, in any of the
+/// formats.
+pub fn parse_number(s: &str) -> Option<Value> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (whole, rest) = match s.split_once(' ') {
+        Some((whole, rest)) => (Some(whole), Some(rest)),
+        None if parse_part(s).is_some() => (None, Some(s)),
+        None => (Some(s), None),
+    };
+    let whole = match whole {
+        Some("N") => 0,
+        Some(whole) => whole.parse().ok().or_else(|| roman_number(whole))?,
+        None => 0,
+    };
+    let value = match rest {
+        Some(rest) => Value::Fraction(Fraction::new(whole, 1) + parse_part(rest)?),
+        None => Value::Number(whole),
+    };
+    Some(if negative {
+        Value::Number(0).sub(value).ok()?
+    } else {
+        value
+    })
+}
+
+/// A proper fraction, as an uncia glyph or `n/d`.
+fn parse_part(s: &str) -> Option<Fraction> {
+    if let Some((n, d)) = s.split_once('/') {
+        let d = d.parse().ok().filter(|&d| d != 0)?;
+        return Some(Fraction::new(n.parse().ok()?, d));
+    }
+    (1..12)
+        .map(|twelfths| Fraction::new(twelfths, 12))
+        .find(|&f| fraction_glyph(f) == Some(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Expr, LobsterParser};
+
+    fn fraction(n: i64, d: i64) -> Value {
+        Value::Fraction(Fraction::new(n, d))
+    }
+
+    #[test]
+    fn roman_integers() {
+        let cases = [(14, "XIV"), (1994, "MCMXCIV"), (0, "N"), (-4, "-IV")];
+        for (n, expected) in cases {
+            assert_eq!(
+                format_value(&Value::Number(n), NumberFormat::Roman),
+                expected
+            );
+        }
+        assert_eq!(
+            format_value(&Value::Number(ROMAN_LIMIT), NumberFormat::Roman),
+            "1000000"
+        );
+    }
+
+    #[test]
+    fn roman_fractions() {
+        let cases = [
+            (fraction(59, 4), "XIV S∴"),
+            (fraction(3, 4), "S∴"),
+            (fraction(1, 2), "S"),
+            (fraction(-13, 12), "-I ·"),
+            (fraction(4, 2), "II"),
+            (fraction(15, 7), "II 1/7"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format_value(&value, NumberFormat::Roman), expected);
+        }
+    }
+
+    #[test]
+    fn mixed_numbers() {
+        let cases = [
+            (fraction(11, 4), "2 3/4"),
+            (fraction(-11, 4), "-2 3/4"),
+            (fraction(3, 4), "3/4"),
+            (fraction(0, 4), "0"),
+            (Value::Number(7), "7"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format_value(&value, NumberFormat::Mixed), expected);
+        }
+    }
+
+    #[test]
+    fn decimal_is_unchanged() {
+        assert_eq!(
+            format_value(&fraction(59, 4), NumberFormat::Decimal),
+            "59/4"
+        );
+        assert_eq!(
+            format_value(&Value::Number(-3), NumberFormat::Decimal),
+            "-3"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut values: Vec<_> = (-30..=30).map(Value::Number).collect();
+        for n in -40..=40 {
+            for d in [2, 3, 4, 6, 7, 12] {
+                values.push(fraction(n, d));
+            }
+        }
+        for value in values {
+            let value = match value {
+                Value::Fraction(f) if f.denominator() == 1 => Value::Number(f.numerator()),
+                value => value,
+            };
+            for format in [
+                NumberFormat::Decimal,
+                NumberFormat::Roman,
+                NumberFormat::Mixed,
+            ] {
+                let text = format_value(&value, format);
+                let back = match parse_number(&text) {
+                    Some(Value::Fraction(f)) if f.denominator() == 1 => {
+                        Value::Number(f.numerator())
+                    }
+                    back => back.unwrap_or_else(|| panic!("{text} does not parse")),
+                };
+                assert_eq!(back, value, "{format:?}: {text}");
+            }
+        }
+    }
+
+    /// Roman output for a single numeral or glyph is also valid source.
+    #[test]
+    fn round_trip_through_parser() {
+        for n in 1..=50 {
+            let text = format_value(&Value::Number(n), NumberFormat::Roman);
+            let expr = LobsterParser::new(&text).parse_expression();
+            assert_eq!(expr, Ok(Expr::Numeral(n)), "{text}");
+        }
+        for twelfths in 1..12 {
+            let f = Fraction::new(twelfths, 12);
+            let text = format_value(&Value::Fraction(f), NumberFormat::Roman);
+            let expr = LobsterParser::new(&text).parse_expression();
+            assert_eq!(expr, Ok(Expr::Fraction(f)), "{text}");
+        }
+    }
+}
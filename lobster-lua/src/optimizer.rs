@@ -0,0 +1,597 @@
+//! Rewrites the AST between parsing and running it. Every pass keeps the
+//! meaning of the program: anything that might behave differently, like an
+//! operation that would fail at runtime, is left alone.
+
+use std::collections::HashMap;
+
+use crate::parser::{BinOp, Expr, Name, Stmt, StmtKind};
+use crate::{Value, binop};
+
+/// Which passes [`optimize`] runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Passes {
+    pub inline_functions: bool,
+    pub fold_constants: bool,
+    pub eliminate_dead_branches: bool,
+}
+
+impl Passes {
+    pub const ALL: Self = Self {
+        inline_functions: true,
+        fold_constants: true,
+        eliminate_dead_branches: true,
+    };
+
+    pub const NONE: Self = Self {
+        inline_functions: false,
+        fold_constants: false,
+        eliminate_dead_branches: false,
+    };
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+/// Runs the enabled passes. Inlining goes first, as it leaves arithmetic on
+/// literals behind, and folding before dead branches, as it decides their
+/// conditions.
+pub fn optimize(mut stmts: Vec<Stmt>, passes: Passes) -> Vec<Stmt> {
+    if passes.inline_functions {
+        stmts = inline_functions(stmts);
+    }
+    if passes.fold_constants {
+        stmts = fold_constants(stmts);
+    }
+    if passes.eliminate_dead_branches {
+        stmts = eliminate_dead_branches(stmts);
+    }
+    stmts
+}
+
+/// A rewrite of the tree. By default the methods rebuild it unchanged with
+/// the `walk_` functions, a pass overrides the ones it cares about.
+trait Pass {
+    fn block(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        walk_block(self, stmts)
+    }
+
+    fn stmt(&mut self, stmt: Stmt) -> Stmt {
+        walk_stmt(self, stmt)
+    }
+
+    fn expr(&mut self, expr: Expr) -> Expr {
+        walk_expr(self, expr)
+    }
+}
+
+fn walk_block<P: Pass + ?Sized>(pass: &mut P, stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(|stmt| pass.stmt(stmt)).collect()
+}
+
+fn walk_stmt<P: Pass + ?Sized>(pass: &mut P, stmt: Stmt) -> Stmt {
+    let kind = match stmt.kind {
+        StmtKind::Return(exprs) => {
+            StmtKind::Return(exprs.into_iter().map(|e| pass.expr(e)).collect())
+        }
+        StmtKind::While { cond, body } => StmtKind::While {
+            cond: pass.expr(cond),
+            body: pass.block(body),
+        },
+        StmtKind::DoEnd { body } => StmtKind::DoEnd {
+            body: pass.block(body),
+        },
+        StmtKind::Assignment { variable, value } => StmtKind::Assignment {
+            variable,
+            value: pass.expr(value),
+        },
+        StmtKind::Local { variable, value } => StmtKind::Local {
+            variable,
+            value: value.map(|value| pass.expr(value)),
+        },
+        StmtKind::FunctionCall {
+            function_name,
+            args,
+        } => StmtKind::FunctionCall {
+            function_name: pass.expr(function_name),
+            args: args.into_iter().map(|arg| pass.expr(arg)).collect(),
+        },
+        StmtKind::If { cond, then, r#else } => StmtKind::If {
+            cond: pass.expr(cond),
+            then: pass.block(then),
+            r#else: pass.block(r#else),
+        },
+        StmtKind::Break => StmtKind::Break,
+        StmtKind::Error => StmtKind::Error,
+    };
+    Stmt { kind, ..stmt }
+}
+
+fn walk_expr<P: Pass + ?Sized>(pass: &mut P, expr: Expr) -> Expr {
+    match expr {
+        Expr::BinOp { op, lhs, rhs } => Expr::BinOp {
+            op,
+            lhs: Box::new(pass.expr(*lhs)),
+            rhs: Box::new(pass.expr(*rhs)),
+        },
+        Expr::Index { table, key } => Expr::Index {
+            table: Box::new(pass.expr(*table)),
+            key: Box::new(pass.expr(*key)),
+        },
+        Expr::FunctionCall {
+            function_name,
+            args,
+        } => Expr::FunctionCall {
+            function_name: Box::new(pass.expr(*function_name)),
+            args: args.into_iter().map(|arg| pass.expr(arg)).collect(),
+        },
+        Expr::FunctionDef {
+            arguments,
+            body,
+            end,
+        } => Expr::FunctionDef {
+            arguments,
+            body: pass.block(body),
+            end,
+        },
+        expr => expr,
+    }
+}
+
+fn literal_value(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::Nil => Some(Value::Nil),
+        Expr::Numeral(n) => Some(Value::Number(*n)),
+        Expr::Fraction(f) => Some(Value::Fraction(*f)),
+        Expr::Boolean(b) => Some(Value::Bool(*b)),
+        Expr::String(s) => Some(Value::String(s.clone())),
+        _ => None,
+    }
+}
+
+fn literal_expr(value: Value) -> Option<Expr> {
+    match value {
+        Value::Nil => Some(Expr::Nil),
+        Value::Number(n) => Some(Expr::Numeral(n)),
+        Value::Fraction(f) => Some(Expr::Fraction(f)),
+        Value::Bool(b) => Some(Expr::Boolean(b)),
+        Value::String(s) => Some(Expr::String(s)),
+        _ => None,
+    }
+}
+
+/// Replaces operations on literals by their result, computed by the same
+/// code the interpreter uses.
+pub fn fold_constants(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    ConstantFolder.block(stmts)
+}
+
+struct ConstantFolder;
+
+impl Pass for ConstantFolder {
+    fn expr(&mut self, expr: Expr) -> Expr {
+        let Expr::BinOp { op, lhs, rhs } = expr else {
+            return walk_expr(self, expr);
+        };
+        let lhs = self.expr(*lhs);
+        // the interpreter skips the right side in these cases
+        match (op, &lhs) {
+            (BinOp::And, Expr::Boolean(false)) => return Expr::Boolean(false),
+            (BinOp::Or, Expr::Boolean(true)) => return Expr::Boolean(true),
+            _ => {}
+        }
+        let rhs = self.expr(*rhs);
+        if let (Some(l), Some(r)) = (literal_value(&lhs), literal_value(&rhs)) {
+            // an error (overflow, division by zero) is left for the program
+            // to run into
+            if let Ok(value) = binop(op, l, r)
+                && let Some(folded) = literal_expr(value)
+            {
+                return folded;
+            }
+        }
+        Expr::BinOp {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
+    }
+}
+
+/// Replaces `if` statements whose condition is a literal by the branch
+/// that always runs.
+pub fn eliminate_dead_branches(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    DeadBranches.block(stmts)
+}
+
+struct DeadBranches;
+
+impl Pass for DeadBranches {
+    fn block(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let mut res = vec![];
+        for stmt in stmts {
+            let stmt = self.stmt(stmt);
+            let StmtKind::If { cond, then, r#else } = stmt.kind else {
+                res.push(stmt);
+                continue;
+            };
+            if literal_value(&cond).is_none() {
+                res.push(Stmt {
+                    kind: StmtKind::If { cond, then, r#else },
+                    ..stmt
+                });
+                continue;
+            }
+            // like the interpreter, anything but `true` takes the else branch
+            let branch = if cond == Expr::Boolean(true) {
+                then
+            } else {
+                r#else
+            };
+            if branch
+                .iter()
+                .any(|s| matches!(s.kind, StmtKind::Local { .. }))
+            {
+                // keep its locals from leaking into this block
+                res.push(Stmt {
+                    kind: StmtKind::DoEnd { body: branch },
+                    ..stmt
+                });
+            } else {
+                res.extend(branch);
+            }
+        }
+        res
+    }
+}
+
+/// A function that is worth inlining: its body is `return <expr>`, where the
+/// expression only uses literals and the parameters.
+#[derive(Debug, Clone)]
+struct Inlinable {
+    params: Vec<Name>,
+    body: Expr,
+}
+
+impl Inlinable {
+    fn new(value: &Expr) -> Option<Self> {
+        let Expr::FunctionDef { arguments, body, .. } = value else {
+            return None;
+        };
+        let [
+            Stmt {
+                kind: StmtKind::Return(exprs),
+                ..
+            },
+        ] = body.as_slice()
+        else {
+            return None;
+        };
+        let [expr] = exprs.as_slice() else {
+            return None;
+        };
+        only_uses(expr, arguments).then(|| Self {
+            params: arguments.clone(),
+            body: expr.clone(),
+        })
+    }
+
+    /// The body with the parameters replaced by `args`, if they are simple
+    /// enough that evaluating them once or not at all makes no difference.
+    fn apply(&self, args: &[Expr]) -> Option<Expr> {
+        let simple = |arg: &Expr| literal_value(arg).is_some() || matches!(arg, Expr::Var(_));
+        if args.len() != self.params.len() || !args.iter().all(simple) {
+            return None;
+        }
+        Some(substitute(&self.body, &self.params, args))
+    }
+}
+
+fn only_uses(expr: &Expr, params: &[Name]) -> bool {
+    match expr {
+        Expr::Var(name) => params.contains(name),
+        Expr::BinOp { lhs, rhs, .. } => only_uses(lhs, params) && only_uses(rhs, params),
+        expr => literal_value(expr).is_some(),
+    }
+}
+
+fn substitute(expr: &Expr, params: &[Name], args: &[Expr]) -> Expr {
+    match expr {
+        Expr::Var(name) => {
+            let i = params.iter().position(|p| p == name).unwrap();
+            args[i].clone()
+        }
+        Expr::BinOp { op, lhs, rhs } => Expr::BinOp {
+            op: *op,
+            lhs: Box::new(substitute(lhs, params, args)),
+            rhs: Box::new(substitute(rhs, params, args)),
+        },
+        expr => expr.clone(),
+    }
+}
+
+/// Replaces calls of small functions by their body.
+///
+/// Only names that are bound exactly once in the whole program qualify, so
+/// the call always refers to that function, and only calls after the
+/// definition in the same block, so it has already run. A program that uses
+/// `debug` can see every call, through `debug.sethook` or `debug.getinfo`,
+/// so nothing in it is inlined.
+pub fn inline_functions(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    if uses_debug(&stmts) {
+        return stmts;
+    }
+    let mut bindings = HashMap::new();
+    count_bindings(&stmts, &mut bindings);
+    Inliner {
+        bindings,
+        known: HashMap::new(),
+    }
+    .block(stmts)
+}
+
+/// Whether the program mentions the `debug` library anywhere.
+fn uses_debug(stmts: &[Stmt]) -> bool {
+    struct Finder(bool);
+
+    impl Pass for Finder {
+        fn expr(&mut self, expr: Expr) -> Expr {
+            if let Expr::Var(name) = &expr {
+                self.0 |= name.name == "debug";
+            }
+            walk_expr(self, expr)
+        }
+    }
+
+    let mut finder = Finder(false);
+    finder.block(stmts.to_vec());
+    finder.0
+}
+
+/// Counts how often every name is assigned, declared or a parameter.
+fn count_bindings(stmts: &[Stmt], counts: &mut HashMap<String, usize>) {
+    struct Counter<'a>(&'a mut HashMap<String, usize>);
+
+    impl Pass for Counter<'_> {
+        fn stmt(&mut self, stmt: Stmt) -> Stmt {
+            if let StmtKind::Assignment { variable, .. } | StmtKind::Local { variable, .. } =
+                &stmt.kind
+            {
+                *self.0.entry(variable.name.clone()).or_default() += 1;
+            }
+            walk_stmt(self, stmt)
+        }
+
+        fn expr(&mut self, expr: Expr) -> Expr {
+            if let Expr::FunctionDef { arguments, .. } = &expr {
+                for argument in arguments {
+                    *self.0.entry(argument.name.clone()).or_default() += 1;
+                }
+            }
+            walk_expr(self, expr)
+        }
+    }
+
+    Counter(counts).block(stmts.to_vec());
+}
+
+struct Inliner {
+    bindings: HashMap<String, usize>,
+    /// Functions defined earlier in the enclosing blocks.
+    known: HashMap<String, Inlinable>,
+}
+
+impl Pass for Inliner {
+    fn block(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let outer = self.known.clone();
+        let mut res = vec![];
+        for stmt in stmts {
+            let stmt = self.stmt(stmt);
+            if let StmtKind::Assignment { variable, value }
+            | StmtKind::Local {
+                variable,
+                value: Some(value),
+            } = &stmt.kind
+                && self.bindings.get(&variable.name) == Some(&1)
+                && let Some(inlinable) = Inlinable::new(value)
+            {
+                self.known.insert(variable.name.clone(), inlinable);
+            }
+            res.push(stmt);
+        }
+        self.known = outer;
+        res
+    }
+
+    fn expr(&mut self, expr: Expr) -> Expr {
+        let expr = walk_expr(self, expr);
+        if let Expr::FunctionCall {
+            function_name,
+            args,
+        } = &expr
+            && let Expr::Var(name) = function_name.as_ref()
+            && let Some(inlined) = self.known.get(&name.name).and_then(|f| f.apply(args))
+        {
+            return inlined;
+        }
+        expr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::LobsterParser;
+
+    macro_rules! optimize_test {
+        ($name:ident, $passes:expr, $source:expr) => {
+            #[test]
+            fn $name() {
+                let parser = LobsterParser::new($source);
+                let result = optimize(parser.parse(), $passes);
+                insta::assert_yaml_snapshot!(result);
+            }
+        };
+    }
+
+    const FOLD: Passes = Passes {
+        fold_constants: true,
+        ..Passes::NONE
+    };
+    const DEAD_BRANCHES: Passes = Passes {
+        eliminate_dead_branches: true,
+        ..Passes::NONE
+    };
+    const INLINE: Passes = Passes {
+        inline_functions: true,
+        ..Passes::NONE
+    };
+
+    optimize_test!(fold_roman_times_fraction, FOLD, "x = MIMIMI * S∷");
+    optimize_test!(fold_nested, FOLD, "x = (I + II) * III - y");
+    optimize_test!(fold_fraction_sum, FOLD, "x = S + ∴ + ·");
+    optimize_test!(fold_comparison, FOLD, "x = II > I and y");
+    optimize_test!(fold_short_circuit, FOLD, "x = false and f()");
+    optimize_test!(
+        fold_inside_function,
+        FOLD,
+        "f = function(a) return a + II * III end"
+    );
+    optimize_test!(fold_keeps_errors, FOLD, "x = I + [[one]]");
+    optimize_test!(fold_keeps_division_by_zero, FOLD, "x = I / 0");
+    optimize_test!(fold_disabled, Passes::NONE, "x = I + I");
+
+    optimize_test!(
+        dead_branch_true,
+        DEAD_BRANCHES,
+        "if true then print(I) else print(II) end"
+    );
+    optimize_test!(
+        dead_branch_non_bool,
+        DEAD_BRANCHES,
+        "if 1 then print(I) else print(II) end"
+    );
+    optimize_test!(
+        dead_branch_keeps_locals_scoped,
+        DEAD_BRANCHES,
+        "if false then else local x = I print(x) end"
+    );
+    optimize_test!(
+        dead_branch_after_folding,
+        Passes::ALL,
+        "if I > II then print(I) end print(II)"
+    );
+    optimize_test!(
+        dead_branch_unknown_condition,
+        DEAD_BRANCHES,
+        "if x then print(I) end"
+    );
+
+    optimize_test!(
+        inline_simple,
+        INLINE,
+        "local double = function(n) return n * II end print(double(x), double(XXI))"
+    );
+    optimize_test!(
+        inline_then_fold,
+        Passes::ALL,
+        "local double = function(n) return n * II end print(double(XXI))"
+    );
+    optimize_test!(
+        inline_not_recursive,
+        INLINE,
+        "f = function(n) return f(n) end print(f(I))"
+    );
+    optimize_test!(
+        inline_not_reassigned,
+        INLINE,
+        "f = function(n) return n end f = print print(f(I))"
+    );
+    optimize_test!(
+        inline_not_before_definition,
+        INLINE,
+        "g = function() return f(I) end f = function(n) return n end print(f(I))"
+    );
+    optimize_test!(
+        inline_not_complex_args,
+        INLINE,
+        "f = function(n) return n + n end print(f(g()))"
+    );
+    optimize_test!(
+        inline_not_with_debug,
+        INLINE,
+        "f = function() return I end debug.sethook(function(e, l) print(e, l) end, [[c]]) print(f())"
+    );
+    optimize_test!(
+        inline_not_free_variables,
+        INLINE,
+        "f = function(n) return n + y end print(f(I))"
+    );
+
+    fn run(source: &str, passes: Passes) -> String {
+        let mut context = crate::Context::new();
+        context.test_stdout = Some(String::new());
+        let ast = optimize(LobsterParser::new(source).parse(), passes);
+        crate::run_block(&ast, &mut context).unwrap();
+        context.test_stdout.unwrap()
+    }
+
+    /// Optimising never changes what a program prints.
+    #[test]
+    fn same_output() {
+        let source = "
+local double = function(n) return n * II end
+half = S
+if double(III) > V then
+    print(double(XXI) * half, MIMIMI * S∷)
+else
+    print([[unreachable]])
+end
+if false then local x = I end
+print(x)
+";
+        assert_eq!(run(source, Passes::ALL), run(source, Passes::NONE));
+    }
+
+    /// A call hook sees the calls an inlined function would not make.
+    #[test]
+    fn same_output_with_hooks() {
+        let source = "
+f = function() return I end
+debug.sethook(function(e, l) print(e, l) end, [[c]])
+print(f())
+";
+        assert_eq!(run(source, Passes::NONE), "call, nil\n1\n");
+        assert_eq!(run(source, Passes::ALL), run(source, Passes::NONE));
+    }
+
+    /// Operators the parser does not produce yet still fail without a
+    /// panic, and are not folded.
+    #[test]
+    fn fold_keeps_operator_errors() {
+        let cases = [
+            (
+                BinOp::Mod,
+                0,
+                "attempt to calculate the remainder with a divisor of zero",
+            ),
+            (BinOp::LShift, 64, "attempt to shift with overflow"),
+            (BinOp::RShift, -1, "attempt to shift with overflow"),
+        ];
+        for (op, r, error) in cases {
+            let expr = Expr::BinOp {
+                op,
+                lhs: Box::new(Expr::Numeral(1)),
+                rhs: Box::new(Expr::Numeral(r)),
+            };
+            assert_eq!(binop(op, Value::Number(1), Value::Number(r)), Err(error.into()));
+            assert_eq!(ConstantFolder.expr(expr.clone()), expr);
+        }
+        assert_eq!(
+            binop(BinOp::Mod, Value::Number(i64::MIN), Value::Number(-1)),
+            Ok(Value::Number(0))
+        );
+    }
+}
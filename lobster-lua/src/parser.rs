@@ -1,9 +1,78 @@
 #![allow(unused)] // TODO
 
-use crate::{fraction::Fraction, tokenizer::{Keyword, Token, Tokenizer}};
+use crate::{fraction::Fraction, numeral::NumberFormat, tokenizer::{Comment, Keyword, Piece, RomanMode, Span, Token, Tokenizer}};
+
+/// A statement together with the source range it was parsed from.
+///
+/// The span is left out of comparisons and snapshots, so two ASTs are equal
+/// when they mean the same thing, wherever they came from.
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl PartialEq for Stmt {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Stmt {}
+
+impl serde::Serialize for Stmt {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.kind.serialize(serializer)
+    }
+}
+
+/// An identifier as written in the source. Like [`Stmt`], it compares and
+/// serializes as just the name.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+#[serde(transparent)]
+pub struct Name {
+    pub name: String,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Name {}
+
+/// Where the `end` of a function definition starts in the source. Like the
+/// span of a [`Name`], it is left out when comparing and serializing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EndPos(pub usize);
+
+impl PartialEq for EndPos {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for EndPos {}
+
+impl std::ops::Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl std::fmt::Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
 
 #[derive(Debug, serde::Serialize,Clone,PartialEq, Eq)]
-pub enum Stmt {
+pub enum StmtKind {
     Break,
     Return(Vec<Expr>),
     While {
@@ -14,11 +83,15 @@ pub enum Stmt {
         body: Vec<Stmt>,
     },
     Assignment {
-        variable: String,
+        variable: Name,
         value: Expr,
     },
+    Local {
+        variable: Name,
+        value: Option<Expr>,
+    },
     FunctionCall {
-        function_name: String,
+        function_name: Expr,
         args: Vec<Expr>,
     },
     If {
@@ -26,6 +99,8 @@ pub enum Stmt {
         then: Vec<Stmt>,
         r#else: Vec<Stmt>,
     },
+    /// Source that failed to parse, see [`LobsterParser::parse_recovering`].
+    Error,
 }
 
 #[derive(Debug, serde::Serialize,Clone,PartialEq, Eq)]
@@ -40,16 +115,24 @@ pub enum Expr {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
-    Var(String),
+    Var(Name),
+    /// `table[key]`, and `table.name` with a string key.
+    Index {
+        table: Box<Expr>,
+        key: Box<Expr>,
+    },
     FunctionCall {
         function_name: Box<Expr>,
         args: Vec<Expr>,
     },
-    #[expect(dead_code)]
     FunctionDef {
-        arguments: Vec<String>,
+        arguments: Vec<Name>,
         body: Vec<Stmt>,
+        #[serde(skip)]
+        end: EndPos,
     },
+    /// A missing expression, see [`LobsterParser::parse_recovering`].
+    Error,
 }
 
 impl Expr {
@@ -67,29 +150,51 @@ impl Expr {
                 rhs.to_s_expr()
             ),
             Expr::Var(name) => name.to_string(),
+            Expr::Index { table, key } => {
+                format!("(index {} {})", table.to_s_expr(), key.to_s_expr())
+            }
             Expr::FunctionCall {
                 function_name,
                 args,
             } => format!(
                 "(call {} {})",
                 function_name.to_s_expr(),
-                args.into_iter()
+                args.iter()
                     .map(|e| e.to_s_expr())
                     .collect::<Vec<_>>()
                     .join(" ")
             ),
-            Expr::FunctionDef { arguments:_, body: _ } => "(fn () <TODO: body>)".to_string(),
+            Expr::FunctionDef { .. } => "(fn () <TODO: body>)".to_string(),
+            Expr::Error => "<error>".to_string(),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct LobsterParser {
-    tokenizer: Tokenizer,
+pub struct LobsterParser<'a> {
+    tokenizer: Tokenizer<'a>,
     current_tok: Token,
     current_pos: usize,
+    current_end: usize,
+    /// Line and column of `current_pos`.
+    current_line_col: (usize, usize),
+    /// Line the previous token was on.
+    prev_line: usize,
+    prev_end: usize,
+    /// Every syntax error so far, the tokenizer's included.
+    errors: Vec<ParseError>,
+    /// The tokenizer rejected something right before the current token.
+    after_lex_error: bool,
+    /// Levels of nesting around the current token, see [`MAX_DEPTH`].
+    depth: usize,
+    /// Print every token read to stderr.
+    trace: bool,
 }
 
+/// How deeply statements and expressions can nest. Everything that walks
+/// the AST recurses, this keeps it from running out of stack.
+pub const MAX_DEPTH: usize = 200;
+
 #[derive(Debug, serde::Serialize,Copy,Clone,PartialEq, Eq)]
 pub enum BinOp {
     Plus,
@@ -116,7 +221,7 @@ pub enum BinOp {
 }
 
 impl BinOp {
-    fn get_precedence(&self) -> (u16, u16) {
+    pub fn get_precedence(&self) -> (u16, u16) {
         match self {
             Self::Or => (10, 11),
             Self::And => (20, 21),
@@ -142,7 +247,7 @@ impl BinOp {
         }
     }
 
-    fn to_s_expr(&self) -> &'static str {
+    pub fn to_s_expr(self) -> &'static str {
         match self {
             BinOp::Plus => "+",
             BinOp::Minus => "-",
@@ -169,63 +274,247 @@ impl BinOp {
     }
 }
 
-impl LobsterParser {
-    pub fn new(source: String) -> Self {
+/// A syntax error, pointing at the token the parser choked on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}..{}", self.message, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl<'a> LobsterParser<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self::with_roman_mode(source, RomanMode::default())
+    }
+
+    /// A parser that reads Roman numerals in `mode` until a pragma says
+    /// otherwise.
+    pub fn with_roman_mode(source: &'a str, mode: RomanMode) -> Self {
         let mut tokenizer = Tokenizer::new(source);
-        let (current_tok, current_pos) = tokenizer.next_token().expect("TODO");
-        Self {
-            current_tok,
-            current_pos,
+        tokenizer.set_roman_mode(mode);
+        Self::from_tokenizer(tokenizer, 0)
+    }
+
+    fn from_tokenizer(tokenizer: Tokenizer<'a>, depth: usize) -> Self {
+        let mut this = Self {
             tokenizer,
+            current_tok: Token::EOF,
+            current_pos: 0,
+            current_end: 0,
+            current_line_col: (1, 1),
+            prev_line: 0,
+            prev_end: 0,
+            errors: vec![],
+            after_lex_error: false,
+            depth,
+            trace: false,
+        };
+        this.advance();
+        this
+    }
+
+    /// Print each token to stderr as it is read, with its line and column.
+    /// The first one has been read already and is printed right away.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+        if trace {
+            self.trace_token();
+        }
+    }
+
+    fn trace_token(&self) {
+        let (line, col) = self.current_line_col;
+        eprintln!("{line}:{col}: {:?}", self.current_tok);
+    }
+
+    pub fn parse(self) -> Vec<Stmt> {
+        self.try_parse().expect("TODO")
+    }
+
+    /// The AST, or the first syntax error in the source.
+    pub fn try_parse(self) -> Result<Vec<Stmt>, ParseError> {
+        Ok(self.parse_with_comments()?.0)
+    }
+
+    /// Like [`LobsterParser::try_parse`], but also hands back every comment
+    /// in the source, for tools that need to reproduce it.
+    pub fn parse_with_comments(mut self) -> Result<(Vec<Stmt>, Vec<Comment>), ParseError> {
+        let res = self.parse_program();
+        if let Some(e) = self.take_errors().into_iter().next() {
+            return Err(e);
         }
+        Ok((res, self.tokenizer.take_comments()))
+    }
+
+    /// Parses as much as it can, and reports every syntax error on the way
+    /// instead of just the first. Whatever failed to parse is left in the
+    /// AST as [`StmtKind::Error`] or [`Expr::Error`].
+    pub fn parse_recovering(mut self) -> (Vec<Stmt>, Vec<ParseError>) {
+        let res = self.parse_program();
+        (res, self.take_errors())
     }
 
-    pub fn parse(mut self) -> Vec<Stmt> {
-        let res = self.parse_block();
-        assert_eq!(self.current_tok, Token::EOF, "TODO");
+    fn parse_program(&mut self) -> Vec<Stmt> {
+        let mut res = self.parse_block();
+        while self.current_tok != Token::EOF {
+            let start = self.current_pos;
+            let e = self.error(format!("Expected end of file, got {:?}", self.current_tok));
+            res.push(self.recover(e, start));
+            res.append(&mut self.parse_block());
+        }
         res
     }
 
+    /// The errors in source order.
+    fn take_errors(&mut self) -> Vec<ParseError> {
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.span.start);
+        errors
+    }
+
+    /// Notes `e` and carries on. An error where the last one was, or right
+    /// after something the tokenizer rejected, is most likely a consequence
+    /// of that, and is left out.
+    fn report(&mut self, e: ParseError) {
+        let follows_lex_error = self.after_lex_error && e.span.start == self.current_pos;
+        if !follows_lex_error
+            && self.errors.last().is_none_or(|last| last.span.start != e.span.start)
+        {
+            self.errors.push(e);
+        }
+    }
+
+    /// Reports `e`, then skips ahead to the next keyword that starts or
+    /// ends a statement, or a name at the start of a line. Returns what was
+    /// skipped, from `start` on, as an error statement.
+    fn recover(&mut self, e: ParseError, start: usize) -> Stmt {
+        self.report(e);
+        if self.current_pos == start {
+            self.advance();
+        }
+        while !syncs(&self.current_tok) && !self.at_line_start_name() {
+            self.advance();
+        }
+        Stmt {
+            kind: StmtKind::Error,
+            span: self.span_from(start),
+        }
+    }
+
+    fn at_line_start_name(&self) -> bool {
+        matches!(self.current_tok, Token::Ident(_)) && self.current_line_col.0 > self.prev_line
+    }
+
+    /// Moves on to the next token, reporting any the tokenizer rejects on
+    /// the way.
     fn advance(&mut self) {
-        println!("advance");
-        let (current_tok, current_pos) = self.tokenizer.next_token().expect("TODO");
-        println!("current_tok: {:?}", current_tok);
-        self.current_pos = current_pos;
-        self.current_tok = current_tok;
+        self.after_lex_error = false;
+        self.tokenizer.set_depth(self.depth);
+        let tok = loop {
+            match self.tokenizer.next_token() {
+                Ok(tok) => break tok,
+                Err(e) => {
+                    self.errors.push(ParseError {
+                        message: e.message,
+                        span: e.span,
+                    });
+                    self.after_lex_error = true;
+                }
+            }
+        };
+        self.prev_end = self.current_end;
+        self.prev_line = self.current_line_col.0;
+        self.current_end = tok.span.end;
+        self.current_pos = tok.span.start;
+        self.current_line_col = (tok.line, tok.col);
+        self.current_tok = tok.value;
+        if self.trace {
+            self.trace_token();
+        }
+    }
+
+    /// An error at the current token.
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            message,
+            span: Span {
+                start: self.current_pos,
+                end: self.current_end,
+            },
+        }
     }
 
+    /// Span from `start` to the end of the last consumed token.
+    fn span_from(&self, start: usize) -> Span {
+        Span {
+            start,
+            end: self.prev_end,
+        }
+    }
+
+    /// Statements up to the end of the block. One that fails to parse is
+    /// reported and recovered from, see [`LobsterParser::recover`].
     fn parse_block(&mut self) -> Vec<Stmt> {
         let mut stmt_list = vec![];
-        loop {
-            if let Token::Keyword(Keyword::Return) = self.current_tok {
-                self.advance();
-                let e = match self.parse_expr() {
-                    None => {
-                        stmt_list.push(Stmt::Return(vec![]));
-                        break;
-                    }
-                    Some(e) => e,
-                };
-                let mut values = vec![e];
-                while self.current_tok == Token::Comma {
-                    self.advance();
-                    values.push(self.parse_expr().expect("todo"));
-                }
-                if self.current_tok == Token::Semicolon {
-                    self.advance();
+        // Checked up front, as a statement that is nested too deeply fails
+        // before it gets to look at the token.
+        while !ends_block(&self.current_tok) {
+            let start = self.current_pos;
+            let is_return = self.current_tok == Token::Keyword(Keyword::Return);
+            let stmt = if is_return {
+                self.parse_return().map(Some)
+            } else {
+                self.parse_stmt()
+            };
+            match stmt {
+                Ok(Some(stmt)) => stmt_list.push(stmt),
+                Ok(None) => {
+                    let e = self.error(format!("Unexpected {:?}", self.current_tok));
+                    stmt_list.push(self.recover(e, start));
                 }
-                stmt_list.push(Stmt::Return(values));
-                break;
+                Err(e) => stmt_list.push(self.recover(e, start)),
             }
-            if let Some(next_stmt) = self.parse_stmt() {
-                stmt_list.push(next_stmt);
-            } else {
+            if is_return && ends_block(&self.current_tok) {
                 break;
             }
+            if is_return {
+                self.report(self.error(format!(
+                    "Expected end of block after return, got {:?}",
+                    self.current_tok
+                )));
+            }
         }
         stmt_list
     }
 
+    /// `return [explist] [;]`, which has to be last in its block.
+    fn parse_return(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.current_pos;
+        self.advance();
+        let mut values = vec![];
+        if let Some(e) = self.parse_expr()? {
+            values.push(e);
+            while self.current_tok == Token::Comma {
+                self.advance();
+                values.push(self.parse_required_expr()?);
+            }
+        }
+        if self.current_tok == Token::Semicolon {
+            self.advance();
+        }
+        Ok(Stmt {
+            kind: StmtKind::Return(values),
+            span: self.span_from(start),
+        })
+    }
+
     fn peak_binop(&mut self) -> Option<BinOp> {
         match &self.current_tok {
             Token::Plus => Some(BinOp::Plus),
@@ -253,39 +542,54 @@ impl LobsterParser {
         }
     }
 
-    fn parse_stmt(&mut self) -> Option<Stmt> {
+    fn parse_stmt(&mut self) -> Result<Option<Stmt>, ParseError> {
+        let start = self.current_pos;
+        let depth = self.depth;
+        self.nest()?;
+        let kind = self.parse_stmt_kind();
+        self.depth = depth;
+        let Some(kind) = kind? else {
+            return Ok(None);
+        };
+        Ok(Some(Stmt {
+            kind,
+            span: self.span_from(start),
+        }))
+    }
+
+    fn parse_stmt_kind(&mut self) -> Result<Option<StmtKind>, ParseError> {
         match &self.current_tok {
             Token::Keyword(Keyword::Break) => {
                 self.advance();
-                Some(Stmt::Break)
+                Ok(Some(StmtKind::Break))
             }
             Token::Keyword(Keyword::While) => {
                 self.advance();
-                let cond = self.parse_expr().expect("todo");
+                let cond = self.parse_required_expr()?;
                 self.expect(&Token::Keyword(Keyword::Do));
                 let body = self.parse_block();
                 self.expect(&Token::Keyword(Keyword::End));
-                Some(Stmt::While { cond, body })
+                Ok(Some(StmtKind::While { cond, body }))
             }
             //Do End
             Token::Keyword(Keyword::Do) => {
                 self.advance();
                 let block = self.parse_block();
                 self.expect(&Token::Keyword(Keyword::End));
-                Some(Stmt::DoEnd { body: block })
+                Ok(Some(StmtKind::DoEnd { body: block }))
             }
             Token::Keyword(Keyword::If) => {
                 self.advance();
-                let cond = self.parse_expr().expect("todo");
+                let cond = self.parse_required_expr()?;
                 self.expect(&Token::Keyword(Keyword::Then));
                 let then = self.parse_block();
 
-                let mut whole = Stmt::If {
+                let mut whole = StmtKind::If {
                     cond,
                     then,
                     r#else: vec![],
                 };
-                let Stmt::If {
+                let StmtKind::If {
                     r#else: else_placeholder,
                     ..
                 } = &mut whole
@@ -295,19 +599,32 @@ impl LobsterParser {
                 let mut else_placeholder = else_placeholder;
 
                 while self.current_tok == Token::Keyword(Keyword::ElseIf) {
+                    let elseif_start = self.current_pos;
                     self.advance();
-                    let cond = self.parse_expr().expect("todo");
+                    let cond = self.parse_required_expr()?;
                     self.expect(&Token::Keyword(Keyword::Then));
                     let then = self.parse_block();
 
-                    *else_placeholder = vec![Stmt::If {
-                        cond,
-                        then,
-                        r#else: vec![],
+                    // The span of an `elseif` runs from its keyword to the
+                    // end of the whole `if`, fixed up below.
+                    *else_placeholder = vec![Stmt {
+                        kind: StmtKind::If {
+                            cond,
+                            then,
+                            r#else: vec![],
+                        },
+                        span: Span {
+                            start: elseif_start,
+                            end: elseif_start,
+                        },
                     }];
                     let [
-                        Stmt::If {
-                            r#else: else_placeholder2,
+                        Stmt {
+                            kind:
+                                StmtKind::If {
+                                    r#else: else_placeholder2,
+                                    ..
+                                },
                             ..
                         },
                     ] = &mut else_placeholder[..]
@@ -327,61 +644,86 @@ impl LobsterParser {
                         self.expect(&Token::Keyword(Keyword::End));
                         *else_placeholder = else_block;
                     }
-                    _ => todo!(),
+                    _ => {
+                        self.report(self.error(format!(
+                            "Expected Keyword(End) or Keyword(Else), got {:?}",
+                            self.current_tok
+                        )));
+                    }
                 }
-                Some(whole)
+                fix_elseif_spans(&mut whole, self.prev_end);
+                Ok(Some(whole))
             }
-            Token::Ident(ident) => {
-                let ident = ident.clone();
+            Token::Keyword(Keyword::Local) => {
                 self.advance();
-                match self.current_tok {
-                    Token::Equals => {
-                        self.advance();
-                        let variable = ident;
-                        let value = self.parse_expr().expect("todo");
-                        Some(Stmt::Assignment { variable, value })
-                        // Assignment
-                    }
-                    Token::ParOpen => {
-                        self.advance();
-                        let mut args = vec![];
-                        while let Some(arg) = self.parse_expr() {
-                            args.push(arg);
-                            if self.current_tok == Token::Comma {
-                                self.advance();
-                            } else {
-                                break;
-                            }
-                        }
-                        self.expect(&Token::ParClose);
-                        Some(Stmt::FunctionCall {
-                            function_name: ident,
-                            args,
-                        })
-
-                        // Function call
-                    }
-                    _ => panic!("unexpected token"),
+                let variable = self.parse_argument()?;
+                let value = if self.current_tok == Token::Equals {
+                    self.advance();
+                    Some(self.parse_required_expr()?)
+                } else {
+                    None
+                };
+                Ok(Some(StmtKind::Local { variable, value }))
+            }
+            Token::Ident(_) => {
+                let ident = self.parse_argument()?;
+                if self.current_tok == Token::Equals {
+                    // Assignment
+                    self.advance();
+                    let variable = ident;
+                    let value = self.parse_required_expr()?;
+                    return Ok(Some(StmtKind::Assignment { variable, value }));
+                }
+                match self.parse_suffixes(Expr::Var(ident))? {
+                    // Function call
+                    Expr::FunctionCall {
+                        function_name,
+                        args,
+                    } => Ok(Some(StmtKind::FunctionCall {
+                        function_name: *function_name,
+                        args,
+                    })),
+                    _ => Err(self.error(format!(
+                        "Expected Equals or ParOpen, got {:?}",
+                        self.current_tok
+                    ))),
                 }
             }
-            _ => None,
+            _ => Ok(None),
         }
     }
 
-    #[track_caller]
+    /// Consumes `tok`. If it is not there, reports that and carries on as if
+    /// it was.
     fn expect(&mut self, tok: &Token) {
         if &self.current_tok == tok {
-            self.advance();
+            self.advance()
         } else {
-            panic!("Expected {tok:?}, got {:?}", self.current_tok);
+            self.report(self.error(format!("Expected {tok:?}, got {:?}", self.current_tok)))
         }
     }
 
-    fn parse_atomic_expr(&mut self) -> Option<Expr> {
+    /// `( [explist] )`, starting at the opening parenthesis.
+    fn parse_args(&mut self) -> Result<Vec<Expr>, ParseError> {
+        self.expect(&Token::ParOpen);
+        let mut args = vec![];
+        while let Some(arg) = self.parse_expr()? {
+            args.push(arg);
+            if self.current_tok == Token::Comma {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        self.expect(&Token::ParClose);
+        Ok(args)
+    }
+
+    fn parse_atomic_expr(&mut self) -> Result<Option<Expr>, ParseError> {
         let expr = match &self.current_tok {
             Token::ParOpen => {
                 self.advance();
-                let res = self.parse_expr().expect("TODO");
+                let res = self.parse_required_expr()?;
                 self.expect(&Token::ParClose);
                 res
             }
@@ -410,10 +752,15 @@ impl LobsterParser {
                 self.advance();
                 Expr::String(s)
             }
-            Token::Ident(name) => {
-                let name = name.clone();
+            Token::Ident(_) => Expr::Var(self.parse_argument()?),
+            Token::Interpolated(pieces) => {
+                let pieces = pieces.clone();
+                let span = Span {
+                    start: self.current_pos,
+                    end: self.current_end,
+                };
                 self.advance();
-                Expr::Var(name)
+                desugar_interpolation(pieces, span)
             }
             Token::Keyword(Keyword::Function) => {
                 self.advance();
@@ -421,75 +768,272 @@ impl LobsterParser {
 
                 let mut arguments = vec![];
                 if self.current_tok != Token::ParClose { 
-                    arguments.push(self.parse_argument());
+                    arguments.push(self.parse_argument()?);
                     loop {
                         if self.current_tok == Token::ParClose { break; }
                         self.expect(&Token::Comma);
-                        arguments.push(self.parse_argument());
+                        arguments.push(self.parse_argument()?);
                     }
                 }
                 self.expect(&Token::ParClose); 
 
                 let body = self.parse_block();
+                let end = EndPos(self.current_pos);
                 self.expect(&Token::Keyword(Keyword::End));
 
-                Expr::FunctionDef { arguments, body }
+                Expr::FunctionDef { arguments, body, end }
             }
-            _ => return None,
+            _ => return Ok(None),
         };
-        eprintln!("expr {expr:?} — {:?}", self.current_tok);
-        if self.current_tok == Token::ParOpen {
-            self.advance();
-            // function call
-            let mut args = vec![];
-            while let Some(arg) = self.parse_expr() {
-                args.push(arg);
-                if self.current_tok == Token::Comma {
+        Ok(Some(self.parse_suffixes(expr)?))
+    }
+
+    /// Calls and indexing after a prefix expression: `f(x).y[z]`.
+    fn parse_suffixes(&mut self, mut expr: Expr) -> Result<Expr, ParseError> {
+        loop {
+            if matches!(self.current_tok, Token::ParOpen | Token::Dot | Token::SqParOpen) {
+                self.nest()?;
+            }
+            expr = match self.current_tok {
+                // function call
+                Token::ParOpen => Expr::FunctionCall {
+                    function_name: Box::new(expr),
+                    args: self.parse_args()?,
+                },
+                Token::Dot => {
                     self.advance();
-                } else {
-                    break;
+                    let field = self.parse_argument()?;
+                    Expr::Index {
+                        table: Box::new(expr),
+                        key: Box::new(Expr::String(field.name)),
+                    }
                 }
+                Token::SqParOpen => {
+                    self.advance();
+                    let key = self.parse_required_expr()?;
+                    self.expect(&Token::SqParClose);
+                    Expr::Index {
+                        table: Box::new(expr),
+                        key: Box::new(key),
+                    }
+                }
+                _ => return Ok(expr),
             }
-            self.expect(&Token::ParClose);
-            Some(Expr::FunctionCall {
-                function_name: Box::new(expr),
-                args,
-            })
-        } else {
-            Some(expr)
         }
     }
 
-    fn parse_expr_inner(&mut self, minimum_binding_power: u16) -> Option<Expr> {
-        let mut lhs = self.parse_atomic_expr()?;
+    /// Goes a level deeper, unless that would be more than [`MAX_DEPTH`].
+    fn nest(&mut self) -> Result<(), ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(too_deep(self.error(String::new()).span));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn parse_expr_inner(&mut self, minimum_binding_power: u16) -> Result<Option<Expr>, ParseError> {
+        let depth = self.depth;
+        let res = self.parse_binops(minimum_binding_power);
+        self.depth = depth;
+        res
+    }
+
+    /// Every operator makes the tree a level deeper, also the left
+    /// associative ones that are parsed in a loop.
+    fn parse_binops(&mut self, minimum_binding_power: u16) -> Result<Option<Expr>, ParseError> {
+        self.nest()?;
+        let Some(mut lhs) = self.parse_atomic_expr()? else {
+            return Ok(None);
+        };
         while let Some(op) = self.peak_binop() {
             let (l_prec, r_prec) = op.get_precedence();
             assert_ne!(minimum_binding_power, l_prec);
             if l_prec < minimum_binding_power {
                 break;
             }
+            self.nest()?;
             self.advance();
-            let rhs = self.parse_expr_inner(r_prec).expect("TODO");
+            let rhs = match self.parse_expr_inner(r_prec)? {
+                Some(rhs) => rhs,
+                None => self.missing_expr(),
+            };
             lhs = Expr::BinOp {
                 op,
                 lhs: Box::new(lhs),
                 rhs: Box::new(rhs),
             }
         }
-        Some(lhs)
+        Ok(Some(lhs))
     }
 
-    fn parse_expr(&mut self) -> Option<Expr> {
+    fn parse_expr(&mut self) -> Result<Option<Expr>, ParseError> {
         self.parse_expr_inner(0)
     }
 
-    fn parse_argument(&mut self) -> String {
+    /// Parses the whole source as a single expression, for evaluating
+    /// watch expressions and the like.
+    pub fn parse_expression(mut self) -> Result<Expr, ParseError> {
+        let expr = self.parse_required_expr()?;
+        if self.current_tok != Token::EOF {
+            self.report(self.error(format!("Expected end of expression, got {:?}", self.current_tok)));
+        }
+        match self.take_errors().into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(expr),
+        }
+    }
+
+    fn parse_required_expr(&mut self) -> Result<Expr, ParseError> {
+        match self.parse_expr()? {
+            Some(expr) => Ok(expr),
+            None => Ok(self.missing_expr()),
+        }
+    }
+
+    /// Reports a missing expression, and stands in for it.
+    fn missing_expr(&mut self) -> Expr {
+        self.report(self.error(format!("Expected expression, got {:?}", self.current_tok)));
+        Expr::Error
+    }
+
+    fn parse_argument(&mut self) -> Result<Name, ParseError> {
         let arg = match &self.current_tok {
-            Token::Ident(name) => name.clone(),
-            _ => panic!("Expected identifier, got {:?}", self.current_tok),
+            Token::Ident(name) => Name {
+                name: name.clone(),
+                span: Span {
+                    start: self.current_pos,
+                    end: self.current_end,
+                },
+            },
+            _ => return Err(self.error(format!("Expected identifier, got {:?}", self.current_tok))),
         };
         self.advance();
-        arg
+        Ok(arg)
+    }
+}
+
+/// Parses the `expr` or `expr:format` of an interpolation, from `start`
+/// right after the `{`. Returns it with the offset after the closing `}`,
+/// and adds the spans of the number literals in it to `literals`.
+pub(crate) fn parse_interpolation(
+    source: &str,
+    start: usize,
+    mode: RomanMode,
+    depth: usize,
+    literals: &mut Vec<Span>,
+) -> Result<(Expr, Option<NumberFormat>, usize), ParseError> {
+    // checked before reading the first token, which can be an interpolated
+    // string itself
+    if depth == MAX_DEPTH {
+        return Err(too_deep(Span { start, end: start }));
+    }
+    let mut tokenizer = Tokenizer::new(source);
+    tokenizer.set_roman_mode(mode);
+    tokenizer.seek(start);
+    let mut parser = LobsterParser::from_tokenizer(tokenizer, depth + 1);
+    let result = parser.parse_interpolation();
+    literals.extend(parser.tokenizer.take_literals());
+    match (result, parser.take_errors().into_iter().next()) {
+        (_, Some(e)) | (Err(e), None) => Err(e),
+        (Ok(result), None) => Ok(result),
+    }
+}
+
+impl LobsterParser<'_> {
+    fn parse_interpolation(&mut self) -> Result<(Expr, Option<NumberFormat>, usize), ParseError> {
+        let expr = self.parse_required_expr()?;
+        // `:` on its own is the uncia glyph for 1/6
+        let mut format = None;
+        if self.tokenizer.source()[self.current_pos..].starts_with(':') {
+            self.tokenizer.unread_literal(self.current_pos);
+            self.advance();
+            let name = self.parse_argument()?;
+            format = Some(NumberFormat::from_name(&name).ok_or_else(|| ParseError {
+                message: format!("unknown number format `{name}`, expected decimal, roman or mixed"),
+                span: name.span,
+            })?);
+        }
+        if self.current_tok != Token::BraceClose {
+            return Err(self.error(format!("Expected BraceClose, got {:?}", self.current_tok)));
+        }
+        Ok((expr, format, self.current_end))
+    }
+}
+
+fn too_deep(span: Span) -> ParseError {
+    ParseError {
+        message: format!("Nested too deeply, the limit is {MAX_DEPTH} levels"),
+        span,
+    }
+}
+
+/// `f[[a{x}b{y:roman}]]` is `[[a]] .. tostring(x) .. [[b]] .. tostring(y, [[roman]])`.
+fn desugar_interpolation(pieces: Vec<Piece>, span: Span) -> Expr {
+    let mut parts: Vec<_> = pieces
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Text(text) => Expr::String(text),
+            Piece::Expr { expr, format } => {
+                let mut args = vec![expr];
+                args.extend(format.map(|format| Expr::String(format.name().to_owned())));
+                Expr::FunctionCall {
+                    function_name: Box::new(Expr::Var(Name {
+                        name: "tostring".to_owned(),
+                        span,
+                    })),
+                    args,
+                }
+            }
+        })
+        .collect();
+    // `..` is right-associative, so `a .. b .. c` is `a .. (b .. c)`
+    let mut expr = parts.pop().unwrap_or(Expr::String(String::new()));
+    while let Some(lhs) = parts.pop() {
+        expr = Expr::BinOp {
+            op: BinOp::Concat,
+            lhs: Box::new(lhs),
+            rhs: Box::new(expr),
+        };
+    }
+    expr
+}
+
+/// Tokens that end a block, so a statement cannot start with them.
+fn ends_block(tok: &Token) -> bool {
+    matches!(
+        tok,
+        Token::EOF
+            | Token::Keyword(Keyword::End | Keyword::Else | Keyword::ElseIf | Keyword::Until)
+    )
+}
+
+/// Where parsing picks up again after an error.
+fn syncs(tok: &Token) -> bool {
+    ends_block(tok)
+        || matches!(
+            tok,
+            Token::Keyword(
+                Keyword::Local
+                    | Keyword::Function
+                    | Keyword::If
+                    | Keyword::While
+                    | Keyword::Do
+                    | Keyword::For
+                    | Keyword::Repeat
+                    | Keyword::Break
+                    | Keyword::Return
+                    | Keyword::Goto
+            )
+        )
+}
+
+fn fix_elseif_spans(stmt: &mut StmtKind, end: usize) {
+    if let StmtKind::If { r#else, .. } = stmt
+        && let [Stmt { kind, span }] = &mut r#else[..]
+        && span.start == span.end
+    {
+        span.end = end;
+        fix_elseif_spans(kind, end);
     }
 }
 
@@ -498,8 +1042,8 @@ mod tests {
     use super::*;
 
     fn check_expr(s: &str, expected: &str) {
-        let mut parser = LobsterParser::new(s.to_owned());
-        let expr = parser.parse_expr().unwrap();
+        let mut parser = LobsterParser::new(s);
+        let expr = parser.parse_expr().unwrap().unwrap();
         assert_eq!(expr.to_s_expr(), expected, "failed when parsing {s:?}");
     }
 
@@ -517,6 +1061,12 @@ mod tests {
     test_expr!(test_expr_variable, "x", "x");
     // test_expr!(test_expr_parens, "1 + (2 + 3)", "(+ 1 (+ 2 3))");
     test_expr!(test_expr_precedence, "1 + 2 * 3", "(+ 1 (* 2 3))");
+    test_expr!(test_expr_field, "a.b.c", "(index (index a \"b\") \"c\")");
+    test_expr!(
+        test_expr_call_chain,
+        "f(1)[x](2)",
+        "(call (index (call f 1) x) 2)"
+    );
     test_expr!(
         test_expr_right_assoc_exp,
         "123^456^789",
@@ -527,7 +1077,7 @@ mod tests {
         ($name:ident, $source:expr) => {
             #[test]
             fn $name() {
-                let parser = LobsterParser::new($source.to_string());
+                let parser = LobsterParser::new($source);
                 let result = parser.parse();
                 insta::assert_yaml_snapshot!(result);
             }
@@ -549,6 +1099,10 @@ mod tests {
     // Left associativity of `and`: 1 and 2 and 3 => (1 and 2) and 3
     parse_test!(test_precedence_and_left_associative, "x = 1 and 2 and 3");
 
+    parse_test!(call_field, "debug.sethook(f, [[l]])");
+    parse_test!(local_with_value, "local x = 1");
+    parse_test!(local_without_value, "local x");
+
     parse_test!(return_something, "return 42");
     parse_test!(return_nothing, "return");
     parse_test!(return_inside_block, "do break return [[]] end");
@@ -557,4 +1111,94 @@ mod tests {
         break_break_mic_check_do_you_read,
         "if nil then break elseif nil then break break else break break break end"
     );
+
+    parse_test!(
+        interpolation,
+        "print(f[[Total: {sum} ({sum / XII:mixed} per month)]], `{x}`, f[[]])"
+    );
+
+    /// An f-string is sugar for what the formatter writes out for it.
+    #[test]
+    fn interpolation_desugars() {
+        let sugar = LobsterParser::new("x = `a {y} b {z:roman}`").parse();
+        let plain =
+            LobsterParser::new("x = [[a ]] .. tostring(y) .. [[ b ]] .. tostring(z, [[roman]])")
+                .parse();
+        assert_eq!(sugar, plain);
+    }
+
+    #[test]
+    fn interpolation_depth_counts() {
+        let n = MAX_DEPTH + 1;
+        let source = format!("x = {}I{}", "`{".repeat(n), "}`".repeat(n));
+        let err = LobsterParser::new(&source).try_parse().unwrap_err();
+        assert_eq!(err.message, "Nested too deeply, the limit is 200 levels");
+    }
+
+    #[test]
+    fn strict_roman_error() {
+        let parser = LobsterParser::with_roman_mode("x = I + IIII", RomanMode::Strict);
+        let err = parser.try_parse().unwrap_err();
+        assert_eq!(err.span, Span { start: 8, end: 12 });
+        assert_eq!(err.message, "`IIII` is not a canonical Roman numeral, write `IV` for 4");
+    }
+
+    fn parse_recovering(source: &str) -> (Vec<Stmt>, Vec<String>) {
+        let (ast, errors) = LobsterParser::new(source).parse_recovering();
+        (ast, errors.iter().map(ParseError::to_string).collect())
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let (ast, errors) = parse_recovering(
+            "x =\nwhile y print(y) end\nlocal 5\nf(1 2)\nz = 1 $ + 2\nif z then",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "Expected expression, got Keyword(While) at 4..9",
+                "Expected Keyword(Do), got Ident(\"print\") at 12..17",
+                "Expected identifier, got NumberLiteral(5) at 31..32",
+                "Expected ParClose, got NumberLiteral(2) at 37..38",
+                "Unhappy? at 46..47",
+                "Expected Keyword(End) or Keyword(Else), got EOF at 61..61",
+            ]
+        );
+        insta::assert_yaml_snapshot!(ast);
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let (ast, errors) = parse_recovering("while x do\n  y = )\n  print(y)\nend\nprint(x)");
+        assert_eq!(errors, vec!["Expected expression, got ParClose at 17..18"]);
+        insta::assert_yaml_snapshot!(ast);
+    }
+
+    #[test]
+    fn stray_tokens_are_skipped() {
+        let (ast, errors) = parse_recovering("end ) elseif ( ,\nx = 1");
+        assert_eq!(
+            errors,
+            vec![
+                "Expected end of file, got Keyword(End) at 0..3",
+                "Expected end of file, got Keyword(ElseIf) at 6..12",
+            ]
+        );
+        assert_eq!(ast.len(), 3);
+        assert!(matches!(&ast[2].kind, StmtKind::Assignment { variable, .. } if variable.name == "x"));
+    }
+
+    #[test]
+    fn strict_roman_inside_interpolation() {
+        let parser = LobsterParser::with_roman_mode("x = f[[{I + IIII}]]", RomanMode::Strict);
+        let err = parser.try_parse().unwrap_err();
+        assert_eq!(err.span, Span { start: 12, end: 16 });
+    }
+
+    #[test]
+    fn romans_off() {
+        let parser = LobsterParser::with_roman_mode("MIX = 1", RomanMode::Off);
+        let ast = parser.parse();
+        assert!(matches!(&ast[0].kind, StmtKind::Assignment { variable, .. } if variable.name == "MIX"));
+    }
 }
@@ -0,0 +1,279 @@
+//! `--profile`: where a script spends its time.
+//!
+//! The profiler is a [`Hook`], so all it sees are statements starting. The
+//! time from one statement starting to the next is charged to the first,
+//! together with the stack of functions it ran in; builtins count towards
+//! the statement that called them. A call is counted when its first
+//! statement runs, so a function without statements never shows up.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::parser::Stmt;
+use crate::{Context, Hook};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FunctionStats {
+    calls: usize,
+    self_time: Duration,
+    /// Including the functions it called.
+    total_time: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct LineStats {
+    hits: usize,
+    time: Duration,
+}
+
+/// The statement running since `last_time`.
+struct Running {
+    stack: Vec<String>,
+    line: usize,
+}
+
+struct Profile {
+    clock: Box<dyn FnMut() -> Duration>,
+    chunk_name: String,
+    running: Option<Running>,
+    last_time: Duration,
+    /// Highest `Frame::id` seen, every higher one is a new call.
+    last_call: usize,
+    total: Duration,
+    functions: HashMap<String, FunctionStats>,
+    lines: HashMap<(String, usize), LineStats>,
+    stacks: BTreeMap<String, Duration>,
+}
+
+impl Profile {
+    fn charge_running(&mut self, now: Duration) {
+        let elapsed = now.saturating_sub(self.last_time);
+        self.last_time = now;
+        let Some(running) = self.running.take() else {
+            return;
+        };
+        let function = running.stack.last().unwrap().clone();
+        self.total += elapsed;
+        self.functions
+            .entry(function.clone())
+            .or_default()
+            .self_time += elapsed;
+        let mut counted: Vec<&String> = vec![];
+        for name in &running.stack {
+            if !counted.contains(&name) {
+                counted.push(name);
+                self.functions.entry(name.clone()).or_default().total_time += elapsed;
+            }
+        }
+        self.lines.entry((function, running.line)).or_default().time += elapsed;
+        *self.stacks.entry(running.stack.join(";")).or_default() += elapsed;
+    }
+
+    fn before_stmt(&mut self, context: &Context, line: usize) {
+        let now = (self.clock)();
+        self.charge_running(now);
+        self.chunk_name = context.chunk_name().to_owned();
+        let frames = context.frames();
+        let top = frames.last().unwrap();
+        let stack: Vec<String> = frames.iter().map(|f| f.function.clone()).collect();
+        if top.id > self.last_call || self.functions.is_empty() {
+            self.last_call = top.id;
+            self.functions
+                .entry(top.function.clone())
+                .or_default()
+                .calls += 1;
+        }
+        self.lines
+            .entry((top.function.clone(), line))
+            .or_default()
+            .hits += 1;
+        self.running = Some(Running { stack, line });
+    }
+}
+
+/// Collects a profile through the hook from [`Profiler::hook`].
+pub struct Profiler(Rc<RefCell<Profile>>);
+
+struct ProfilerHook(Rc<RefCell<Profile>>);
+
+impl Hook for ProfilerHook {
+    fn before_stmt(&mut self, stmt: &Stmt, context: &mut Context) {
+        let line = context.line_of(stmt.span.start);
+        self.0.borrow_mut().before_stmt(context, line);
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let start = Instant::now();
+        Self::with_clock(Box::new(move || start.elapsed()))
+    }
+
+    /// A profiler that reads the time from `clock`, which should count up
+    /// from when the program starts.
+    pub fn with_clock(clock: Box<dyn FnMut() -> Duration>) -> Self {
+        Self(Rc::new(RefCell::new(Profile {
+            clock,
+            chunk_name: String::new(),
+            running: None,
+            last_time: Duration::ZERO,
+            last_call: 0,
+            total: Duration::ZERO,
+            functions: HashMap::new(),
+            lines: HashMap::new(),
+            stacks: BTreeMap::new(),
+        })))
+    }
+
+    /// The hook to give to [`Context::add_hook`].
+    pub fn hook(&self) -> Box<dyn Hook> {
+        Box::new(ProfilerHook(self.0.clone()))
+    }
+
+    /// Charges the last statement, call this when the program is done.
+    pub fn finish(&self) {
+        let mut profile = self.0.borrow_mut();
+        let now = (profile.clock)();
+        profile.charge_running(now);
+    }
+
+    /// A table of functions and one of lines, each sorted by the time spent
+    /// in them.
+    pub fn flat(&self) -> String {
+        let profile = self.0.borrow();
+        let percent = |time: Duration| {
+            if profile.total.is_zero() {
+                0.0
+            } else {
+                100.0 * time.as_secs_f64() / profile.total.as_secs_f64()
+            }
+        };
+        let mut res = format!(
+            "{:>7}  {:>10}  {:>10}  {:>6}  function\n",
+            "self %", "self", "total", "calls"
+        );
+        let mut functions: Vec<_> = profile.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.self_time.cmp(&a.1.self_time).then(a.0.cmp(b.0)));
+        for (name, stats) in functions {
+            res.push_str(&format!(
+                "{:>6.2}%  {:>10}  {:>10}  {:>6}  {name}\n",
+                percent(stats.self_time),
+                millis(stats.self_time),
+                millis(stats.total_time),
+                stats.calls
+            ));
+        }
+        res.push_str(&format!(
+            "\n{:>7}  {:>10}  {:>6}  line\n",
+            "self %", "self", "hits"
+        ));
+        let mut lines: Vec<_> = profile.lines.iter().collect();
+        lines.sort_by(|a, b| {
+            b.1.time
+                .cmp(&a.1.time)
+                .then(a.0.1.cmp(&b.0.1))
+                .then(a.0.0.cmp(&b.0.0))
+        });
+        for ((function, line), stats) in lines {
+            res.push_str(&format!(
+                "{:>6.2}%  {:>10}  {:>6}  {}:{line} in {function}\n",
+                percent(stats.time),
+                millis(stats.time),
+                stats.hits,
+                profile.chunk_name
+            ));
+        }
+        res
+    }
+
+    /// One line per stack with the microseconds spent in it, the input
+    /// `flamegraph.pl` and friends expect.
+    pub fn folded(&self) -> String {
+        let profile = self.0.borrow();
+        let mut res = String::new();
+        for (stack, time) in &profile.stacks {
+            let micros = time.as_micros();
+            if micros > 0 {
+                res.push_str(&format!("{stack} {micros}\n"));
+            }
+        }
+        res
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn millis(time: Duration) -> String {
+    format!("{:.3}ms", time.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::LobsterParser;
+    use crate::run_block;
+
+    /// Profiles `source` with a clock that ticks a millisecond every time
+    /// it is read, so every statement takes exactly that long.
+    fn profile(source: &str) -> Profiler {
+        let mut ticks = 0;
+        let profiler = Profiler::with_clock(Box::new(move || {
+            ticks += 1;
+            Duration::from_millis(ticks)
+        }));
+        let mut context = Context::new();
+        context.test_stdout = Some(String::new());
+        context.set_source("test.lua", source);
+        context.add_hook(profiler.hook());
+        run_block(&LobsterParser::new(source).parse(), &mut context).unwrap();
+        profiler.finish();
+        profiler
+    }
+
+    const SOURCE: &str = "\
+f = function(n)
+  x = n
+  return x
+end
+f(I)
+f(II)
+g = function() return f(III) end
+g()
+";
+
+    #[test]
+    fn flat_profile() {
+        insta::assert_snapshot!(profile(SOURCE).flat());
+    }
+
+    #[test]
+    fn folded_stacks() {
+        assert_eq!(
+            profile(SOURCE).folded(),
+            "main chunk 5000\nmain chunk;f 4000\nmain chunk;g 1000\nmain chunk;g;f 2000\n"
+        );
+    }
+
+    #[test]
+    fn recursion_counts_total_time_once() {
+        let source = "\
+f = function(n)
+  if n == 0 then return 0 end
+  return f(n - 1)
+end
+f(II)
+";
+        let profiler = profile(source);
+        let profile = profiler.0.borrow();
+        let f = profile.functions["f"];
+        assert_eq!(f.calls, 3);
+        assert_eq!(f.total_time, Duration::from_millis(6));
+        assert_eq!(profile.total, Duration::from_millis(8));
+    }
+}
@@ -0,0 +1,185 @@
+//! `lobster-lua repl`: reads a line, runs it, prints the result.
+//!
+//! Lines starting with `:` are commands for the REPL itself, see [`HELP`].
+
+use std::io::{self, BufRead, Write};
+
+use crate::numeral::{NumberFormat, format_value};
+use crate::parser::{Expr, LobsterParser, ParseError};
+use crate::tokenizer::RomanMode;
+use crate::{Context, Value, eval, run_block};
+
+const HELP: &str = "\
+:roman [creative|strict|off]   show or set how Roman numerals are read
+:numbers [decimal|roman|mixed] show or set how numbers are printed
+:help                          show this
+:quit                          leave
+";
+
+const PROMPT: &str = "> ";
+
+pub struct Repl {
+    context: Context,
+    roman_mode: RomanMode,
+}
+
+impl Repl {
+    pub fn new(roman_mode: RomanMode) -> Self {
+        Self {
+            context: Context::new(),
+            roman_mode,
+        }
+    }
+
+    pub fn context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Reads lines until `:quit` or the end of `input`.
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        loop {
+            write!(output, "{PROMPT}")?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 || line.trim() == ":quit" {
+                return Ok(());
+            }
+            self.line(line.trim_end(), &mut output)?;
+        }
+    }
+
+    fn line(&mut self, line: &str, output: &mut impl Write) -> io::Result<()> {
+        if let Some(command) = line.strip_prefix(':') {
+            return self.command(command, output);
+        }
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        // an expression prints its value, anything else is run as statements
+        let parser = LobsterParser::with_roman_mode(line, self.roman_mode);
+        let result = match parser.parse_expression() {
+            Ok(expr) => {
+                let is_call = matches!(expr, Expr::FunctionCall { .. });
+                self.context.catch_error(|context| eval(&expr, context))
+                    .map(|value| (!(is_call && value == Value::Nil)).then_some(value))
+            }
+            Err(_) => {
+                let parser = LobsterParser::with_roman_mode(line, self.roman_mode);
+                match parser.try_parse() {
+                    Ok(ast) => self.context.catch_error(|context| run_block(&ast, context)).map(|_| None),
+                    Err(e) => return show_error(line, &e, output),
+                }
+            }
+        };
+        match result {
+            Ok(Some(value)) => {
+                let format = self.context.number_format();
+                writeln!(output, "{}", format_value(&value, format))
+            }
+            Ok(None) => Ok(()),
+            Err(message) => writeln!(output, "error: {message}"),
+        }
+    }
+
+    fn command(&mut self, command: &str, output: &mut impl Write) -> io::Result<()> {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let arg = arg.trim();
+        match (name, arg) {
+            ("roman", "") => writeln!(output, "{:?}", self.roman_mode),
+            ("roman", mode) => match RomanMode::from_name(mode) {
+                Some(mode) => {
+                    self.roman_mode = mode;
+                    Ok(())
+                }
+                None => writeln!(output, "expected creative, strict or off"),
+            },
+            ("numbers", "") => writeln!(output, "{:?}", self.context.number_format()),
+            ("numbers", format) => match NumberFormat::from_name(format) {
+                Some(format) => {
+                    self.context.set_number_format(format);
+                    Ok(())
+                }
+                None => writeln!(output, "expected decimal, roman or mixed"),
+            },
+            ("help", _) => write!(output, "{HELP}"),
+            _ => writeln!(output, "unknown command `:{name}`, try `:help`"),
+        }
+    }
+}
+
+/// Carets under the offending part of the line, lined up with the prompt
+/// the line was typed after.
+fn show_error(line: &str, e: &ParseError, output: &mut impl Write) -> io::Result<()> {
+    let start = e.span.start.min(line.len());
+    let end = e.span.end.clamp(start, line.len());
+    let indent = PROMPT.len() + line[..start].chars().count();
+    let width = line[start..end].chars().count().max(1);
+    writeln!(
+        output,
+        "{}{} {}",
+        " ".repeat(indent),
+        "^".repeat(width),
+        e.message
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(mode: RomanMode, input: &str) -> (String, String) {
+        let mut repl = Repl::new(mode);
+        repl.context().test_stdout = Some(String::new());
+        let mut output = vec![];
+        repl.run(input.as_bytes(), &mut output).unwrap();
+        let stdout = repl.context().test_stdout.take().unwrap();
+        (String::from_utf8(output).unwrap(), stdout)
+    }
+
+    #[test]
+    fn expressions_and_statements() {
+        let (out, stdout) = session(RomanMode::Creative, "x = XIV\nx + S∴\nprint(x)\n");
+        assert_eq!(out, "> > 59/4\n> > ");
+        assert_eq!(stdout, "14\n");
+    }
+
+    #[test]
+    fn strict_mode_points_at_numeral() {
+        let (out, _) = session(RomanMode::Strict, "x = I + IIII\n");
+        assert_eq!(
+            out,
+            ">           ^^^^ `IIII` is not a canonical Roman numeral, write `IV` for 4\n> "
+        );
+    }
+
+    #[test]
+    fn switch_modes() {
+        let (out, _) = session(
+            RomanMode::Creative,
+            ":roman\nIIII\n:roman off\nIIII = 1\nIIII\n:roman sloppy\n:numbers roman\nIIII + 2\n:quit\nIIII\n",
+        );
+        assert_eq!(
+            out,
+            "> Creative\n> 4\n> > > 1\n> expected creative, strict or off\n> > III\n> "
+        );
+    }
+
+    #[test]
+    fn runtime_errors_are_shown() {
+        let (out, _) = session(RomanMode::Creative, "1 + [[a]]\n");
+        assert_eq!(out, "> error: attempt to perform arithmetic on a string value\n> ");
+    }
+
+    /// A failed call is dropped from the stack, so errors do not pile up
+    /// into a stack overflow.
+    #[test]
+    fn errors_unwind_calls() {
+        let errors = "f()\n".repeat(crate::MAX_CALL_DEPTH + 1);
+        let (out, stdout) = session(
+            RomanMode::Creative,
+            &format!("f = function() return I + [[a]] end\n{errors}print(I)\n"),
+        );
+        assert!(!out.contains("stack overflow"), "{out}");
+        assert_eq!(stdout, "1\n");
+    }
+}
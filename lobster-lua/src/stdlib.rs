@@ -0,0 +1,165 @@
+//! The functions a script can call without defining them.
+
+use std::collections::HashMap;
+
+use crate::json::{self, EncodeOptions};
+use crate::numeral::{NumberFormat, format_value};
+use crate::table::Table;
+use crate::{Context, RuntimeError, Value};
+
+/// Globals that exist before the script runs.
+pub const GLOBAL_NAMES: &[&str] = &["print", "tostring", "debug", "string", "json"];
+
+pub(crate) fn globals() -> HashMap<String, Value> {
+    let debug = Table::new();
+    for name in ["traceback", "getinfo", "sethook"] {
+        debug.set(name.into(), Value::Builtin(name));
+    }
+    let string = Table::new();
+    string.set("format".into(), Value::Builtin("format"));
+    let json = Table::new();
+    for name in ["encode", "decode"] {
+        json.set(name.into(), Value::Builtin(name));
+    }
+    HashMap::from([
+        ("print".to_owned(), Value::Builtin("print")),
+        ("tostring".to_owned(), Value::Builtin("tostring")),
+        ("debug".to_owned(), Value::Table(debug)),
+        ("string".to_owned(), Value::Table(string)),
+        ("json".to_owned(), Value::Table(json)),
+    ])
+}
+
+pub(crate) fn call_builtin(
+    name: &str,
+    args: Vec<Value>,
+    context: &mut Context,
+) -> Result<Value, RuntimeError> {
+    Ok(match name {
+        "print" => {
+            let format = context.number_format();
+            let mut line = args
+                .iter()
+                .map(|a| format_value(a, format))
+                .collect::<Vec<_>>()
+                .join(", ");
+            line.push('\n');
+            context.write_stdout(&line);
+            Value::Nil
+        }
+        "tostring" => {
+            let format = match args.get(1) {
+                Some(Value::String(name)) => NumberFormat::from_name(name).ok_or_else(|| {
+                    RuntimeError::new(format!("unknown number format {name}"))
+                })?,
+                _ => context.number_format(),
+            };
+            let value = args.first().unwrap_or(&Value::Nil);
+            Value::String(format_value(value, format))
+        }
+        "format" => string_format(args, context)?,
+        "encode" => {
+            let value = args.first().unwrap_or(&Value::Nil);
+            let opts = EncodeOptions::from_value(args.get(1).unwrap_or(&Value::Nil))
+                .map_err(|e| RuntimeError::new(format!("json.encode: {e}")))?;
+            match json::encode(value, opts) {
+                Ok(text) => Value::String(text),
+                Err(e) => return Err(RuntimeError::new(format!("json.encode: {e}"))),
+            }
+        }
+        "decode" => match args.first() {
+            Some(Value::String(text)) => json::decode(text)
+                .map_err(|e| RuntimeError::new(format!("json.decode: {e}")))?,
+            _ => return Err("bad argument #1 to 'decode' (string expected)".into()),
+        },
+        "traceback" => {
+            let traceback = context.traceback();
+            match args.first() {
+                Some(Value::Nil) | None => Value::String(traceback),
+                Some(message) => Value::String(format!("{message}\n{traceback}")),
+            }
+        }
+        "getinfo" => getinfo(args, context),
+        "sethook" => {
+            let mut args = args.into_iter();
+            let function = args.next().unwrap_or(Value::Nil);
+            let mask = match args.next() {
+                Some(Value::String(mask)) => mask,
+                _ => String::new(),
+            };
+            context.set_script_hook(function, mask);
+            Value::Nil
+        }
+        // builtins only come from `globals`
+        _ => unreachable!("unknown builtin {name}"),
+    })
+}
+
+/// `debug.getinfo(level)`: level 1 is the function calling `getinfo`, 2 its
+/// caller and so on. Returns nil past the main chunk.
+fn getinfo(args: Vec<Value>, context: &Context) -> Value {
+    let level = match args.first() {
+        Some(Value::Number(n)) => *n,
+        _ => 1,
+    };
+    let frames = context.frames();
+    let Some(frame) = usize::try_from(level)
+        .ok()
+        .filter(|&level| level >= 1)
+        .and_then(|level| frames.len().checked_sub(level))
+        .map(|i| &frames[i])
+    else {
+        return Value::Nil;
+    };
+    let main = frame.scope_start == 0;
+    let info = Table::new();
+    info.set("currentline".into(), Value::Number(frame.line as i64));
+    info.set(
+        "short_src".into(),
+        Value::String(context.chunk_name().to_owned()),
+    );
+    info.set(
+        "what".into(),
+        Value::String(if main { "main" } else { "Lua" }.to_owned()),
+    );
+    if !main {
+        info.set("name".into(), Value::String(frame.function.clone()));
+    }
+    Value::Table(info)
+}
+
+/// `string.format(fmt, ...)`. Knows `%s` (like `tostring`), `%d` (decimal),
+/// `%R` (Roman), `%M` (mixed) and `%%`.
+fn string_format(args: Vec<Value>, context: &Context) -> Result<Value, RuntimeError> {
+    let mut args = args.into_iter();
+    let Some(Value::String(fmt)) = args.next() else {
+        return Err("bad argument #1 to 'format' (string expected)".into());
+    };
+    let mut res = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        let format = match chars.next() {
+            Some('%') => {
+                res.push('%');
+                continue;
+            }
+            Some('s') => context.number_format(),
+            Some('d') => NumberFormat::Decimal,
+            Some('R') => NumberFormat::Roman,
+            Some('M') => NumberFormat::Mixed,
+            Some(c) => {
+                return Err(RuntimeError::new(format!(
+                    "invalid conversion '%{c}' to 'format'"
+                )));
+            }
+            None => return Err("invalid conversion '%' to 'format'".into()),
+        };
+        let value = args.next().unwrap_or(Value::Nil);
+        res.push_str(&format_value(&value, format));
+    }
+    Ok(Value::String(res))
+}
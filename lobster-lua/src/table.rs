@@ -0,0 +1,100 @@
+//! Lua tables. A table value is a reference, like in Lua: copies share
+//! their contents and compare by identity.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::Value;
+
+/// The values that can index a table. Keys are kept sorted, so iterating a
+/// table is deterministic.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TableKey {
+    Bool(bool),
+    Number(i64),
+    String(String),
+}
+
+impl TableKey {
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(b) => Some(Self::Bool(*b)),
+            Value::Number(n) => Some(Self::Number(*n)),
+            Value::Fraction(f) if f.denominator() == 1 => Some(Self::Number(f.numerator())),
+            Value::String(s) => Some(Self::String(s.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match self {
+            Self::Bool(b) => Value::Bool(*b),
+            Self::Number(n) => Value::Number(*n),
+            Self::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+impl From<&str> for TableKey {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_owned())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Table(Rc<RefCell<BTreeMap<TableKey, Value>>>);
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &TableKey) -> Value {
+        self.0.borrow().get(key).cloned().unwrap_or(Value::Nil)
+    }
+
+    /// Sets `key`; assigning `nil` removes it.
+    pub fn set(&self, key: TableKey, value: Value) {
+        if value == Value::Nil {
+            self.0.borrow_mut().remove(&key);
+        } else {
+            self.0.borrow_mut().insert(key, value);
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(TableKey, Value)> {
+        self.0
+            .borrow()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Identity of the table, for printing and cycle detection.
+    pub fn id(&self) -> *const () {
+        Rc::as_ptr(&self.0).cast()
+    }
+}
+
+impl PartialEq for Table {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Table {}
+
+// Not derived: printing the contents would never end for a table that
+// contains itself.
+impl std::fmt::Debug for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "table: {:p}", self.id())
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "table: {:p}", self.id())
+    }
+}
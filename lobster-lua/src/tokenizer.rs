@@ -1,9 +1,10 @@
 use crate::fraction::Fraction;
+use crate::numeral::NumberFormat;
+use crate::parser::{Expr, parse_interpolation};
 
-#[expect(dead_code)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    #[expect(clippy::upper_case_acronyms)]
+    #[allow(clippy::upper_case_acronyms)]
     EOF,
     Keyword(Keyword),
     StringLiteral(String),
@@ -43,6 +44,23 @@ pub enum Token {
     Dot,
     DoubleDot,
     TripleDot,
+    /// A `--` comment, including the dashes. Only produced with trivia on.
+    Comment(String),
+    /// A run of whitespace. Only produced with trivia on.
+    Whitespace,
+    /// `f[[...]]` or `` `...` ``, with the expressions in it already parsed.
+    Interpolated(Vec<Piece>),
+}
+
+/// A part of an interpolated string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Text(String),
+    /// `{expr}`, or `{expr:roman}` to write numbers in another format.
+    Expr {
+        expr: Expr,
+        format: Option<NumberFormat>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
@@ -78,36 +96,262 @@ pub enum Keyword {
     And,
 }
 
+/// Byte range `start..end` into the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A token, or a piece of one, with where it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span,
+    /// 1-based line and column (in chars) of `span.start`.
+    pub line: usize,
+    pub col: usize,
+}
+
+/// 1-based line and column (in chars) of the byte offset `pos`.
+pub fn line_col(source: &str, pos: usize) -> (usize, usize) {
+    let before = &source[..pos];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// A `--` comment, kept as trivia so the formatter can put it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// The comment including its leading `--`.
+    pub text: String,
+    pub span: Span,
+    /// Nothing but whitespace precedes the comment on its line.
+    pub own_line: bool,
+}
+
+/// A token that could not be read, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+/// How words made of `IVXLCDM` are read. A script can switch modes with a
+/// `--!roman strict` comment, which applies from there on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RomanMode {
+    /// Any such word is a number, however it is spelled: `IIIIII` is 6.
+    #[default]
+    Creative,
+    /// Only the canonical spelling is a number, `IIIIII` is an error.
+    Strict,
+    /// They are all identifiers, and so is `S`. The fraction glyphs stay.
+    Off,
+}
+
+impl RomanMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "creative" => Some(Self::Creative),
+            "strict" => Some(Self::Strict),
+            "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// Reads tokens off a borrowed source, one at a time or as an iterator.
+///
+/// The iterator stops after the last token, [`Tokenizer::next_token`]
+/// keeps returning [`Token::EOF`] instead. After an error, both carry on
+/// with the rest of the source.
 #[derive(Debug)]
-pub struct Tokenizer {
-    source: String,
+pub struct Tokenizer<'a> {
+    source: &'a str,
     pos: usize,
+    /// A byte offset with its line and column, so positions are found by
+    /// scanning forward from the last one instead of from the start.
+    cursor: (usize, usize, usize),
+    comments: Vec<Comment>,
+    /// Spans of the number literals read so far, including those inside
+    /// interpolated strings.
+    literals: Vec<Span>,
+    roman_mode: RomanMode,
+    /// A broken pragma or an unfinished comment, reported with the next
+    /// token.
+    comment_error: Option<LexError>,
+    trivia: bool,
+    /// How deeply the parser reading the tokens is nested, which the
+    /// expressions in an interpolated string start from.
+    depth: usize,
 }
 
-impl Tokenizer {
-    pub fn new(source: String) -> Self {
-        Self { source, pos: 0 }
+impl<'a> Tokenizer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            pos: 0,
+            cursor: (0, 1, 1),
+            comments: vec![],
+            literals: vec![],
+            roman_mode: RomanMode::default(),
+            comment_error: None,
+            trivia: false,
+            depth: 0,
+        }
+    }
+
+    /// Carries on reading from `pos` instead.
+    pub(crate) fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /// The mode to start in, before any pragma.
+    pub fn set_roman_mode(&mut self, mode: RomanMode) {
+        self.roman_mode = mode;
+    }
+
+    pub fn roman_mode(&self) -> RomanMode {
+        self.roman_mode
+    }
+
+    /// Hand out comments and whitespace as [`Token::Comment`] and
+    /// [`Token::Whitespace`] instead of skipping them, so the tokens cover
+    /// the whole source.
+    pub fn set_trivia(&mut self, trivia: bool) {
+        self.trivia = trivia;
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// Byte offset right after the last token.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// All comments read so far, in source order.
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        std::mem::take(&mut self.comments)
+    }
+
+    /// Where every number and fraction literal read so far is spelled, in
+    /// source order.
+    pub fn take_literals(&mut self) -> Vec<Span> {
+        std::mem::take(&mut self.literals)
+    }
+
+    /// Forgets the literal read at `start`, which the parser took for
+    /// something else.
+    pub(crate) fn unread_literal(&mut self, start: usize) {
+        self.literals.retain(|span| span.start != start);
     }
 
-    fn remaining(&self) -> &str {
+    fn remaining(&self) -> &'a str {
         &self.source[self.pos..]
     }
 
-    fn skip_ws(&mut self) {
-        while let Some(c) = self.remaining().chars().next() {
-            if c.is_whitespace() {
-                self.pos += c.len_utf8();
-            } else if self.remaining().starts_with("--") {
-                self.pos += 2;
-                if self.multiline_string().is_none() {
-                    if let Some(idx) = self.remaining().find("\n") {
-                        self.pos += idx;
-                    } else {
-                        self.pos += self.remaining().len()
-                    }
-                }
+    /// Line and column of `pos`, which is usually after the last one asked.
+    fn line_col(&mut self, pos: usize) -> (usize, usize) {
+        let (from, mut line, mut col) = self.cursor;
+        if pos < from {
+            return line_col(self.source, pos);
+        }
+        for c in self.source[from..pos].chars() {
+            if c == '\n' {
+                line += 1;
+                col = 1;
             } else {
-                break;
+                col += 1;
+            }
+        }
+        self.cursor = (pos, line, col);
+        (line, col)
+    }
+
+    fn spanned(&mut self, value: Token, start: usize) -> Spanned<Token> {
+        let (line, col) = self.line_col(start);
+        Spanned {
+            value,
+            span: Span {
+                start,
+                end: self.pos,
+            },
+            line,
+            col,
+        }
+    }
+
+    fn skip_trivia(&mut self) {
+        while self.trivia_token().is_some() {}
+    }
+
+    /// A run of whitespace or a comment.
+    fn trivia_token(&mut self) -> Option<Token> {
+        let start = self.pos;
+        let ws = self
+            .remaining()
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(self.remaining().len());
+        if ws > 0 {
+            self.pos += ws;
+            return Some(Token::Whitespace);
+        }
+        if !self.remaining().starts_with("--") {
+            return None;
+        }
+        self.pos += 2;
+        match self.multiline_string("comment") {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                if let Some(idx) = self.remaining().find("\n") {
+                    self.pos += idx;
+                } else {
+                    self.pos += self.remaining().len()
+                }
+            }
+            Err(e) => self.comment_error = Some(e),
+        }
+        self.pragma(start);
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let text = self.source[start..self.pos].trim_end().to_owned();
+        self.comments.push(Comment {
+            text: text.clone(),
+            span: Span {
+                start,
+                end: self.pos,
+            },
+            own_line: self.source[line_start..start].trim().is_empty(),
+        });
+        Some(Token::Comment(text))
+    }
+
+    /// Handles `--!roman <mode>` in the comment starting at `start`.
+    fn pragma(&mut self, start: usize) {
+        let text = &self.source[start + 2..self.pos];
+        let Some(mode) = text.strip_prefix("!roman") else {
+            return;
+        };
+        let mode = mode.trim();
+        match RomanMode::from_name(mode) {
+            Some(mode) => self.roman_mode = mode,
+            None => {
+                self.comment_error = Some(LexError {
+                    message: format!(
+                        "unknown Roman numeral mode `{mode}`, expected creative, strict or off"
+                    ),
+                    span: Span {
+                        start,
+                        end: self.pos,
+                    },
+                });
             }
         }
     }
@@ -120,14 +364,9 @@ impl Tokenizer {
     this is a string
     ]====]
      */
-    fn multiline_string(&mut self) -> Option<String> {
-        let mut chars = self.remaining().chars();
-        if chars.next() != Some('[') {
-            return None;
-        };
-        let num_eqs = chars.clone().take_while(|&c| c == '=').count();
-        if chars.nth(num_eqs) != Some('[') {
-            return None;
+    fn multiline_string(&mut self, what: &str) -> Result<Option<String>, LexError> {
+        let Some(num_eqs) = self.long_bracket() else {
+            return Ok(None);
         };
         let start = self.pos + 1 + num_eqs + 1;
 
@@ -136,19 +375,146 @@ impl Tokenizer {
         endmarker.push_str(&"=".repeat(num_eqs));
         endmarker.push(']');
 
-        let end = self
-            .remaining()
-            .find(&endmarker)
-            .expect("TODO should have an end");
+        let Some(end) = self.source[start..].find(&endmarker) else {
+            let start = self.pos;
+            self.pos = self.source.len();
+            return Err(LexError {
+                message: format!("unfinished long {what}, expected `{endmarker}`"),
+                span: Span {
+                    start,
+                    end: self.pos,
+                },
+            });
+        };
+
+        let content_end = start + end;
+        self.pos = content_end + endmarker.len();
+        Ok(Some(self.source[start..content_end].to_owned()))
+    }
+
+    /// The number of `=` if a long bracket like `[[` or `[==[` starts here.
+    fn long_bracket(&self) -> Option<usize> {
+        let mut chars = self.remaining().chars();
+        if chars.next() != Some('[') {
+            return None;
+        };
+        let num_eqs = chars.clone().take_while(|&c| c == '=').count();
+        (chars.nth(num_eqs) == Some('[')).then_some(num_eqs)
+    }
+
+    /// `f[[...]]` after the `f`, or `` `...` ``. Doubled braces stand for
+    /// themselves, a single `{` starts an expression. Between backticks,
+    /// `` \` ``, `\\`, `\n` and `\t` are escapes.
+    fn interpolated_string(&mut self, start: usize) -> Result<Token, LexError> {
+        let (endmarker, escapes) = match self.long_bracket() {
+            Some(num_eqs) => {
+                self.pos += num_eqs + 2;
+                (format!("]{}]", "=".repeat(num_eqs)), false)
+            }
+            None => {
+                self.pos += 1;
+                ("`".to_owned(), true)
+            }
+        };
+        let mut pieces = vec![];
+        let mut text = String::new();
+        loop {
+            let rest = self.remaining();
+            if rest.starts_with(&endmarker) {
+                self.pos += endmarker.len();
+                break;
+            }
+            let Some(c) = rest.chars().next() else {
+                let expected = match escapes {
+                    true => "a closing backtick".to_owned(),
+                    false => format!("`{endmarker}`"),
+                };
+                return Err(LexError {
+                    message: format!("unfinished interpolated string, expected {expected}"),
+                    span: Span {
+                        start,
+                        end: self.pos,
+                    },
+                });
+            };
+            let doubled = rest[c.len_utf8()..].starts_with(c);
+            match c {
+                '{' | '}' if doubled => {
+                    text.push(c);
+                    self.pos += 2;
+                }
+                '{' => {
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    let interpolation = parse_interpolation(
+                        self.source,
+                        self.pos + 1,
+                        self.roman_mode,
+                        self.depth,
+                        &mut self.literals,
+                    );
+                    let (expr, format, end) = match interpolation {
+                        Ok(interpolation) => interpolation,
+                        Err(e) => return Err(self.skip_past(&endmarker, e.message, e.span)),
+                    };
+                    pieces.push(Piece::Expr { expr, format });
+                    self.pos = end;
+                }
+                '}' => {
+                    let span = Span {
+                        start: self.pos,
+                        end: self.pos + 1,
+                    };
+                    let message = "single `}` in an interpolated string, write `}}` for a brace";
+                    return Err(self.skip_past(&endmarker, message.to_owned(), span));
+                }
+                '\\' if escapes => {
+                    let escaped = rest[1..].chars().next();
+                    let c = match escaped {
+                        Some('\\') => '\\',
+                        Some('`') => '`',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        _ => {
+                            let len = 1 + escaped.map_or(0, char::len_utf8);
+                            let span = Span {
+                                start: self.pos,
+                                end: self.pos + len,
+                            };
+                            let message = format!("unknown escape `{}`", &rest[..len]);
+                            return Err(self.skip_past(&endmarker, message, span));
+                        }
+                    };
+                    text.push(c);
+                    self.pos += 2;
+                }
+                c => {
+                    text.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+        if !text.is_empty() || pieces.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Token::Interpolated(pieces))
+    }
 
-        let content_end = self.pos + end;
-        self.pos += end + endmarker.len();
-        Some(self.source[start..content_end].to_owned())
+    /// Skips to after the next `endmarker`, to carry on after an error in
+    /// the string it ends.
+    fn skip_past(&mut self, endmarker: &str, message: String, span: Span) -> LexError {
+        self.pos = span.end.max(self.pos);
+        self.pos = match self.remaining().find(endmarker) {
+            Some(idx) => self.pos + idx + endmarker.len(),
+            None => self.source.len(),
+        };
+        LexError { message, span }
     }
 
     fn check_for_identifier(&mut self) -> Option<String> {
         if let Some(c) = self.remaining().chars().next() {
-            if !c.is_alphabetic() && !is_emoji(c) {
+            if !c.is_alphabetic() && c != '_' && !is_emoji(c) {
                 return None;
             }
             let last_idx = self
@@ -163,58 +529,108 @@ impl Tokenizer {
         }
     }
 
-    fn check_for_number(&mut self) -> Option<i64> {
+    fn check_for_number(&mut self) -> Result<Option<i64>, LexError> {
         let non_numeric_idx = self
             .remaining()
-            .find(|c: char| !c.is_numeric())
+            .find(|c: char| !c.is_ascii_digit())
             .unwrap_or(self.remaining().len());
         if non_numeric_idx == 0 {
-            return None;
+            return Ok(None);
         }
-        let res = self.remaining()[0..non_numeric_idx].parse().expect("TODO");
+        let digits = &self.remaining()[0..non_numeric_idx];
+        let start = self.pos;
         self.pos += non_numeric_idx;
-        Some(res)
+        match digits.parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(LexError {
+                message: format!("`{digits}` is too large for a number"),
+                span: Span {
+                    start,
+                    end: self.pos,
+                },
+            }),
+        }
     }
 
-    pub fn next_token(&mut self) -> Result<(Token, usize), String> {
-        self.skip_ws();
+    pub fn next_token(&mut self) -> Result<Spanned<Token>, LexError> {
+        if !self.trivia {
+            self.skip_trivia();
+        }
+        if let Some(e) = self.comment_error.take() {
+            return Err(e);
+        }
+        let start = self.pos;
+        let token = match self.trivia.then(|| self.trivia_token()).flatten() {
+            Some(token) => token,
+            None => self.token()?,
+        };
+        let token = self.spanned(token, start);
+        if matches!(
+            token.value,
+            Token::NumberLiteral(_) | Token::FractionLiteral(_)
+        ) {
+            self.literals.push(token.span);
+        }
+        Ok(token)
+    }
 
+    fn token(&mut self) -> Result<Token, LexError> {
         if self.pos == self.source.len() {
-            return Ok((Token::EOF, self.pos));
+            return Ok(Token::EOF);
         }
 
         for (s, tok) in ROMAN_MAPPING {
             if self.remaining().starts_with(s) {
                 self.pos += s.len();
-                return Ok((tok.clone(), self.pos - s.len()));
+                return Ok(tok.clone());
             }
         }
 
         let start_pos = self.pos;
+        if self.remaining().starts_with('`') {
+            return self.interpolated_string(start_pos);
+        }
         if let Some(identifier) = self.check_for_identifier() {
-            if identifier == "S" {
-                return Ok((Token::FractionLiteral(Fraction::new(1, 2)), start_pos));
+            if identifier == "f" && self.long_bracket().is_some() {
+                return self.interpolated_string(start_pos);
             }
-            if let Some(roman) = roman_number(&identifier) {
-                return Ok((Token::NumberLiteral(roman), start_pos));
+            if self.roman_mode != RomanMode::Off {
+                if identifier == "S" {
+                    return Ok(Token::FractionLiteral(Fraction::new(1, 2)));
+                }
+                if let Some(roman) = roman_number(&identifier) {
+                    if self.roman_mode == RomanMode::Strict {
+                        let canonical = canonical_roman(roman);
+                        if canonical.as_deref() != Some(identifier.as_str()) {
+                            return Err(LexError {
+                                message: non_canonical_message(&identifier, roman, canonical),
+                                span: Span {
+                                    start: start_pos,
+                                    end: self.pos,
+                                },
+                            });
+                        }
+                    }
+                    return Ok(Token::NumberLiteral(roman));
+                }
             }
             if let Some((_, kw)) = KEYWORDS.iter().find(|(name, _)| identifier == *name) {
-                return Ok((Token::Keyword(*kw), start_pos));
+                return Ok(Token::Keyword(*kw));
             }
-            return Ok((Token::Ident(identifier), start_pos));
+            return Ok(Token::Ident(identifier));
         }
-        if let Some(s) = self.multiline_string() {
-            return Ok((Token::StringLiteral(s), start_pos));
+        if let Some(s) = self.multiline_string("string")? {
+            return Ok(Token::StringLiteral(s));
         }
 
-        if let Some(n) = self.check_for_number() {
-            return Ok((Token::NumberLiteral(n), start_pos));
+        if let Some(n) = self.check_for_number()? {
+            return Ok(Token::NumberLiteral(n));
         }
 
         for (s, tok) in MAPPING {
             if self.remaining().starts_with(s) {
                 self.pos += s.len();
-                return Ok((tok.clone(), self.pos - s.len()));
+                return Ok(tok.clone());
             }
         }
 
@@ -224,11 +640,52 @@ impl Tokenizer {
         //     _ => None,
         // };
         // if let Some(token) = token {
-        //     return Ok((token, start_pos));
+        //     return Ok(token);
         // }
 
-        Err("Unhappy?".to_owned())
+        // skip it, so whoever wants to can carry on after the error
+        self.pos += self.remaining().chars().next().map_or(0, char::len_utf8);
+        Err(LexError {
+            message: "Unhappy?".to_owned(),
+            span: Span {
+                start: start_pos,
+                end: self.pos,
+            },
+        })
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Result<Spanned<Token>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_token() {
+            Ok(Spanned {
+                value: Token::EOF, ..
+            }) => None,
+            res => Some(res),
+        }
+    }
+}
+
+fn non_canonical_message(lexeme: &str, n: i64, canonical: Option<String>) -> String {
+    match canonical {
+        Some(canonical) => {
+            format!("`{lexeme}` is not a canonical Roman numeral, write `{canonical}` for {n}")
+        }
+        None => format!("`{lexeme}` is not a canonical Roman numeral, it reads as {n}"),
+    }
+}
+
+/// The uncia glyph spelling of a fraction, if it has one.
+pub fn fraction_glyph(f: Fraction) -> Option<&'static str> {
+    if f == Fraction::new(1, 2) {
+        return Some("S");
     }
+    ROMAN_MAPPING
+        .iter()
+        .find(|(_, tok)| *tok == Token::FractionLiteral(f))
+        .map(|(s, _)| *s)
 }
 
 fn is_emoji(c: char) -> bool {
@@ -245,7 +702,38 @@ fn is_emoji(c: char) -> bool {
     )
 }
 
-fn roman_number(s: &str) -> Option<i64> {
+/// The canonical (subtractive) Roman numeral for a positive number.
+/// Thousands beyond `MMM` are just more `M`s.
+pub fn canonical_roman(mut n: i64) -> Option<String> {
+    const DIGITS: &[(i64, &str)] = &[
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    if n <= 0 {
+        return None;
+    }
+    let mut res = String::new();
+    for &(value, digits) in DIGITS {
+        while n >= value {
+            res.push_str(digits);
+            n -= value;
+        }
+    }
+    Some(res)
+}
+
+pub fn roman_number(s: &str) -> Option<i64> {
     let mut res = 0;
     let mut last = None;
     for c in s.chars() {
@@ -312,6 +800,11 @@ const MAPPING: &[(&str, Token)] = &[
     (";", Token::Semicolon),
     ("^", Token::Caret),
     (">", Token::Gt),
+    ("[", Token::SqParOpen),
+    ("]", Token::SqParClose),
+    ("{", Token::BraceOpen),
+    ("}", Token::BraceClose),
+    (".", Token::Dot),
 ];
 
 const ROMAN_MAPPING: &[(&str, Token)] = &[
@@ -399,4 +892,238 @@ mod tests {
             assert_eq!(Some(i), res);
         }
     }
+
+    fn tokens(source: &str, mode: RomanMode) -> Result<Vec<Token>, LexError> {
+        let mut tokenizer = Tokenizer::new(source);
+        tokenizer.set_roman_mode(mode);
+        tokenizer.map(|tok| Ok(tok?.value)).collect()
+    }
+
+    #[test]
+    fn strict_romans() {
+        assert_eq!(
+            tokens("XIV MCMXCIV S", RomanMode::Strict),
+            Ok(vec![
+                Token::NumberLiteral(14),
+                Token::NumberLiteral(1994),
+                Token::FractionLiteral(Fraction::new(1, 2)),
+            ])
+        );
+        assert_eq!(
+            tokens("x = IIIIII", RomanMode::Strict),
+            Err(LexError {
+                message: "`IIIIII` is not a canonical Roman numeral, write `VI` for 6".to_owned(),
+                span: Span { start: 4, end: 10 },
+            })
+        );
+    }
+
+    #[test]
+    fn romans_off() {
+        assert_eq!(
+            tokens("XIV S S∴", RomanMode::Off),
+            Ok(vec![
+                Token::Ident("XIV".to_owned()),
+                Token::Ident("S".to_owned()),
+                Token::FractionLiteral(Fraction::new(3, 4)),
+            ])
+        );
+    }
+
+    #[test]
+    fn roman_pragma() {
+        assert_eq!(
+            tokens(
+                "IIII --!roman off\nIIII --!roman creative\nIIII",
+                RomanMode::Strict
+            )
+            .unwrap_err()
+            .span,
+            Span { start: 0, end: 4 }
+        );
+        assert_eq!(
+            tokens(
+                "IIII --!roman off\nIIII --!roman creative\nIIII",
+                RomanMode::Creative
+            ),
+            Ok(vec![
+                Token::NumberLiteral(4),
+                Token::Ident("IIII".to_owned()),
+                Token::NumberLiteral(4),
+            ])
+        );
+        assert_eq!(
+            tokens("--!roman sloppy\nI", RomanMode::Creative)
+                .unwrap_err()
+                .span,
+            Span { start: 0, end: 15 }
+        );
+    }
+
+    #[test]
+    fn spans_and_positions() {
+        let tokens: Vec<_> = Tokenizer::new("x = [[a\nb]]\n  🦞 ..  II")
+            .map(Result::unwrap)
+            .map(|tok| (tok.value, tok.span.start, tok.span.end, tok.line, tok.col))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Ident("x".to_owned()), 0, 1, 1, 1),
+                (Token::Equals, 2, 3, 1, 3),
+                (Token::StringLiteral("a\nb".to_owned()), 4, 11, 1, 5),
+                (Token::Ident("🦞".to_owned()), 14, 18, 3, 3),
+                (Token::DoubleDot, 19, 21, 3, 5),
+                (Token::NumberLiteral(2), 23, 25, 3, 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn trivia_covers_the_source() {
+        let source = "x = 1 -- one\n--[[ two\n]] y";
+        let mut tokenizer = Tokenizer::new(source);
+        tokenizer.set_trivia(true);
+        let tokens: Vec<_> = tokenizer.map(Result::unwrap).collect();
+        let text: String = tokens
+            .iter()
+            .map(|tok| &source[tok.span.start..tok.span.end])
+            .collect();
+        assert_eq!(text, source);
+        assert_eq!(
+            tokens
+                .iter()
+                .filter_map(|tok| match &tok.value {
+                    Token::Comment(text) => Some((text.as_str(), tok.line)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            vec![("-- one", 1), ("--[[ two\n]]", 2)]
+        );
+    }
+
+    #[test]
+    fn carries_on_after_errors() {
+        let tokens: Vec<_> = Tokenizer::new("a $ b").collect();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(
+            tokens[1],
+            Err(LexError {
+                message: "Unhappy?".to_owned(),
+                span: Span { start: 2, end: 3 },
+            })
+        );
+        assert_eq!(
+            tokens[2].as_ref().unwrap().value,
+            Token::Ident("b".to_owned())
+        );
+    }
+
+    #[test]
+    fn interpolated_strings() {
+        let token = |source| Tokenizer::new(source).next_token().map(|tok| tok.value);
+        let var = |name: &str| {
+            Expr::Var(crate::parser::Name {
+                name: name.to_owned(),
+                span: Span::default(),
+            })
+        };
+        assert_eq!(
+            token("f[[a {x} {{b}} {y:roman}]]"),
+            Ok(Token::Interpolated(vec![
+                Piece::Text("a ".to_owned()),
+                Piece::Expr {
+                    expr: var("x"),
+                    format: None,
+                },
+                Piece::Text(" {b} ".to_owned()),
+                Piece::Expr {
+                    expr: var("y"),
+                    format: Some(NumberFormat::Roman),
+                },
+            ]))
+        );
+        assert_eq!(
+            token(r"`\`{[[}]]}\\`"),
+            Ok(Token::Interpolated(vec![
+                Piece::Text("`".to_owned()),
+                Piece::Expr {
+                    expr: Expr::String("}".to_owned()),
+                    format: None,
+                },
+                Piece::Text("\\".to_owned()),
+            ]))
+        );
+        assert_eq!(
+            token("f[=[]]]=]"),
+            Ok(Token::Interpolated(vec![Piece::Text("]]".to_owned())]))
+        );
+        assert_eq!(token("f [[a]]"), Ok(Token::Ident("f".to_owned())));
+    }
+
+    #[test]
+    fn interpolation_errors_point_inside() {
+        let cases = [
+            (
+                "f[[a {x +} b]] c",
+                "Expected expression, got BraceClose",
+                9..10,
+            ),
+            (
+                "f[[{x y}]] c",
+                "Expected BraceClose, got Ident(\"y\")",
+                6..7,
+            ),
+            (
+                "f[[{x:fancy}]] c",
+                "unknown number format `fancy`, expected decimal, roman or mixed",
+                6..11,
+            ),
+            (
+                "f[[a } b]] c",
+                "single `}` in an interpolated string, write `}}` for a brace",
+                5..6,
+            ),
+            (r"`a \q` c", "unknown escape `\\q`", 3..5),
+        ];
+        for (source, message, span) in cases {
+            let tokens: Vec<_> = Tokenizer::new(source).collect();
+            let span = Span {
+                start: span.start,
+                end: span.end,
+            };
+            assert_eq!(
+                tokens[0],
+                Err(LexError {
+                    message: message.to_owned(),
+                    span,
+                }),
+                "{source}"
+            );
+            // and carries on after the string
+            assert_eq!(
+                tokens[1].as_ref().unwrap().value,
+                Token::Ident("c".to_owned())
+            );
+        }
+        let tokens: Vec<_> = Tokenizer::new("x = `abc").collect();
+        assert_eq!(
+            tokens[2],
+            Err(LexError {
+                message: "unfinished interpolated string, expected a closing backtick".to_owned(),
+                span: Span { start: 4, end: 8 },
+            })
+        );
+    }
+
+    #[test]
+    fn test_canonical_romans() {
+        for i in 1..=4000 {
+            let s = canonical_roman(i).unwrap();
+            assert_eq!(roman_number(&s), Some(i), "{s}");
+        }
+        assert_eq!(canonical_roman(1994).as_deref(), Some("MCMXCIV"));
+        assert_eq!(canonical_roman(4997).as_deref(), Some("MMMMCMXCVII"));
+        assert_eq!(canonical_roman(0), None);
+    }
 }
@@ -0,0 +1,502 @@
+//! `lobster-lua transpile`: turns a lobster program into plain Lua 5.4.
+//!
+//! Most of lobster is Lua already, so most of the output is the program
+//! written back out. The differences are lowered:
+//!
+//! - Roman numerals and uncia glyphs become decimal integers, and fractions
+//!   become tables of a small runtime library that is emitted at the top of
+//!   every file, see `transpile_runtime.lua`. `/` and `//` always make a
+//!   fraction, `^` stays an integer, `%` truncates, `>>` keeps the sign and
+//!   shifts past 63 are errors, so these are calls into the runtime too.
+//! - Integer keys are shifted by one, so `t[0]` reads the first element of a
+//!   Lua sequence.
+//! - `if` and `while` only take `true`, where Lua would take any value but
+//!   `nil` and `false`.
+//! - A function returns only its first value.
+//! - Names that are not ASCII, like `🦞`, are spelled with their code points.
+//!
+//! `print` and `tostring` come from the runtime, and only write decimal
+//! numbers, so a `{x:roman}` in an f-string is an error; every other global is
+//! whatever the host's Lua has. Lobster looks up the names a function uses
+//! when it runs, while Lua closes over them where the function is written, so
+//! a function that reads a local of its caller does not work the same.
+
+use crate::parser::{BinOp, Expr, LobsterParser, ParseError, Stmt, StmtKind};
+use crate::tokenizer::RomanMode;
+
+const INDENT: &str = "  ";
+const RUNTIME: &str = include_str!("transpile_runtime.lua");
+
+pub fn transpile(source: &str, roman_mode: RomanMode) -> Result<String, ParseError> {
+    let parser = LobsterParser::with_roman_mode(source, roman_mode);
+    let ast = parser.try_parse()?;
+    let mut out = String::from("-- Generated by `lobster-lua transpile`, runs on Lua 5.4.\n");
+    out.push_str("local __lobster = (function()\n");
+    out.push_str(RUNTIME);
+    out.push_str("end)()\n");
+    out.push_str("local print, tostring = __lobster.print, __lobster.tostring\n\n");
+    out.push_str(&transpile_program(&ast));
+    Ok(out)
+}
+
+/// The program without the runtime in front of it.
+pub fn transpile_program(stmts: &[Stmt]) -> String {
+    let mut transpiler = Transpiler {
+        out: String::new(),
+        indent: 0,
+    };
+    transpiler.block(stmts);
+    transpiler.out
+}
+
+struct Transpiler {
+    out: String,
+    indent: usize,
+}
+
+impl Transpiler {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.stmt(stmt, i + 1 == stmts.len());
+        }
+    }
+
+    fn nested_block(&mut self, stmts: &[Stmt]) {
+        self.indent += 1;
+        self.block(stmts);
+        self.indent -= 1;
+    }
+
+    /// Lua only allows `return` as the last statement of a block.
+    fn stmt(&mut self, stmt: &Stmt, last: bool) {
+        match &stmt.kind {
+            StmtKind::Break => self.line("break"),
+            StmtKind::Return(exprs) => {
+                let ret = match exprs.as_slice() {
+                    [] => "return".to_owned(),
+                    [expr] => format!("return {}", self.expr(expr)),
+                    exprs => format!("return __lobster.first({})", self.expr_list(exprs)),
+                };
+                if last {
+                    self.line(&ret);
+                } else {
+                    self.line(&format!("do {ret} end"));
+                }
+            }
+            StmtKind::While { cond, body } => {
+                let cond = self.condition(cond);
+                self.line(&format!("while {cond} do"));
+                self.nested_block(body);
+                self.line("end");
+            }
+            StmtKind::DoEnd { body } => {
+                self.line("do");
+                self.nested_block(body);
+                self.line("end");
+            }
+            StmtKind::Assignment { variable, value } => {
+                let value = self.expr(value);
+                self.line(&format!("{} = {value}", name(&variable.name)));
+            }
+            StmtKind::Local { variable, value } => match value {
+                Some(value) => {
+                    let value = self.expr(value);
+                    self.line(&format!("local {} = {value}", name(&variable.name)));
+                }
+                None => self.line(&format!("local {}", name(&variable.name))),
+            },
+            StmtKind::FunctionCall {
+                function_name,
+                args,
+            } => {
+                let call = self.call(function_name, args);
+                self.line(&call);
+            }
+            StmtKind::Error => unreachable!("try_parse has no error nodes"),
+            StmtKind::If { cond, then, r#else } => {
+                let cond = self.condition(cond);
+                self.line(&format!("if {cond} then"));
+                self.if_tail(then, r#else);
+            }
+        }
+    }
+
+    fn if_tail(&mut self, then: &[Stmt], r#else: &[Stmt]) {
+        self.nested_block(then);
+        match r#else {
+            [] => {}
+            [
+                Stmt {
+                    kind: StmtKind::If { cond, then, r#else },
+                    ..
+                },
+            ] => {
+                let cond = self.condition(cond);
+                self.line(&format!("elseif {cond} then"));
+                return self.if_tail(then, r#else);
+            }
+            stmts => {
+                self.line("else");
+                self.nested_block(stmts);
+            }
+        }
+        self.line("end");
+    }
+
+    /// `cond == true`, unless `cond` can only be a boolean anyway.
+    fn condition(&mut self, cond: &Expr) -> String {
+        let res = self.expr(cond);
+        match cond {
+            Expr::Boolean(_) => res,
+            Expr::BinOp { op, .. } if is_boolean(*op) => res,
+            Expr::BinOp { .. } => format!("({res}) == true"),
+            _ => format!("{res} == true"),
+        }
+    }
+
+    /// An expression that gets called or indexed, in parentheses unless it
+    /// is a name, call or index already.
+    fn prefix(&mut self, expr: &Expr) -> String {
+        let res = self.expr(expr);
+        match expr {
+            Expr::Var(_) | Expr::FunctionCall { .. } | Expr::Index { .. } => res,
+            _ => format!("({res})"),
+        }
+    }
+
+    fn call(&mut self, function_name: &Expr, args: &[Expr]) -> String {
+        let callee = self.prefix(function_name);
+        let args = self.expr_list(args);
+        format!("{callee}({args})")
+    }
+
+    fn expr_list(&mut self, exprs: &[Expr]) -> String {
+        exprs
+            .iter()
+            .map(|e| self.expr(e))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Nil => "nil".to_owned(),
+            Expr::Numeral(i64::MIN) => "math.mininteger".to_owned(),
+            Expr::Numeral(n) if *n < 0 => format!("(-{})", n.unsigned_abs()),
+            Expr::Numeral(n) => n.to_string(),
+            Expr::Fraction(f) => {
+                format!("__lobster.frac({}, {})", f.numerator(), f.denominator())
+            }
+            Expr::Boolean(b) => b.to_string(),
+            Expr::String(s) => quoted(s),
+            Expr::BinOp { op, lhs, rhs } => self.binop(*op, lhs, rhs),
+            Expr::Var(var) => name(&var.name),
+            Expr::Index { table, key } => {
+                let table = self.prefix(table);
+                match key.as_ref() {
+                    Expr::String(key) if is_lua_name(key) => format!("{table}.{key}"),
+                    Expr::String(_) | Expr::Boolean(_) => format!("{table}[{}]", self.expr(key)),
+                    Expr::Numeral(n) if *n < i64::MAX => format!("{table}[{}]", n + 1),
+                    key => format!("__lobster.index({table}, {})", self.expr(key)),
+                }
+            }
+            Expr::FunctionCall {
+                function_name,
+                args,
+            } => self.call(function_name, args),
+            Expr::Error => unreachable!("try_parse has no error nodes"),
+            Expr::FunctionDef { arguments, body, .. } => {
+                let mut inner = Transpiler {
+                    out: String::new(),
+                    indent: self.indent + 1,
+                };
+                inner.block(body);
+                let arguments: Vec<String> = arguments.iter().map(|a| name(&a.name)).collect();
+                let mut res = format!("function({})\n", arguments.join(", "));
+                res.push_str(&inner.out);
+                for _ in 0..self.indent {
+                    res.push_str(INDENT);
+                }
+                res.push_str("end");
+                res
+            }
+        }
+    }
+
+    /// Operators Lua has with the same meaning are written as they are, with
+    /// parentheses where [`BinOp::get_precedence`] needs them. The others
+    /// are calls into the runtime.
+    fn binop(&mut self, op: BinOp, lhs: &Expr, rhs: &Expr) -> String {
+        let runtime = match op {
+            BinOp::Div | BinOp::IDiv => Some("div"),
+            BinOp::Exp => Some("pow"),
+            BinOp::Mod => Some("mod"),
+            BinOp::LShift => Some("shl"),
+            BinOp::RShift => Some("shr"),
+            _ => None,
+        };
+        if let Some(function) = runtime {
+            let lhs = self.expr(lhs);
+            let rhs = self.expr(rhs);
+            return format!("__lobster.{function}({lhs}, {rhs})");
+        }
+        let (l_prec, r_prec) = op.get_precedence();
+        let lhs_str = self.expr(lhs);
+        let lhs_str = match lhs {
+            Expr::BinOp { op: inner, .. }
+                if is_native(*inner) && l_prec >= inner.get_precedence().1 =>
+            {
+                format!("({lhs_str})")
+            }
+            _ => lhs_str,
+        };
+        let rhs_str = self.expr(rhs);
+        let rhs_str = match rhs {
+            Expr::BinOp { op: inner, .. }
+                if is_native(*inner) && inner.get_precedence().0 < r_prec =>
+            {
+                format!("({rhs_str})")
+            }
+            _ => rhs_str,
+        };
+        format!("{lhs_str} {} {rhs_str}", op.to_s_expr())
+    }
+}
+
+/// Whether `op` is written as a Lua operator rather than a runtime call.
+fn is_native(op: BinOp) -> bool {
+    !matches!(
+        op,
+        BinOp::Div | BinOp::IDiv | BinOp::Exp | BinOp::Mod | BinOp::LShift | BinOp::RShift
+    )
+}
+
+fn is_boolean(op: BinOp) -> bool {
+    matches!(
+        op,
+        BinOp::And
+            | BinOp::Or
+            | BinOp::GT
+            | BinOp::LT
+            | BinOp::GEQ
+            | BinOp::LEQ
+            | BinOp::Equals
+            | BinOp::NotEquals
+    )
+}
+
+/// Lua keywords are lobster keywords too, so any ASCII name can stay. Other
+/// names get `_u<hex>_` for each character Lua does not allow, and a `__`
+/// in front so they cannot clash with an ASCII name.
+fn name(name: &str) -> String {
+    if name.is_ascii() {
+        return name.to_owned();
+    }
+    let mut res = "__".to_owned();
+    for c in name.chars() {
+        if c.is_ascii() {
+            res.push(c);
+        } else {
+            res.push_str(&format!("_u{:x}_", c as u32));
+        }
+    }
+    res
+}
+
+fn is_lua_name(s: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+        "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&s)
+}
+
+/// A double-quoted Lua string. Unlike a long bracket string, this keeps a
+/// leading newline.
+fn quoted(s: &str) -> String {
+    let mut res = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_ascii_control() => res.push_str(&format!("\\{}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! transpile_test {
+        ($name:ident, $source:expr) => {
+            #[test]
+            fn $name() {
+                let ast = LobsterParser::new($source).parse();
+                insta::assert_snapshot!(transpile_program(&ast));
+            }
+        };
+    }
+
+    transpile_test!(
+        romans_and_fractions,
+        "x = XIV + S∴\ny = (1 + 2) * III / IV\nprint(x, y)"
+    );
+    transpile_test!(
+        lowered_operators,
+        "a = 7 / 2 * 4 - 1\nb = II ^ X ^ 2\nc = (1 / 2) ^ 2 .. [[!]]"
+    );
+    transpile_test!(
+        zero_based_index,
+        "t = json.decode([[ [10, 20] ]])\nprint(t[0], t[i], t.name, t[ [[end]] ], t[true])"
+    );
+    transpile_test!(
+        conditions_are_strict,
+        "while x do x = f(x) end\nif a == 1 and b then y = 1 elseif g() then y = 2 else y = 3 end"
+    );
+    transpile_test!(
+        functions_and_returns,
+        "add = function(a, b)\n  if a then return a, b end\n  return a + b\nend\ndo return end"
+    );
+    transpile_test!(
+        names_and_strings,
+        "local crème\n🦞 = [[line\n\"quoted\" \\ end]]\nprint(🦞, crème)"
+    );
+
+    /// Runs `source` in the interpreter and its transpiled Lua in `lua5.4`
+    /// or `lua`, checking that both print the same. `list` is `[10, 20, 30]`
+    /// in both. Returns the interpreter's error, if there is one.
+    fn run_both(source: &str) -> Option<String> {
+        let mut context = crate::Context::new();
+        context.test_stdout = Some(String::new());
+        let list = crate::json::decode("[10, 20, 30]").unwrap();
+        context.insert_global("list".to_owned(), list);
+        let ast = LobsterParser::new(source).parse();
+        let error = crate::run_block(&ast, &mut context)
+            .err()
+            .map(|e| e.message);
+
+        let lua = transpile(source, RomanMode::Creative).unwrap();
+        let out = context.test_stdout.unwrap();
+        check_lua(
+            &format!("list = {{ 10, 20, 30 }}\n{lua}"),
+            &out,
+            error.as_deref(),
+        );
+        error
+    }
+
+    /// Runs `lua` and checks it prints `out`, then fails with `error` if
+    /// there is one.
+    fn check_lua(lua: &str, out: &str, error: Option<&str>) {
+        use std::process::Command;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let file = std::env::temp_dir().join(format!(
+            "lobster-transpile-{}-{}.lua",
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&file, lua).unwrap();
+        let run = ["lua5.4", "lua"]
+            .into_iter()
+            .find_map(|lua| Command::new(lua).arg(&file).output().ok())
+            .expect("no lua5.4 or lua on PATH");
+        std::fs::remove_file(&file).unwrap();
+        let stderr = String::from_utf8(run.stderr).unwrap();
+        assert_eq!(String::from_utf8(run.stdout).unwrap(), out, "{stderr}");
+        match error {
+            None => assert!(run.status.success(), "{stderr}"),
+            Some(error) => assert!(stderr.contains(error), "{stderr}"),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs lua5.4 or lua on PATH, which CI installs"]
+    fn runs_like_the_interpreter() {
+        run_both("x = XIV + S∴\ny = (1 + 2) * III / IV\nprint(x, y, x - y, x * y)");
+        run_both("i = 2 - 1\nprint(list[0], list[II], list[i], list[III])");
+        run_both("print(II ^ X, (0 - 1) ^ 4294967297, 3 ^ 0, 0 - 3 ^ 3, II ^ LXII)");
+        let errors = [
+            (
+                "print(II ^ 4294967296)",
+                "attempt to multiply with overflow",
+            ),
+            ("print(III ^ XL)", "attempt to multiply with overflow"),
+            (
+                "print(II ^ (0 - 1))",
+                "attempt to raise to a negative power",
+            ),
+            ("print(1 / 0)", "division by zero"),
+        ];
+        for (source, error) in errors {
+            assert_eq!(run_both(source).as_deref(), Some(error));
+        }
+    }
+
+    /// `%`, `//` and the shifts have no syntax yet, so these go straight to
+    /// the interpreter's operators.
+    #[test]
+    #[ignore = "needs lua5.4 or lua on PATH, which CI installs"]
+    fn operators_without_syntax_run_like_the_interpreter() {
+        let cases = [
+            (BinOp::Mod, 7, -3),
+            (BinOp::Mod, -7, 3),
+            (BinOp::IDiv, 7, 2),
+            (BinOp::LShift, 1, 63),
+            (BinOp::LShift, 3, 0),
+            (BinOp::LShift, 1, 64),
+            (BinOp::LShift, 1, -1),
+            (BinOp::RShift, -8, 1),
+            (BinOp::RShift, -1, 63),
+            (BinOp::RShift, i64::MIN, 62),
+            (BinOp::RShift, i64::MAX, 63),
+            (BinOp::RShift, 1, 64),
+            (BinOp::RShift, 1, i64::MIN),
+        ];
+        for (op, l, r) in cases {
+            let expr = Expr::BinOp {
+                op,
+                lhs: Box::new(Expr::Numeral(l)),
+                rhs: Box::new(Expr::Numeral(r)),
+            };
+            let lua = transpile("", RomanMode::Creative).unwrap();
+            let mut transpiler = Transpiler {
+                out: String::new(),
+                indent: 0,
+            };
+            let lua = format!("{lua}print({})\n", transpiler.expr(&expr));
+            match crate::binop(op, crate::Value::Number(l), crate::Value::Number(r)) {
+                Ok(value) => check_lua(&lua, &format!("{value}\n"), None),
+                Err(e) => check_lua(&lua, "", Some(&e.message)),
+            }
+        }
+    }
+
+    #[test]
+    fn runtime_is_in_front() {
+        let lua = transpile("print(1 / 2)", RomanMode::Creative).unwrap();
+        assert!(lua.starts_with("-- Generated by `lobster-lua transpile`"));
+        assert!(lua.contains("function L.frac(n, d)"));
+        assert!(lua.ends_with("\nprint(__lobster.div(1, 2))\n"));
+    }
+}
@@ -0,0 +1,164 @@
+-- Runtime for Lua transpiled from lobster-lua: exact fractions and the
+-- places where lobster and Lua disagree.
+local L = {}
+
+local Fraction = {}
+
+local function gcd(a, b)
+  while b ~= 0 do
+    a, b = b, a % b
+  end
+  return a
+end
+
+function L.frac(n, d)
+  if d == 0 then
+    error("division by zero", 2)
+  end
+  local g = gcd(math.abs(n), math.abs(d))
+  if d < 0 then
+    n, d = -n, -d
+  end
+  return setmetatable({ n = n // g, d = d // g }, Fraction)
+end
+
+local function lift(x)
+  if getmetatable(x) == Fraction then
+    return x
+  elseif math.type(x) == "integer" then
+    return L.frac(x, 1)
+  end
+  error("attempt to perform arithmetic on a " .. type(x) .. " value", 3)
+end
+
+Fraction.__add = function(a, b)
+  a, b = lift(a), lift(b)
+  return L.frac(a.n * b.d + b.n * a.d, a.d * b.d)
+end
+
+Fraction.__sub = function(a, b)
+  a, b = lift(a), lift(b)
+  return L.frac(a.n * b.d - b.n * a.d, a.d * b.d)
+end
+
+Fraction.__mul = function(a, b)
+  a, b = lift(a), lift(b)
+  return L.frac(a.n * b.n, a.d * b.d)
+end
+
+Fraction.__eq = function(a, b)
+  return a.n == b.n and a.d == b.d
+end
+
+Fraction.__lt = function(a, b)
+  a, b = lift(a), lift(b)
+  return a.n * b.d < b.n * a.d
+end
+
+Fraction.__le = function(a, b)
+  a, b = lift(a), lift(b)
+  return a.n * b.d <= b.n * a.d
+end
+
+Fraction.__tostring = function(a)
+  return a.n .. "/" .. a.d
+end
+
+-- `/` always makes a fraction, even of two integers.
+function L.div(a, b)
+  a, b = lift(a), lift(b)
+  return L.frac(a.n * b.d, a.d * b.n)
+end
+
+local function checked_mul(a, b)
+  local res = a * b
+  if a ~= 0 and (res // a ~= b or (a == -1 and b == math.mininteger)) then
+    error("attempt to multiply with overflow", 3)
+  end
+  return res
+end
+
+-- `^` on integers stays an integer, and errors instead of wrapping.
+function L.pow(a, b)
+  if math.type(a) ~= "integer" or math.type(b) ~= "integer" then
+    local bad = math.type(a) ~= "integer" and a or b
+    error("attempt to perform arithmetic on a " .. type(bad) .. " value", 2)
+  elseif b < 0 then
+    error("attempt to raise to a negative power", 2)
+  end
+  local res = 1
+  while b > 0 do
+    if b % 2 == 1 then
+      res = checked_mul(res, a)
+    end
+    b = b // 2
+    if b > 0 then
+      a = checked_mul(a, a)
+    end
+  end
+  return res
+end
+
+-- `%` truncates like lobster's, rather than flooring like Lua's.
+function L.mod(a, b)
+  return math.fmod(a, b)
+end
+
+local function shift_amount(a, b)
+  if math.type(a) ~= "integer" or math.type(b) ~= "integer" then
+    local bad = math.type(a) ~= "integer" and a or b
+    error("attempt to perform bitwise operation on a " .. type(bad) .. " value", 3)
+  elseif b < 0 or b >= 64 then
+    error("attempt to shift with overflow", 3)
+  end
+  return b
+end
+
+-- Shifts by 64 or more, or by a negative amount, are errors rather than 0.
+function L.shl(a, b)
+  return a << shift_amount(a, b)
+end
+
+-- `>>` keeps the sign.
+function L.shr(a, b)
+  b = shift_amount(a, b)
+  if a >= 0 then
+    return a >> b
+  end
+  return ~(~a >> b)
+end
+
+-- Lobster counts from 0, Lua tables from 1.
+function L.index(t, k)
+  if math.type(k) == "integer" then
+    k = k + 1
+  end
+  return t[k]
+end
+
+-- A lobster function returns its first value only.
+function L.first(...)
+  return (...)
+end
+
+-- Only decimal numbers: `tostring(x, [[roman]])` is an error here.
+function L.tostring(v, format)
+  if format ~= nil and format ~= "decimal" then
+    error("tostring format " .. tostring(format) .. " is not supported", 2)
+  elseif v == nil then
+    return "nil"
+  elseif type(v) == "function" then
+    return "function"
+  end
+  return tostring(v)
+end
+
+function L.print(...)
+  local parts = {}
+  for i = 1, select("#", ...) do
+    parts[i] = L.tostring((select(i, ...)))
+  end
+  io.write(table.concat(parts, ", "), "\n")
+end
+
+return L
@@ -0,0 +1,104 @@
+//! `sudoku bench`: times the solvers on the puzzles from stdin.
+
+use std::time::{Duration, Instant};
+
+use super::bitfield_tactics::Search;
+use super::format::Format;
+use super::solver::{self, Solver};
+use super::PlayingField;
+
+const USAGE: &str =
+    "Usage: sudoku bench [--solver dlx|bitfield|naive]... [--budget SECONDS] < puzzles";
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut solvers: Vec<Box<dyn Solver>> = vec![];
+    let mut budget = Duration::from_secs(10);
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {arg}\n{USAGE}"))?;
+        match arg.as_str() {
+            "--solver" => solvers.push(solver::by_name(value)?),
+            "--budget" => {
+                let seconds: f64 = value
+                    .parse()
+                    .ok()
+                    .filter(|s: &f64| s.is_finite() && *s > 0.0)
+                    .ok_or_else(|| format!("Not a number of seconds: {value:?}"))?;
+                budget = Duration::from_secs_f64(seconds);
+            }
+            _ => return Err(format!("Unknown option {arg}\n{USAGE}")),
+        }
+    }
+    if solvers.is_empty() {
+        solvers = solver::NAMES
+            .iter()
+            .map(|name| solver::by_name(name))
+            .collect::<Result<_, _>>()?;
+    }
+
+    let text = std::io::read_to_string(std::io::stdin()).map_err(|e| format!("stdin: {e}"))?;
+    let read = Format::detect(&text, None).read_each(&text);
+    let mut puzzles = vec![];
+    for (idx, puzzle) in read.into_iter().enumerate() {
+        match puzzle {
+            Ok(puzzle) => puzzles.push(puzzle.play),
+            Err(e) => eprintln!("Sudoko {idx}: invalid: {e}, left out"),
+        }
+    }
+
+    println!(
+        "{:<10}{:>8}{:>8}{:>12}{:>12}{:>12}",
+        "solver", "puzzles", "solved", "total ms", "mean µs", "max ms"
+    );
+    for solver in &solvers {
+        bench(solver.as_ref(), &puzzles, budget);
+    }
+    Ok(())
+}
+
+/// Solves the puzzles in order until they are done or the time budget is
+/// used up, and prints a line of the table. A puzzle still being solved when
+/// the budget runs out is given up on.
+fn bench(solver: &dyn Solver, puzzles: &[PlayingField], budget: Duration) {
+    let mut total = Duration::ZERO;
+    let mut max = Duration::ZERO;
+    let mut done = 0;
+    let mut solved = 0;
+    let mut out_of_time = false;
+
+    for puzzle in puzzles {
+        let start = Instant::now();
+        let mut search = Search {
+            deadline: Some(start + budget.saturating_sub(total)),
+            ..Default::default()
+        };
+        let solution = solver.solve(puzzle, &mut search);
+        let elapsed = start.elapsed();
+
+        total += elapsed;
+        max = max.max(elapsed);
+        done += 1;
+        if search.timed_out {
+            out_of_time = true;
+            break;
+        }
+        if solution.is_ok_and(|s| s.is_solved()) {
+            solved += 1;
+        }
+    }
+
+    let mean = total.as_secs_f64() * 1e6 / done.max(1) as f64;
+    println!(
+        "{:<10}{:>8}{:>8}{:>12.1}{:>12.1}{:>12.2}{}",
+        solver.name(),
+        done,
+        solved,
+        total.as_secs_f64() * 1e3,
+        mean,
+        max.as_secs_f64() * 1e3,
+        if out_of_time { " (out of time)" } else { "" },
+    );
+}
@@ -1,47 +1,101 @@
-use super::{group_indices, Number, PlayingField};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::variants::Constraint;
+use super::{Number, PlayingField};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BitfiedTacticsSolver {
-    fields: [BitVector; 9 * 9],
+    box_size: usize,
+    fields: Vec<BitVector>,
+    /// Rows, columns, boxes and variant regions, shared between all clones
+    /// of a solver.
+    groups: Rc<[Vec<usize>]>,
+    /// The groups each field belongs to, as indices into `groups`.
+    groups_of_field: Rc<[Vec<usize>]>,
+    /// Variant constraints that are not groups, see [`Self::propagate`].
+    constraints: Arc<[Constraint]>,
 }
 
-static TIMESTAMP: std::sync::Mutex<Option<std::time::Instant>> = std::sync::Mutex::new(None);
+/// Limits and counters of one search.
+#[derive(Clone, Debug, Default)]
+pub struct Search {
+    /// When to give up.
+    pub deadline: Option<Instant>,
+    /// Whether the search gave up at the deadline.
+    pub timed_out: bool,
+    /// Fields guessed so far.
+    pub guesses: usize,
+    /// Fields set so far without a guess, because only one number was left
+    /// for them.
+    pub propagations: usize,
+}
+
+impl Search {
+    /// Whether the deadline has passed, which also sets
+    /// [`Self::timed_out`].
+    pub fn out_of_time(&mut self) -> bool {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.timed_out = true;
+        }
+        self.timed_out
+    }
+}
 
 impl BitfiedTacticsSolver {
     pub fn new(play: &PlayingField) -> Self {
+        let side = play.side();
+        let groups: Rc<[Vec<usize>]> = play.groups().into();
+        let mut groups_of_field = vec![vec![]; play.fields.len()];
+        for (i, group) in groups.iter().enumerate() {
+            for &idx in group {
+                groups_of_field[idx].push(i);
+            }
+        }
         let mut this = BitfiedTacticsSolver {
-            fields: play.fields.map(From::from),
+            box_size: play.box_size,
+            fields: play
+                .fields
+                .iter()
+                .map(|&number| BitVector::new(number, side))
+                .collect(),
+            groups,
+            groups_of_field: groups_of_field.into(),
+            constraints: Arc::clone(&play.constraints),
         };
 
+        // A contradiction between the clues leaves a field without any
+        // possible number, so the solvers find no solution.
         for (idx, number) in play.iter_populated_fields() {
-            this.set_field(idx, number);
-        }
-
-        for field in this.fields.iter() {
-            // TODO: Unsolveable cases panic for now
-            assert!(field.num_set_bits() != 0);
+            if this.fields[idx].mask & (1 << (number - 1)) == 0 {
+                this.fields[idx].mask = 0;
+                break;
+            }
+            if !this.set_field(idx, number) {
+                break;
+            }
         }
 
         this
     }
 
-    pub fn eliminate(&mut self, idx: usize, bit_mask: u16) -> bool {
-        for group in group_indices() {
-            if !group.contains(&idx) {
-                continue;
-            }
-
-            for member_idx in group {
+    pub fn eliminate(&mut self, idx: usize, bit_mask: u32) -> bool {
+        let groups_of_field = Rc::clone(&self.groups_of_field);
+        for &group in &groups_of_field[idx] {
+            let groups = Rc::clone(&self.groups);
+            for &member_idx in &groups[group] {
                 if member_idx != idx {
                     // Only change the field when the bitmask would modify it
                     if (self.fields[member_idx].mask & bit_mask) != 0 {
                         self.fields[member_idx].mask &= !bit_mask;
                         match self.fields[member_idx].num_set_bits() {
                             // If we have only one possible value, we also have to eliminate its rows, column and cells.
-                            1 => {
-                                if !self.eliminate(member_idx, self.fields[member_idx].mask) {
-                                    return false;
-                                }
+                            1 if !self.eliminate(member_idx, self.fields[member_idx].mask) => {
+                                return false;
                             }
                             // If there is no possible value for this field, the whole state is incorrect.
                             0 => return false,
@@ -68,79 +122,212 @@ impl BitfiedTacticsSolver {
         self.eliminate(idx, bit_mask)
     }
 
+    /// The first solution, or `None` if there is none.
     pub fn try_solve(&self) -> Option<Self> {
-        {
-            let mut timestamp = TIMESTAMP.lock().unwrap();
-            let timestamp = timestamp.get_or_insert_with(|| std::time::Instant::now());
-            let now = std::time::Instant::now();
-            if now - *timestamp > std::time::Duration::from_secs(10) {
-                println!("{}", self.extract());
-                *timestamp = now;
-            }
+        self.first_solution(&mut Search::default())
+    }
+
+    /// The first solution within the limits of `search`, see
+    /// [`Search::timed_out`] for why there is none.
+    pub fn first_solution(&self, search: &mut Search) -> Option<Self> {
+        let mut solution = None;
+        self.search(search, &mut |solved| {
+            solution = Some(solved.clone());
+            false
+        });
+        solution
+    }
+
+    /// Counts the solutions, stopping once `limit` of them have been found.
+    pub fn count_solutions(&self, limit: usize) -> usize {
+        if limit == 0 {
+            return 0;
         }
+        let mut count = 0;
+        self.search(&mut Search::default(), &mut |_| {
+            count += 1;
+            count < limit
+        });
+        count
+    }
+
+    /// Whether there is exactly one solution.
+    pub fn is_unique(&self) -> bool {
+        self.count_solutions(2) == 1
+    }
 
+    /// Up to `limit` solutions, in the order [`Self::count_solutions`] finds
+    /// them, that turn up within the limits of `search`.
+    pub fn solutions(&self, limit: usize, search: &mut Search) -> Vec<PlayingField> {
+        let mut solutions = vec![];
+        if limit > 0 {
+            self.search(search, &mut |solution| {
+                solutions.push(solution.extract());
+                solutions.len() < limit
+            });
+        }
+        solutions
+    }
+
+    /// How many fields have to be guessed before the first solution is
+    /// found, or `None` if there is no solution.
+    pub fn guesses(&self) -> Option<usize> {
+        let mut search = Search::default();
+        let solved = self.first_solution(&mut search).is_some();
+        solved.then_some(search.guesses)
+    }
+
+    /// Visits every solution until `found` returns false or the deadline
+    /// passes. Returns false if the search was stopped early.
+    fn search(&self, search: &mut Search, found: &mut dyn FnMut(&Self) -> bool) -> bool {
+        if search.out_of_time() {
+            return false;
+        }
         if self.fields.iter().any(|f| f.num_set_bits() == 0) {
-            return None;
+            return true;
         }
 
-        if !self.extract().check_constraints() {
-            println!("Unsolvable: {}", self.extract());
-            self.extract().print_bad_constraints();
-            //return None;
-            panic!("Unsolvable");
+        let mut this = self.clone();
+        if !this.propagate() {
+            return true;
         }
+        let this = &this;
+        search.propagations += this.settled() - self.settled();
 
-        let mut sorted_fields: Vec<(usize, BitVector)> = self
+        let Some((idx, bv)) = this
             .fields
             .iter()
             .copied()
             .enumerate()
             .filter(|(_i, f)| f.num_set_bits() != 1)
-            .collect();
-        sorted_fields.sort_by_key(|(_i, f)| f.num_set_bits());
-
-        let (idx, bv) = match sorted_fields.first().copied() {
-            // empty -> all solved
-            None => return Some(self.clone()),
-            // not solveable
-            Some((_, field)) if field.num_set_bits() == 0 => {
-                unreachable!();
-            }
-            // all good
-            Some(f) => f,
+            .min_by_key(|(_i, f)| f.num_set_bits())
+        else {
+            return found(this);
         };
 
-        debug_assert!(bv.num_set_bits() != 0);
-        debug_assert!(bv.num_set_bits() != 1);
-
+        search.guesses += 1;
         for number in bv.iter_possible_numbers() {
-            let mut new_field = self.clone();
+            let mut new_field = this.clone();
             if !new_field.set_field(idx, number) {
                 continue;
-            };
-            //dbg!(idx, number);
-            if let Some(new_field) = new_field.try_solve() {
-                return Some(new_field);
+            }
+            search.propagations += new_field.settled() - this.settled() - 1;
+            if !new_field.search(search, found) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// How many fields have only one number left.
+    fn settled(&self) -> usize {
+        self.fields.iter().filter(|f| f.num_set_bits() == 1).count()
+    }
+
+    /// Sets hidden singles and lets the variant constraints prune
+    /// candidates, until neither changes anything. Returns false on a
+    /// contradiction.
+    fn propagate(&mut self) -> bool {
+        let side = self.box_size * self.box_size;
+        let constraints = Arc::clone(&self.constraints);
+        loop {
+            if !self.set_hidden_singles() {
+                return false;
+            }
+            let before: Vec<u32> = self.fields.iter().map(|f| f.mask).collect();
+            let mut masks = before.clone();
+            if !constraints.iter().all(|c| c.prune(&mut masks, side)) {
+                return false;
+            }
+            if masks == before {
+                return true;
+            }
+
+            for (field, &mask) in self.fields.iter_mut().zip(&masks) {
+                field.mask = mask;
+            }
+            for (idx, (&old, &new)) in before.iter().zip(&masks).enumerate() {
+                if old != new && new.count_ones() == 1 && !self.eliminate(idx, new) {
+                    return false;
+                }
             }
         }
+    }
 
-        // No number works
-        None
+    /// Sets every field that is the only place left for a number in one of
+    /// its groups, until there are none left. Returns false on a contradiction.
+    fn set_hidden_singles(&mut self) -> bool {
+        let side = self.box_size * self.box_size;
+        let all = BitVector::new(Number(None), side).mask;
+        let groups = Rc::clone(&self.groups);
+        loop {
+            let mut progress = false;
+            // Only groups of `side` fields must hold every number, cages
+            // for example are smaller.
+            for group in groups.iter().filter(|g| g.len() == side) {
+                let mut once = 0;
+                let mut more = 0;
+                for &idx in group {
+                    more |= once & self.fields[idx].mask;
+                    once |= self.fields[idx].mask;
+                }
+                if once != all {
+                    return false;
+                }
+
+                let hidden = once & !more;
+                for &idx in group {
+                    let field = self.fields[idx];
+                    let mask = field.mask & hidden;
+                    if mask == 0 || field.num_set_bits() == 1 {
+                        continue;
+                    }
+                    if mask.count_ones() != 1 {
+                        return false;
+                    }
+                    let number = BitVector { mask }.iter_possible_numbers().next().unwrap();
+                    if !self.set_field(idx, number) {
+                        return false;
+                    }
+                    progress = true;
+                }
+            }
+            if !progress {
+                return true;
+            }
+        }
     }
 
     pub fn extract(&self) -> PlayingField {
         PlayingField {
-            fields: self.fields.map(BitVector::to_exact_number),
+            box_size: self.box_size,
+            fields: self
+                .fields
+                .iter()
+                .copied()
+                .map(BitVector::to_exact_number)
+                .collect(),
+            constraints: Arc::clone(&self.constraints),
         }
     }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct BitVector {
-    mask: u16,
+    mask: u32,
 }
 
 impl BitVector {
+    /// The possibilities for a field of a board with `side` symbols.
+    pub fn new(value: Number, side: usize) -> Self {
+        BitVector {
+            mask: match value {
+                Number(Some(i)) => 1 << (i - 1),
+                Number(None) => (1 << side) - 1,
+            },
+        }
+    }
+
     pub fn to_exact_number(self) -> Number {
         if self.mask.count_ones() != 1 {
             Number(None)
@@ -152,7 +339,7 @@ impl BitVector {
 
     pub fn num_set_bits(&self) -> u8 {
         u8::try_from(self.mask.count_ones())
-            .expect("how do you fit more than 255 ones into an u16?!")
+            .expect("how do you fit more than 255 ones into an u32?!")
     }
 
     pub fn iter_possible_numbers(&self) -> PossibleNumberIterator {
@@ -160,17 +347,6 @@ impl BitVector {
     }
 }
 
-impl From<Number> for BitVector {
-    fn from(value: Number) -> Self {
-        BitVector {
-            mask: match value {
-                Number(Some(i)) => 1 << (i - 1),
-                Number(None) => 0x1ff,
-            },
-        }
-    }
-}
-
 pub struct PossibleNumberIterator {
     index: usize,
     bit_vector: BitVector,
@@ -191,7 +367,7 @@ impl Iterator for PossibleNumberIterator {
     fn next(&mut self) -> Option<Self::Item> {
         while self.bit_vector.mask & (1 << self.index) == 0 {
             self.index += 1;
-            if self.index == 10 {
+            if self.index == u32::BITS as usize {
                 return None;
             }
         }
@@ -199,3 +375,46 @@ impl Iterator for PossibleNumberIterator {
         Some(u8::try_from(self.index).expect("big mask no work my dude"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(line: &str, limit: usize) -> usize {
+        let puzzle = PlayingField::new(line).unwrap();
+        BitfiedTacticsSolver::new(&puzzle).count_solutions(limit)
+    }
+
+    #[test]
+    fn counts_no_solutions() {
+        // Nothing is repeated, but the third field of the first row can hold
+        // neither 1 or 2 from its row nor 3 or 4 from its column.
+        let line = "12........4...3.";
+        assert!(PlayingField::new(line).unwrap().check_constraints());
+        assert_eq!(count(line, 2), 0);
+        assert!(!BitfiedTacticsSolver::new(&PlayingField::new(line).unwrap()).is_unique());
+    }
+
+    #[test]
+    fn counts_one_solution() {
+        let line =
+            "003020600900305001001806400008102900700000008006708200002609500800203009005010300";
+        assert_eq!(count(line, 2), 1);
+        assert_eq!(count(line, usize::MAX), 1);
+        assert!(BitfiedTacticsSolver::new(&PlayingField::new(line).unwrap()).is_unique());
+    }
+
+    #[test]
+    fn counts_several_solutions_up_to_the_limit() {
+        let empty = "0".repeat(16);
+        assert_eq!(count(&empty, usize::MAX), 288);
+        assert_eq!(count(&empty, 10), 10);
+        assert_eq!(count(&empty, 1), 1);
+        assert_eq!(count(&empty, 0), 0);
+        assert!(!BitfiedTacticsSolver::new(&PlayingField::empty(2)).is_unique());
+
+        // Far too many to count them all, so this only returns because
+        // counting stops at the limit.
+        assert_eq!(count(&"0".repeat(81), 5), 5);
+    }
+}
@@ -0,0 +1,701 @@
+//! The commands that work through a file of puzzles, `solve`, `check`,
+//! `rate`, `hint` and `explain`, and the options they share.
+//!
+//! A puzzle that cannot be read or solved gets a line of its own and counts
+//! in the summary on stderr, the others are still worked on. `solve` works
+//! on several puzzles at once, one per thread, and still prints them in
+//! order.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::bitfield_tactics::{BitfiedTacticsSolver, Search};
+use super::format::{Format, ParseError, Puzzle};
+use super::solver::{self, Failure, Solver};
+use super::{rating, tactics};
+
+pub const COMMANDS: [&str; 5] = ["solve", "check", "rate", "hint", "explain"];
+
+const USAGE: &str = "Usage: sudoku [solve|check|rate|hint|explain] [--input FILE] \
+    [--output FILE] [--format FORMAT] [--output-format FORMAT] \
+    [--solver dlx|bitfield|naive] [--timeout SECONDS] [--jobs N] [--stats FILE]";
+
+/// The options of one command. Puzzles are read from stdin and results
+/// written to stdout unless files are given.
+pub struct Options {
+    input: Option<String>,
+    output: Option<String>,
+    /// The format of the input, detected if not given.
+    format: Option<Format>,
+    /// The format of solutions, `solve` prints grids if not given.
+    output_format: Option<Format>,
+    solver: Box<dyn Solver>,
+    /// How long to try each puzzle.
+    timeout: Option<Duration>,
+    /// How many puzzles `solve` works on at once.
+    jobs: usize,
+    /// Where `solve` writes the time, guesses and propagations of each
+    /// puzzle as CSV.
+    stats: Option<String>,
+}
+
+impl Options {
+    /// Parses the options of `command`, which takes only those that apply
+    /// to it.
+    pub fn parse(command: &str, args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            input: None,
+            output: None,
+            format: None,
+            output_format: None,
+            solver: solver::by_name("bitfield")?,
+            timeout: None,
+            jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+            stats: None,
+        };
+        let allowed: &[&str] = match command {
+            "solve" => &[
+                "--solver",
+                "--timeout",
+                "--output-format",
+                "--jobs",
+                "--stats",
+            ],
+            "check" | "rate" | "hint" | "explain" => &["--timeout"],
+            _ => &[],
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let arg = match arg.as_str() {
+                "-i" => "--input",
+                "-o" => "--output",
+                "-f" => "--format",
+                arg => arg,
+            };
+            let unknown = || format!("Unknown option {arg} for {command}\n{USAGE}");
+            if !["--input", "--output", "--format"].contains(&arg) && !allowed.contains(&arg) {
+                return Err(unknown());
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {arg}\n{USAGE}"))?;
+            match arg {
+                "--input" => options.input = Some(value.clone()),
+                "--output" => options.output = Some(value.clone()),
+                "--format" => options.format = Some(Format::by_name(value)?),
+                "--output-format" => options.output_format = Some(Format::by_name(value)?),
+                "--solver" => options.solver = solver::by_name(value)?,
+                "--timeout" => {
+                    let seconds: f64 = value
+                        .parse()
+                        .ok()
+                        .filter(|s: &f64| s.is_finite() && *s > 0.0)
+                        .ok_or_else(|| format!("Not a number of seconds: {value:?}"))?;
+                    options.timeout = Some(Duration::from_secs_f64(seconds));
+                }
+                "--jobs" => {
+                    options.jobs = value
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("Not a number of threads: {value:?}"))?;
+                }
+                "--stats" => options.stats = Some(value.clone()),
+                _ => return Err(unknown()),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Every puzzle of the input, or why it could not be read.
+    fn read(&self) -> Result<Vec<Result<Puzzle, ParseError>>, String> {
+        let text = match &self.input {
+            Some(path) => std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?,
+            None => io::read_to_string(io::stdin()).map_err(|e| format!("stdin: {e}"))?,
+        };
+        let format = self
+            .format
+            .unwrap_or_else(|| Format::detect(&text, self.input.as_deref()));
+        Ok(format.read_each(&text))
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|timeout| Instant::now() + timeout)
+    }
+}
+
+/// Where a command writes its results: `path`, or stdout without one.
+pub fn output(path: Option<&str>) -> Result<Box<dyn Write>, String> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).map_err(|e| format!("{path}: {e}"))?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    })
+}
+
+/// Runs one of [`COMMANDS`] and prints its summary to stderr.
+pub fn run(command: &str, args: &[String]) -> Result<(), String> {
+    let options = Options::parse(command, args)?;
+    let puzzles = options.read()?;
+    let mut out = output(options.output.as_deref())?;
+    let mut stats = match &options.stats {
+        Some(path) => Some(output(Some(path))?),
+        None => None,
+    };
+
+    let mut summary = Summary::new();
+    let result = match command {
+        "solve" => solve(
+            &options,
+            puzzles,
+            &mut out,
+            stats.as_deref_mut(),
+            &mut summary,
+        ),
+        "check" => check(&options, puzzles, &mut out, &mut summary),
+        "rate" => rate(&options, puzzles, &mut out, &mut summary),
+        "hint" => hint(&options, puzzles, &mut out, &mut summary),
+        "explain" => explain(&options, puzzles, &mut out, &mut summary),
+        _ => return Err(format!("Unknown command {command:?}\n{USAGE}")),
+    };
+    result
+        .and_then(|()| out.flush())
+        .map_err(|e| format!("{}: {e}", options.output.as_deref().unwrap_or("stdout")))?;
+    eprintln!("{summary}");
+    Ok(())
+}
+
+/// How many puzzles ended which way, in the order the outcomes came up,
+/// and how the solver did on those it tried.
+struct Summary {
+    start: Instant,
+    counts: Vec<(String, usize)>,
+    /// The time, guesses and propagations of each puzzle that was tried.
+    tries: Vec<(Duration, usize, usize)>,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Summary {
+            start: Instant::now(),
+            counts: vec![],
+            tries: vec![],
+        }
+    }
+
+    fn tried(&mut self, time: Duration, search: &Search) {
+        self.tries.push((time, search.guesses, search.propagations));
+    }
+
+    /// The time below which `share` of the tries took, by nearest rank.
+    fn percentile(&self, share: f64) -> Duration {
+        let mut times: Vec<Duration> = self.tries.iter().map(|t| t.0).collect();
+        times.sort();
+        let rank = (share * times.len() as f64).ceil() as usize;
+        times[rank.clamp(1, times.len()) - 1]
+    }
+
+    fn add(&mut self, outcome: impl ToString) {
+        let outcome = outcome.to_string();
+        match self.counts.iter_mut().find(|(o, _n)| *o == outcome) {
+            Some((_o, n)) => *n += 1,
+            None => self.counts.push((outcome, 1)),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: usize = self.counts.iter().map(|(_o, n)| n).sum();
+        write!(
+            f,
+            "{total} puzzle{} in {:.2} s",
+            if total == 1 { "" } else { "s" },
+            self.start.elapsed().as_secs_f64()
+        )?;
+        for (i, (outcome, n)) in self.counts.iter().enumerate() {
+            write!(f, "{} {n} {outcome}", if i == 0 { ":" } else { "," })?;
+        }
+        if self.tries.is_empty() {
+            return Ok(());
+        }
+
+        let tries = self.tries.len() as f64;
+        let guesses: usize = self.tries.iter().map(|t| t.1).sum();
+        let propagations: usize = self.tries.iter().map(|t| t.2).sum();
+        write!(
+            f,
+            "\n{:.1} puzzles/s, median {:.3} ms, p99 {:.3} ms, \
+             {:.1} guesses and {:.1} propagations per puzzle",
+            tries / self.start.elapsed().as_secs_f64(),
+            self.percentile(0.5).as_secs_f64() * 1e3,
+            self.percentile(0.99).as_secs_f64() * 1e3,
+            guesses as f64 / tries,
+            propagations as f64 / tries,
+        )
+    }
+}
+
+/// The puzzle, or why it is not a valid one.
+fn valid(puzzle: Result<Puzzle, ParseError>) -> Result<Puzzle, String> {
+    let puzzle = puzzle.map_err(|e| format!("invalid: {e}"))?;
+    match puzzle.play.bad_constraints().first() {
+        Some(group) => Err(format!("invalid: Bad Constraint in {group}")),
+        None => Ok(puzzle),
+    }
+}
+
+/// What became of one puzzle of a batch.
+struct Attempt {
+    /// The solved puzzle, or why there is none.
+    solution: Result<Puzzle, String>,
+    /// How long the solver took, `None` if the puzzle was invalid.
+    time: Option<Duration>,
+    search: Search,
+}
+
+fn attempt(options: &Options, puzzle: Result<Puzzle, ParseError>) -> Attempt {
+    let mut search = Search::default();
+    let puzzle = match valid(puzzle) {
+        Ok(puzzle) => puzzle,
+        Err(e) => {
+            return Attempt {
+                solution: Err(e),
+                time: None,
+                search,
+            }
+        }
+    };
+
+    search.deadline = options.deadline();
+    let start = Instant::now();
+    let solution = options
+        .solver
+        .solve(&puzzle.play, &mut search)
+        .map(|solved| Puzzle {
+            play: solved,
+            ..puzzle
+        })
+        .map_err(|failure| failure.to_string());
+    Attempt {
+        solution,
+        time: Some(start.elapsed()),
+        search,
+    }
+}
+
+/// Solves the puzzles on `--jobs` threads and hands each attempt to `done`
+/// in the order of the puzzles, as soon as those before it are done.
+fn solve_all(
+    options: &Options,
+    puzzles: Vec<Result<Puzzle, ParseError>>,
+    done: &mut dyn FnMut(usize, Attempt) -> io::Result<()>,
+) -> io::Result<()> {
+    let jobs = options.jobs.min(puzzles.len());
+    let queue = Mutex::new(puzzles.into_iter().enumerate());
+    let (send, receive) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let (queue, send) = (&queue, send.clone());
+            scope.spawn(move || loop {
+                let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                let Some((idx, puzzle)) = next else {
+                    break;
+                };
+                // Only fails once `done` has, then the rest is not needed.
+                if send.send((idx, attempt(options, puzzle))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(send);
+
+        let mut waiting = BTreeMap::new();
+        let mut next = 0;
+        for (idx, attempt) in receive {
+            waiting.insert(idx, attempt);
+            while let Some(attempt) = waiting.remove(&next) {
+                done(next, attempt)?;
+                next += 1;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Solves each puzzle. Without `--output-format` each solution is drawn
+/// under its number, with one the solutions are written in that format and
+/// the puzzles without one are listed on stderr. With `stats` each puzzle
+/// gets a CSV row there.
+fn solve(
+    options: &Options,
+    puzzles: Vec<Result<Puzzle, ParseError>>,
+    out: &mut dyn Write,
+    mut stats: Option<&mut (dyn Write + '_)>,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    if let Some(stats) = stats.as_mut() {
+        writeln!(
+            stats,
+            "puzzle,solver,outcome,microseconds,guesses,propagations"
+        )?;
+    }
+    let mut solutions = vec![];
+    solve_all(options, puzzles, &mut |idx, attempt| {
+        let label = match &attempt.solution {
+            Ok(_) => "solved",
+            Err(e) => outcome(e),
+        };
+        if let Some(time) = attempt.time {
+            summary.tried(time, &attempt.search);
+        }
+        if let Some(stats) = stats.as_mut() {
+            write!(stats, "{idx},{},{label}", options.solver.name())?;
+            match attempt.time {
+                Some(time) => writeln!(
+                    stats,
+                    ",{},{},{}",
+                    time.as_micros(),
+                    attempt.search.guesses,
+                    attempt.search.propagations
+                )?,
+                None => writeln!(stats, ",,,")?,
+            }
+        }
+        summary.add(label);
+
+        match (attempt.solution, options.output_format) {
+            (Ok(solution), None) => {
+                writeln!(out, "Sudoko {idx}")?;
+                writeln!(out, "{}", solution.play)?;
+            }
+            (Ok(solution), Some(_)) => solutions.push(solution),
+            (Err(e), Some(_)) => eprintln!("Sudoko {idx}: {e}"),
+            (Err(e), None) => writeln!(out, "Sudoko {idx}: {e}")?,
+        }
+        Ok(())
+    })?;
+    if let Some(format) = options.output_format {
+        write!(out, "{}", format.write(&solutions))?;
+    }
+    if let Some(stats) = stats {
+        stats.flush()?;
+    }
+    Ok(())
+}
+
+/// The word of a message the summary counts it under.
+fn outcome(message: &str) -> &str {
+    match message.split_once(':') {
+        Some((outcome, _rest)) => outcome,
+        None => message,
+    }
+}
+
+/// Labels each puzzle as invalid, unsolvable, unique or multiple. For
+/// several solutions, two of them are printed as a witness.
+fn check(
+    options: &Options,
+    puzzles: Vec<Result<Puzzle, ParseError>>,
+    out: &mut dyn Write,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    for (idx, puzzle) in puzzles.into_iter().enumerate() {
+        let puzzle = match valid(puzzle) {
+            Ok(puzzle) => puzzle,
+            Err(e) => {
+                writeln!(out, "Sudoko {idx}: {e}")?;
+                summary.add(outcome(&e));
+                continue;
+            }
+        };
+
+        let mut search = Search {
+            deadline: options.deadline(),
+            ..Search::default()
+        };
+        let solutions = BitfiedTacticsSolver::new(&puzzle.play).solutions(2, &mut search);
+        let label = match solutions.as_slice() {
+            [_, _, ..] => "multiple",
+            _ if search.timed_out => "timed out",
+            [] => "unsolvable",
+            [_] => "unique",
+        };
+        writeln!(out, "Sudoko {idx}: {label}")?;
+        for solution in solutions.iter().filter(|_| label == "multiple") {
+            writeln!(out, "{}", solution.to_line())?;
+        }
+        summary.add(label);
+    }
+    Ok(())
+}
+
+/// Prints the difficulty of each puzzle.
+fn rate(
+    options: &Options,
+    puzzles: Vec<Result<Puzzle, ParseError>>,
+    out: &mut dyn Write,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    let pipeline = tactics::Pipeline::standard();
+    for (idx, puzzle) in puzzles.into_iter().enumerate() {
+        let rating = valid(puzzle).and_then(|puzzle| {
+            let mut search = Search {
+                deadline: options.deadline(),
+                ..Search::default()
+            };
+            rating::rate(&puzzle.play, &pipeline, &mut search).ok_or_else(|| {
+                let failure = if search.timed_out {
+                    Failure::TimedOut
+                } else {
+                    Failure::Unsolvable
+                };
+                failure.to_string()
+            })
+        });
+        match rating {
+            Ok(rating) => {
+                writeln!(out, "Sudoko {idx}: {rating}")?;
+                summary.add(format!("{:?}", rating.grade()).to_lowercase());
+            }
+            Err(e) => {
+                writeln!(out, "Sudoko {idx}: {e}")?;
+                summary.add(outcome(&e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The easiest deduction that gets each puzzle further.
+fn hint(
+    options: &Options,
+    puzzles: Vec<Result<Puzzle, ParseError>>,
+    out: &mut dyn Write,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    let pipeline = tactics::Pipeline::standard();
+    for (idx, puzzle) in puzzles.into_iter().enumerate() {
+        let mut search = Search {
+            deadline: options.deadline(),
+            ..Search::default()
+        };
+        let puzzle = valid(puzzle).and_then(|puzzle| {
+            match solver::Bitfield.solve(&puzzle.play, &mut search) {
+                Ok(_) => Ok(puzzle),
+                Err(failure) => Err(failure.to_string()),
+            }
+        });
+        let puzzle = match puzzle {
+            Ok(puzzle) => puzzle,
+            Err(e) => {
+                writeln!(out, "Sudoko {idx}: {e}")?;
+                summary.add(outcome(&e));
+                continue;
+            }
+        };
+
+        let grid = tactics::Grid::new(&puzzle.play);
+        match pipeline.next_step(&grid, &mut search) {
+            _ if grid.is_solved() => {
+                writeln!(out, "Sudoko {idx}: solved already")?;
+                summary.add("solved already");
+            }
+            None if search.timed_out => {
+                writeln!(out, "Sudoko {idx}: {}", Failure::TimedOut)?;
+                summary.add(Failure::TimedOut);
+            }
+            Some(step) => {
+                writeln!(out, "Sudoko {idx}: {step}")?;
+                summary.add("hint");
+            }
+            None => {
+                writeln!(
+                    out,
+                    "Sudoko {idx}: no technique applies, the next step needs guessing"
+                )?;
+                summary.add("needs guessing");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Solves each puzzle step by step, explaining every deduction. With a
+/// timeout, the steps found before it are still printed.
+fn explain(
+    options: &Options,
+    puzzles: Vec<Result<Puzzle, ParseError>>,
+    out: &mut dyn Write,
+    summary: &mut Summary,
+) -> io::Result<()> {
+    let pipeline = tactics::Pipeline::standard();
+    for (idx, puzzle) in puzzles.into_iter().enumerate() {
+        let puzzle = match valid(puzzle) {
+            Ok(puzzle) => puzzle,
+            Err(e) => {
+                writeln!(out, "Sudoko {idx}: {e}")?;
+                summary.add(outcome(&e));
+                continue;
+            }
+        };
+
+        writeln!(out, "Sudoko {idx}")?;
+        let mut grid = tactics::Grid::new(&puzzle.play);
+        let mut search = Search {
+            deadline: options.deadline(),
+            ..Search::default()
+        };
+        for (i, step) in pipeline.solve(&mut grid, &mut search).iter().enumerate() {
+            writeln!(out, "{:>4}. {}", i + 1, step)?;
+        }
+        if search.timed_out {
+            writeln!(out, "Timed out")?;
+            summary.add(Failure::TimedOut);
+        } else if grid.is_solved() {
+            writeln!(out, "Solved")?;
+            summary.add("solved");
+        } else {
+            writeln!(
+                out,
+                "No technique applies any more, the rest needs guessing"
+            )?;
+            summary.add("needs guessing");
+        }
+        writeln!(out, "{}", grid.to_playing_field())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn puzzles(text: &str) -> Vec<Result<Puzzle, ParseError>> {
+        Format::Line.read_each(text)
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn bad_puzzles_do_not_stop_the_others() {
+        let text = format!(
+            "{}\n12x\n11{}\n{}\n",
+            &include_str!("../msk_009.csv")[..81],
+            "0".repeat(79),
+            "0".repeat(81),
+        );
+        let options = Options::parse("check", &[]).unwrap();
+        let mut out = vec![];
+        let mut summary = Summary::new();
+        check(&options, puzzles(&text), &mut out, &mut summary).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let labels: Vec<&str> = out.lines().filter(|l| l.starts_with("Sudoko")).collect();
+        assert_eq!(
+            labels,
+            [
+                "Sudoko 0: unique",
+                "Sudoko 1: invalid: line 2, column 1: Wrong number of fields: 3, \
+                 expected 16, 81, 256 or 625",
+                "Sudoko 2: invalid: Bad Constraint in ROW 1",
+                "Sudoko 3: multiple",
+            ]
+        );
+        assert_eq!(
+            summary.counts,
+            [
+                ("unique".to_string(), 1),
+                ("invalid".to_string(), 2),
+                ("multiple".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn parallel_solving_keeps_the_order() {
+        let text: String = include_str!("../msk_009.csv")
+            .lines()
+            .take(40)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let text = format!("{text}\n12x\n");
+        let run = |jobs: &str| {
+            let options = Options::parse("solve", &args(&["--jobs", jobs])).unwrap();
+            let (mut out, mut stats) = (vec![], vec![]);
+            let mut summary = Summary::new();
+            solve(
+                &options,
+                puzzles(&text),
+                &mut out,
+                Some(&mut stats),
+                &mut summary,
+            )
+            .unwrap();
+            assert_eq!(summary.tries.len(), 40);
+            (
+                String::from_utf8(out).unwrap(),
+                String::from_utf8(stats).unwrap(),
+            )
+        };
+
+        let (out, stats) = run("4");
+        assert_eq!(out, run("1").0);
+        let rows: Vec<Vec<&str>> = stats.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 42);
+        assert_eq!(
+            rows[0],
+            [
+                "puzzle",
+                "solver",
+                "outcome",
+                "microseconds",
+                "guesses",
+                "propagations"
+            ]
+        );
+        for (idx, row) in rows[1..41].iter().enumerate() {
+            assert_eq!(row[..3], [idx.to_string().as_str(), "bitfield", "solved"]);
+            assert!(row[5].parse::<usize>().unwrap() > 0);
+        }
+        assert_eq!(rows[41], ["40", "bitfield", "invalid", "", "", ""]);
+    }
+
+    #[test]
+    fn slow_puzzles_time_out() {
+        let options = Options::parse("solve", &args(&["--solver", "naive", "--timeout", "0.01"]));
+        let options = options.unwrap();
+        let mut out = vec![];
+        let mut summary = Summary::new();
+        let text = &include_str!("../msk_009.csv")[..81];
+        solve(&options, puzzles(text), &mut out, None, &mut summary).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Sudoko 0: timed out\n");
+
+        for command in ["rate", "hint"] {
+            let options = Options::parse(command, &args(&["--timeout", "0.000000001"])).unwrap();
+            let mut out = vec![];
+            let mut summary = Summary::new();
+            let run = if command == "rate" { rate } else { hint };
+            run(&options, puzzles(text), &mut out, &mut summary).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "Sudoko 0: timed out\n");
+        }
+
+        let options = Options::parse("explain", &args(&["--timeout", "0.000000001"])).unwrap();
+        let mut out = vec![];
+        explain(&options, puzzles(text), &mut out, &mut Summary::new()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Sudoko 0\nTimed out\n"), "{out}");
+
+        assert!(Options::parse("explain", &args(&["--jobs", "1"])).is_err());
+        assert!(Options::parse("solve", &args(&["--timeout", "-1"])).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+//! Knuth's Algorithm X with Dancing Links.
+//!
+//! [`ExactCover`] knows nothing about sudoku: it picks rows of a 0/1 matrix
+//! so that every primary column is covered exactly once and every secondary
+//! column at most once. [`sudoku_solutions`] models a board as such a matrix,
+//! and variants or other puzzles can build their own.
+
+use std::time::Instant;
+
+use super::bitfield_tactics::Search;
+use super::variants::Constraint;
+use super::{Number, PlayingField};
+
+/// Rules the matrix cannot express, see [`ExactCover::search_pruned`].
+pub type Prune<'a> = dyn FnMut(&[usize], &[bool]) -> Option<Vec<usize>> + 'a;
+
+/// A sparse 0/1 matrix as circular doubly linked lists, one per row and
+/// per column. Node 0 is the root, nodes `1..=columns` the column headers.
+pub struct ExactCover {
+    left: Vec<usize>,
+    right: Vec<usize>,
+    up: Vec<usize>,
+    down: Vec<usize>,
+    /// The header each node belongs to.
+    header: Vec<usize>,
+    /// The row each node belongs to, unused for headers.
+    row: Vec<usize>,
+    /// Nodes in each column, indexed by header.
+    size: Vec<usize>,
+    /// The first node of each row, `None` for rows without columns.
+    first: Vec<Option<usize>>,
+    /// Whether each row can still be chosen.
+    live: Vec<bool>,
+    /// Columns the search branched on, with more than one row left.
+    pub branches: usize,
+    /// Columns the search covered with the only row left for them.
+    pub forced: usize,
+}
+
+impl ExactCover {
+    /// A matrix without rows. Columns `0..primary` must be covered, the
+    /// `secondary` ones after them may be.
+    pub fn new(primary: usize, secondary: usize) -> Self {
+        let headers = primary + secondary + 1;
+        let mut this = ExactCover {
+            left: (0..headers).collect(),
+            right: (0..headers).collect(),
+            up: (0..headers).collect(),
+            down: (0..headers).collect(),
+            header: (0..headers).collect(),
+            row: vec![usize::MAX; headers],
+            size: vec![0; headers],
+            first: vec![],
+            live: vec![],
+            branches: 0,
+            forced: 0,
+        };
+        // Only primary columns are in the root's list, so they are the
+        // only ones a solution has to cover.
+        for h in 1..=primary {
+            this.left[h] = h - 1;
+            this.right[h - 1] = h;
+            this.right[h] = 0;
+            this.left[0] = h;
+        }
+        this
+    }
+
+    /// Adds a row with ones in the given columns and returns its index.
+    pub fn add_row(&mut self, columns: &[usize]) -> usize {
+        let row = self.live.len();
+        self.live.push(true);
+
+        let mut first = None;
+        for &column in columns {
+            let header = column + 1;
+            let node = self.header.len();
+            self.header.push(header);
+            self.row.push(row);
+            self.size[header] += 1;
+
+            self.up.push(self.up[header]);
+            self.down.push(header);
+            let last = self.up[header];
+            self.down[last] = node;
+            self.up[header] = node;
+
+            match first {
+                None => {
+                    first = Some(node);
+                    self.left.push(node);
+                    self.right.push(node);
+                }
+                Some(first) => {
+                    self.left.push(self.left[first]);
+                    self.right.push(first);
+                    let last = self.left[first];
+                    self.right[last] = node;
+                    self.left[first] = node;
+                }
+            }
+        }
+        self.first.push(first);
+        row
+    }
+
+    /// Calls `found` with the rows of each solution until it returns false.
+    /// Returns false if the search was stopped early.
+    pub fn search(&mut self, found: &mut dyn FnMut(&[usize]) -> bool) -> bool {
+        self.search_from(&mut vec![], &mut |_, _| Some(vec![]), found)
+    }
+
+    /// Like [`Self::search`], for rules the matrix cannot express. Before
+    /// each choice `prune` gets the rows chosen so far and which rows can
+    /// still be chosen, and returns the ones its rules take out, or `None`
+    /// when they cannot hold any more.
+    pub fn search_pruned(
+        &mut self,
+        prune: &mut Prune<'_>,
+        found: &mut dyn FnMut(&[usize]) -> bool,
+    ) -> bool {
+        self.search_from(&mut vec![], prune, found)
+    }
+
+    /// Up to `limit` solutions, each as the indices of its rows.
+    pub fn solutions(&mut self, limit: usize) -> Vec<Vec<usize>> {
+        let mut solutions = vec![];
+        if limit > 0 {
+            self.search(&mut |rows| {
+                solutions.push(rows.to_vec());
+                solutions.len() < limit
+            });
+        }
+        solutions
+    }
+
+    fn search_from(
+        &mut self,
+        chosen: &mut Vec<usize>,
+        prune: &mut Prune<'_>,
+        found: &mut dyn FnMut(&[usize]) -> bool,
+    ) -> bool {
+        let Some(ruled_out) = prune(chosen, &self.live) else {
+            return true;
+        };
+        for &row in &ruled_out {
+            self.hide_row(row);
+        }
+        let keep_going = self.choose(chosen, prune, found);
+        for &row in ruled_out.iter().rev() {
+            self.unhide_row(row);
+        }
+        keep_going
+    }
+
+    fn choose(
+        &mut self,
+        chosen: &mut Vec<usize>,
+        prune: &mut Prune<'_>,
+        found: &mut dyn FnMut(&[usize]) -> bool,
+    ) -> bool {
+        if self.right[0] == 0 {
+            return found(chosen);
+        }
+
+        // The column with the fewest rows left branches the least.
+        let mut header = self.right[0];
+        let mut h = self.right[header];
+        while h != 0 {
+            if self.size[h] < self.size[header] {
+                header = h;
+            }
+            h = self.right[h];
+        }
+        match self.size[header] {
+            0 => return true,
+            1 => self.forced += 1,
+            _ => self.branches += 1,
+        }
+
+        self.cover(header);
+        let mut node = self.down[header];
+        let mut keep_going = true;
+        while node != header && keep_going {
+            chosen.push(self.row[node]);
+            let mut j = self.right[node];
+            while j != node {
+                self.cover(self.header[j]);
+                j = self.right[j];
+            }
+
+            keep_going = self.search_from(chosen, prune, found);
+
+            let mut j = self.left[node];
+            while j != node {
+                self.uncover(self.header[j]);
+                j = self.left[j];
+            }
+            chosen.pop();
+            node = self.down[node];
+        }
+        self.uncover(header);
+        keep_going
+    }
+
+    fn cover(&mut self, header: usize) {
+        let (l, r) = (self.left[header], self.right[header]);
+        self.right[l] = r;
+        self.left[r] = l;
+
+        let mut i = self.down[header];
+        while i != header {
+            self.live[self.row[i]] = false;
+            let mut j = self.right[i];
+            while j != i {
+                let (u, d) = (self.up[j], self.down[j]);
+                self.down[u] = d;
+                self.up[d] = u;
+                self.size[self.header[j]] -= 1;
+                j = self.right[j];
+            }
+            i = self.down[i];
+        }
+    }
+
+    fn uncover(&mut self, header: usize) {
+        let mut i = self.up[header];
+        while i != header {
+            self.live[self.row[i]] = true;
+            let mut j = self.left[i];
+            while j != i {
+                self.size[self.header[j]] += 1;
+                let (u, d) = (self.up[j], self.down[j]);
+                self.down[u] = j;
+                self.up[d] = j;
+                j = self.left[j];
+            }
+            i = self.up[i];
+        }
+
+        let (l, r) = (self.left[header], self.right[header]);
+        self.right[l] = header;
+        self.left[r] = header;
+    }
+
+    /// Takes a row that can still be chosen out of all its columns.
+    fn hide_row(&mut self, row: usize) {
+        self.live[row] = false;
+        let Some(first) = self.first[row] else {
+            return;
+        };
+        let mut j = first;
+        loop {
+            let (u, d) = (self.up[j], self.down[j]);
+            self.down[u] = d;
+            self.up[d] = u;
+            self.size[self.header[j]] -= 1;
+            j = self.right[j];
+            if j == first {
+                break;
+            }
+        }
+    }
+
+    /// Undoes [`Self::hide_row`], in the reverse order rows were hidden.
+    fn unhide_row(&mut self, row: usize) {
+        self.live[row] = true;
+        let Some(first) = self.first[row] else {
+            return;
+        };
+        let mut j = first;
+        loop {
+            j = self.left[j];
+            self.size[self.header[j]] += 1;
+            let (u, d) = (self.up[j], self.down[j]);
+            self.down[u] = j;
+            self.up[d] = j;
+            if j == first {
+                break;
+            }
+        }
+    }
+}
+
+/// Up to `limit` solutions of a board. Each row of the matrix places one
+/// number in one field and covers the field and that number in every group
+/// of the field. Groups of `side` fields must hold every number, smaller
+/// ones like cages become secondary columns, as do the
+/// [conflicts](Constraint::conflicts) of variant
+/// constraints. The rest, like the sums of cages, prune the search.
+/// Branches count as guesses of `search`, columns with a single row left as
+/// propagations.
+pub fn sudoku_solutions(
+    play: &PlayingField,
+    limit: usize,
+    search: &mut Search,
+) -> Vec<PlayingField> {
+    let deadline = search.deadline;
+    let side = play.side();
+    let cells = side * side;
+    let groups = play.groups();
+    let (full, partial): (Vec<_>, Vec<_>) = groups.iter().partition(|g| g.len() == side);
+
+    // Full groups come first, so their columns are the primary ones. The
+    // columns of a group start with the one for number 1.
+    let mut columns_of = vec![vec![]; cells * side];
+    for (i, group) in full.iter().chain(&partial).enumerate() {
+        for &idx in group.iter() {
+            for n in 0..side {
+                columns_of[idx * side + n].push(cells + i * side + n);
+            }
+        }
+    }
+    let mut conflicts = vec![];
+    let mut unencoded = vec![];
+    for constraint in play.constraints.iter() {
+        match constraint.conflicts(side) {
+            // Jigsaws and regions are all groups.
+            _ if matches!(constraint, Constraint::Jigsaw(_) | Constraint::Region(_)) => {}
+            pairs if pairs.is_empty() => unencoded.push(constraint),
+            pairs => conflicts.extend(pairs),
+        }
+    }
+    let first = cells + groups.len() * side;
+    for (i, conflict) in conflicts.iter().enumerate() {
+        for &(idx, number) in conflict {
+            columns_of[idx * side + usize::from(number) - 1].push(first + i);
+        }
+    }
+
+    let mut cover = ExactCover::new(
+        cells + full.len() * side,
+        partial.len() * side + conflicts.len(),
+    );
+    let mut placements = vec![];
+    for (idx, field) in play.fields.iter().enumerate() {
+        let numbers = match field.as_number() {
+            Some(n) => n..=n,
+            None => 1..=side as u8,
+        };
+        for number in numbers {
+            let mut columns = vec![idx];
+            columns.extend(&columns_of[idx * side + usize::from(number) - 1]);
+            cover.add_row(&columns);
+            placements.push((idx, number));
+        }
+    }
+
+    let board = |rows: &[usize]| {
+        let mut solved = play.clone();
+        for &row in rows {
+            let (idx, number) = placements[row];
+            solved.fields[idx] = Number(Some(number));
+        }
+        solved
+    };
+    let mut solutions = vec![];
+    if unencoded.is_empty() && deadline.is_none() {
+        solutions = cover
+            .solutions(limit)
+            .iter()
+            .map(|rows| board(rows))
+            .collect();
+    } else if limit > 0 {
+        let timed_out = &mut search.timed_out;
+        let bit = |number: u8| 1u32 << (number - 1);
+        // Like `BitfiedTacticsSolver::propagate`: the constraints narrow down
+        // the candidates the matrix has left until they stop changing.
+        let mut prune = |chosen: &[usize], live: &[bool]| {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                *timed_out = true;
+                return None;
+            }
+            if unencoded.is_empty() {
+                return Some(vec![]);
+            }
+            let mut masks = vec![0; cells];
+            for &row in chosen {
+                let (idx, number) = placements[row];
+                masks[idx] |= bit(number);
+            }
+            for (&(idx, number), _) in placements.iter().zip(live).filter(|(_, &live)| live) {
+                masks[idx] |= bit(number);
+            }
+            loop {
+                let before = masks.clone();
+                if !unencoded.iter().all(|c| c.prune(&mut masks, side)) {
+                    return None;
+                }
+                if masks == before {
+                    break;
+                }
+            }
+            let ruled_out = placements.iter().zip(live).enumerate();
+            Some(
+                ruled_out
+                    .filter(|&(_, (&(idx, number), &live))| live && masks[idx] & bit(number) == 0)
+                    .map(|(row, _)| row)
+                    .collect(),
+            )
+        };
+        cover.search_pruned(&mut prune, &mut |rows| {
+            solutions.push(board(rows));
+            solutions.len() < limit
+        });
+    }
+    search.guesses += cover.branches;
+    search.propagations += cover.forced;
+    solutions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitfield_tactics::BitfiedTacticsSolver;
+
+    /// The example from Knuth's paper: rows 0, 3 and 4 cover every column.
+    #[test]
+    fn knuths_example() {
+        let mut cover = ExactCover::new(7, 0);
+        for row in [
+            &[2, 4, 5][..],
+            &[0, 3, 6],
+            &[1, 2, 5],
+            &[0, 3],
+            &[1, 6],
+            &[3, 4, 6],
+        ] {
+            cover.add_row(row);
+        }
+        let mut solutions = cover.solutions(10);
+        solutions[0].sort();
+        assert_eq!(solutions, [[0, 3, 4]]);
+    }
+
+    #[test]
+    fn pruned_rows_are_not_chosen() {
+        // Rows 0 and 1 both cover columns 0 and 1, rows 2 and 3 one each.
+        let mut cover = ExactCover::new(2, 0);
+        cover.add_row(&[0, 1]);
+        cover.add_row(&[0, 1]);
+        cover.add_row(&[0]);
+        cover.add_row(&[1]);
+        let mut solutions = vec![];
+        cover.search_pruned(
+            &mut |chosen, live| {
+                assert!(chosen.iter().all(|&row| !live[row]));
+                // Row 0 is ruled out, and nothing goes with row 2.
+                match chosen {
+                    [2] => None,
+                    _ => Some((0..1).filter(|&row| live[row]).collect()),
+                }
+            },
+            &mut |rows| {
+                solutions.push(rows.to_vec());
+                true
+            },
+        );
+        solutions.iter_mut().for_each(|rows| rows.sort());
+        assert_eq!(solutions, [vec![1]]);
+        // Everything is back once the search is done.
+        assert_eq!(cover.solutions(10).len(), 3);
+    }
+
+    #[test]
+    fn secondary_columns_are_covered_at_most_once() {
+        let mut cover = ExactCover::new(2, 1);
+        cover.add_row(&[0, 2]);
+        cover.add_row(&[1, 2]);
+        cover.add_row(&[0]);
+        cover.add_row(&[1]);
+        let mut solutions = cover.solutions(10);
+        solutions.iter_mut().for_each(|rows| rows.sort());
+        solutions.sort();
+        assert_eq!(solutions, [vec![0, 3], vec![1, 2], vec![2, 3]]);
+    }
+
+    #[test]
+    fn enumerates_all_4x4_grids() {
+        let empty = PlayingField::empty(2);
+        let solutions = sudoku_solutions(&empty, usize::MAX, &mut Search::default());
+        assert_eq!(solutions.len(), 288);
+        assert!(solutions.iter().all(PlayingField::is_solved));
+    }
+
+    #[test]
+    fn agrees_with_bitfield() {
+        let puzzles = include_str!("../example.csv")
+            .lines()
+            .chain(include_str!("../example_16.csv").lines())
+            .chain(include_str!("../msk_009.csv").lines().take(20));
+        for line in puzzles {
+            let puzzle = PlayingField::new(line).unwrap();
+            let lines = |solutions: Vec<PlayingField>| {
+                let mut lines: Vec<_> = solutions.iter().map(PlayingField::to_line).collect();
+                lines.sort();
+                lines
+            };
+            let dlx = lines(sudoku_solutions(
+                &puzzle,
+                usize::MAX,
+                &mut Search::default(),
+            ));
+            let solver = BitfiedTacticsSolver::new(&puzzle);
+            let bitfield = lines(solver.solutions(usize::MAX, &mut Search::default()));
+            assert_eq!(dlx, bitfield, "{line}");
+        }
+    }
+}
@@ -0,0 +1,931 @@
+//! Reading and writing puzzles in the formats other sudoku programs use.
+//!
+//! [`read`] tells the formats apart by the file extension or, failing that,
+//! by the text itself. Errors point at the line and column they are in.
+
+use std::fmt;
+use std::path::Path;
+
+use super::tactics;
+use super::{Number, PlayingField, MAX_BOX_SIZE, SYMBOLS};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One puzzle per line, like `example.csv` and SadMan `.sdm` files.
+    Line,
+    /// One row per line, with or without the borders `Display` draws.
+    Grid,
+    /// SadMan `.sdk`: `#A author`, `#D name` and `#L level`, then the rows.
+    Sdk,
+    /// The candidates of each field, as Hodoku and others print them.
+    PencilMarks,
+    /// Objects with `puzzle` and optionally `name`, `author` and
+    /// `difficulty`, alone or in a list.
+    Json,
+}
+
+/// The names `--format` accepts, `sdm` is the same as `line`.
+pub const NAMES: [&str; 6] = ["line", "sdm", "grid", "sdk", "pencil", "json"];
+
+/// What a file says about a puzzle besides its fields.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub difficulty: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Puzzle {
+    pub play: PlayingField,
+    pub meta: Metadata,
+    /// Candidates of each field from pencil marks, bit `n - 1` for number `n`.
+    pub candidates: Option<Vec<u32>>,
+}
+
+impl Puzzle {
+    pub fn new(play: PlayingField) -> Self {
+        Puzzle {
+            play,
+            meta: Metadata::default(),
+            candidates: None,
+        }
+    }
+}
+
+/// A parse error, with the line and column it is in, both from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        ParseError {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+const USAGE: &str = "Usage: sudoku convert [--from FORMAT] [--to FORMAT] [FILE]";
+
+/// `sudoku convert`: reads puzzles from a file or stdin and writes them to
+/// stdout in another format, one line per puzzle by default.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut from = None;
+    let mut to = Format::Line;
+    let mut path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" | "--to" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {arg}\n{USAGE}"))?;
+                let format = Format::by_name(value)?;
+                if arg == "--from" {
+                    from = Some(format);
+                } else {
+                    to = format;
+                }
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.as_str()),
+            _ => return Err(format!("Unknown option {arg}\n{USAGE}")),
+        }
+    }
+
+    let text = match path {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?,
+        None => std::io::read_to_string(std::io::stdin()).map_err(|e| e.to_string())?,
+    };
+    let name = path.unwrap_or("stdin");
+    let puzzles = match from {
+        Some(format) => format.read(&text),
+        None => read(&text, path),
+    };
+    let puzzles = puzzles.map_err(|e| format!("{name}: {e}"))?;
+    print!("{}", to.write(&puzzles));
+    Ok(())
+}
+
+/// Reads the puzzles of a text in the format [`Format::detect`] finds.
+pub fn read(text: &str, path: Option<&str>) -> Result<Vec<Puzzle>, ParseError> {
+    Format::detect(text, path).read(text)
+}
+
+impl Format {
+    pub fn by_name(name: &str) -> Result<Self, String> {
+        match name {
+            "line" | "sdm" => Ok(Format::Line),
+            "grid" => Ok(Format::Grid),
+            "sdk" => Ok(Format::Sdk),
+            "pencil" => Ok(Format::PencilMarks),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "Unknown format {name:?}, expected one of {}",
+                NAMES.join(", ")
+            )),
+        }
+    }
+
+    /// The format of a file: `.sdk`, `.sdm` and `.json` go by their
+    /// extension, anything else by what its first lines look like.
+    pub fn detect(text: &str, path: Option<&str>) -> Self {
+        let extension = path
+            .and_then(|path| Path::new(path).extension())
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("sdk") => return Format::Sdk,
+            Some("sdm") => return Format::Line,
+            Some("json") => return Format::Json,
+            _ => {}
+        }
+
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.iter().any(|line| is_sdk_header(line)) {
+            return Format::Sdk;
+        }
+        let mut rows = lines
+            .iter()
+            .filter(|line| !line.starts_with('#') && !is_border(line) && !is_title(line));
+        let Some(first) = rows.clone().next() else {
+            return Format::Line;
+        };
+        if first.starts_with('{') || first.starts_with('[') {
+            return Format::Json;
+        }
+        if rows.any(|row| {
+            let tokens: Vec<&str> = row
+                .split(['|', ' ', '\t'])
+                .filter(|t| !t.is_empty())
+                .collect();
+            tokens.len() >= 4 && tokens.iter().any(|t| t.chars().count() > 1)
+        }) {
+            return Format::PencilMarks;
+        }
+        if board_size(first.chars().count()).is_some() {
+            Format::Line
+        } else {
+            Format::Grid
+        }
+    }
+
+    pub fn read(self, text: &str) -> Result<Vec<Puzzle>, ParseError> {
+        match self {
+            Format::Line => read_lines(text),
+            Format::Grid => read_grids(text, false),
+            Format::Sdk => read_grids(text, true),
+            Format::PencilMarks => read_pencil_marks(text),
+            Format::Json => read_json(text),
+        }
+    }
+
+    /// Like [`Self::read`], but a bad line of the line format only loses
+    /// that puzzle. In other formats an error ends the file.
+    pub fn read_each(self, text: &str) -> Vec<Result<Puzzle, ParseError>> {
+        match self {
+            Format::Line => read_each_line(text).collect(),
+            _ => match self.read(text) {
+                Ok(puzzles) => puzzles.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            },
+        }
+    }
+
+    pub fn write(self, puzzles: &[Puzzle]) -> String {
+        match self {
+            Format::Line => puzzles
+                .iter()
+                .map(|p| format!("{}\n", p.play.to_line()))
+                .collect(),
+            Format::Grid => puzzles
+                .iter()
+                .map(|p| p.play.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Format::Sdk => puzzles.iter().map(write_sdk).collect::<Vec<_>>().join("\n"),
+            Format::PencilMarks => puzzles
+                .iter()
+                .map(write_pencil_marks)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Format::Json => write_json(puzzles),
+        }
+    }
+}
+
+/// The box size of a board with `fields` fields.
+fn board_size(fields: usize) -> Option<usize> {
+    (2..=MAX_BOX_SIZE).find(|n| n.pow(4) == fields)
+}
+
+/// The box size of a board with `side` fields in a row.
+fn box_size_of_side(side: usize) -> Option<usize> {
+    (2..=MAX_BOX_SIZE).find(|n| n * n == side)
+}
+
+/// Lines like `+---+===+` or `*-----*` between rows.
+fn is_border(line: &str) -> bool {
+    line.contains(['-', '='])
+        && line
+            .chars()
+            .all(|c| matches!(c, '+' | '-' | '=' | '*' | '.' | ':' | '\'' | '|' | ' '))
+}
+
+/// The `Sudoko 3` lines `sudoku` prints above each board.
+fn is_title(line: &str) -> bool {
+    line.starts_with("Sudoko ")
+}
+
+/// `#A`, `#D` and the other metadata lines of `.sdk` files.
+fn is_sdk_header(line: &str) -> bool {
+    let mut chars = line.chars();
+    line == "[Puzzle]"
+        || chars.next() == Some('#')
+            && chars.next().is_some_and(|c| c.is_ascii_uppercase())
+            && chars.next().is_none_or(|c| c == ' ')
+}
+
+/// Parses the fields of a line that starts at `column`, for a board with
+/// `box_size`.
+fn parse_fields(
+    symbols: &str,
+    box_size: usize,
+    line: usize,
+    column: usize,
+) -> Result<Vec<Number>, ParseError> {
+    symbols
+        .chars()
+        .enumerate()
+        .map(|(i, c)| Number::new(c, box_size).map_err(|e| ParseError::new(line, column + i, e)))
+        .collect()
+}
+
+fn read_lines(text: &str) -> Result<Vec<Puzzle>, ParseError> {
+    read_each_line(text).collect()
+}
+
+/// Each puzzle of a text in the line format, or why its line is not one.
+fn read_each_line(text: &str) -> impl Iterator<Item = Result<Puzzle, ParseError>> + '_ {
+    text.lines()
+        .enumerate()
+        .map(|(n, raw)| (n + 1, raw, raw.trim()))
+        .filter(|(_n, _raw, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, raw, line)| {
+            let column = raw.find(line).unwrap_or(0) + 1;
+            let len = line.chars().count();
+            let box_size = board_size(len).ok_or_else(|| {
+                ParseError::new(
+                    n,
+                    column,
+                    format!("Wrong number of fields: {len}, expected 16, 81, 256 or 625"),
+                )
+            })?;
+            let mut play = PlayingField::empty(box_size);
+            play.fields = parse_fields(line, box_size, n, column)?;
+            Ok(Puzzle::new(play))
+        })
+}
+
+/// The fields of a row of a grid. Rows as `Display` draws them have a
+/// separator every four characters, so a blank is three spaces. Other rows
+/// have one character per field, apart from spaces and `|`.
+fn parse_row(raw: &str, n: usize) -> Result<Vec<(usize, char)>, ParseError> {
+    let chars: Vec<char> = raw.chars().collect();
+    let start = chars.iter().take_while(|c| c.is_whitespace()).count();
+    let row = raw.trim_end().chars().count() - start;
+    let drawn =
+        row % 4 == 1 && row > 4 && (0..=row / 4).all(|k| matches!(chars[start + 4 * k], '|' | 'I'));
+
+    if !drawn {
+        return Ok(chars
+            .iter()
+            .enumerate()
+            .filter(|(_i, c)| !c.is_whitespace() && **c != '|')
+            .map(|(i, &c)| (i + 1, c))
+            .collect());
+    }
+    (0..row / 4)
+        .map(|k| {
+            let column = start + 4 * k + 1;
+            let cell: String = chars[column..column + 3].iter().collect();
+            match cell.trim() {
+                "" => Ok((column + 2, '.')),
+                symbol if symbol.chars().count() == 1 => {
+                    let offset = cell.find(symbol).unwrap_or(0);
+                    Ok((column + 1 + offset, symbol.chars().next().unwrap()))
+                }
+                symbol => Err(ParseError::new(
+                    n,
+                    column + 1,
+                    format!("Not a symbol: {symbol:?}"),
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Reads grids row by row. For `.sdk` files, `#` lines are metadata of the
+/// next grid instead of comments.
+fn read_grids(text: &str, sdk: bool) -> Result<Vec<Puzzle>, ParseError> {
+    let mut puzzles = vec![];
+    let mut meta = Metadata::default();
+    let mut fields = vec![];
+    let mut side = None;
+    let mut last = 0;
+
+    for (n, raw) in text.lines().enumerate().map(|(n, raw)| (n + 1, raw)) {
+        let line = raw.trim();
+        if line.is_empty() || is_border(line) || is_title(line) || line == "[Puzzle]" {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('#') {
+            if sdk {
+                let (code, value) = header.split_at(header.len().min(1));
+                let value = Some(value.trim().to_string());
+                match code {
+                    "A" => meta.author = value,
+                    "D" => meta.name = value,
+                    "L" => meta.difficulty = value,
+                    _ => {}
+                }
+            }
+            continue;
+        }
+
+        last = n;
+        let row = parse_row(raw, n)?;
+        let width = *side.get_or_insert(row.len());
+        let box_size = box_size_of_side(width).ok_or_else(|| {
+            ParseError::new(
+                n,
+                1,
+                format!("{width} fields in a row, expected 4, 9, 16 or 25"),
+            )
+        })?;
+        if row.len() != width {
+            return Err(ParseError::new(
+                n,
+                1,
+                format!("{} fields in a row, expected {width}", row.len()),
+            ));
+        }
+        for (column, c) in row {
+            fields.push(Number::new(c, box_size).map_err(|e| ParseError::new(n, column, e))?);
+        }
+
+        if fields.len() == width * width {
+            let mut play = PlayingField::empty(box_size);
+            play.fields = std::mem::take(&mut fields);
+            puzzles.push(Puzzle {
+                play,
+                meta: std::mem::take(&mut meta),
+                candidates: None,
+            });
+            side = None;
+        }
+    }
+
+    if let Some(width) = side {
+        return Err(ParseError::new(
+            last,
+            1,
+            format!("Grid ends after {} of {width} rows", fields.len() / width),
+        ));
+    }
+    Ok(puzzles)
+}
+
+fn write_sdk(puzzle: &Puzzle) -> String {
+    let mut out = String::new();
+    for (code, value) in [
+        ('A', &puzzle.meta.author),
+        ('D', &puzzle.meta.name),
+        ('L', &puzzle.meta.difficulty),
+    ] {
+        if let Some(value) = value {
+            out += &format!("#{code} {value}\n");
+        }
+    }
+    for row in puzzle.play.fields.chunks(puzzle.play.side()) {
+        out.extend(row.iter().map(|n| n.as_symbol().unwrap_or('.')));
+        out.push('\n');
+    }
+    out
+}
+
+/// Reads grids with the candidates of each field, separated by spaces.
+/// Fields with a single candidate are filled in.
+fn read_pencil_marks(text: &str) -> Result<Vec<Puzzle>, ParseError> {
+    let mut puzzles = vec![];
+    let mut candidates = vec![];
+    let mut side = None;
+    let mut last = 0;
+
+    for (n, raw) in text.lines().enumerate().map(|(n, raw)| (n + 1, raw)) {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || is_border(line) || is_title(line) {
+            continue;
+        }
+
+        last = n;
+        let mut tokens = vec![];
+        let mut start = None;
+        for (i, c) in raw.chars().chain([' ']).enumerate() {
+            match (c.is_whitespace() || c == '|', start) {
+                (true, Some(s)) => {
+                    tokens.push((s + 1, raw.chars().skip(s).take(i - s).collect::<String>()));
+                    start = None;
+                }
+                (false, None) => start = Some(i),
+                _ => {}
+            }
+        }
+
+        let width = *side.get_or_insert(tokens.len());
+        let box_size = box_size_of_side(width).ok_or_else(|| {
+            ParseError::new(
+                n,
+                1,
+                format!("{width} fields in a row, expected 4, 9, 16 or 25"),
+            )
+        })?;
+        if tokens.len() != width {
+            return Err(ParseError::new(
+                n,
+                1,
+                format!("{} fields in a row, expected {width}", tokens.len()),
+            ));
+        }
+        for (column, token) in tokens {
+            let mut mask = 0u32;
+            for (number, i) in parse_fields(&token, box_size, n, column)?.iter().zip(0..) {
+                match number.as_number() {
+                    Some(number) => mask |= 1 << (number - 1),
+                    None => {
+                        return Err(ParseError::new(n, column + i, "Not a candidate"));
+                    }
+                }
+            }
+            candidates.push(mask);
+        }
+
+        if candidates.len() == width * width {
+            let mut play = PlayingField::empty(box_size);
+            for (field, &mask) in play.fields.iter_mut().zip(&candidates) {
+                if mask.count_ones() == 1 {
+                    *field = Number(Some(mask.trailing_zeros() as u8 + 1));
+                }
+            }
+            puzzles.push(Puzzle {
+                play,
+                meta: Metadata::default(),
+                candidates: Some(std::mem::take(&mut candidates)),
+            });
+            side = None;
+        }
+    }
+
+    if let Some(width) = side {
+        return Err(ParseError::new(
+            last,
+            1,
+            format!(
+                "Grid ends after {} of {width} rows",
+                candidates.len() / width
+            ),
+        ));
+    }
+    Ok(puzzles)
+}
+
+/// The candidates of a puzzle, from its pencil marks or else what its
+/// clues leave open.
+fn candidates(puzzle: &Puzzle) -> Vec<u32> {
+    if let Some(candidates) = &puzzle.candidates {
+        return candidates.clone();
+    }
+    let grid = tactics::Grid::new(&puzzle.play);
+    (0..puzzle.play.fields.len())
+        .map(|idx| grid.candidates(idx))
+        .collect()
+}
+
+fn write_pencil_marks(puzzle: &Puzzle) -> String {
+    let box_size = puzzle.play.box_size;
+    let side = puzzle.play.side();
+    let marks: Vec<String> = candidates(puzzle)
+        .into_iter()
+        .map(|mask| {
+            SYMBOLS
+                .chars()
+                .take(side)
+                .enumerate()
+                .filter(|(i, _c)| mask & (1 << i) != 0)
+                .map(|(_i, c)| c)
+                .collect()
+        })
+        .collect();
+    let width = marks.iter().map(String::len).max().unwrap_or(1).max(1);
+
+    let border = format!(
+        "+{}\n",
+        format!("{}+", "-".repeat(box_size * (width + 1) + 1)).repeat(box_size)
+    );
+    let mut out = border.clone();
+    for (r, row) in marks.chunks(side).enumerate() {
+        for (c, mark) in row.iter().enumerate() {
+            if c % box_size == 0 {
+                out += "| ";
+            }
+            out += &format!("{mark:width$} ");
+        }
+        out += "|\n";
+        if r % box_size == box_size - 1 {
+            out += &border;
+        }
+    }
+    out
+}
+
+/// The values a JSON puzzle file uses, each with the offset it starts at.
+enum Json {
+    Null,
+    Bool,
+    Number(String),
+    String(String),
+    Array(Vec<(usize, Json)>),
+    Object(Vec<(String, usize, Json)>),
+}
+
+/// A recursive descent parser for the JSON [`read_json`] needs.
+struct JsonParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, pos: usize, message: impl Into<String>) -> ParseError {
+        let before = &self.text[..pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        ParseError::new(line, column, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            Ok(())
+        } else {
+            Err(self.error(self.pos, format!("Expected {c:?}")))
+        }
+    }
+
+    fn value(&mut self) -> Result<(usize, Json), ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let value = match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut members = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        self.skip_whitespace();
+                        let (at, key) = self.value()?;
+                        let Json::String(key) = key else {
+                            return Err(self.error(at, "Expected a key"));
+                        };
+                        self.expect(':')?;
+                        let (at, value) = self.value()?;
+                        members.push((key, at, value));
+                        self.skip_whitespace();
+                        match self.peek() {
+                            Some(',') => self.pos += 1,
+                            Some('}') => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return Err(self.error(self.pos, "Expected ',' or '}'")),
+                        }
+                    }
+                }
+                Json::Object(members)
+            }
+            Some('[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        items.push(self.value()?);
+                        self.skip_whitespace();
+                        match self.peek() {
+                            Some(',') => self.pos += 1,
+                            Some(']') => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return Err(self.error(self.pos, "Expected ',' or ']'")),
+                        }
+                    }
+                }
+                Json::Array(items)
+            }
+            Some('"') => Json::String(self.string()?),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let len = self.text[self.pos..]
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .unwrap_or(self.text.len() - self.pos);
+                self.pos += len;
+                Json::Number(self.text[start..self.pos].to_string())
+            }
+            _ => {
+                let rest = &self.text[self.pos..];
+                if let Some(word) = ["null", "true", "false"]
+                    .iter()
+                    .find(|w| rest.starts_with(*w))
+                {
+                    self.pos += word.len();
+                    if *word == "null" {
+                        Json::Null
+                    } else {
+                        Json::Bool
+                    }
+                } else {
+                    return Err(self.error(self.pos, "Expected a value"));
+                }
+            }
+        };
+        Ok((start, value))
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut out = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_i, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let hex: String = chars.by_ref().take(4).map(|(_i, c)| c).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error(self.pos + i, "Bad escape"))?
+                        }
+                        Some(c @ ('"' | '\\' | '/')) => c,
+                        _ => return Err(self.error(self.pos + i, "Bad escape")),
+                    };
+                    out.push(escaped);
+                }
+                c => out.push(c),
+            }
+        }
+        Err(self.error(start, "Unterminated string"))
+    }
+}
+
+/// Reads `{"puzzle": "…", …}` or a list of such objects. The puzzle is
+/// one line as [`Format::Line`] has it, other keys than the metadata are
+/// ignored.
+fn read_json(text: &str) -> Result<Vec<Puzzle>, ParseError> {
+    let mut parser = JsonParser { text, pos: 0 };
+    let (at, value) = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error(parser.pos, "Text after the end"));
+    }
+
+    let objects = match value {
+        Json::Array(items) => items,
+        object => vec![(at, object)],
+    };
+    let mut puzzles = vec![];
+    for (at, object) in objects {
+        let Json::Object(members) = object else {
+            return Err(parser.error(at, "Expected an object"));
+        };
+        let mut meta = Metadata::default();
+        let mut play = None;
+        for (key, at, value) in members {
+            let text = match value {
+                Json::String(s) | Json::Number(s) => Some(s),
+                Json::Null | Json::Bool | Json::Array(_) | Json::Object(_) => None,
+            };
+            match (key.as_str(), text) {
+                ("puzzle", Some(line)) => {
+                    let len = line.chars().count();
+                    let box_size = board_size(len).ok_or_else(|| {
+                        parser.error(
+                            at,
+                            format!("Wrong number of fields: {len}, expected 16, 81, 256 or 625"),
+                        )
+                    })?;
+                    let start = parser.error(at + 1, "");
+                    let mut field = PlayingField::empty(box_size);
+                    field.fields = parse_fields(&line, box_size, start.line, start.column)?;
+                    play = Some(field);
+                }
+                ("puzzle", None) => return Err(parser.error(at, "The puzzle must be a string")),
+                ("name", text) => meta.name = text,
+                ("author", text) => meta.author = text,
+                ("difficulty", text) => meta.difficulty = text,
+                _ => {}
+            }
+        }
+        let play = play.ok_or_else(|| parser.error(at, "Missing \"puzzle\""))?;
+        puzzles.push(Puzzle {
+            play,
+            meta,
+            candidates: None,
+        });
+    }
+    Ok(puzzles)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn write_json(puzzles: &[Puzzle]) -> String {
+    let objects: Vec<String> = puzzles
+        .iter()
+        .map(|p| {
+            let mut members = vec![];
+            for (key, value) in [
+                ("name", &p.meta.name),
+                ("author", &p.meta.author),
+                ("difficulty", &p.meta.difficulty),
+            ] {
+                if let Some(value) = value {
+                    members.push(format!("\"{key}\": {}", json_string(value)));
+                }
+            }
+            members.push(format!("\"puzzle\": {}", json_string(&p.play.to_line())));
+            format!("  {{{}}}", members.join(", "))
+        })
+        .collect();
+    format!("[\n{}\n]\n", objects.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUZZLE: &str =
+        "000000010400000000020000000000050407008000300001090000300400200050100000000806000";
+
+    fn lines(puzzles: &[Puzzle]) -> Vec<String> {
+        puzzles.iter().map(|p| p.play.to_line()).collect()
+    }
+
+    #[test]
+    fn every_format_reads_what_it_writes() {
+        let mut puzzle = Puzzle::new(PlayingField::new(PUZZLE).unwrap());
+        puzzle.meta = Metadata {
+            name: Some("Seventeen \"clues\"".to_string()),
+            author: Some("Gordon Royle".to_string()),
+            difficulty: Some("Hard".to_string()),
+        };
+        let large = Puzzle::new(
+            PlayingField::new(include_str!("../example_16.csv").lines().next().unwrap()).unwrap(),
+        );
+
+        for format in [
+            Format::Line,
+            Format::Grid,
+            Format::Sdk,
+            Format::PencilMarks,
+            Format::Json,
+        ] {
+            for puzzles in [vec![puzzle.clone()], vec![puzzle.clone(), large.clone()]] {
+                let text = format.write(&puzzles);
+                assert_eq!(Format::detect(&text, None), format, "{text}");
+                let read = read(&text, None).unwrap();
+                if format == Format::PencilMarks {
+                    // Pencil marks fill in the fields with one candidate left.
+                    let candidates = read.iter().map(|p| p.candidates.clone().unwrap());
+                    assert!(candidates.eq(puzzles.iter().map(super::candidates)));
+                } else {
+                    assert_eq!(lines(&read), lines(&puzzles), "{format:?}");
+                }
+                if matches!(format, Format::Sdk | Format::Json) {
+                    assert_eq!(read[0].meta, puzzle.meta);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reads_blanks_grids_and_extensions() {
+        let dots = PUZZLE.replace('0', ".");
+        let underscores = PUZZLE.replace('0', "_");
+        for text in [&dots, &underscores] {
+            assert_eq!(lines(&read(text, None).unwrap()), [PUZZLE]);
+        }
+
+        let rows: Vec<String> = PUZZLE
+            .as_bytes()
+            .chunks(9)
+            .map(|row| {
+                let row = std::str::from_utf8(row).unwrap().replace('0', ".");
+                format!("{} | {} | {}", &row[..3], &row[3..6], &row[6..])
+            })
+            .collect();
+        let text = rows.join("\n");
+        assert_eq!(Format::detect(&text, None), Format::Grid);
+        assert_eq!(lines(&read(&text, None).unwrap()), [PUZZLE]);
+        assert_eq!(Format::detect(&text, Some("puzzle.sdk")), Format::Sdk);
+        assert_eq!(Format::detect(PUZZLE, Some("puzzles.SDM")), Format::Line);
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        let error = |text: &str| read(text, None).err().unwrap().to_string();
+        assert_eq!(
+            error(&format!("{PUZZLE}\n  {}x", &PUZZLE[1..])),
+            "line 2, column 83: Not a symbol: 'x'"
+        );
+        assert_eq!(
+            Format::Line.read("\n 1234").err().unwrap().to_string(),
+            "line 2, column 2: Wrong number of fields: 4, expected 16, 81, 256 or 625"
+        );
+        assert_eq!(
+            error(&format!("[\n  {{\"puzzle\": \"{}x\"}}\n]", &PUZZLE[1..])),
+            "line 2, column 95: Not a symbol: 'x'"
+        );
+        assert_eq!(
+            error("{\"puzzle\": \"12\",\n \"name\" 3}"),
+            "line 2, column 9: Expected ':'"
+        );
+        assert_eq!(
+            error("12.. | 34..\n"),
+            "line 1, column 1: 8 fields in a row, expected 4, 9, 16 or 25"
+        );
+        assert_eq!(
+            error("12 | ..\n..1\n"),
+            "line 2, column 1: 3 fields in a row, expected 4"
+        );
+        assert_eq!(
+            error("12 | ..\n"),
+            "line 1, column 1: Grid ends after 1 of 4 rows"
+        );
+    }
+}
@@ -0,0 +1,370 @@
+//! `sudoku generate`: puzzles with exactly one solution.
+//!
+//! A random full grid is filled in by the [`BitfiedTacticsSolver`], then
+//! clues are taken away in a random order for as long as the solution stays
+//! unique and the puzzle does not get harder than asked for.
+
+use std::io::Write as _;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::bitfield_tactics::BitfiedTacticsSolver;
+use super::cli;
+use super::format::{Format, Puzzle};
+use super::{Number, PlayingField, MAX_BOX_SIZE};
+
+const USAGE: &str = "Usage: sudoku generate [--seed N] [--count N] [--clues N] \
+    [--symmetry rotational|mirror|none] [--difficulty easy|medium|hard] [--box-size N] \
+    [--output FILE] [--format FORMAT]";
+
+/// Fresh grids to try before giving up on a clue count or difficulty.
+const MAX_ATTEMPTS: usize = 100;
+
+/// Puzzles that take at most this many guesses are medium, more is hard.
+const MAX_MEDIUM_GUESSES: usize = 5;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(args)?;
+    eprintln!("Seed {}", options.seed);
+
+    let mut rng = Rng::new(options.seed);
+    let mut puzzles = vec![];
+    for _ in 0..options.count {
+        let mut puzzle = Puzzle::new(generate(&options, &mut rng)?);
+        puzzle.meta.difficulty = options.difficulty.map(|d| format!("{d:?}").to_lowercase());
+        puzzles.push(puzzle);
+    }
+
+    let mut out = cli::output(options.output.as_deref())?;
+    write!(out, "{}", options.format.write(&puzzles))
+        .and_then(|()| out.flush())
+        .map_err(|e| format!("{}: {e}", options.output.as_deref().unwrap_or("stdout")))
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub seed: u64,
+    pub count: usize,
+    pub box_size: usize,
+    /// Stop taking clues away once this few are left.
+    pub clues: Option<usize>,
+    pub symmetry: Symmetry,
+    pub difficulty: Option<Difficulty>,
+    pub output: Option<String>,
+    pub format: Format,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            seed: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64),
+            count: 1,
+            box_size: 3,
+            clues: None,
+            symmetry: Symmetry::None,
+            difficulty: None,
+            output: None,
+            format: Format::Line,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value for {arg}\n{USAGE}"))?;
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("Not a number for {arg}: {value:?}"))
+            };
+            match arg.as_str() {
+                "--seed" => options.seed = number()? as u64,
+                "--count" => options.count = number()?,
+                "--clues" => options.clues = Some(number()?),
+                "--symmetry" => options.symmetry = value.parse()?,
+                "--difficulty" => options.difficulty = Some(value.parse()?),
+                "--box-size" => options.box_size = number()?,
+                "--output" => options.output = Some(value.clone()),
+                "--format" => options.format = Format::by_name(value)?,
+                _ => return Err(format!("Unknown option {arg}\n{USAGE}")),
+            }
+        }
+
+        if !(2..=MAX_BOX_SIZE).contains(&options.box_size) {
+            return Err(format!(
+                "Box size must be between 2 and {MAX_BOX_SIZE}, not {}",
+                options.box_size
+            ));
+        }
+        Ok(options)
+    }
+}
+
+/// Which fields have to stay clues or blanks together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    /// A field and the one opposite it through the centre.
+    Rotational,
+    /// A field and the one opposite it through the middle column.
+    Mirror,
+    None,
+}
+
+impl Symmetry {
+    /// The fields that are taken away together with `idx`.
+    fn orbit(self, idx: usize, side: usize) -> Vec<usize> {
+        let (row, col) = (idx / side, idx % side);
+        let partner = match self {
+            Symmetry::Rotational => side * side - 1 - idx,
+            Symmetry::Mirror => row * side + side - 1 - col,
+            Symmetry::None => idx,
+        };
+        if partner == idx {
+            vec![idx]
+        } else {
+            vec![idx, partner]
+        }
+    }
+}
+
+impl std::str::FromStr for Symmetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rotational" => Ok(Symmetry::Rotational),
+            "mirror" => Ok(Symmetry::Mirror),
+            "none" => Ok(Symmetry::None),
+            _ => Err(format!("Unknown symmetry {s:?}\n{USAGE}")),
+        }
+    }
+}
+
+/// How hard a puzzle is for the [`BitfiedTacticsSolver`], by how many
+/// fields it has to guess. This is not the grade of `sudoku rate`, which
+/// goes by the techniques a person would need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Difficulty {
+    /// No guesses: the solver's propagation, setting the only number left
+    /// for a field or the only place left for a number, is enough.
+    Easy,
+    /// Needs at most [`MAX_MEDIUM_GUESSES`] guesses.
+    Medium,
+    /// Needs more guesses than that.
+    Hard,
+}
+
+impl Difficulty {
+    pub fn of(puzzle: &PlayingField) -> Option<Difficulty> {
+        let guesses = BitfiedTacticsSolver::new(puzzle).guesses()?;
+        Some(match guesses {
+            0 => Difficulty::Easy,
+            1..=MAX_MEDIUM_GUESSES => Difficulty::Medium,
+            _ => Difficulty::Hard,
+        })
+    }
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("Unknown difficulty {s:?}\n{USAGE}")),
+        }
+    }
+}
+
+/// Generates one puzzle with a unique solution, trying fresh grids until
+/// one reaches the clue count and difficulty in `options`.
+pub fn generate(options: &Options, rng: &mut Rng) -> Result<PlayingField, String> {
+    for _ in 0..MAX_ATTEMPTS {
+        let puzzle = remove_clues(random_solution(options.box_size, rng), options, rng);
+        let clues = puzzle.iter_populated_fields().count();
+        if options.clues.is_some_and(|target| clues > target) {
+            continue;
+        }
+        if options
+            .difficulty
+            .is_some_and(|target| Difficulty::of(&puzzle) != Some(target))
+        {
+            continue;
+        }
+        return Ok(puzzle);
+    }
+
+    Err(format!(
+        "No puzzle found in {MAX_ATTEMPTS} attempts, try more clues or an easier difficulty"
+    ))
+}
+
+/// A random full grid: the boxes on the diagonal do not share a row or
+/// column, so they are shuffled independently and the solver fills in the rest.
+fn random_solution(box_size: usize, rng: &mut Rng) -> PlayingField {
+    let mut field = PlayingField::empty(box_size);
+    let side = field.side();
+    for b in 0..box_size {
+        let mut numbers: Vec<u8> = (1..=side as u8).collect();
+        rng.shuffle(&mut numbers);
+        for (n, number) in numbers.into_iter().enumerate() {
+            let row = b * box_size + n / box_size;
+            let col = b * box_size + n % box_size;
+            field.fields[row * side + col] = Number(Some(number));
+        }
+    }
+
+    BitfiedTacticsSolver::new(&field)
+        .try_solve()
+        .expect("the diagonal boxes never conflict")
+        .extract()
+}
+
+/// Takes clues away in a random order while the solution stays unique.
+fn remove_clues(solution: PlayingField, options: &Options, rng: &mut Rng) -> PlayingField {
+    let side = solution.side();
+    let mut puzzle = solution;
+    let mut clues = puzzle.fields.len();
+    let mut order: Vec<usize> = (0..puzzle.fields.len()).collect();
+    rng.shuffle(&mut order);
+
+    for idx in order {
+        if puzzle.fields[idx].as_number().is_none() {
+            continue;
+        }
+        let orbit = options.symmetry.orbit(idx, side);
+        if options
+            .clues
+            .is_some_and(|target| clues < target + orbit.len())
+        {
+            continue;
+        }
+
+        let mut candidate = puzzle.clone();
+        for &i in &orbit {
+            candidate.fields[i] = Number(None);
+        }
+        if !BitfiedTacticsSolver::new(&candidate).is_unique() {
+            continue;
+        }
+        if options
+            .difficulty
+            .is_some_and(|target| Difficulty::of(&candidate) > Some(target))
+        {
+            continue;
+        }
+        puzzle = candidate;
+        clues -= orbit.len();
+    }
+
+    puzzle
+}
+
+/// SplitMix64, so a seed gives the same puzzles on every machine.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Fisher–Yates shuffle.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitfield_tactics::Search;
+    use crate::dlx;
+
+    fn options(args: &str) -> Options {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Options::parse(&args).unwrap()
+    }
+
+    fn puzzles(options: &Options, count: usize) -> Vec<PlayingField> {
+        let mut rng = Rng::new(options.seed);
+        (0..count)
+            .map(|_| generate(options, &mut rng).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_puzzles() {
+        let lines = |args| -> Vec<String> {
+            puzzles(&options(args), 3)
+                .iter()
+                .map(PlayingField::to_line)
+                .collect()
+        };
+        assert_eq!(lines("--seed 42"), lines("--seed 42"));
+        assert_ne!(lines("--seed 42"), lines("--seed 43"));
+    }
+
+    #[test]
+    fn puzzles_have_one_solution_and_their_symmetry() {
+        for (symmetry, name) in [
+            (Symmetry::Rotational, "rotational"),
+            (Symmetry::Mirror, "mirror"),
+            (Symmetry::None, "none"),
+        ] {
+            for box_size in [2, 3] {
+                let options = options(&format!("--seed 7 --symmetry {name} --box-size {box_size}"));
+                for puzzle in puzzles(&options, 3) {
+                    let line = puzzle.to_line();
+                    let solutions = dlx::sudoku_solutions(&puzzle, 2, &mut Search::default());
+                    assert_eq!(solutions.len(), 1, "{line}");
+
+                    let side = puzzle.side();
+                    let is_clue = |row: usize, col: usize| {
+                        puzzle.fields[row * side + col].as_number().is_some()
+                    };
+                    for row in 0..side {
+                        for col in 0..side {
+                            let partner = match symmetry {
+                                Symmetry::Rotational => is_clue(side - 1 - row, side - 1 - col),
+                                Symmetry::Mirror => is_clue(row, side - 1 - col),
+                                Symmetry::None => continue,
+                            };
+                            assert_eq!(is_clue(row, col), partner, "{name} {line}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn puzzles_have_the_difficulty_and_clues_asked_for() {
+        for (difficulty, name) in [(Difficulty::Easy, "easy"), (Difficulty::Medium, "medium")] {
+            let options = options(&format!("--seed 3 --difficulty {name}"));
+            for puzzle in puzzles(&options, 2) {
+                assert_eq!(Difficulty::of(&puzzle), Some(difficulty));
+            }
+        }
+        for puzzle in puzzles(&options("--seed 3 --clues 30"), 2) {
+            assert!(puzzle.iter_populated_fields().count() <= 30);
+        }
+    }
+}
@@ -1,7 +1,16 @@
 use core::fmt;
-use std::io::BufRead as _;
+use std::sync::Arc;
 
+mod bench;
 mod bitfield_tactics;
+mod cli;
+mod dlx;
+mod format;
+mod generate;
+mod rating;
+mod solver;
+mod tactics;
+mod variants;
 
 fn main() {
     // let zeroed = PlayingField::new(
@@ -27,137 +36,159 @@ fn main() {
     //dbg!(solved.is_solved());
 
     //solved.print_bad_constraints();
-    read_from_stdin();
-
-    return;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some(command) if cli::COMMANDS.contains(&command) => cli::run(command, &args[1..]),
+        Some("generate") => generate::run(&args[1..]),
+        Some("bench") => bench::run(&args[1..]),
+        Some("variant") => variants::run(&args[1..]),
+        Some("convert") => format::run(&args[1..]),
+        // Options without a command solve, as `sudoku < puzzles` always did.
+        None => cli::run("solve", &args),
+        Some(option) if option.starts_with('-') => cli::run("solve", &args),
+        Some(command) => Err(format!(
+            "Unknown command {command:?}, expected one of {}, generate, bench, variant or convert",
+            cli::COMMANDS.join(", ")
+        )),
+    };
+    exit_on_error(result);
 }
 
-#[allow(dead_code)]
-fn read_from_stdin() {
-    let stdin = std::io::stdin();
-    let stdin = stdin.lock();
-
-    for (idx, line) in stdin.lines().enumerate() {
-        let Ok(line) = line else {
-            break;
-        };
-
-        let Ok(pf) = PlayingField::new(&line) else {
-            continue;
-        };
-
-        // Solved
-        //let pf = PlayingField::new(
-        //    "123456789456789123789123456234567891567891234891234567345678912678912345912345678",
-        //)
-        //.unwrap();
-        // println!("{}", pf);
-
-        // println!("Checked Constraints: {}", pf.check_constraints());
-        // println!("Checked Completed: {}", pf.is_complete());
-        // println!("Checked Solved: {}", pf.is_solved());
-
-        // for g in group_indices() {
-        //     let mut pf = PlayingField::default();
-        //     pf.set_group(g, Coloring(Some(8)));
-        //     println!("{}", pf);
-        // }
-
-        println!("Sudoko {idx}");
-        let solver = bitfield_tactics::BitfiedTacticsSolver::new(&pf);
-        // println!("Solver: {}", solver.extract());
-        let solved = solver.try_solve().unwrap().extract();
-        if solved.is_solved() {
-            println!("{}", solved);
-        } else {
-            println!("No solution found");
-            return;
-        }
+fn exit_on_error(result: Result<(), String>) {
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
     }
 }
 
+/// Largest supported box size. A 25×25 board has 25 symbols, which is as
+/// many as fit the symbol set and well within a `u32` bit vector.
+const MAX_BOX_SIZE: usize = 5;
+
+/// Symbols beyond 9 are letters, so a 25×25 board uses `1`–`9` and `A`–`P`.
+const SYMBOLS: &str = "123456789ABCDEFGHIJKLMNOP";
+
+/// A board of `box_size² × box_size²` fields, stored row by row.
 #[derive(Clone, PartialEq, Eq)]
 struct PlayingField {
-    fields: [Number; 9 * 9],
+    box_size: usize,
+    fields: Vec<Number>,
+    /// Variant rules on top of rows, columns and boxes, shared by the copies
+    /// of a board, also across threads.
+    constraints: Arc<[variants::Constraint]>,
 }
 
-fn group_indices() -> Vec<[usize; 9]> {
+/// All rows, then all columns, then all boxes of a board with the given box size.
+fn group_indices(box_size: usize) -> Vec<Vec<usize>> {
+    let side = box_size * box_size;
     let mut res = vec![];
-    const IOTA: [usize; 9] = [0, 1, 2, 3, 4, 5, 6, 7, 8];
-    const CELL: [usize; 9] = [0, 1, 2, 9, 10, 11, 18, 19, 20];
 
-    for row in 0..9 {
-        res.push(IOTA.map(|n| n + row * 9));
+    for row in 0..side {
+        res.push((0..side).map(|n| n + row * side).collect());
     }
 
-    for col in 0..9 {
-        res.push(IOTA.map(|n| n * 9 + col));
+    for col in 0..side {
+        res.push((0..side).map(|n| n * side + col).collect());
     }
 
-    for row in 0..3 {
-        for col in 0..3 {
-            let base_idx = row * 9 * 3 + col * 3;
-            res.push(CELL.map(|n| n + base_idx));
+    for row in 0..box_size {
+        for col in 0..box_size {
+            let base_idx = row * side * box_size + col * box_size;
+            res.push(
+                (0..side)
+                    .map(|n| base_idx + (n / box_size) * side + n % box_size)
+                    .collect(),
+            );
         }
     }
 
     res
 }
 
-fn group_index_index_to_human_readable(i: usize) -> String {
-    match i {
-        0..=8 => format!("ROW {}", i + 1),
-        9..=17 => format!("COL {}", i - 9 + 1),
-        18..=26 => format!("SQUARE {}", i - 18 + 1),
-        _ => panic!("Invalid group index index {i}"),
+fn group_index_index_to_human_readable(i: usize, box_size: usize) -> String {
+    let side = box_size * box_size;
+    match i / side {
+        0 => format!("ROW {}", i + 1),
+        1 => format!("COL {}", i - side + 1),
+        2 => format!("SQUARE {}", i - 2 * side + 1),
+        _ => format!("REGION {}", i - 3 * side + 1),
     }
 }
 
 impl PlayingField {
+    /// Parses one field per character, row by row. The board size follows
+    /// from the length: 16, 81, 256 or 625 characters.
     pub fn new(f: &str) -> Result<PlayingField, String> {
+        let len = f.chars().count();
+        let box_size = (2..=MAX_BOX_SIZE)
+            .find(|n| n * n * n * n == len)
+            .ok_or_else(|| format!("Wrong number of fields: {len}, expected 16, 81, 256 or 625"))?;
+
         let mut fields = vec![];
         for c in f.chars() {
-            fields.push(Number::new(c)?);
+            fields.push(Number::new(c, box_size)?);
         }
         Ok(PlayingField {
-            fields: fields.try_into().unwrap(),
+            box_size,
+            fields,
+            constraints: Arc::from([]),
         })
     }
 
-    pub fn set_group(&mut self, group: [usize; 9], color: Number) {
-        for idx in group {
-            self.fields[idx] = color;
+    /// An empty board with the given box size.
+    pub fn empty(box_size: usize) -> PlayingField {
+        assert!(
+            (2..=MAX_BOX_SIZE).contains(&box_size),
+            "Unsupported box size {box_size}"
+        );
+        PlayingField {
+            box_size,
+            fields: vec![Number(None); box_size.pow(4)],
+            constraints: Arc::from([]),
         }
     }
 
-    pub fn print_bad_constraints(&self) {
-        for (i, g) in group_indices().into_iter().enumerate() {
-            let mut numbers: Vec<_> = g
-                .into_iter()
-                .filter_map(|i| self.fields[i].as_number())
-                .collect();
-            numbers.sort();
-            if numbers.windows(2).any(|w| w[0] == w[1]) {
-                println!(
-                    "Bad Constraint in {}",
-                    group_index_index_to_human_readable(i)
-                );
-            }
+    /// The number of fields in a row, column or box.
+    pub fn side(&self) -> usize {
+        self.box_size * self.box_size
+    }
+
+    #[allow(dead_code)]
+    pub fn set_group(&mut self, group: &[usize], color: Number) {
+        for &idx in group {
+            self.fields[idx] = color;
         }
     }
 
+    /// The groups that hold some number twice and the variant constraints
+    /// that are broken, in human readable form.
+    pub fn bad_constraints(&self) -> Vec<String> {
+        let groups = self
+            .groups()
+            .into_iter()
+            .enumerate()
+            .filter(|(_i, g)| !self.check_group(g))
+            .map(|(i, _g)| group_index_index_to_human_readable(i, self.box_size));
+        let variants = self
+            .constraints
+            .iter()
+            .filter(|c| !c.holds(self))
+            .map(|c| c.describe(self.side()));
+        groups.chain(variants).collect()
+    }
+
     pub fn check_constraints(&self) -> bool {
-        for g in group_indices() {
-            let mut numbers: Vec<_> = g
-                .into_iter()
-                .filter_map(|i| self.fields[i].as_number())
-                .collect();
-            numbers.sort();
-            if numbers.windows(2).any(|w| w[0] == w[1]) {
-                return false;
-            }
-        }
-        true
+        self.groups().iter().all(|g| self.check_group(g))
+            && self.constraints.iter().all(|c| c.holds(self))
+    }
+
+    fn check_group(&self, group: &[usize]) -> bool {
+        let mut numbers: Vec<_> = group
+            .iter()
+            .filter_map(|&i| self.fields[i].as_number())
+            .collect();
+        numbers.sort();
+        !numbers.windows(2).any(|w| w[0] == w[1])
     }
 
     pub fn is_complete(&self) -> bool {
@@ -168,30 +199,47 @@ impl PlayingField {
         self.is_complete() && self.check_constraints()
     }
 
-    pub fn try_field_recursive_solver(&self) -> Option<Self> {
+    pub fn try_field_recursive_solver(
+        &self,
+        search: &mut bitfield_tactics::Search,
+    ) -> Result<Self, solver::Failure> {
+        if search.out_of_time() {
+            return Err(solver::Failure::TimedOut);
+        }
         let first_unset_index = if let Some(first_unset_index) =
             self.fields.iter().position(|c| c.as_number().is_none())
         {
             first_unset_index
         } else if self.is_solved() {
-            return Some(self.clone());
+            return Ok(self.clone());
         } else {
-            return None;
+            return Err(solver::Failure::Unsolvable);
         };
 
+        search.guesses += 1;
         let mut new_field = self.clone();
-        for i in 1..=9 {
+        for i in 1..=self.side() as u8 {
             new_field.fields[first_unset_index] = Number(Some(i));
             if !new_field.check_constraints() {
                 continue;
             }
             if new_field.is_solved() {
-                return Some(new_field);
-            } else if let Some(pf) = new_field.try_field_recursive_solver() {
-                return Some(pf);
+                return Ok(new_field);
+            }
+            match new_field.try_field_recursive_solver(search) {
+                Err(solver::Failure::Unsolvable) => {}
+                result => return result,
             }
         }
-        None
+        Err(solver::Failure::Unsolvable)
+    }
+
+    /// One character per field, row by row, as [`PlayingField::new`] reads it.
+    pub fn to_line(&self) -> String {
+        self.fields
+            .iter()
+            .map(|n| n.as_symbol().unwrap_or('0'))
+            .collect()
     }
 
     pub fn iter_populated_fields(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
@@ -207,49 +255,117 @@ impl PlayingField {
 pub struct Number(Option<u8>);
 
 impl Number {
-    pub fn new(c: char) -> Result<Self, String> {
-        match c.to_digit(10) {
-            Some(0) => Ok(Number(None)),
-            Some(d) if d <= 9 => Ok(Number(Some(d.try_into().unwrap()))),
-            Some(d) => Err(format!("Number out of range: {}", d)),
-            None => Err(format!("Not a digit: {:?}", c)),
+    /// Parses a symbol of a board with the given box size. `0`, `.` and `_`
+    /// are empty fields, letters are accepted in either case.
+    pub fn new(c: char, box_size: usize) -> Result<Self, String> {
+        if matches!(c, '0' | '.' | '_') {
+            return Ok(Number(None));
+        }
+        match SYMBOLS.find(c.to_ascii_uppercase()) {
+            Some(d) if d < box_size * box_size => Ok(Number(Some(d as u8 + 1))),
+            Some(_) => Err(format!("Number out of range: {}", c)),
+            None => Err(format!("Not a symbol: {:?}", c)),
         }
     }
 
     pub fn as_number(&self) -> Option<u8> {
         self.0
     }
+
+    /// The character this number is written as.
+    pub fn as_symbol(&self) -> Option<char> {
+        self.0
+            .map(|n| char::from(SYMBOLS.as_bytes()[usize::from(n) - 1]))
+    }
 }
 
 impl Default for PlayingField {
     fn default() -> Self {
-        PlayingField {
-            fields: [Number(None); 81],
-        }
+        PlayingField::empty(3)
     }
 }
 
 impl fmt::Display for PlayingField {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "+===+===+===+===+===+===+===+===+===+")?;
-        for (i, line) in self.fields.chunks_exact(9).enumerate() {
+        let side = self.side();
+        let thick = format!("+{}", "===+".repeat(side));
+        let thin = format!("+{}", "---+".repeat(side));
+        writeln!(f, "{thick}")?;
+        for (i, line) in self.fields.chunks_exact(side).enumerate() {
             write!(f, "I")?;
             for (j, field) in line.iter().enumerate() {
-                let sep = if j % 3 == 2 { "I" } else { "|" };
-                if let Some(num) = field.0 {
-                    write!(f, "{:^3}{}", num, sep)?;
+                let sep = if j % self.box_size == self.box_size - 1 {
+                    "I"
+                } else {
+                    "|"
+                };
+                if let Some(symbol) = field.as_symbol() {
+                    write!(f, "{:^3}{}", symbol, sep)?;
                 } else {
                     write!(f, "   {}", sep)?;
                 }
             }
-            writeln!(f, "")?;
-            if i % 3 == 2 {
-                writeln!(f, "+===+===+===+===+===+===+===+===+===+")?;
+            writeln!(f)?;
+            if i % self.box_size == self.box_size - 1 {
+                writeln!(f, "{thick}")?;
             } else {
-                writeln!(f, "+---+---+---+---+---+---+---+---+---+")?;
+                writeln!(f, "{thin}")?;
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_beyond_nine_are_letters() {
+        for (i, c) in SYMBOLS.chars().enumerate() {
+            let number = Number::new(c, MAX_BOX_SIZE).unwrap();
+            assert_eq!(number.as_number(), Some(i as u8 + 1));
+            assert_eq!(number.as_symbol(), Some(c));
+            assert_eq!(
+                Number::new(c.to_ascii_lowercase(), MAX_BOX_SIZE),
+                Ok(number)
+            );
+        }
+        assert_eq!(Number::new('G', 4).unwrap().as_number(), Some(16));
+        assert_eq!(
+            Number::new('G', 3),
+            Err("Number out of range: G".to_string())
+        );
+        assert_eq!(
+            Number::new('h', 4),
+            Err("Number out of range: h".to_string())
+        );
+        assert_eq!(Number::new('Q', 5), Err("Not a symbol: 'Q'".to_string()));
+        assert_eq!(Number::new('.', 5), Ok(Number(None)));
+    }
+
+    #[test]
+    fn reads_16x16_and_25x25_boards() {
+        for (text, side) in [
+            (
+                include_str!("../example_16.csv").lines().next().unwrap(),
+                16,
+            ),
+            (include_str!("../example_25.csv").trim(), 25),
+        ] {
+            let line = text.replace('.', "0");
+            for text in [text.to_string(), text.to_ascii_lowercase()] {
+                let play = PlayingField::new(&text).unwrap();
+                assert_eq!(play.side(), side);
+                assert_eq!(play.to_line(), line);
+            }
+
+            let grid = format::Format::Grid
+                .write(&[format::Puzzle::new(PlayingField::new(text).unwrap())]);
+            assert!(grid.contains('G'), "{grid}");
+            let read = format::read(&grid, None).unwrap();
+            assert_eq!(read[0].play.to_line(), line);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Grading puzzles on a scale like Sudoku Explainer's: by the hardest
+//! technique a person needs to solve them, with the number of steps to
+//! tell apart puzzles that need the same technique.
+
+use core::fmt;
+
+use super::bitfield_tactics::{BitfiedTacticsSolver, Search};
+use super::tactics::{Grid, Pipeline};
+use super::PlayingField;
+
+/// Score of a puzzle the techniques cannot finish, before adding for how
+/// much the [`BitfiedTacticsSolver`] has to branch.
+const BACKTRACKING: f32 = 6.6;
+
+/// Highest score, for the puzzles that branch the most.
+const MAX_SCORE: f32 = 11.0;
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Rating {
+    pub score: f32,
+    pub steps: usize,
+    /// The hardest technique used, `None` when no step was needed.
+    pub hardest: Option<&'static str>,
+    /// Guesses after the techniques ran out, zero if they solved it.
+    pub guesses: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Grade {
+    /// Singles only.
+    Easy,
+    /// Up to pairs, pointing and X-Wing.
+    Medium,
+    /// Up to triples, quads, Swordfish, XY-Wing and colouring.
+    Hard,
+    /// Needs guessing.
+    Diabolical,
+}
+
+impl Rating {
+    pub fn grade(&self) -> Grade {
+        match self.score {
+            s if s <= 2.3 => Grade::Easy,
+            s if s <= 3.4 => Grade::Medium,
+            s if s < BACKTRACKING => Grade::Hard,
+            _ => Grade::Diabolical,
+        }
+    }
+}
+
+/// Rates a puzzle, or `None` if it has no solution or the deadline of
+/// `search` passed first, see [`Search::timed_out`].
+pub fn rate(puzzle: &PlayingField, pipeline: &Pipeline, search: &mut Search) -> Option<Rating> {
+    BitfiedTacticsSolver::new(puzzle).first_solution(search)?;
+
+    let mut grid = Grid::new(puzzle);
+    let steps = pipeline.solve(&mut grid, search);
+    if search.timed_out {
+        return None;
+    }
+    let hardest = steps
+        .iter()
+        .max_by(|a, b| pipeline.rating(a).total_cmp(&pipeline.rating(b)));
+    let mut rating = Rating {
+        score: hardest.map_or(1.0, |step| pipeline.rating(step)),
+        steps: steps.len(),
+        hardest: hardest.map(|step| step.technique),
+        guesses: 0,
+    };
+
+    if !grid.is_solved() {
+        let mut rest = Search {
+            deadline: search.deadline,
+            ..Search::default()
+        };
+        let solved = BitfiedTacticsSolver::new(&grid.to_playing_field()).first_solution(&mut rest);
+        search.timed_out = rest.timed_out;
+        solved?;
+        rating.guesses = rest.guesses;
+        let branching = (rating.guesses as f32 + 1.0).log2() * 0.75;
+        rating.score = (BACKTRACKING + branching).min(MAX_SCORE);
+    }
+    Some(rating)
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} {:?}", self.score, self.grade())?;
+        if let Some(hardest) = self.hardest {
+            write!(f, ", hardest {hardest}")?;
+        }
+        write!(f, ", {} steps", self.steps)?;
+        if self.guesses > 0 {
+            write!(f, ", then {} guesses", self.guesses)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Puzzles of known difficulty and the technique that makes them so.
+    /// The first is Project Euler's easy grid 01, the last two Arto
+    /// Inkala's "AI Escargot" and "world's hardest sudoku"; the others are
+    /// lines of `msk_009.csv`, counted from zero.
+    const CORPUS: &[(&str, Grade, Option<&str>)] = &[
+        (
+            "003020600900305001001806400008102900700000008006708200002609500800203009005010300",
+            Grade::Easy,
+            Some("Hidden Single"),
+        ),
+        // Line 1.
+        (
+            "000000000000942080160000029000000008906000001400250000004000000020008090050000700",
+            Grade::Easy,
+            Some("Naked Single"),
+        ),
+        // Line 13.
+        (
+            "002046000004080005070030009000002000305700000700000400006000093000054078000000000",
+            Grade::Medium,
+            Some("Pointing"),
+        ),
+        // Line 22.
+        (
+            "020007005000000000600095001070004130000000200001050006700010800080070000000200049",
+            Grade::Medium,
+            Some("Naked Pair"),
+        ),
+        // Line 44.
+        (
+            "000000000000067000007080012000000000030006500501000893070000000009001024004390008",
+            Grade::Medium,
+            Some("X-Wing"),
+        ),
+        // Line 108.
+        (
+            "002040000680051000700000080000030005000000900205900701000009078000006090006300000",
+            Grade::Medium,
+            Some("Hidden Pair"),
+        ),
+        // Line 91.
+        (
+            "007006000000000104040203009408000006900810000000009000000000030001035080200008500",
+            Grade::Hard,
+            Some("Hidden Triple"),
+        ),
+        // Line 2.
+        (
+            "000007000090001000000045006000020000036000410500000809000000004000018000081500032",
+            Grade::Hard,
+            Some("XY-Wing"),
+        ),
+        // Line 0.
+        (
+            "094000130000000000000076002080010000032000000000200060000050400000008007006304008",
+            Grade::Hard,
+            Some("Simple Colouring"),
+        ),
+        (
+            "100007090030020008009600500005300900010080002600004000300000010040000007007000300",
+            Grade::Diabolical,
+            Some("Hidden Single"),
+        ),
+        (
+            "800000000003600000070090200050007000000045700000100030001000068008500010090000400",
+            Grade::Diabolical,
+            None,
+        ),
+    ];
+
+    fn rate(puzzle: &str) -> Rating {
+        let puzzle = PlayingField::new(puzzle).unwrap();
+        super::rate(&puzzle, &Pipeline::standard(), &mut Search::default()).unwrap()
+    }
+
+    #[test]
+    fn calibration() {
+        for &(puzzle, grade, hardest) in CORPUS {
+            let rating = rate(puzzle);
+            assert_eq!(rating.grade(), grade, "{puzzle}: {rating}");
+            assert_eq!(rating.hardest, hardest, "{puzzle}: {rating}");
+        }
+    }
+
+    #[test]
+    fn harder_grades_score_higher() {
+        let ratings: Vec<_> = CORPUS
+            .iter()
+            .map(|&(p, grade, _)| (grade, rate(p)))
+            .collect();
+        for (grade, rating) in &ratings {
+            for (other_grade, other) in &ratings {
+                if grade < other_grade {
+                    assert!(rating.score < other.score, "{rating} < {other}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn backtracking_scores_by_branching() {
+        let escargot = rate(CORPUS[CORPUS.len() - 2].0);
+        let inkala = rate(CORPUS[CORPUS.len() - 1].0);
+        assert!(escargot.guesses > 0 && escargot.guesses < inkala.guesses);
+        assert!(escargot.score < inkala.score);
+        assert_eq!(inkala.score, MAX_SCORE);
+    }
+
+    #[test]
+    fn unsolvable_has_no_rating() {
+        let puzzle = format!("12345678{}9{}", "0".repeat(9), "0".repeat(63));
+        let puzzle = PlayingField::new(&puzzle).unwrap();
+        let mut search = Search::default();
+        assert_eq!(
+            super::rate(&puzzle, &Pipeline::standard(), &mut search),
+            None
+        );
+        assert!(!search.timed_out);
+    }
+}
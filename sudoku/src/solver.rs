@@ -0,0 +1,147 @@
+//! The solving backends behind one interface, so the CLI can pick one.
+
+use std::fmt;
+
+use super::bitfield_tactics::{BitfiedTacticsSolver, Search};
+use super::{dlx, PlayingField};
+
+/// Shared by the threads of a batch, so it has to be `Sync`.
+pub trait Solver: Sync {
+    fn name(&self) -> &'static str;
+
+    /// A solution of `puzzle`, giving up once the deadline of `search` has
+    /// passed. Counts its guesses and propagations in `search`.
+    fn solve(&self, puzzle: &PlayingField, search: &mut Search) -> Result<PlayingField, Failure>;
+}
+
+/// Why a solver returned no solution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    Unsolvable,
+    /// The deadline passed before the search was done.
+    TimedOut,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Unsolvable => write!(f, "unsolvable"),
+            Failure::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// The names `--solver` accepts.
+pub const NAMES: [&str; 3] = ["dlx", "bitfield", "naive"];
+
+pub fn by_name(name: &str) -> Result<Box<dyn Solver>, String> {
+    match name {
+        "dlx" => Ok(Box::new(Dlx)),
+        "bitfield" => Ok(Box::new(Bitfield)),
+        "naive" => Ok(Box::new(Naive)),
+        _ => Err(format!(
+            "Unknown solver {name:?}, expected one of {}",
+            NAMES.join(", ")
+        )),
+    }
+}
+
+/// [`PlayingField::try_field_recursive_solver`]: tries every number in the
+/// first empty field and checks all constraints after each. Every field it
+/// fills is a guess, it never propagates.
+pub struct Naive;
+
+impl Solver for Naive {
+    fn name(&self) -> &'static str {
+        "naive"
+    }
+
+    fn solve(&self, puzzle: &PlayingField, search: &mut Search) -> Result<PlayingField, Failure> {
+        if !puzzle.check_constraints() {
+            return Err(Failure::Unsolvable);
+        }
+        puzzle.try_field_recursive_solver(search)
+    }
+}
+
+/// The [`BitfiedTacticsSolver`]: candidate bit vectors, singles, and
+/// guessing in the field with the fewest candidates.
+pub struct Bitfield;
+
+impl Solver for Bitfield {
+    fn name(&self) -> &'static str {
+        "bitfield"
+    }
+
+    fn solve(&self, puzzle: &PlayingField, search: &mut Search) -> Result<PlayingField, Failure> {
+        match BitfiedTacticsSolver::new(puzzle).first_solution(search) {
+            Some(solved) => Ok(solved.extract()),
+            None if search.timed_out => Err(Failure::TimedOut),
+            None => Err(Failure::Unsolvable),
+        }
+    }
+}
+
+/// Exact cover with Dancing Links, see [`dlx`].
+pub struct Dlx;
+
+impl Solver for Dlx {
+    fn name(&self) -> &'static str {
+        "dlx"
+    }
+
+    fn solve(&self, puzzle: &PlayingField, search: &mut Search) -> Result<PlayingField, Failure> {
+        match dlx::sudoku_solutions(puzzle, 1, search).pop() {
+            Some(solved) => Ok(solved),
+            None if search.timed_out => Err(Failure::TimedOut),
+            None => Err(Failure::Unsolvable),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Solves `puzzle` and checks that the solution keeps its clues. Some of
+    /// the examples have more than one solution, so the solvers need not
+    /// agree.
+    fn assert_solves(solver: &dyn Solver, puzzle: &PlayingField) -> PlayingField {
+        let solution = solver.solve(puzzle, &mut Search::default()).unwrap();
+        assert!(
+            solution.is_solved(),
+            "{} on {}",
+            solver.name(),
+            puzzle.to_line()
+        );
+        for (idx, n) in puzzle.iter_populated_fields() {
+            assert_eq!(solution.fields[idx].as_number(), Some(n));
+        }
+        solution
+    }
+
+    #[test]
+    fn solves_16x16() {
+        for line in include_str!("../example_16.csv").lines() {
+            let puzzle = PlayingField::new(line).unwrap();
+            assert_eq!(puzzle.side(), 16);
+            for solver in [&Bitfield as &dyn Solver, &Naive, &Dlx] {
+                assert_solves(solver, &puzzle);
+            }
+        }
+    }
+
+    /// The naive solver takes seconds on the whole 25×25 example, so it gets
+    /// the puzzle with the first 20 rows of a solution filled in.
+    #[test]
+    fn solves_25x25() {
+        let puzzle = PlayingField::new(include_str!("../example_25.csv").trim()).unwrap();
+        assert_eq!(puzzle.side(), 25);
+        assert_solves(&Dlx, &puzzle);
+        let solution = assert_solves(&Bitfield, &puzzle);
+
+        let mut easier = puzzle.clone();
+        easier.fields[..20 * 25].copy_from_slice(&solution.fields[..20 * 25]);
+        assert_solves(&Naive, &easier);
+    }
+}
@@ -0,0 +1,933 @@
+//! Solving the way a person would: one deduction at a time, each recorded as
+//! a [`Step`] that says which technique found it, where, and what it changes.
+//!
+//! Unlike the [`BitfiedTacticsSolver`](super::bitfield_tactics::BitfiedTacticsSolver),
+//! nothing happens implicitly: placing a number removes it from the
+//! candidates of its peers, and everything else is a step of its own.
+
+use core::fmt;
+
+use super::bitfield_tactics::Search;
+use super::{group_index_index_to_human_readable, group_indices, Number, PlayingField};
+
+/// The pencil marks of a puzzle being solved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grid {
+    box_size: usize,
+    values: Vec<Number>,
+    /// Possible numbers of each empty field, bit `n - 1` for number `n`.
+    candidates: Vec<u32>,
+    groups: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    /// Fills in the candidates that the clues do not rule out.
+    pub fn new(play: &PlayingField) -> Self {
+        let side = play.side();
+        let mut grid = Grid {
+            box_size: play.box_size,
+            values: vec![Number(None); play.fields.len()],
+            candidates: vec![(1 << side) - 1; play.fields.len()],
+            groups: group_indices(play.box_size),
+        };
+        for (idx, number) in play.iter_populated_fields() {
+            grid.place(idx, number);
+        }
+        grid
+    }
+
+    pub fn side(&self) -> usize {
+        self.box_size * self.box_size
+    }
+
+    pub fn is_solved(&self) -> bool {
+        self.values.iter().all(|n| n.as_number().is_some())
+    }
+
+    /// The numbers a field can still hold, just its own once it is filled.
+    pub fn candidates(&self, idx: usize) -> u32 {
+        match self.values[idx].as_number() {
+            Some(number) => bit(number),
+            None => self.candidates[idx],
+        }
+    }
+
+    pub fn to_playing_field(&self) -> PlayingField {
+        let mut play = PlayingField::empty(self.box_size);
+        play.fields = self.values.clone();
+        play
+    }
+
+    pub fn apply(&mut self, step: &Step) {
+        for &(idx, number) in &step.placements {
+            self.place(idx, number);
+        }
+        for &(idx, number) in &step.eliminations {
+            self.candidates[idx] &= !bit(number);
+        }
+    }
+
+    fn place(&mut self, idx: usize, number: u8) {
+        self.values[idx] = Number(Some(number));
+        self.candidates[idx] = 0;
+        for peer in 0..self.values.len() {
+            if self.sees(idx, peer) {
+                self.candidates[peer] &= !bit(number);
+            }
+        }
+    }
+
+    fn row(&self, idx: usize) -> usize {
+        idx / self.side()
+    }
+
+    fn col(&self, idx: usize) -> usize {
+        idx % self.side()
+    }
+
+    fn square(&self, idx: usize) -> usize {
+        self.row(idx) / self.box_size * self.box_size + self.col(idx) / self.box_size
+    }
+
+    /// The row, column and box of a field, as indices into `groups`.
+    fn groups_of(&self, idx: usize) -> [usize; 3] {
+        let side = self.side();
+        [
+            self.row(idx),
+            side + self.col(idx),
+            2 * side + self.square(idx),
+        ]
+    }
+
+    /// Whether two different fields share a group.
+    fn sees(&self, a: usize, b: usize) -> bool {
+        a != b
+            && (self.row(a) == self.row(b)
+                || self.col(a) == self.col(b)
+                || self.square(a) == self.square(b))
+    }
+
+    /// The fields of a group that could still hold `number`.
+    fn positions(&self, group: usize, number: u8) -> Vec<usize> {
+        self.groups[group]
+            .iter()
+            .copied()
+            .filter(|&idx| self.candidates[idx] & bit(number) != 0)
+            .collect()
+    }
+
+    /// Eliminations of `number` from `fields`, where it is still a candidate.
+    fn eliminate(&self, fields: impl IntoIterator<Item = usize>, number: u8) -> Vec<(usize, u8)> {
+        fields
+            .into_iter()
+            .filter(|&idx| self.candidates[idx] & bit(number) != 0)
+            .map(|idx| (idx, number))
+            .collect()
+    }
+}
+
+/// One deduction: placed numbers and removed candidates, and the pattern
+/// they follow from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub technique: &'static str,
+    /// Rows, columns and boxes of the pattern, as indices into [`group_indices`].
+    pub groups: Vec<usize>,
+    /// Fields that make up the pattern.
+    pub cells: Vec<usize>,
+    /// Numbers the pattern is about.
+    pub numbers: Vec<u8>,
+    pub placements: Vec<(usize, u8)>,
+    pub eliminations: Vec<(usize, u8)>,
+    box_size: usize,
+}
+
+impl Step {
+    fn new(technique: &'static str, grid: &Grid) -> Self {
+        Step {
+            technique,
+            groups: vec![],
+            cells: vec![],
+            numbers: vec![],
+            placements: vec![],
+            eliminations: vec![],
+            box_size: grid.box_size,
+        }
+    }
+
+    fn makes_progress(&self) -> bool {
+        !self.placements.is_empty() || !self.eliminations.is_empty()
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = self.box_size * self.box_size;
+        let cell = |idx: usize| format!("r{}c{}", idx / side + 1, idx % side + 1);
+        let symbol = |n: u8| Number(Some(n)).as_symbol().unwrap();
+        let join = |items: Vec<String>| items.join(", ");
+
+        write!(f, "{}", self.technique)?;
+        if !self.groups.is_empty() {
+            let groups = self.groups.iter();
+            let groups = groups.map(|&g| group_index_index_to_human_readable(g, self.box_size));
+            write!(f, " in {}", join(groups.collect()))?;
+        }
+        if !self.cells.is_empty() {
+            write!(
+                f,
+                " on {}",
+                join(self.cells.iter().map(|&c| cell(c)).collect())
+            )?;
+        }
+        if !self.numbers.is_empty() {
+            let numbers = self.numbers.iter().map(|&n| symbol(n).to_string());
+            write!(f, " with {}", join(numbers.collect()))?;
+        }
+
+        let placements = self.placements.iter();
+        let placements = placements.map(|&(idx, n)| format!("{} = {}", cell(idx), symbol(n)));
+        let eliminations = self.eliminations.iter();
+        let eliminations = eliminations.map(|&(idx, n)| format!("{} <> {}", cell(idx), symbol(n)));
+        write!(f, ": {}", join(placements.chain(eliminations).collect()))
+    }
+}
+
+/// A solving technique.
+pub trait Tactic {
+    fn name(&self) -> &'static str;
+
+    /// How hard the technique is on the Sudoku Explainer scale.
+    fn rating(&self) -> f32;
+
+    /// The first deduction this technique finds, without applying it.
+    fn find(&self, grid: &Grid) -> Option<Step>;
+}
+
+/// Techniques to try in order, easiest first.
+pub struct Pipeline {
+    tactics: Vec<Box<dyn Tactic>>,
+}
+
+impl Pipeline {
+    pub fn new(tactics: Vec<Box<dyn Tactic>>) -> Self {
+        Pipeline { tactics }
+    }
+
+    /// Every technique in this module, in the order of the Sudoku Explainer scale.
+    pub fn standard() -> Self {
+        Pipeline::new(vec![
+            Box::new(HiddenSingle),
+            Box::new(NakedSingle),
+            Box::new(Pointing),
+            Box::new(BoxLineReduction),
+            Box::new(NakedSubset(2)),
+            Box::new(Fish(2)),
+            Box::new(HiddenSubset(2)),
+            Box::new(NakedSubset(3)),
+            Box::new(Fish(3)),
+            Box::new(HiddenSubset(3)),
+            Box::new(XyWing),
+            Box::new(SimpleColouring),
+            Box::new(NakedSubset(4)),
+            Box::new(HiddenSubset(4)),
+        ])
+    }
+
+    /// The rating of the technique that found `step`.
+    pub fn rating(&self, step: &Step) -> f32 {
+        self.tactics
+            .iter()
+            .find(|t| t.name() == step.technique)
+            .map_or(0.0, |t| t.rating())
+    }
+
+    /// The deduction of the easiest technique that finds one. `None` if
+    /// none does or the deadline of `search` passed first, see
+    /// [`Search::timed_out`].
+    pub fn next_step(&self, grid: &Grid, search: &mut Search) -> Option<Step> {
+        for tactic in &self.tactics {
+            if search.out_of_time() {
+                return None;
+            }
+            if let Some(step) = tactic.find(grid) {
+                return Some(step);
+            }
+        }
+        None
+    }
+
+    /// Applies steps until the puzzle is solved, no technique finds
+    /// anything or the deadline of `search` passes, and returns the steps
+    /// taken.
+    pub fn solve(&self, grid: &mut Grid, search: &mut Search) -> Vec<Step> {
+        let mut steps = vec![];
+        while !grid.is_solved() {
+            let Some(step) = self.next_step(grid, search) else {
+                break;
+            };
+            grid.apply(&step);
+            steps.push(step);
+        }
+        steps
+    }
+}
+
+/// A field with a single candidate left.
+pub struct NakedSingle;
+
+impl Tactic for NakedSingle {
+    fn name(&self) -> &'static str {
+        "Naked Single"
+    }
+
+    fn rating(&self) -> f32 {
+        2.3
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        let idx = (0..grid.values.len()).find(|&idx| grid.candidates[idx].count_ones() == 1)?;
+        let mut step = Step::new(self.name(), grid);
+        step.placements
+            .push((idx, numbers(grid.candidates[idx]).next().unwrap()));
+        Some(step)
+    }
+}
+
+/// A number with a single place left in a group.
+pub struct HiddenSingle;
+
+impl Tactic for HiddenSingle {
+    fn name(&self) -> &'static str {
+        "Hidden Single"
+    }
+
+    fn rating(&self) -> f32 {
+        1.5
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        for group in 0..grid.groups.len() {
+            for number in 1..=grid.side() as u8 {
+                if let [idx] = grid.positions(group, number)[..] {
+                    let mut step = Step::new(self.name(), grid);
+                    step.groups.push(group);
+                    step.placements.push((idx, number));
+                    return Some(step);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// `n` fields of a group with only `n` candidates between them: those
+/// numbers go nowhere else in the group. Pairs, triples and quads.
+pub struct NakedSubset(pub usize);
+
+impl Tactic for NakedSubset {
+    fn name(&self) -> &'static str {
+        subset_name("Naked", self.0)
+    }
+
+    fn rating(&self) -> f32 {
+        match self.0 {
+            2 => 3.0,
+            3 => 3.6,
+            _ => 5.0,
+        }
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        for (group, members) in grid.groups.iter().enumerate() {
+            let small: Vec<usize> = members
+                .iter()
+                .copied()
+                .filter(|&idx| (2..=self.0 as u32).contains(&grid.candidates[idx].count_ones()))
+                .collect();
+            for cells in combinations(&small, self.0) {
+                let union = cells.iter().fold(0, |acc, &idx| acc | grid.candidates[idx]);
+                if union.count_ones() as usize != self.0 {
+                    continue;
+                }
+
+                let mut step = Step::new(self.name(), grid);
+                let others = members.iter().copied().filter(|idx| !cells.contains(idx));
+                for number in numbers(union) {
+                    step.eliminations
+                        .extend(grid.eliminate(others.clone(), number));
+                }
+                if step.makes_progress() {
+                    step.groups.push(group);
+                    step.numbers = numbers(union).collect();
+                    step.cells = cells;
+                    return Some(step);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// `n` numbers with only `n` places between them in a group: those places
+/// hold nothing else. Pairs, triples and quads.
+pub struct HiddenSubset(pub usize);
+
+impl Tactic for HiddenSubset {
+    fn name(&self) -> &'static str {
+        subset_name("Hidden", self.0)
+    }
+
+    fn rating(&self) -> f32 {
+        match self.0 {
+            2 => 3.4,
+            3 => 4.0,
+            _ => 5.4,
+        }
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        for group in 0..grid.groups.len() {
+            let few: Vec<u8> = (1..=grid.side() as u8)
+                .filter(|&n| (2..=self.0).contains(&grid.positions(group, n).len()))
+                .collect();
+            for subset in combinations(&few, self.0) {
+                let mut cells: Vec<usize> = subset
+                    .iter()
+                    .flat_map(|&n| grid.positions(group, n))
+                    .collect();
+                cells.sort();
+                cells.dedup();
+                if cells.len() != self.0 {
+                    continue;
+                }
+
+                let mut step = Step::new(self.name(), grid);
+                let keep = subset.iter().fold(0, |acc, &n| acc | bit(n));
+                for &idx in &cells {
+                    for number in numbers(grid.candidates[idx] & !keep) {
+                        step.eliminations.push((idx, number));
+                    }
+                }
+                if step.makes_progress() {
+                    step.groups.push(group);
+                    step.cells = cells;
+                    step.numbers = subset;
+                    return Some(step);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn subset_name(kind: &str, size: usize) -> &'static str {
+    match (kind, size) {
+        ("Naked", 2) => "Naked Pair",
+        ("Naked", 3) => "Naked Triple",
+        ("Naked", 4) => "Naked Quad",
+        ("Hidden", 2) => "Hidden Pair",
+        ("Hidden", 3) => "Hidden Triple",
+        ("Hidden", 4) => "Hidden Quad",
+        _ => panic!("No name for a {kind} subset of {size}"),
+    }
+}
+
+/// A number whose places in a box all lie on one row or column: it goes
+/// nowhere else on that line.
+pub struct Pointing;
+
+impl Tactic for Pointing {
+    fn name(&self) -> &'static str {
+        "Pointing"
+    }
+
+    fn rating(&self) -> f32 {
+        2.6
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        let side = grid.side();
+        for square in 2 * side..3 * side {
+            for number in 1..=side as u8 {
+                let cells = grid.positions(square, number);
+                if let Some(step) = line_box_step(self.name(), grid, square, &cells, number) {
+                    return Some(step);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A number whose places on a row or column all lie in one box: it goes
+/// nowhere else in that box.
+pub struct BoxLineReduction;
+
+impl Tactic for BoxLineReduction {
+    fn name(&self) -> &'static str {
+        "Box/Line Reduction"
+    }
+
+    fn rating(&self) -> f32 {
+        2.8
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        let side = grid.side();
+        for line in 0..2 * side {
+            for number in 1..=side as u8 {
+                let cells = grid.positions(line, number);
+                if let Some(step) = line_box_step(self.name(), grid, line, &cells, number) {
+                    return Some(step);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Eliminations for `cells` in `group` that all share another group too.
+fn line_box_step(
+    technique: &'static str,
+    grid: &Grid,
+    group: usize,
+    cells: &[usize],
+    number: u8,
+) -> Option<Step> {
+    if cells.len() < 2 {
+        return None;
+    }
+    for other in grid.groups_of(cells[0]) {
+        if other == group
+            || !cells
+                .iter()
+                .all(|&idx| grid.groups_of(idx).contains(&other))
+        {
+            continue;
+        }
+        let outside = grid.groups[other].iter().copied();
+        let outside = outside.filter(|idx| !cells.contains(idx));
+        let mut step = Step::new(technique, grid);
+        step.eliminations = grid.eliminate(outside, number);
+        if step.makes_progress() {
+            step.groups = vec![group, other];
+            step.cells = cells.to_vec();
+            step.numbers = vec![number];
+            return Some(step);
+        }
+    }
+    None
+}
+
+/// `n` rows where a number has its places in the same `n` columns, so those
+/// columns need it from these rows only, or the same with rows and columns
+/// swapped. X-Wing for two, Swordfish for three.
+pub struct Fish(pub usize);
+
+impl Tactic for Fish {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            2 => "X-Wing",
+            3 => "Swordfish",
+            4 => "Jellyfish",
+            n => panic!("No name for a fish of {n}"),
+        }
+    }
+
+    fn rating(&self) -> f32 {
+        match self.0 {
+            2 => 3.2,
+            3 => 3.8,
+            _ => 5.2,
+        }
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        let side = grid.side();
+        for number in 1..=side as u8 {
+            for (base, cover) in [(0, side), (side, 0)] {
+                // Per line, the crossing lines it has the number on.
+                let crossings = |line: usize| {
+                    let positions = grid.positions(base + line, number);
+                    positions.iter().fold(0u32, |acc, &idx| {
+                        let cross = if base == 0 {
+                            grid.col(idx)
+                        } else {
+                            grid.row(idx)
+                        };
+                        acc | 1 << cross
+                    })
+                };
+                let lines: Vec<usize> = (0..side)
+                    .filter(|&line| (2..=self.0 as u32).contains(&crossings(line).count_ones()))
+                    .collect();
+                for fish in combinations(&lines, self.0) {
+                    let covered = fish.iter().fold(0, |acc, &line| acc | crossings(line));
+                    if covered.count_ones() as usize != self.0 {
+                        continue;
+                    }
+
+                    let cover_groups: Vec<usize> = (0..side)
+                        .filter(|c| covered & 1 << c != 0)
+                        .map(|c| cover + c)
+                        .collect();
+                    let outside = cover_groups
+                        .iter()
+                        .flat_map(|&g| grid.groups[g].iter().copied());
+                    let outside = outside.filter(|&idx| {
+                        let line = if base == 0 {
+                            grid.row(idx)
+                        } else {
+                            grid.col(idx)
+                        };
+                        !fish.contains(&line)
+                    });
+                    let mut step = Step::new(self.name(), grid);
+                    step.eliminations = grid.eliminate(outside, number);
+                    if step.makes_progress() {
+                        step.groups = fish.iter().map(|&line| base + line).collect();
+                        step.groups.extend(cover_groups);
+                        step.numbers = vec![number];
+                        return Some(step);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A pivot with candidates `xy` that sees pincers with `xz` and `yz`:
+/// whichever the pivot is, one pincer is `z`, so anything that sees both
+/// pincers is not `z`.
+pub struct XyWing;
+
+impl Tactic for XyWing {
+    fn name(&self) -> &'static str {
+        "XY-Wing"
+    }
+
+    fn rating(&self) -> f32 {
+        4.2
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        let fields = 0..grid.values.len();
+        let pairs: Vec<usize> = fields
+            .clone()
+            .filter(|&idx| grid.candidates[idx].count_ones() == 2)
+            .collect();
+        for &pivot in &pairs {
+            let xy = grid.candidates[pivot];
+            for &first in &pairs {
+                let xz = grid.candidates[first];
+                if !grid.sees(pivot, first) || (xy & xz).count_ones() != 1 {
+                    continue;
+                }
+                let z = xz & !xy;
+                for &second in &pairs {
+                    let yz = grid.candidates[second];
+                    if !grid.sees(pivot, second) || yz != (xy & !xz) | z {
+                        continue;
+                    }
+
+                    let number = numbers(z).next().unwrap();
+                    let both = fields
+                        .clone()
+                        .filter(|&idx| grid.sees(idx, first) && grid.sees(idx, second));
+                    let mut step = Step::new(self.name(), grid);
+                    step.eliminations = grid.eliminate(both, number);
+                    if step.makes_progress() {
+                        step.cells = vec![pivot, first, second];
+                        step.numbers = numbers(xy | z).collect();
+                        return Some(step);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Chains of groups where a number has exactly two places, coloured
+/// alternately: one colour holds the number. If two fields of a colour see
+/// each other, that colour is wrong; a field that sees both colours is not
+/// the number.
+pub struct SimpleColouring;
+
+impl Tactic for SimpleColouring {
+    fn name(&self) -> &'static str {
+        "Simple Colouring"
+    }
+
+    /// Not on the Sudoku Explainer scale, placed between XY-Wing and the
+    /// quads as other solvers rank it.
+    fn rating(&self) -> f32 {
+        4.5
+    }
+
+    fn find(&self, grid: &Grid) -> Option<Step> {
+        for number in 1..=grid.side() as u8 {
+            let links: Vec<(usize, usize)> = (0..grid.groups.len())
+                .filter_map(|group| match grid.positions(group, number)[..] {
+                    [a, b] => Some((a, b)),
+                    _ => None,
+                })
+                .collect();
+
+            let mut colour: Vec<Option<bool>> = vec![None; grid.values.len()];
+            for &(start, _) in &links {
+                if colour[start].is_some() {
+                    continue;
+                }
+                let mut chain = vec![start];
+                colour[start] = Some(true);
+                let mut i = 0;
+                while let Some(&idx) = chain.get(i) {
+                    i += 1;
+                    for &(a, b) in &links {
+                        let next = if idx == a {
+                            b
+                        } else if idx == b {
+                            a
+                        } else {
+                            continue;
+                        };
+                        if colour[next].is_none() {
+                            colour[next] = Some(!colour[idx].unwrap());
+                            chain.push(next);
+                        }
+                    }
+                }
+
+                let (on, off): (Vec<usize>, Vec<usize>) =
+                    chain.iter().partition(|&&idx| colour[idx] == Some(true));
+                let mut step = Step::new(self.name(), grid);
+                for same in [&on, &off] {
+                    if same.iter().any(|&a| same.iter().any(|&b| grid.sees(a, b))) {
+                        step.eliminations = grid.eliminate(same.iter().copied(), number);
+                        break;
+                    }
+                }
+                if step.eliminations.is_empty() {
+                    let sees_both = (0..grid.values.len()).filter(|&idx| {
+                        !chain.contains(&idx)
+                            && on.iter().any(|&a| grid.sees(idx, a))
+                            && off.iter().any(|&b| grid.sees(idx, b))
+                    });
+                    step.eliminations = grid.eliminate(sees_both, number);
+                }
+                if step.makes_progress() {
+                    step.cells = chain;
+                    step.numbers = vec![number];
+                    return Some(step);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn bit(number: u8) -> u32 {
+    1 << (number - 1)
+}
+
+/// The numbers in a candidate mask, smallest first.
+fn numbers(mask: u32) -> impl Iterator<Item = u8> + Clone {
+    (1..=u32::BITS as u8).filter(move |&n| mask & bit(n) != 0)
+}
+
+/// All ways to pick `k` of `items`, keeping their order.
+fn combinations<T: Copy>(items: &[T], k: usize) -> Vec<Vec<T>> {
+    if k == 0 {
+        return vec![vec![]];
+    }
+    let mut res = vec![];
+    for (i, &first) in items.iter().enumerate() {
+        for mut rest in combinations(&items[i + 1..], k - 1) {
+            rest.insert(0, first);
+            res.push(rest);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitfield_tactics::BitfiedTacticsSolver;
+
+    /// Solves `puzzle` up to the first step of `technique`, checks that it
+    /// is the next step and agrees with the solution, and describes it.
+    fn first_step(puzzle: &str, technique: &str) -> String {
+        let puzzle = PlayingField::new(puzzle).unwrap();
+        let solution = BitfiedTacticsSolver::new(&puzzle)
+            .try_solve()
+            .unwrap()
+            .extract();
+        let pipeline = Pipeline::standard();
+        let mut grid = Grid::new(&puzzle);
+        loop {
+            let step = pipeline
+                .next_step(&grid, &mut Search::default())
+                .expect(technique);
+            for &(idx, number) in &step.placements {
+                assert_eq!(solution.fields[idx].as_number(), Some(number), "{step}");
+            }
+            for &(idx, number) in &step.eliminations {
+                assert_ne!(solution.fields[idx].as_number(), Some(number), "{step}");
+            }
+            if step.technique == technique {
+                return step.to_string();
+            }
+            grid.apply(&step);
+        }
+    }
+
+    #[test]
+    fn hidden_single() {
+        // Line 0 of `msk_009.csv`.
+        let puzzle =
+            "094000130000000000000076002080010000032000000000200060000050400000008007006304008";
+        assert_eq!(
+            first_step(puzzle, "Hidden Single"),
+            "Hidden Single in ROW 1: r1c1 = 7"
+        );
+    }
+
+    #[test]
+    fn naked_single() {
+        // Line 0 of `msk_009.csv`.
+        let puzzle =
+            "094000130000000000000076002080010000032000000000200060000050400000008007006304008";
+        assert_eq!(first_step(puzzle, "Naked Single"), "Naked Single: r5c8 = 7");
+    }
+
+    #[test]
+    fn pointing() {
+        // Line 0 of `msk_009.csv`.
+        let puzzle =
+            "094000130000000000000076002080010000032000000000200060000050400000008007006304008";
+        assert_eq!(
+            first_step(puzzle, "Pointing"),
+            "Pointing in SQUARE 4, ROW 6 on r6c1, r6c2 with 1: r6c9 <> 1"
+        );
+    }
+
+    #[test]
+    fn box_line_reduction() {
+        // Line 15 of `msk_009.csv`.
+        let puzzle =
+            "000000003005002014000080060000000000946000000030004206000700000000030680070291000";
+        assert_eq!(
+            first_step(puzzle, "Box/Line Reduction"),
+            "Box/Line Reduction in ROW 5, SQUARE 6 on r5c7, r5c9 with 1: r4c7 <> 1, r4c9 <> 1"
+        );
+    }
+
+    #[test]
+    fn naked_pair() {
+        // Line 2 of `msk_009.csv`.
+        let puzzle =
+            "000007000090001000000045006000020000036000410500000809000000004000018000081500032";
+        assert_eq!(
+            first_step(puzzle, "Naked Pair"),
+            "Naked Pair in ROW 4 on r4c2, r4c4 with 1, 7: r4c1 <> 1, r4c1 <> 7, r4c3 <> 7"
+        );
+    }
+
+    #[test]
+    fn naked_triple() {
+        // Line 125 of `msk_009.csv`.
+        let puzzle =
+            "000000000000105000040008002009003100000010000680750004007060030090000700054000980";
+        assert_eq!(first_step(puzzle, "Naked Triple"), "Naked Triple in ROW 3 on r3c3, r3c4, r3c7 with 3, 5, 6: r3c5 <> 3, r3c1 <> 5, r3c8 <> 5, r3c8 <> 6");
+    }
+
+    #[test]
+    fn naked_quad() {
+        // Line 796 of `msk_009.csv`.
+        let puzzle =
+            "007600000000007020001380500000030000080004105005200740000400000604800350010003009";
+        assert_eq!(first_step(puzzle, "Naked Quad"), "Naked Quad in ROW 4 on r4c3, r4c7, r4c8, r4c9 with 2, 6, 8, 9: r4c2 <> 6, r4c6 <> 6, r4c6 <> 8, r4c1 <> 9, r4c2 <> 9, r4c4 <> 9, r4c6 <> 9");
+    }
+
+    #[test]
+    fn hidden_pair() {
+        // Line 5 of `msk_009.csv`.
+        let puzzle =
+            "000005000020004010030080020000008400800600000090010705006000000950003060003000001";
+        assert_eq!(first_step(puzzle, "Hidden Pair"), "Hidden Pair in COL 7 on r2c7, r3c7 with 5, 6: r2c7 <> 3, r2c7 <> 8, r2c7 <> 9, r3c7 <> 9");
+    }
+
+    #[test]
+    fn hidden_triple() {
+        // Line 472 of `msk_009.csv`.
+        let puzzle =
+            "005000000637120000041000073000900000000040302800000010500200001020001435000000080";
+        assert_eq!(first_step(puzzle, "Hidden Triple"), "Hidden Triple in SQUARE 4 on r4c1, r4c3, r6c3 with 2, 3, 4: r4c3 <> 6, r6c3 <> 6, r6c3 <> 9");
+    }
+
+    #[test]
+    fn hidden_quad() {
+        // Line 905 of `msk_009.csv`.
+        let puzzle =
+            "000000000000875200708600000005000000804000020637050400000001000140308005003090014";
+        assert_eq!(first_step(puzzle, "Hidden Quad"), "Hidden Quad in ROW 1 on r1c2, r1c7, r1c8, r1c9 with 5, 6, 7, 8: r1c2 <> 2, r1c2 <> 9, r1c7 <> 1, r1c7 <> 3, r1c7 <> 9, r1c8 <> 3, r1c8 <> 4, r1c8 <> 9, r1c9 <> 1, r1c9 <> 3, r1c9 <> 9");
+    }
+
+    #[test]
+    fn x_wing() {
+        // Line 52 of `msk_009.csv`.
+        let puzzle =
+            "905800076000000000700049030000000009001008200000370000070000000430005080008090005";
+        assert_eq!(
+            first_step(puzzle, "X-Wing"),
+            "X-Wing in ROW 2, ROW 9, COL 2, COL 8 with 2: r3c2 <> 2, r7c8 <> 2"
+        );
+    }
+
+    #[test]
+    fn swordfish() {
+        // Line 228 of `msk_009.csv`.
+        let puzzle =
+            "073000000200006000004000309000000080701080400020000761000010020006020000000938040";
+        assert_eq!(
+            first_step(puzzle, "Swordfish"),
+            "Swordfish in ROW 2, ROW 4, ROW 7, COL 2, COL 3, COL 7 with 9: r8c2 <> 9"
+        );
+    }
+
+    #[test]
+    fn xy_wing() {
+        // Line 2 of `msk_009.csv`.
+        let puzzle =
+            "000007000090001000000045006000020000036000410500000809000000004000018000081500032";
+        assert_eq!(
+            first_step(puzzle, "XY-Wing"),
+            "XY-Wing on r2c1, r2c4, r3c2 with 2, 6, 7: r2c3 <> 2, r3c4 <> 2"
+        );
+    }
+
+    #[test]
+    fn simple_colouring() {
+        // Line 12 of `msk_009.csv`.
+        let puzzle =
+            "000008020000006930098070001000000000009210000700000096240090000000300180000000003";
+        assert_eq!(first_step(puzzle, "Simple Colouring"), "Simple Colouring on r2c1, r2c5, r1c3, r3c4, r4c3, r3c7, r6c4 with 4: r4c5 <> 4, r4c7 <> 4");
+    }
+
+    #[test]
+    fn solving_stops_at_the_deadline() {
+        let puzzle = PlayingField::new(&include_str!("../msk_009.csv")[..81]).unwrap();
+        let mut grid = Grid::new(&puzzle);
+        let mut search = Search {
+            deadline: Some(std::time::Instant::now()),
+            ..Search::default()
+        };
+        assert_eq!(Pipeline::standard().solve(&mut grid, &mut search), []);
+        assert!(search.timed_out);
+        assert_eq!(grid, Grid::new(&puzzle));
+    }
+}
@@ -0,0 +1,527 @@
+//! Constraints beyond rows, columns and boxes, and the puzzle files that
+//! declare them.
+//!
+//! Regions, jigsaw boxes and the fields of killer cages become extra groups
+//! through [`PlayingField::groups`], so everything that works with groups
+//! honours them. The other constraints check a board with
+//! [`Constraint::holds`] and narrow down candidates with [`Constraint::prune`].
+
+use super::bitfield_tactics::Search;
+use super::solver;
+use super::PlayingField;
+
+const USAGE: &str = "Usage: sudoku variant FILE [--solver dlx|bitfield|naive]";
+
+/// `sudoku variant`: solves the puzzles of a puzzle file.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let (path, solver) = match args {
+        [path] => (path, solver::by_name("bitfield")?),
+        [path, flag, name] if flag == "--solver" => (path, solver::by_name(name)?),
+        _ => return Err(format!("Unknown arguments {args:?}\n{USAGE}")),
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let puzzles = parse_puzzle_file(&text).map_err(|e| format!("{path}: {e}"))?;
+
+    for (idx, puzzle) in puzzles.iter().enumerate() {
+        let rules: Vec<String> = puzzle
+            .constraints
+            .iter()
+            .map(|c| c.describe(puzzle.side()))
+            .collect();
+        if rules.is_empty() {
+            println!("Sudoko {idx}");
+        } else {
+            println!("Sudoko {idx}: {}", rules.join(", "));
+        }
+        match solver.solve(puzzle, &mut Search::default()) {
+            Ok(solved) if solved.is_solved() => println!("{solved}"),
+            _ => println!("No solution found"),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    /// Irregular boxes that replace the square ones.
+    Jigsaw(Vec<Vec<usize>>),
+    /// Fields that hold no number twice, like the diagonals of X-sudoku.
+    Region(Vec<usize>),
+    /// Fields with different numbers that add up to `sum`.
+    Cage { sum: u32, cells: Vec<usize> },
+    /// Fields a knight's move apart hold different numbers.
+    AntiKnight,
+    /// Fields that touch, even diagonally, hold different numbers.
+    AntiKing,
+    /// Fields side by side do not hold consecutive numbers.
+    NonConsecutive,
+    /// Numbers that grow from the bulb, the first field, to the tip.
+    Thermo(Vec<usize>),
+    /// Numbers on the arrow that add up to the number in the circle.
+    Arrow { circle: usize, arrow: Vec<usize> },
+}
+
+impl Constraint {
+    /// The fields of this constraint that also form a group.
+    pub fn region(&self) -> Option<&[usize]> {
+        match self {
+            Constraint::Region(cells) | Constraint::Cage { cells, .. } => Some(cells),
+            _ => None,
+        }
+    }
+
+    /// Whether a board, which may still have empty fields, can satisfy this
+    /// constraint. Groups are checked through [`PlayingField::groups`].
+    pub fn holds(&self, play: &PlayingField) -> bool {
+        let side = play.side();
+        let number = |idx: usize| play.fields[idx].as_number().map(u32::from);
+        match self {
+            Constraint::Jigsaw(_) | Constraint::Region(_) => true,
+            Constraint::Cage { sum, cells } => {
+                let filled: u32 = cells.iter().filter_map(|&i| number(i)).sum();
+                let empty = cells.iter().filter(|&&i| number(i).is_none()).count() as u32;
+                (filled + empty..=filled + empty * side as u32).contains(sum)
+            }
+            Constraint::AntiKnight | Constraint::AntiKing | Constraint::NonConsecutive => {
+                (0..play.fields.len()).all(|a| {
+                    let Some(n) = number(a) else {
+                        return true;
+                    };
+                    self.related(a, side).into_iter().all(|b| match number(b) {
+                        Some(m) if self == &Constraint::NonConsecutive => n.abs_diff(m) != 1,
+                        Some(m) => n != m,
+                        None => true,
+                    })
+                })
+            }
+            Constraint::Thermo(cells) => cells.iter().enumerate().all(|(i, &a)| {
+                cells[i + 1..]
+                    .iter()
+                    .enumerate()
+                    .all(|(j, &b)| match (number(a), number(b)) {
+                        (Some(n), Some(m)) => n + (j as u32) < m,
+                        _ => true,
+                    })
+            }),
+            Constraint::Arrow { circle, arrow } => {
+                let filled: u32 = arrow.iter().filter_map(|&i| number(i)).sum();
+                let empty = arrow.iter().filter(|&&i| number(i).is_none()).count() as u32;
+                match number(*circle) {
+                    Some(total) => (filled + empty..=filled + empty * side as u32).contains(&total),
+                    None => filled + empty <= side as u32,
+                }
+            }
+        }
+    }
+
+    /// Removes candidates this constraint rules out, one bit per number in
+    /// each mask. Returns false when a field is left without candidates.
+    pub fn prune(&self, masks: &mut [u32], side: usize) -> bool {
+        match self {
+            Constraint::Jigsaw(_) | Constraint::Region(_) => {}
+            Constraint::AntiKnight | Constraint::AntiKing | Constraint::NonConsecutive => {
+                for a in 0..masks.len() {
+                    if masks[a].count_ones() != 1 {
+                        continue;
+                    }
+                    let ruled_out = if self == &Constraint::NonConsecutive {
+                        (masks[a] << 1) | (masks[a] >> 1)
+                    } else {
+                        masks[a]
+                    };
+                    for b in self.related(a, side) {
+                        masks[b] &= !ruled_out;
+                    }
+                }
+            }
+            Constraint::Cage { sum, cells } => {
+                let low: u32 = cells.iter().map(|&i| lowest(masks[i])).sum();
+                let high: u32 = cells.iter().map(|&i| highest(masks[i])).sum();
+                for &i in cells {
+                    let others = (
+                        low.saturating_sub(lowest(masks[i])),
+                        high.saturating_sub(highest(masks[i])),
+                    );
+                    masks[i] &= between(sum.saturating_sub(others.1), sum.saturating_sub(others.0));
+                }
+            }
+            Constraint::Thermo(cells) => {
+                let mut above = 0;
+                for &i in cells {
+                    masks[i] &= between(above + 1, side as u32);
+                    above = lowest(masks[i]);
+                }
+                let mut below = side as u32 + 1;
+                for &i in cells.iter().rev() {
+                    masks[i] &= between(1, below.saturating_sub(1));
+                    below = highest(masks[i]);
+                }
+            }
+            Constraint::Arrow { circle, arrow } => {
+                let low: u32 = arrow.iter().map(|&i| lowest(masks[i])).sum();
+                let high: u32 = arrow.iter().map(|&i| highest(masks[i])).sum();
+                masks[*circle] &= between(low, high);
+                let (min, max) = (lowest(masks[*circle]), highest(masks[*circle]));
+                for &i in arrow {
+                    let others = (
+                        low.saturating_sub(lowest(masks[i])),
+                        high.saturating_sub(highest(masks[i])),
+                    );
+                    masks[i] &= between(min.saturating_sub(others.1), max.saturating_sub(others.0));
+                }
+            }
+        }
+        masks.iter().all(|&m| m != 0)
+    }
+
+    /// Pairs of placements, a field and a number, that cannot both be made.
+    /// Cages and arrows constrain more than two fields and have none.
+    pub fn conflicts(&self, side: usize) -> Vec<[(usize, u8); 2]> {
+        let numbers = 1..=side as u8;
+        let mut conflicts = vec![];
+        match self {
+            Constraint::AntiKnight | Constraint::AntiKing | Constraint::NonConsecutive => {
+                for a in 0..side * side {
+                    for b in self.related(a, side).into_iter().filter(|&b| b > a) {
+                        for n in numbers.clone() {
+                            if self != &Constraint::NonConsecutive {
+                                conflicts.push([(a, n), (b, n)]);
+                            } else if n < side as u8 {
+                                conflicts.push([(a, n), (b, n + 1)]);
+                                conflicts.push([(a, n + 1), (b, n)]);
+                            }
+                        }
+                    }
+                }
+            }
+            Constraint::Thermo(cells) => {
+                for pair in cells.windows(2) {
+                    for n in numbers.clone() {
+                        for m in 1..=n {
+                            conflicts.push([(pair[0], n), (pair[1], m)]);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        conflicts
+    }
+
+    /// Fields that may not hold the same (or for non-consecutive, a
+    /// neighbouring) number as `idx`.
+    fn related(&self, idx: usize, side: usize) -> Vec<usize> {
+        let steps: &[(isize, isize)] = match self {
+            Constraint::AntiKnight => &[
+                (-2, -1),
+                (-2, 1),
+                (-1, -2),
+                (-1, 2),
+                (1, -2),
+                (1, 2),
+                (2, -1),
+                (2, 1),
+            ],
+            Constraint::AntiKing => &[
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ],
+            Constraint::NonConsecutive => &[(-1, 0), (0, -1), (0, 1), (1, 0)],
+            _ => &[],
+        };
+        let (row, col) = ((idx / side) as isize, (idx % side) as isize);
+        steps
+            .iter()
+            .map(|(dr, dc)| (row + dr, col + dc))
+            .filter(|&(r, c)| (0..side as isize).contains(&r) && (0..side as isize).contains(&c))
+            .map(|(r, c)| r as usize * side + c as usize)
+            .collect()
+    }
+
+    /// A name for messages about this constraint.
+    pub fn describe(&self, side: usize) -> String {
+        let cell = |idx: &usize| format!("r{}c{}", idx / side + 1, idx % side + 1);
+        match self {
+            Constraint::Jigsaw(_) => "JIGSAW".to_string(),
+            Constraint::Region(cells) => format!("REGION at {}", cell(&cells[0])),
+            Constraint::Cage { sum, cells } => format!("CAGE {sum} at {}", cell(&cells[0])),
+            Constraint::AntiKnight => "ANTI-KNIGHT".to_string(),
+            Constraint::AntiKing => "ANTI-KING".to_string(),
+            Constraint::NonConsecutive => "NON-CONSECUTIVE".to_string(),
+            Constraint::Thermo(cells) => format!("THERMO at {}", cell(&cells[0])),
+            Constraint::Arrow { circle, .. } => format!("ARROW at {}", cell(circle)),
+        }
+    }
+}
+
+/// The smallest number in a mask, or 0 for an empty one.
+fn lowest(mask: u32) -> u32 {
+    if mask == 0 {
+        0
+    } else {
+        mask.trailing_zeros() + 1
+    }
+}
+
+/// The largest number in a mask, or 0 for an empty one.
+fn highest(mask: u32) -> u32 {
+    u32::BITS - mask.leading_zeros()
+}
+
+/// A mask of the numbers from `low` to `high`.
+fn between(low: u32, high: u32) -> u32 {
+    let low = low.max(1);
+    if low > high || high == 0 {
+        return 0;
+    }
+    let upto = |n: u32| if n >= 32 { u32::MAX } else { (1 << n) - 1 };
+    upto(high) & !upto(low - 1)
+}
+
+/// Parses a puzzle file. Each puzzle starts with `grid`, followed by the
+/// fields as one line or as one line per row, and then one constraint per line:
+///
+/// ```text
+/// # An X-sudoku with a killer cage.
+/// grid
+/// 000000000
+/// ...
+/// diagonals
+/// cage 15 r1c1 r1c2 r2c1
+/// ```
+///
+/// The constraints are `diagonals`, `region CELLS`, `jigsaw LABELS` (one
+/// label per field, each label marking a box), `cage SUM CELLS`,
+/// `anti-knight`, `anti-king`, `non-consecutive`, `thermo CELLS` from bulb
+/// to tip and `arrow CIRCLE CELLS`. No field may be given twice in one
+/// constraint. Lines starting with `#` are comments.
+pub fn parse_puzzle_file(text: &str) -> Result<Vec<PlayingField>, String> {
+    let mut puzzles = vec![];
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line.trim()))
+        .filter(|(_n, line)| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+
+    while let Some((n, line)) = lines.next() {
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap();
+        let args: Vec<&str> = words.collect();
+        let at = |e: String| format!("line {n}: {e}");
+
+        if keyword == "grid" {
+            let mut fields = args.concat();
+            if fields.is_empty() {
+                let Some(&(_, first)) = lines.peek() else {
+                    return Err(at("grid without fields".to_string()));
+                };
+                let rows = first.chars().count();
+                for _ in 0..rows {
+                    let (_, row) = lines
+                        .next()
+                        .ok_or_else(|| at(format!("grid needs {rows} rows")))?;
+                    fields.push_str(row);
+                }
+            }
+            puzzles.push(PlayingField::new(&fields).map_err(at)?);
+            continue;
+        }
+
+        let Some(puzzle) = puzzles.last_mut() else {
+            return Err(at(format!("{keyword} before the first grid")));
+        };
+        let side = puzzle.side();
+        let cells = |args: &[&str]| -> Result<Vec<usize>, String> {
+            let mut cells = vec![];
+            for cell in args {
+                let idx = parse_cell(cell, side)?;
+                if cells.contains(&idx) {
+                    return Err(format!("field {cell:?} given twice"));
+                }
+                cells.push(idx);
+            }
+            Ok(cells)
+        };
+        let new = match (keyword, &args[..]) {
+            ("diagonals", []) => vec![
+                Constraint::Region((0..side).map(|i| i * side + i).collect()),
+                Constraint::Region((0..side).map(|i| i * side + side - 1 - i).collect()),
+            ],
+            ("region", fields) if !fields.is_empty() => {
+                vec![Constraint::Region(cells(fields).map_err(at)?)]
+            }
+            ("jigsaw", labels) => vec![Constraint::Jigsaw(
+                parse_jigsaw(&labels.concat(), side).map_err(at)?,
+            )],
+            ("cage", [sum, fields @ ..]) if !fields.is_empty() => vec![Constraint::Cage {
+                sum: sum.parse().map_err(|_| at(format!("not a sum: {sum:?}")))?,
+                cells: cells(fields).map_err(at)?,
+            }],
+            ("anti-knight", []) => vec![Constraint::AntiKnight],
+            ("anti-king", []) => vec![Constraint::AntiKing],
+            ("non-consecutive", []) => vec![Constraint::NonConsecutive],
+            ("thermo", fields) if fields.len() >= 2 => {
+                vec![Constraint::Thermo(cells(fields).map_err(at)?)]
+            }
+            ("arrow", [_circle, arrow @ ..]) if !arrow.is_empty() => {
+                let fields = cells(&args).map_err(at)?;
+                vec![Constraint::Arrow {
+                    circle: fields[0],
+                    arrow: fields[1..].to_vec(),
+                }]
+            }
+            _ => return Err(at(format!("cannot read {line:?}"))),
+        };
+        let mut constraints = puzzle.constraints.to_vec();
+        constraints.extend(new);
+        puzzle.constraints = constraints.into();
+    }
+
+    Ok(puzzles)
+}
+
+/// Reads `r3c12` style field names.
+fn parse_cell(cell: &str, side: usize) -> Result<usize, String> {
+    let parsed = cell
+        .strip_prefix('r')
+        .and_then(|rest| rest.split_once('c'))
+        .and_then(|(r, c)| Some((r.parse::<usize>().ok()?, c.parse::<usize>().ok()?)));
+    match parsed {
+        Some((r, c)) if (1..=side).contains(&r) && (1..=side).contains(&c) => {
+            Ok((r - 1) * side + c - 1)
+        }
+        _ => Err(format!("not a field: {cell:?}")),
+    }
+}
+
+/// Groups the fields by label, each label making one box of `side` fields.
+fn parse_jigsaw(labels: &str, side: usize) -> Result<Vec<Vec<usize>>, String> {
+    let labels: Vec<char> = labels.chars().collect();
+    if labels.len() != side * side {
+        return Err(format!(
+            "jigsaw needs {} labels, not {}",
+            side * side,
+            labels.len()
+        ));
+    }
+    let mut names: Vec<char> = labels.clone();
+    names.sort();
+    names.dedup();
+
+    let boxes: Vec<Vec<usize>> = names
+        .iter()
+        .map(|&name| (0..labels.len()).filter(|&i| labels[i] == name).collect())
+        .collect();
+    if boxes.len() != side || boxes.iter().any(|b| b.len() != side) {
+        return Err(format!("jigsaw needs {side} labels of {side} fields each"));
+    }
+    Ok(boxes)
+}
+
+impl PlayingField {
+    /// Rows, columns and boxes as [`group_indices`](super::group_indices)
+    /// has them, with jigsaw boxes in place of square ones, then extra
+    /// regions and cages.
+    pub fn groups(&self) -> Vec<Vec<usize>> {
+        let side = self.side();
+        let mut groups = super::group_indices(self.box_size);
+        for constraint in self.constraints.iter() {
+            if let Constraint::Jigsaw(boxes) = constraint {
+                groups.splice(2 * side..3 * side, boxes.iter().cloned());
+            }
+        }
+        groups.extend(
+            self.constraints
+                .iter()
+                .filter_map(|c| c.region())
+                .map(|cells| cells.to_vec()),
+        );
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitfield_tactics::BitfiedTacticsSolver;
+    use crate::dlx;
+
+    #[test]
+    fn parses_constraints_and_reports_lines() {
+        let text = "# comment\ngrid\n1000\n0000\n0000\n0000\nanti-king\ncage 3 r1c2 r2c2\n";
+        let puzzles = parse_puzzle_file(text).unwrap();
+        assert_eq!(puzzles.len(), 1);
+        assert_eq!(
+            &puzzles[0].constraints[..],
+            [
+                Constraint::AntiKing,
+                Constraint::Cage {
+                    sum: 3,
+                    cells: vec![1, 5]
+                }
+            ]
+        );
+
+        let err = |text: &str| parse_puzzle_file(text).err().unwrap();
+        assert_eq!(err("diagonals"), "line 1: diagonals before the first grid");
+        assert_eq!(
+            err(&format!("grid {}\n\nthermo r1c1 r10c1", "0".repeat(81))),
+            "line 3: not a field: \"r10c1\""
+        );
+
+        let grid = format!("grid {}\n", "0".repeat(81));
+        for (constraint, cell) in [
+            ("cage 18 r1c1 r1c1", "r1c1"),
+            ("region r1c1 r2c2 r2c2", "r2c2"),
+            ("thermo r1c1 r1c2 r1c1", "r1c1"),
+            ("arrow r5c5 r5c6 r5c5", "r5c5"),
+        ] {
+            assert_eq!(
+                err(&format!("{grid}{constraint}")),
+                format!("line 2: field \"{cell}\" given twice")
+            );
+        }
+    }
+
+    #[test]
+    fn holds_and_prune_agree() {
+        let mut play = PlayingField::new("1200000000000000").unwrap();
+        play.constraints = vec![Constraint::NonConsecutive].into();
+        assert!(!play.check_constraints());
+
+        let thermo = Constraint::Thermo(vec![0, 1, 2]);
+        let mut masks = vec![0b1111; 16];
+        assert!(thermo.prune(&mut masks, 4));
+        assert_eq!(masks[..3], [0b0011, 0b0110, 0b1100]);
+        masks[1] = 0b0001;
+        assert!(!thermo.prune(&mut masks, 4));
+    }
+
+    #[test]
+    fn example_puzzles_are_unique() {
+        let puzzles = parse_puzzle_file(include_str!("../example_variants.txt")).unwrap();
+        for puzzle in puzzles {
+            let lines = |solutions: Vec<PlayingField>| {
+                assert!(solutions.iter().all(PlayingField::is_solved));
+                solutions
+                    .iter()
+                    .map(PlayingField::to_line)
+                    .collect::<Vec<_>>()
+            };
+            let solver = BitfiedTacticsSolver::new(&puzzle);
+            let bitfield = lines(solver.solutions(2, &mut Search::default()));
+            assert_eq!(bitfield.len(), 1, "{}", puzzle.to_line());
+            assert_eq!(
+                lines(dlx::sudoku_solutions(&puzzle, 2, &mut Search::default())),
+                bitfield
+            );
+        }
+    }
+}